@@ -14,6 +14,8 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 use toml_edit;
 
+use redbpf::build::BPF_MARCH;
+
 use crate::CommandError;
 
 #[derive(Debug)]
@@ -69,7 +71,7 @@ pub fn build_program(
     out_dir: &Path,
     program: &str,
 ) -> Result<(), Error> {
-    let llc_args = ["-march=bpf", "-filetype=obj", "-o"];
+    let llc_args = [BPF_MARCH, "-filetype=obj", "-o"];
     let elf_target = out_dir.join(format!("{}.elf", program));
 
     let current_dir = env::current_dir().unwrap();
@@ -17,6 +17,8 @@ use tokio::runtime::Runtime;
 use tokio::signal;
 
 pub fn load(program: &PathBuf, interface: Option<&str>) -> Result<(), CommandError> {
+    // kernels before 5.11 charge maps and programs against RLIMIT_MEMLOCK
+    let _ = redbpf::bump_memlock_rlimit();
     let mut runtime = Runtime::new().unwrap();
     let _ = runtime.block_on(async {
         let mut loader = Loader::new()
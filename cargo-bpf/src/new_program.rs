@@ -98,6 +98,7 @@ use cty::*;
 use cty::*;
 
 use redbpf_probes::bindings::*;
+use redbpf_probes::kprobe::*;
 use redbpf_probes::maps::*;
 use redbpf_macros::{{map, program, kprobe}};
 
@@ -112,7 +113,7 @@ program!(0xFFFFFFFE, "GPL");
 // static mut syscall_events: PerfMap<SomeEvent> = PerfMap::new();
 //
 // #[kprobe("syscall_enter")]
-// pub extern "C" fn syscall_enter(ctx: *mut c_void) -> i32 {{
+// pub extern "C" fn syscall_enter(ctx: KProbeContext) -> i32 {{
 //   let pid_tgid = bpf_get_current_pid_tgid();
 //   ...
 //
@@ -120,7 +121,7 @@ program!(0xFFFFFFFE, "GPL");
 //     id: pid_tgid >> 32,
 //     ...
 //   }};
-//   unsafe {{ syscall_events.insert(ctx, event) }};
+//   unsafe {{ syscall_events.insert(ctx.inner(), event) }};
 //
 //   return 0;
 // }}
@@ -9,9 +9,13 @@
 #include <net/inet_sock.h>
 #include <linux/udp.h>
 #include <linux/tcp.h>
+#include <linux/icmp.h>
+#include <linux/icmpv6.h>
 #include <linux/in.h>
 #include <linux/ip.h>
+#include <linux/ipv6.h>
 #include <linux/if_ether.h>
+#include <linux/if_arp.h>
 #pragma clang diagnostic pop
 
 #include <linux/skbuff.h>
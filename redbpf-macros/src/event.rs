@@ -0,0 +1,229 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Expansion of `#[derive(BpfEvent)]`.
+
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{Attribute, Data, DeriveInput, Expr, Field, Fields, Meta, NestedMeta, Result, Type};
+
+const INTEGERS: [&str; 12] = [
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+];
+
+/// How a field is stored in the event.
+#[derive(Clone, Copy, PartialEq)]
+enum ByteOrder {
+    Native,
+    Big,
+}
+
+pub(crate) fn derive_bpf_event(input: DeriveInput) -> Result<TokenStream2> {
+    check_repr_c(&input)?;
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "`BpfEvent` can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut reads = Vec::new();
+    let mut values = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let order = byte_order(field)?;
+        let ty = &field.ty;
+        let value = Ident::new(&format!("__field{}", i), Span::call_site());
+        let read = read_value(ty, quote!(offset), order)?;
+        reads.push(quote! {
+            let align = ::core::mem::align_of::<#ty>();
+            let offset = (end + align - 1) / align * align;
+            let #value: #ty = #read;
+            let end = offset + ::core::mem::size_of::<#ty>();
+        });
+        values.push(value);
+    }
+    let construct = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote!(Self { #(#names: #values),* })
+        }
+        Fields::Unnamed(_) => quote!(Self(#(#values),*)),
+        Fields::Unit => quote!(Self),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Parses an event from the bytes the program output, as read
+            /// from a perf or ring buffer, or returns `None` if there are
+            /// too few of them or a field holds an invalid value.
+            #[allow(dead_code, unused_variables)]
+            pub fn from_bytes(bytes: &[u8]) -> ::core::option::Option<Self> {
+                if bytes.len() < ::core::mem::size_of::<Self>() {
+                    return ::core::option::Option::None;
+                }
+                let end = 0usize;
+                #(#reads)*
+                ::core::option::Option::Some(#construct)
+            }
+        }
+    })
+}
+
+/// Fails unless the layout of the struct is `#[repr(C)]`, which both sides
+/// agree on.
+fn check_repr_c(input: &DeriveInput) -> Result<()> {
+    let mut repr_c = false;
+    for attr in input.attrs.iter().filter(|attr| attr.path.is_ident("repr")) {
+        if let Meta::List(list) = attr.parse_meta()? {
+            for repr in list.nested.iter() {
+                match repr {
+                    NestedMeta::Meta(meta) if meta.path().is_ident("C") => repr_c = true,
+                    NestedMeta::Meta(meta) if meta.path().is_ident("packed") => {
+                        return Err(syn::Error::new(
+                            meta.span(),
+                            "`BpfEvent` doesn't support packed structs",
+                        ))
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    if !repr_c {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "`BpfEvent` requires `#[repr(C)]`, so that the layout is the same in the program",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the byte order set with `#[bpf_event(big_endian)]`.
+fn byte_order(field: &Field) -> Result<ByteOrder> {
+    let mut order = ByteOrder::Native;
+    let attrs = field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("bpf_event"));
+    for attr in attrs {
+        for meta in nested_meta(attr)? {
+            match meta {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("big_endian") => {
+                    order = ByteOrder::Big
+                }
+                meta => return Err(syn::Error::new(meta.span(), "expected `big_endian`")),
+            }
+        }
+    }
+    Ok(order)
+}
+
+fn nested_meta(attr: &Attribute) -> Result<Vec<NestedMeta>> {
+    match attr.parse_meta()? {
+        Meta::List(list) => Ok(list.nested.into_iter().collect()),
+        meta => Err(syn::Error::new(meta.span(), "expected `bpf_event(...)`")),
+    }
+}
+
+/// Returns an expression reading a `ty` at `offset` into `bytes`, stored in
+/// `order`, which evaluates to `None` from the enclosing function if the
+/// value is invalid.
+fn read_value(ty: &Type, offset: TokenStream2, order: ByteOrder) -> Result<TokenStream2> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            let ident = path.path.get_ident().map(|ident| ident.to_string());
+            match ident.as_deref() {
+                Some(int) if INTEGERS.contains(&int) => {
+                    let from_bytes = match order {
+                        ByteOrder::Native => quote!(from_ne_bytes),
+                        ByteOrder::Big => quote!(from_be_bytes),
+                    };
+                    Ok(quote! {{
+                        let size = ::core::mem::size_of::<#ty>();
+                        let mut buf = [0u8; ::core::mem::size_of::<#ty>()];
+                        buf.copy_from_slice(bytes.get(#offset..#offset + size)?);
+                        <#ty>::#from_bytes(buf)
+                    }})
+                }
+                Some(float @ "f32") | Some(float @ "f64") => {
+                    let bits = if float == "f32" {
+                        quote!(u32)
+                    } else {
+                        quote!(u64)
+                    };
+                    let read = read_value(&syn::parse2(bits)?, offset, order)?;
+                    Ok(quote!(<#ty>::from_bits(#read)))
+                }
+                Some("bool") => {
+                    no_byte_order(ty, order)?;
+                    Ok(quote! {
+                        match *bytes.get(#offset)? {
+                            0 => false,
+                            1 => true,
+                            _ => return ::core::option::Option::None,
+                        }
+                    })
+                }
+                _ => {
+                    // another event, laid out in place
+                    no_byte_order(ty, order)?;
+                    Ok(quote!(<#ty>::from_bytes(bytes.get(#offset..)?)?))
+                }
+            }
+        }
+        Type::Array(array) => {
+            let elem = &*array.elem;
+            if let Type::Path(path) = elem {
+                if !is_primitive(path) {
+                    return Err(syn::Error::new(
+                        elem.span(),
+                        "`BpfEvent` only supports arrays of integers, floats and `bool`",
+                    ));
+                }
+            }
+            let len: &Expr = &array.len;
+            let read = read_value(elem, quote!(at), order)?;
+            Ok(quote! {{
+                let mut array: #ty = [::core::default::Default::default(); #len];
+                for (i, value) in array.iter_mut().enumerate() {
+                    let at = #offset + i * ::core::mem::size_of::<#elem>();
+                    *value = #read;
+                }
+                array
+            }})
+        }
+        _ => Err(syn::Error::new(
+            ty.span(),
+            "`BpfEvent` fields must be integers, floats, `bool`, arrays of those, or events",
+        )),
+    }
+}
+
+fn is_primitive(path: &syn::TypePath) -> bool {
+    match path.path.get_ident().map(|ident| ident.to_string()) {
+        Some(ident) => {
+            INTEGERS.contains(&ident.as_str()) || ["f32", "f64", "bool"].contains(&ident.as_str())
+        }
+        None => false,
+    }
+}
+
+fn no_byte_order(ty: &Type, order: ByteOrder) -> Result<()> {
+    if order == ByteOrder::Big {
+        return Err(syn::Error::new(
+            ty.span(),
+            "`big_endian` only applies to integers and floats",
+        ));
+    }
+    Ok(())
+}
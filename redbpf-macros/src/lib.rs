@@ -28,6 +28,7 @@ to simplify creating and building eBPF programs.
 #![no_main]
 use redbpf_macros::{program, kprobe, xdp};
 use redbpf_probes::bindings::*;
+use redbpf_probes::kprobe::KProbeContext;
 use redbpf_probes::xdp::{XdpAction, XdpContext};
 
 // configure kernel version compatibility and license
@@ -40,7 +41,7 @@ pub extern "C" fn example_xdp_probe(ctx: XdpContext) -> XdpAction {
 }
 
 #[kprobe("__x64_sys_clone")]
-pub extern "C" fn example_kprobe(ctx: *mut pt_regs) {
+pub extern "C" fn example_kprobe(ctx: KProbeContext) {
     ...
 }
 ```
@@ -55,10 +56,12 @@ use syn::parse::{Parse, ParseStream};
 use syn::punctuated::Punctuated;
 use syn::token::Comma;
 use syn::{
-    parse_macro_input, parse_quote, parse_str, Block, Expr, ExprLit, File, FnArg, ItemFn, Lit, Pat,
-    PatIdent, PatType, Result, Stmt,
+    parse_macro_input, parse_quote, parse_str, DeriveInput, Expr, ExprLit, File, FnArg, ItemFn,
+    ItemStatic, Lit, LitStr, Pat, PatIdent, PatType, Result, Stmt, Token, Type,
 };
 
+mod event;
+
 fn inline_string_literal(e: &Expr) -> (TokenStream2, TokenStream2) {
     let mut bytes = match e {
         Expr::Lit(ExprLit {
@@ -132,44 +135,194 @@ pub fn program(input: TokenStream) -> TokenStream {
 /// Attribute macro that must be used when creating [eBPF
 /// maps](https://redsift.github.io/rust/redbpf/doc/redbpf_probes/maps/index.html).
 ///
+/// `pinning = by_name` pins the map to `/sys/fs/bpf/<name>` when the module
+/// is loaded, or reuses the map pinned there already, so that programs
+/// loaded independently share it.
+///
 /// # Example
 /// ```
 /// #[map("dns_queries")]
 /// static mut queries: PerfMap<Query> = PerfMap::new();
+///
+/// #[map("blocklist", pinning = by_name)]
+/// static mut blocklist: HashMap<u32, u8> = HashMap::with_max_entries(1024);
 /// ```
 #[proc_macro_attribute]
 pub fn map(attrs: TokenStream, item: TokenStream) -> TokenStream {
-    let attrs = parse_macro_input!(attrs as Expr);
-    let name = match attrs {
-        Expr::Lit(ExprLit {
-            lit: Lit::Str(s), ..
-        }) => s.value().clone(),
-        _ => panic!("expected string literal"),
-    };
+    let MapAttrs { name, pin_by_name } = parse_macro_input!(attrs as MapAttrs);
+    let item = parse_macro_input!(item as ItemStatic);
 
     let section_name = format!("maps/{}", name);
-    let item = TokenStream2::from(item);
-    let tokens = quote! {
+    let mut tokens = quote! {
         #[no_mangle]
         #[link_section = #section_name]
         #item
     };
+    if pin_by_name {
+        tokens.extend(pinning(&item.ident, &name));
+    }
 
     tokens.into()
 }
 
-fn probe_impl(ty: &str, attrs: TokenStream, item: ItemFn) -> TokenStream {
-    let name = if attrs.is_empty() {
+/// The arguments of the `map` attribute.
+struct MapAttrs {
+    name: String,
+    pin_by_name: bool,
+}
+
+impl Parse for MapAttrs {
+    fn parse(input: ParseStream) -> Result<MapAttrs> {
+        let name: LitStr = input.parse()?;
+        let mut pin_by_name = false;
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            if key != "pinning" {
+                return Err(syn::Error::new(key.span(), "expected `pinning`"));
+            }
+            pin_by_name = parse_pinning(input)?;
+        }
+
+        Ok(MapAttrs {
+            name: name.value(),
+            pin_by_name,
+        })
+    }
+}
+
+/// Parses the value of a `pinning` argument, returning whether it is
+/// `by_name`.
+fn parse_pinning(input: ParseStream) -> Result<bool> {
+    let pinning: Ident = input.parse()?;
+    match pinning.to_string().as_str() {
+        "by_name" => Ok(true),
+        "none" => Ok(false),
+        _ => Err(syn::Error::new(
+            pinning.span(),
+            "expected `by_name` or `none`",
+        )),
+    }
+}
+
+/// Marks the map `name` as pinned by name, with a `pinning/<name>` section
+/// holding `LIBBPF_PIN_BY_NAME`.
+fn pinning(ident: &Ident, name: &str) -> TokenStream2 {
+    let pinning = Ident::new(&format!("{}_PINNING", ident), Span::call_site());
+    let section_name = format!("pinning/{}", name);
+    quote! {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        #[link_section = #section_name]
+        static #pinning: u32 = 1;
+    }
+}
+
+/// The declaration `declare_map!` takes.
+struct MapDecl {
+    ident: Ident,
+    ty: Type,
+    max_entries: Expr,
+    flags: Option<Expr>,
+    pin_by_name: bool,
+}
+
+impl Parse for MapDecl {
+    fn parse(input: ParseStream) -> Result<MapDecl> {
+        let ident = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let max_entries = input.parse()?;
+
+        let mut decl = MapDecl {
+            ident,
+            ty,
+            max_entries,
+            flags: None,
+            pin_by_name: false,
+        };
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            if key == "flags" {
+                decl.flags = Some(input.parse()?);
+            } else if key == "pinning" {
+                decl.pin_by_name = parse_pinning(input)?;
+            } else {
+                return Err(syn::Error::new(key.span(), "expected `flags` or `pinning`"));
+            }
+        }
+
+        Ok(decl)
+    }
+}
+
+/// Declares a map, in place of a `#[map]` static.
+///
+/// `declare_map!(NAME: Type = max_entries)` defines the static `NAME`,
+/// placed in the section of the map `name`, which is what userspace finds it
+/// by. `Type` is any of the map types of `redbpf-probes`. Optionally,
+/// `BPF_F_*` map `flags` and `pinning` can be given, as for `#[map]`.
+///
+/// # Example
+/// ```
+/// // the "conns" map
+/// declare_map!(CONNS: HashMap<FlowKey, Stats> = 10240);
+/// declare_map!(SESSIONS: HashMap<u64, Session> = 4096, flags = BPF_F_NO_PREALLOC);
+/// declare_map!(BLOCKLIST: HashMap<IpKey, u8> = 1024, pinning = by_name);
+/// declare_map!(EVENTS: PerfMap<Event> = 1024);
+/// declare_map!(PACKETS: xdp::PerfMap<Packet> = 1024);
+/// declare_map!(LOG: EventChannel<Record> = 256 * 1024);
+/// ```
+#[proc_macro]
+pub fn declare_map(input: TokenStream) -> TokenStream {
+    let MapDecl {
+        ident,
+        ty,
+        max_entries,
+        flags,
+        pin_by_name,
+    } = parse_macro_input!(input as MapDecl);
+    let name = ident.to_string().to_lowercase();
+    let section_name = format!("maps/{}", name);
+    let flags = flags.map_or_else(|| quote! { 0 }, |flags| quote! { #flags });
+    let mut tokens = quote! {
+        #[no_mangle]
+        #[link_section = #section_name]
+        static mut #ident: #ty = <#ty>::with_flags(#max_entries, #flags);
+    };
+
+    if pin_by_name {
+        tokens.extend(pinning(&ident, &name));
+    }
+
+    tokens.into()
+}
+
+fn probe_name(attrs: TokenStream, item: &ItemFn) -> String {
+    if attrs.is_empty() {
         item.sig.ident.to_string()
     } else {
-        match parse_macro_input!(attrs as Expr) {
-            Expr::Lit(ExprLit {
+        match syn::parse::<Expr>(attrs) {
+            Ok(Expr::Lit(ExprLit {
                 lit: Lit::Str(s), ..
-            }) => s.value().clone(),
+            })) => s.value().clone(),
             _ => panic!("expected string literal"),
         }
-    };
+    }
+}
 
+fn probe_impl(ty: &str, attrs: TokenStream, item: ItemFn) -> TokenStream {
+    let name = probe_name(attrs, &item);
     let section_name = format!("{}/{}", ty, name);
     let tokens = quote! {
         #[no_mangle]
@@ -180,36 +333,180 @@ fn probe_impl(ty: &str, attrs: TokenStream, item: ItemFn) -> TokenStream {
     tokens.into()
 }
 
+/// Replaces the context argument of a probe with the raw pointer the kernel
+/// passes, and binds the original argument to the typed context `ctx_ty`
+/// wrapping it, so that each probe type only gets its own context methods.
+fn wrap_context(item: &mut ItemFn, raw_ty: TokenStream2, ctx_ty: TokenStream2, field: &str) {
+    let field = Ident::new(field, Span::call_site());
+    wrap_context_with(
+        item,
+        raw_ty,
+        |raw_ctx| quote! { #ctx_ty { #field: #raw_ctx } },
+    );
+}
+
+/// Like `wrap_context`, but builds the typed context with `init`, which
+/// gets the raw context argument.
+fn wrap_context_with(
+    item: &mut ItemFn,
+    raw_ty: TokenStream2,
+    init: impl FnOnce(&Ident) -> TokenStream2,
+) {
+    let arg = match item.sig.inputs.pop() {
+        Some(arg) => arg.into_value(),
+        None => return,
+    };
+    let (pat, ty) = match arg {
+        FnArg::Typed(PatType { pat, ty, .. }) => (pat, ty),
+        _ => panic!("unexpected probe signature"),
+    };
+    let ident = if let Pat::Ident(PatIdent { ident, .. }) = &*pat {
+        ident
+    } else {
+        panic!("unexpected probe signature")
+    };
+    let raw_ctx = Ident::new(&format!("_raw_{}", ident), Span::call_site());
+    let arg: FnArg = parse_quote! { #raw_ctx: #raw_ty };
+    item.sig.inputs.push(arg);
+    let init = init(&raw_ctx);
+    let ctx: Stmt = parse_quote! { let #pat: #ty = #init; };
+    item.block.stmts.insert(0, ctx);
+}
+
+fn wrap_kprobe_context(item: &mut ItemFn) {
+    wrap_context(
+        item,
+        quote! { *mut ::redbpf_probes::bindings::pt_regs },
+        quote! { ::redbpf_probes::kprobe::KProbeContext },
+        "ctx",
+    );
+}
+
 /// Attribute macro that must be used to define [`kprobes`](https://www.kernel.org/doc/Documentation/kprobes.txt).
 ///
 /// # Example
 /// ```
 /// #[kprobe("__x64_sys_clone")]
-/// pub extern "C" fn clone_enter(ctx: *mut pt_regs) {
+/// pub extern "C" fn clone_enter(ctx: KProbeContext) {
 ///     // this is executed when clone() is invoked
 ///     ...
 /// }
 /// ```
 #[proc_macro_attribute]
 pub fn kprobe(attrs: TokenStream, item: TokenStream) -> TokenStream {
-    let item = parse_macro_input!(item as ItemFn);
+    let mut item = parse_macro_input!(item as ItemFn);
+    wrap_kprobe_context(&mut item);
     probe_impl("kprobe", attrs, item).into()
 }
 
+/// Returns whether the context argument of the probe `item` is a `ty`.
+fn takes_context(item: &ItemFn, ty: &str) -> bool {
+    match item.sig.inputs.last() {
+        Some(FnArg::Typed(PatType { ty: arg_ty, .. })) => match &**arg_ty {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .map(|segment| segment.ident == ty)
+                .unwrap_or(false),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
 /// Attribute macro that must be used to define [`kretprobes`](https://www.kernel.org/doc/Documentation/kprobes.txt).
 ///
+/// Kretprobes taking a `KRetProbeContext` get the arguments the function was
+/// called with as well, which a kprobe generated alongside stashes in the
+/// `<probe>_entry_args` map.
+///
 /// # Example
 /// ```
 /// #[kretprobe("__x64_sys_clone")]
-/// pub extern "C" fn clone_exit(ctx: *mut pt_regs) {
+/// pub extern "C" fn clone_exit(ctx: KProbeContext) {
 ///     // this is executed when clone() returns
 ///     ...
 /// }
+///
+/// #[kretprobe("tcp_sendmsg")]
+/// pub extern "C" fn tcp_sendmsg_exit(ctx: KRetProbeContext) {
+///     let size = ctx.entry_args().map(|args| args.arg(2));
+///     ...
+/// }
 /// ```
 #[proc_macro_attribute]
 pub fn kretprobe(attrs: TokenStream, item: TokenStream) -> TokenStream {
-    let item = parse_macro_input!(item as ItemFn);
-    probe_impl("kretprobe", attrs, item).into()
+    let mut item = parse_macro_input!(item as ItemFn);
+    if !takes_context(&item, "KRetProbeContext") {
+        wrap_kprobe_context(&mut item);
+        return probe_impl("kretprobe", attrs, item).into();
+    }
+
+    let name = probe_name(attrs.clone(), &item);
+    let ident = item.sig.ident.clone();
+    let map = Ident::new(&format!("{}_entry_args", ident), Span::call_site());
+    let map_section = format!("maps/{}", map);
+    let entry = Ident::new(&format!("{}_entry", ident), Span::call_site());
+    let entry_section = format!("kprobe/{}", name);
+    wrap_context_with(
+        &mut item,
+        quote! { *mut ::redbpf_probes::bindings::pt_regs },
+        |raw_ctx| {
+            quote! {
+                unsafe {
+                    ::redbpf_probes::kprobe::KRetProbeContext::new(
+                        #raw_ctx,
+                        ::core::ptr::addr_of_mut!(#map),
+                    )
+                }
+            }
+        },
+    );
+
+    let mut tokens = TokenStream2::from(probe_impl("kretprobe", attrs, item));
+    tokens.extend(quote! {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        #[link_section = #map_section]
+        static mut #map: ::redbpf_probes::bindings::bpf_map_def =
+            ::redbpf_probes::kprobe::entry_args_map();
+
+        #[no_mangle]
+        #[link_section = #entry_section]
+        pub extern "C" fn #entry(ctx: *mut ::redbpf_probes::bindings::pt_regs) -> i32 {
+            unsafe {
+                ::redbpf_probes::kprobe::stash_entry_args(ctx, ::core::ptr::addr_of_mut!(#map))
+            };
+            0
+        }
+    });
+
+    tokens.into()
+}
+
+/// Attribute macro that must be used to define fentry probes.
+///
+/// Functions of loadable modules are named `module:function`.
+///
+/// # Example
+/// ```
+/// #[fentry("i915:intel_irq_handler")]
+/// pub extern "C" fn irq_enter(ctx: FEntryContext) -> i32 {
+///     let irq = ctx.arg(0);
+///     ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn fentry(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as ItemFn);
+    wrap_context(
+        &mut item,
+        quote! { *mut u64 },
+        quote! { ::redbpf_probes::fentry::FEntryContext },
+        "ctx",
+    );
+    probe_impl("fentry", attrs, item)
 }
 
 /// Attribute macro that must be used to define [`XDP` probes](https://www.iovisor.org/technology/xdp).
@@ -228,20 +525,155 @@ pub fn kretprobe(attrs: TokenStream, item: TokenStream) -> TokenStream {
 #[proc_macro_attribute]
 pub fn xdp(attrs: TokenStream, item: TokenStream) -> TokenStream {
     let mut item = parse_macro_input!(item as ItemFn);
-    let arg = item.sig.inputs.pop().unwrap();
-    let pat = match arg.value() {
-        FnArg::Typed(PatType { pat, .. }) => pat,
-        _ => panic!("unexpected xdp probe signature"),
-    };
-    let ident = if let Pat::Ident(PatIdent { ident, .. }) = &**pat {
-        ident
-    } else {
-        panic!("unexpected xdp probe signature")
-    };
-    let raw_ctx = Ident::new(&format!("_raw_{}", ident), Span::call_site());
-    let arg: FnArg = parse_quote! { #raw_ctx: *mut xdp_md };
-    item.sig.inputs.push(arg);
-    let ctx: Stmt = parse_quote! { let #ident = XdpContext { ctx: #raw_ctx }; };
-    item.block.stmts.insert(0, ctx);
+    wrap_context(
+        &mut item,
+        quote! { *mut ::redbpf_probes::bindings::xdp_md },
+        quote! { ::redbpf_probes::xdp::XdpContext },
+        "ctx",
+    );
     probe_impl("xdp", attrs, item).into()
 }
+
+/// Attribute macro that must be used to define traffic control programs.
+///
+/// See also the [TC API provided by
+/// `redbpf-probes`](https://redsift.github.io/rust/redbpf/doc/redbpf_probes/tc/index.html).
+///
+/// # Example
+/// ```
+/// #[tc_action]
+/// pub extern "C" fn drop_all(skb: SkBuffContext) -> TcAction {
+///     ...
+///     TcAction::Shot
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn tc_action(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as ItemFn);
+    wrap_context(
+        &mut item,
+        quote! { *mut ::redbpf_probes::bindings::__sk_buff },
+        quote! { ::redbpf_probes::tc::SkBuffContext },
+        "skb",
+    );
+    probe_impl("tc_action", attrs, item)
+}
+
+/// Attribute macro that must be used to define cgroup socket address
+/// programs.
+///
+/// The argument is the hook the program runs at: `bind4`, `bind6`,
+/// `connect4`, `connect6`, `sendmsg4` or `sendmsg6`.
+///
+/// See also the [socket address API provided by
+/// `redbpf-probes`](https://redsift.github.io/rust/redbpf/doc/redbpf_probes/sock_addr/index.html).
+///
+/// # Example
+/// ```
+/// #[cgroup_sockaddr(connect4)]
+/// pub extern "C" fn block_port_25(ctx: SockAddrContext) -> i32 {
+///     (ctx.user_port() != 25) as i32
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn cgroup_sockaddr(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let hook = parse_macro_input!(attrs as Ident).to_string();
+    match hook.as_str() {
+        "bind4" | "bind6" | "connect4" | "connect6" | "sendmsg4" | "sendmsg6" => (),
+        _ => panic!("unknown socket address hook `{}`", hook),
+    }
+    let mut item = parse_macro_input!(item as ItemFn);
+    wrap_context(
+        &mut item,
+        quote! { *mut ::redbpf_probes::bindings::bpf_sock_addr },
+        quote! { ::redbpf_probes::sock_addr::SockAddrContext },
+        "ctx",
+    );
+    probe_impl(&format!("cgroup_{}", hook), TokenStream::new(), item)
+}
+
+/// Attribute macro that must be used to define tracepoint programs.
+///
+/// See also the [tracepoint API provided by
+/// `redbpf-probes`](https://redsift.github.io/rust/redbpf/doc/redbpf_probes/tracepoint/index.html).
+///
+/// # Example
+/// ```
+/// #[tracepoint("sys_enter_write")]
+/// pub extern "C" fn enter_write(ctx: TracePointContext) -> i32 {
+///     ...
+///     0
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn tracepoint(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as ItemFn);
+    wrap_context(
+        &mut item,
+        quote! { *mut ::core::ffi::c_void },
+        quote! { ::redbpf_probes::tracepoint::TracePointContext },
+        "ctx",
+    );
+    probe_impl("tracepoint", attrs, item)
+}
+
+/// Attribute macro that must be used to define socket filters.
+///
+/// See also the [socket filter API provided by
+/// `redbpf-probes`](https://redsift.github.io/rust/redbpf/doc/redbpf_probes/socket_filter/index.html).
+///
+/// # Example
+/// ```
+/// #[socket_filter]
+/// pub extern "C" fn keep_all(skb: SocketFilterContext) -> i32 {
+///     skb.len() as i32
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn socket_filter(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as ItemFn);
+    wrap_context(
+        &mut item,
+        quote! { *mut ::redbpf_probes::bindings::__sk_buff },
+        quote! { ::redbpf_probes::socket_filter::SocketFilterContext },
+        "skb",
+    );
+    probe_impl("socketfilter", attrs, item)
+}
+
+/// Derive macro generating a `from_bytes` parser for events shared between a
+/// program and userspace.
+///
+/// The struct must be `#[repr(C)]`, so that its layout is the same on both
+/// sides. `from_bytes(&[u8]) -> Option<Self>` reads each field from where
+/// `#[repr(C)]` puts it, without requiring the bytes to be aligned, and
+/// returns `None` if there are fewer bytes than the struct takes up or a
+/// `bool` holds anything but 0 or 1.
+///
+/// Fields may be integers, floats, `bool`, arrays of those, or other structs
+/// deriving `BpfEvent`. Integers copied from packet headers, which are in
+/// network byte order, are converted to host byte order if marked with
+/// `#[bpf_event(big_endian)]`.
+///
+/// # Example
+/// ```
+/// #[repr(C)]
+/// #[derive(Clone, Copy, BpfEvent)]
+/// pub struct Connection {
+///     pub pid: u32,
+///     #[bpf_event(big_endian)]
+///     pub dport: u16,
+///     pub comm: [u8; 16],
+/// }
+///
+/// // in userspace
+/// let conn = Connection::from_bytes(&sample).unwrap();
+/// println!("{} connected to port {}", conn.pid, conn.dport);
+/// ```
+#[proc_macro_derive(BpfEvent, attributes(bpf_event))]
+pub fn bpf_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    event::derive_bpf_event(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
@@ -46,6 +46,13 @@ fn main() {
         .map(|dir| format!("-I{}", dir))
         .collect();
     flags.extend(redbpf::build::BUILD_FLAGS.iter().map(|f| f.to_string()));
+    // when building for the `bpfel` and `bpfeb` targets, lay out the types
+    // like the target does, rather than like the host
+    if env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("bpf") {
+        let endian = env::var("CARGO_CFG_TARGET_ENDIAN").unwrap();
+        let target = if endian == "big" { "bpfeb" } else { "bpfel" };
+        flags.extend(vec!["-target".to_string(), target.to_string()]);
+    }
     flags.push("-Wno-unused-function".to_string());
     flags.push("-Wno-unused-variable".to_string());
     flags.push("-Wno-address-of-packed-member".to_string());
@@ -61,21 +68,33 @@ fn main() {
         .whitelist_type("s32")
         .whitelist_type("bpf_.*")
         .whitelist_var("BPF_.*")
+        .whitelist_type("btf_ptr")
+        .whitelist_type("seq_file")
         // XDP
         .whitelist_type("xdp_md")
         .whitelist_type("ethhdr")
         .whitelist_type("iphdr")
+        .whitelist_type("ipv6hdr")
         .whitelist_type("tcphdr")
         .whitelist_type("udphdr")
+        .whitelist_type("icmphdr")
+        .whitelist_type("icmp6hdr")
+        .whitelist_type("arphdr")
         .whitelist_type("xdp_action")
         .whitelist_type("__sk_.*")
         .whitelist_type("sk_.*")
         .whitelist_type("inet_sock")
+        // laid out by the running kernel, only read at CO-RE offsets
+        .whitelist_type("task_struct")
+        .opaque_type("task_struct")
         .whitelist_var("ETH_.*")
+        .whitelist_var("ARPHRD_.*")
+        .whitelist_var("ARPOP_.*")
         .whitelist_var("IPPROTO_.*")
         .whitelist_var("SOCK_.*")
         .whitelist_var("SK_FL_.*")
         .whitelist_var("AF_.*")
+        .whitelist_var("TC_ACT_.*")
         .opaque_type("xregs_state")
         .generate()
         .expect("Unable to generate bindings!");
@@ -0,0 +1,45 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Runs the XDP packet parsers on arbitrary packets.
+//!
+//! `TestPacket` ends the packet at an inaccessible page, and cargo-fuzz
+//! builds with AddressSanitizer, so any read past the end of the packet
+//! crashes:
+//!
+//! ```text
+//! cargo +nightly fuzz run xdp_parsers
+//! ```
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use redbpf_probes::xdp::TestPacket;
+
+fuzz_target!(|bytes: &[u8]| {
+    let mut packet = TestPacket::new(bytes).expect("no memory below 4 GiB");
+    let ctx = packet.context();
+    assert_eq!(ctx.len() as usize, bytes.len());
+
+    ctx.eth_proto();
+    ctx.is_ipv6();
+    if let Some(eth) = ctx.eth() {
+        unsafe { eth.read_unaligned() };
+    }
+    if let Some(ip) = ctx.ip() {
+        unsafe { ip.read_unaligned() };
+    }
+    if let Some(transport) = ctx.transport() {
+        transport.source();
+        transport.dest();
+    }
+    if let Some(data) = ctx.data() {
+        assert!(data.offset() + data.len() == bytes.len());
+        let payload = data.slice(data.len()).unwrap();
+        assert_eq!(payload, &bytes[data.offset()..]);
+        assert!(data.slice(data.len() + 1).is_none());
+        data.read::<u64>();
+    }
+});
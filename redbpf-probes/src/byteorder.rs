@@ -0,0 +1,327 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Network byte order conversions.
+
+Header fields such as ports and addresses are stored in network (big-endian)
+byte order. The `ntoh*` and `hton*` functions convert single values, while
+`NetworkEndian` reads big-endian integers out of byte slices, for example
+when parsing payloads.
+
+# Example
+
+Parse a length-prefixed record at the start of the packet data:
+
+```
+use redbpf_probes::byteorder::NetworkEndian;
+
+#[xdp]
+pub extern "C" fn tlv(ctx: XdpContext) -> XdpAction {
+    let data = match ctx.data() {
+        Some(data) => data,
+        None => return XdpAction::Pass,
+    };
+    let header = match data.slice(3) {
+        Some(header) => header,
+        None => return XdpAction::Pass,
+    };
+    let kind = header[0];
+    let len = NetworkEndian::read_u16(&header[1..]).unwrap();
+    if kind == 1 && len > 512 {
+        return XdpAction::Drop;
+    }
+
+    XdpAction::Pass
+}
+```
+
+# Header bitfields
+
+The kernel headers lay out the bitfields of `iphdr` and `tcphdr` depending
+on the byte order of the target, and the generated accessors such as
+`iphdr::ihl()` follow the headers the bindings were generated from, which
+are the ones of the host. `ip_ihl`, `tcp_doff` and `tcp_flags` read the
+fields from their place in the header instead, which is right on any
+target, including big-endian ones such as `bpfeb` on s390x.
+ */
+use crate::bindings::{iphdr, tcphdr};
+
+/// Converts a `u16` from network to host byte order.
+#[inline]
+pub const fn ntohs(v: u16) -> u16 {
+    u16::from_be(v)
+}
+
+/// Converts a `u32` from network to host byte order.
+#[inline]
+pub const fn ntohl(v: u32) -> u32 {
+    u32::from_be(v)
+}
+
+/// Converts a `u16` from host to network byte order.
+#[inline]
+pub const fn htons(v: u16) -> u16 {
+    v.to_be()
+}
+
+/// Converts a `u32` from host to network byte order.
+#[inline]
+pub const fn htonl(v: u32) -> u32 {
+    v.to_be()
+}
+
+/// Reads big-endian integers from the start of byte slices.
+///
+/// The methods return `None` if the slice is too short.
+pub struct NetworkEndian;
+
+impl NetworkEndian {
+    #[inline]
+    pub fn read_u16(buf: &[u8]) -> Option<u16> {
+        if buf.len() < 2 {
+            return None;
+        }
+        Some(u16::from_be_bytes([buf[0], buf[1]]))
+    }
+
+    #[inline]
+    pub fn read_u32(buf: &[u8]) -> Option<u32> {
+        if buf.len() < 4 {
+            return None;
+        }
+        Some(u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]))
+    }
+
+    #[inline]
+    pub fn read_u64(buf: &[u8]) -> Option<u64> {
+        if buf.len() < 8 {
+            return None;
+        }
+        Some(u64::from_be_bytes([
+            buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7],
+        ]))
+    }
+}
+
+/// An IPv4 or IPv6 address to key maps with.
+///
+/// The address is stored as the 16 bytes of an IPv6 address in network
+/// byte order, with IPv4 addresses mapped to `::ffff:a.b.c.d`. Probes build
+/// keys from the header fields, which are in network byte order already,
+/// and userspace builds them from `Ipv4Addr` and `Ipv6Addr` with
+/// `redbpf::IpKey`, which has the same layout. Both sides therefore agree on
+/// the key of an address, whatever the byte order of the host.
+///
+/// # Example
+///
+/// Drop packets from the addresses in a blocklist filled in by userspace:
+///
+/// ```
+/// use redbpf_probes::byteorder::IpKey;
+/// use redbpf_probes::maps::HashMap;
+///
+/// #[map("blocklist")]
+/// static mut BLOCKLIST: HashMap<IpKey, u8> = HashMap::with_max_entries(1024);
+///
+/// #[xdp]
+/// pub extern "C" fn blocklist(ctx: XdpContext) -> XdpAction {
+///     let ip = match ctx.ip() {
+///         Some(ip) => ip,
+///         None => return XdpAction::Pass,
+///     };
+///     let source = IpKey::from_ipv4(unsafe { (*ip).saddr });
+///     if unsafe { BLOCKLIST.get(source) }.is_some() {
+///         return XdpAction::Drop;
+///     }
+///
+///     XdpAction::Pass
+/// }
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpKey {
+    addr: [u8; 16],
+}
+
+impl IpKey {
+    /// Creates the key of an IPv4 address in network byte order, such as
+    /// `iphdr::saddr`.
+    #[inline]
+    pub const fn from_ipv4(addr: u32) -> IpKey {
+        let [a, b, c, d] = addr.to_ne_bytes();
+        IpKey {
+            addr: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d],
+        }
+    }
+
+    /// Creates the key of an IPv6 address in network byte order, such as
+    /// the addresses of `bpf_sock_tuple`.
+    #[inline]
+    pub fn from_ipv6(addr: [u32; 4]) -> IpKey {
+        let mut key = IpKey { addr: [0; 16] };
+        for (i, word) in addr.iter().enumerate() {
+            key.addr[i * 4..][..4].copy_from_slice(&word.to_ne_bytes());
+        }
+        key
+    }
+
+    /// Returns the address as IPv6 bytes in network byte order.
+    #[inline]
+    pub const fn octets(&self) -> [u8; 16] {
+        self.addr
+    }
+}
+
+/// Returns the length of the IPv4 header `ip` in 32 bit words, its `ihl`
+/// field.
+///
+/// # Safety
+///
+/// `ip` must point to a readable `iphdr`.
+#[inline]
+pub unsafe fn ip_ihl(ip: *const iphdr) -> u8 {
+    *(ip as *const u8) & 0x0f
+}
+
+/// Returns the length of the TCP header `tcp` in 32 bit words, its `doff`
+/// field.
+///
+/// # Safety
+///
+/// `tcp` must point to a readable `tcphdr`.
+#[inline]
+pub unsafe fn tcp_doff(tcp: *const tcphdr) -> u8 {
+    *(tcp as *const u8).add(12) >> 4
+}
+
+/// Returns the flags of the TCP header `tcp`.
+///
+/// # Safety
+///
+/// `tcp` must point to a readable `tcphdr`.
+#[inline]
+pub unsafe fn tcp_flags(tcp: *const tcphdr) -> TcpFlags {
+    TcpFlags(*(tcp as *const u8).add(13))
+}
+
+/// The flags of a TCP header, as in the 14th byte of the header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TcpFlags(pub u8);
+
+impl TcpFlags {
+    pub const FIN: u8 = 1 << 0;
+    pub const SYN: u8 = 1 << 1;
+    pub const RST: u8 = 1 << 2;
+    pub const PSH: u8 = 1 << 3;
+    pub const ACK: u8 = 1 << 4;
+    pub const URG: u8 = 1 << 5;
+    pub const ECE: u8 = 1 << 6;
+    pub const CWR: u8 = 1 << 7;
+
+    /// Returns `true` if all of `flags` are set.
+    #[inline]
+    pub fn contains(&self, flags: u8) -> bool {
+        self.0 & flags == flags
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let port = u16::from_ne_bytes([0x1f, 0x90]);
+        assert_eq!(ntohs(port), 8080);
+        assert_eq!(htons(8080), port);
+
+        let addr = u32::from_ne_bytes([192, 168, 0, 1]);
+        assert_eq!(ntohl(addr), 0xc0a8_0001);
+        assert_eq!(htonl(0xc0a8_0001), addr);
+    }
+
+    #[test]
+    fn test_read() {
+        let buf = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        assert_eq!(NetworkEndian::read_u16(&buf), Some(0x0102));
+        assert_eq!(NetworkEndian::read_u32(&buf), Some(0x0102_0304));
+        assert_eq!(NetworkEndian::read_u64(&buf), Some(0x0102_0304_0506_0708));
+        assert_eq!(NetworkEndian::read_u16(&buf[7..]), None);
+        assert_eq!(NetworkEndian::read_u32(&buf[5..]), None);
+        assert_eq!(NetworkEndian::read_u64(&buf[1..]), None);
+    }
+
+    #[test]
+    fn test_ip_key() {
+        // 192.168.0.1 as stored in iphdr
+        let addr = u32::from_ne_bytes([192, 168, 0, 1]);
+        assert_eq!(
+            IpKey::from_ipv4(addr).octets(),
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 168, 0, 1]
+        );
+        assert_eq!(IpKey::from_ipv4(addr), IpKey::from_ipv4(htonl(0xc0a8_0001)));
+
+        // fe80::1
+        let addr = [htonl(0xfe80_0000), 0, 0, htonl(1)];
+        assert_eq!(
+            IpKey::from_ipv6(addr).octets(),
+            [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_header_bitfields() {
+        // IPv4 headers as sent on the wire: version 4 with ihl 5, and
+        // version 4 with ihl 15, the longest header with options
+        #[rustfmt::skip]
+        let short_ip = [
+            0x45, 0x00, 0x00, 0x3c, 0x1c, 0x46, 0x40, 0x00,
+            0x40, 0x06, 0xb1, 0xe6, 0xc0, 0xa8, 0x00, 0x68,
+            0xc0, 0xa8, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut long_ip = [0u8; 60];
+        long_ip[0] = 0x4f;
+        // TCP headers: doff 10 with SYN, doff 5 with FIN, PSH and ACK
+        #[rustfmt::skip]
+        let syn = [
+            0xa2, 0x7c, 0x00, 0x50, 0x3a, 0x1f, 0x40, 0x25,
+            0x00, 0x00, 0x00, 0x00, 0xa0, 0x02, 0xfa, 0xf0,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        #[rustfmt::skip]
+        let fin = [
+            0x00, 0x50, 0xa2, 0x7c, 0x12, 0x34, 0x56, 0x78,
+            0x3a, 0x1f, 0x40, 0x26, 0x50, 0x19, 0x01, 0xf5,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+
+        unsafe {
+            for (ip, ihl) in [(&short_ip[..], 5), (&long_ip[..], 15)].iter() {
+                let hdr = ip.as_ptr() as *const iphdr;
+                assert_eq!(ip_ihl(hdr), *ihl);
+                assert_eq!((*hdr).ihl() as u8, *ihl);
+                assert_eq!((*hdr).version(), 4);
+            }
+
+            let hdr = syn.as_ptr() as *const tcphdr;
+            assert_eq!(tcp_doff(hdr), 10);
+            assert_eq!((*hdr).doff(), 10);
+            assert_eq!(tcp_flags(hdr), TcpFlags(TcpFlags::SYN));
+            assert_eq!(((*hdr).syn(), (*hdr).ack(), (*hdr).fin()), (1, 0, 0));
+
+            let hdr = fin.as_ptr() as *const tcphdr;
+            assert_eq!(tcp_doff(hdr), 5);
+            assert_eq!((*hdr).doff(), 5);
+            let flags = tcp_flags(hdr);
+            assert!(flags.contains(TcpFlags::FIN | TcpFlags::PSH | TcpFlags::ACK));
+            assert!(!flags.contains(TcpFlags::SYN));
+            assert_eq!(((*hdr).syn(), (*hdr).ack(), (*hdr).fin()), (0, 1, 1));
+            assert_eq!((*hdr).psh(), 1);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Connection tracking
+
+XDP and TC programs can look packets up in the kernel's connection tracker
+through the kfuncs of `nf_conntrack` (kernel 5.18 or later), rather than
+keeping track of connections themselves. The kfuncs are resolved against
+the BTF of `vmlinux` or of the `nf_conntrack` module when the program is
+loaded. On kernels without them, lookups find nothing.
+
+A lookup holds a reference on the entry, which a `ConntrackEntry` releases
+when dropped.
+
+# Example
+
+A stateful firewall, which lets in new connections to port 22 only, and
+packets of connections the kernel already tracks:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+use redbpf_macros::{program, xdp};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[xdp]
+pub extern "C" fn stateful_firewall(ctx: XdpContext) -> XdpAction {
+    let transport = match ctx.transport() {
+        Some(transport) => transport,
+        None => return XdpAction::Pass,
+    };
+    if ctx.ct_lookup().is_some() {
+        return XdpAction::Pass;
+    }
+
+    if transport.dest() == 22 {
+        XdpAction::Pass
+    } else {
+        XdpAction::Drop
+    }
+}
+```
+ */
+use cty::*;
+
+use crate::bindings::*;
+use crate::kfunc_exists;
+
+/// The kernel's `struct nf_conn`, whose layout isn't part of the uapi.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct nf_conn {
+    _unused: [u8; 0],
+}
+
+/// Options of the lookup kfuncs, in the layout of kernels before 6.6,
+/// which later kernels accept as well.
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+struct bpf_ct_opts {
+    netns_id: i32,
+    error: i32,
+    l4proto: u8,
+    dir: u8,
+    reserved: [u8; 2],
+}
+
+extern "C" {
+    fn bpf_xdp_ct_lookup(
+        xdp_ctx: *mut xdp_md,
+        bpf_tuple: *mut bpf_sock_tuple,
+        tuple__sz: u32,
+        opts: *mut bpf_ct_opts,
+        opts__sz: u32,
+    ) -> *mut nf_conn;
+    fn bpf_skb_ct_lookup(
+        skb_ctx: *mut __sk_buff,
+        bpf_tuple: *mut bpf_sock_tuple,
+        tuple__sz: u32,
+        opts: *mut bpf_ct_opts,
+        opts__sz: u32,
+    ) -> *mut nf_conn;
+    fn bpf_ct_release(nfct: *mut nf_conn);
+    fn bpf_ct_change_timeout(nfct: *mut nf_conn, timeout: u32) -> c_int;
+    fn bpf_ct_change_status(nfct: *mut nf_conn, status: u32) -> c_int;
+}
+
+/// Network namespace of the program's context.
+const CURRENT_NETNS: i32 = -1;
+
+/// A conntrack entry found by a lookup, released when dropped.
+pub struct ConntrackEntry {
+    ct: *mut nf_conn,
+}
+
+impl ConntrackEntry {
+    /// Looks up the connection of an XDP packet with the given tuple.
+    ///
+    /// `l4proto` is `IPPROTO_TCP` or `IPPROTO_UDP`. `tuple_size` is the size
+    /// of the `ipv4` or `ipv6` member of the tuple, whichever is used.
+    #[inline]
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn lookup_xdp(
+        ctx: *mut xdp_md,
+        tuple: &mut bpf_sock_tuple,
+        tuple_size: usize,
+        l4proto: u8,
+    ) -> Option<ConntrackEntry> {
+        if !kfunc_exists!(bpf_xdp_ct_lookup) {
+            return None;
+        }
+        let mut opts = ConntrackEntry::opts(l4proto);
+        let ct = unsafe {
+            bpf_xdp_ct_lookup(
+                ctx,
+                tuple,
+                tuple_size as u32,
+                &mut opts,
+                core::mem::size_of::<bpf_ct_opts>() as u32,
+            )
+        };
+        ConntrackEntry::from_ptr(ct)
+    }
+
+    /// Looks up the connection of a TC packet with the given tuple, see
+    /// `lookup_xdp`.
+    #[inline]
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn lookup_skb(
+        skb: *mut __sk_buff,
+        tuple: &mut bpf_sock_tuple,
+        tuple_size: usize,
+        l4proto: u8,
+    ) -> Option<ConntrackEntry> {
+        if !kfunc_exists!(bpf_skb_ct_lookup) {
+            return None;
+        }
+        let mut opts = ConntrackEntry::opts(l4proto);
+        let ct = unsafe {
+            bpf_skb_ct_lookup(
+                skb,
+                tuple,
+                tuple_size as u32,
+                &mut opts,
+                core::mem::size_of::<bpf_ct_opts>() as u32,
+            )
+        };
+        ConntrackEntry::from_ptr(ct)
+    }
+
+    #[inline]
+    fn opts(l4proto: u8) -> bpf_ct_opts {
+        bpf_ct_opts {
+            netns_id: CURRENT_NETNS,
+            l4proto,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    fn from_ptr(ct: *mut nf_conn) -> Option<ConntrackEntry> {
+        if ct.is_null() {
+            return None;
+        }
+
+        Some(ConntrackEntry { ct })
+    }
+
+    /// Returns the raw `nf_conn`.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut nf_conn {
+        self.ct
+    }
+
+    /// Sets the entry to expire `timeout` milliseconds from now (kernel 6.0
+    /// or later).
+    #[inline]
+    pub fn set_timeout(&self, timeout: u32) -> bool {
+        kfunc_exists!(bpf_ct_change_timeout) && unsafe { bpf_ct_change_timeout(self.ct, timeout) } == 0
+    }
+
+    /// Changes the `IPS_*` status bits of the entry that may be changed
+    /// after it's confirmed, such as `IPS_ASSURED` (kernel 6.0 or later).
+    #[inline]
+    pub fn set_status(&self, status: u32) -> bool {
+        kfunc_exists!(bpf_ct_change_status) && unsafe { bpf_ct_change_status(self.ct, status) } == 0
+    }
+}
+
+impl Drop for ConntrackEntry {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { bpf_ct_release(self.ct) }
+    }
+}
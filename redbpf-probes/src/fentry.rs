@@ -0,0 +1,88 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+fentry probes
+
+fentry probes run on the entry of kernel functions like kprobes, but are
+attached through the function's BTF, and are cheaper to run. The arguments
+are passed as an array of 64 bit values, which the verifier checks against
+the function's prototype.
+
+Functions of loadable modules are named `module:function`, and are looked up
+in the module's BTF under `/sys/kernel/btf/<module>`.
+
+# Example
+
+Count the interrupts the `i915` GPU driver handles, by irq number:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::fentry::*;
+use redbpf_probes::maps::HashMap;
+use redbpf_macros::{fentry, map, program};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[map("irqs")]
+static mut IRQS: HashMap<u64, u64> = HashMap::with_max_entries(64);
+
+// irqreturn_t intel_irq_handler(int irq, void *arg)
+#[fentry("i915:intel_irq_handler")]
+pub extern "C" fn count_irqs(ctx: FEntryContext) -> i32 {
+    let irq = ctx.arg(0);
+    unsafe {
+        let count = IRQS.get(irq).copied().unwrap_or(0);
+        IRQS.set(irq, count + 1);
+    }
+
+    0
+}
+```
+ */
+use core::ptr;
+
+/// Context object provided to fentry probes.
+///
+/// The `fentry` attribute macro wraps the raw argument array in an
+/// `FEntryContext`.
+pub struct FEntryContext {
+    pub ctx: *mut u64,
+}
+
+impl FEntryContext {
+    /// Returns the raw pointer to the arguments.
+    #[inline]
+    pub fn inner(&self) -> *mut u64 {
+        self.ctx
+    }
+
+    /// Returns the `n`th argument of the function.
+    ///
+    /// Reading past the arguments the function takes fails verification.
+    #[inline]
+    pub fn arg(&self, n: usize) -> u64 {
+        unsafe { ptr::read(self.ctx.add(n)) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arg() {
+        let mut args = [3u64, 0xdead_beef];
+        let ctx = FEntryContext {
+            ctx: args.as_mut_ptr(),
+        };
+
+        assert_eq!(ctx.arg(0), 3);
+        assert_eq!(ctx.arg(1), 0xdead_beef);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Hashing fixed-size keys
+
+Load balancers spread flows over backends by the hash of their addresses
+and ports, or of the first bytes of their packets, as copied with
+`XdpContext::copy_header`. The verifier rejects loops it can't bound, so
+the hashes here take arrays rather than slices: the length is known at
+compile time, and the loops over it are unrolled.
+
+Two hashes are provided:
+
+* `fnv1a`, the 32 bit FNV-1a hash, which is the cheapest for short keys;
+* `xxhash32`, the 32 bit xxHash, which mixes its input better and takes a
+  seed.
+
+Both compute exactly the reference algorithms, on the bytes in the order
+they are given, so that `redbpf::hash` computes the same hashes in
+userspace, e.g. to fill in maps ahead of the program.
+
+# Example
+
+A consistent-hash load balancer. Userspace assigns each of the 256 slots
+of `slots` to a backend, and moves as few slots as possible when backends
+come and go. Packets of a flow always hash to the same slot, and are sent
+out of the interface of its backend:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::bindings::xdp_action_XDP_PASS;
+use redbpf_probes::hash::xxhash32;
+use redbpf_probes::maps::HashMap;
+use redbpf_probes::xdp::{DevMap, XdpAction, XdpContext};
+use redbpf_macros::{map, program, xdp};
+
+program!(0xFFFFFFFE, "GPL");
+
+const SLOTS: u32 = 256;
+const SEED: u32 = 0x5eed;
+
+#[map("slots")]
+static mut slots: HashMap<u32, u32> = HashMap::with_max_entries(SLOTS);
+
+#[map("backends")]
+static mut backends: DevMap = DevMap::with_max_entries(64);
+
+#[xdp]
+pub extern "C" fn balance(ctx: XdpContext) -> XdpAction {
+    let (saddr, daddr, transport) = match (ctx.source_ipv4(), ctx.dest_ipv4(), ctx.transport()) {
+        (Some(saddr), Some(daddr), Some(transport)) => (saddr, daddr, transport),
+        _ => return XdpAction::Pass,
+    };
+    // the flow key, in network byte order
+    let mut key = [0u8; 12];
+    key[0..4].copy_from_slice(&saddr.to_be_bytes());
+    key[4..8].copy_from_slice(&daddr.to_be_bytes());
+    key[8..10].copy_from_slice(&transport.source().to_be_bytes());
+    key[10..12].copy_from_slice(&transport.dest().to_be_bytes());
+
+    let slot = xxhash32(&key, SEED) % SLOTS;
+    match unsafe { slots.get(slot) } {
+        Some(&backend) => unsafe { backends.redirect(backend, xdp_action_XDP_PASS as u64) },
+        None => XdpAction::Pass,
+    }
+}
+```
+ */
+
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+const PRIME32_1: u32 = 0x9e37_79b1;
+const PRIME32_2: u32 = 0x85eb_ca77;
+const PRIME32_3: u32 = 0xc2b2_ae3d;
+const PRIME32_4: u32 = 0x27d4_eb2f;
+const PRIME32_5: u32 = 0x1656_67b1;
+
+/// Returns the 32 bit FNV-1a hash of `bytes`.
+///
+/// Every byte is xored into the hash, which is then multiplied by the FNV
+/// prime, starting from the offset basis `0x811c9dc5`.
+#[inline(always)]
+pub fn fnv1a<const N: usize>(bytes: &[u8; N]) -> u32 {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut i = 0;
+    while i < N {
+        hash = (hash ^ bytes[i] as u32).wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash
+}
+
+/// Returns the 32 bit xxHash of `bytes`, with `seed`.
+///
+/// This is `XXH32` of the reference implementation: the bytes are read as
+/// little-endian words, whatever the byte order of the machine.
+#[inline(always)]
+pub fn xxhash32<const N: usize>(bytes: &[u8; N], seed: u32) -> u32 {
+    let mut i = 0;
+    let mut hash = if N >= 16 {
+        let mut acc = [
+            seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2),
+            seed.wrapping_add(PRIME32_2),
+            seed,
+            seed.wrapping_sub(PRIME32_1),
+        ];
+        while i + 16 <= N {
+            let mut lane = 0;
+            while lane < 4 {
+                acc[lane] = xxh32_round(acc[lane], read_u32(bytes, i + lane * 4));
+                lane += 1;
+            }
+            i += 16;
+        }
+        acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME32_5)
+    };
+    hash = hash.wrapping_add(N as u32);
+
+    while i + 4 <= N {
+        hash = hash.wrapping_add(read_u32(bytes, i).wrapping_mul(PRIME32_3));
+        hash = hash.rotate_left(17).wrapping_mul(PRIME32_4);
+        i += 4;
+    }
+    while i < N {
+        hash = hash.wrapping_add((bytes[i] as u32).wrapping_mul(PRIME32_5));
+        hash = hash.rotate_left(11).wrapping_mul(PRIME32_1);
+        i += 1;
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME32_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME32_3);
+    hash ^ (hash >> 16)
+}
+
+#[inline(always)]
+fn xxh32_round(acc: u32, lane: u32) -> u32 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME32_2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME32_1)
+}
+
+#[inline(always)]
+fn read_u32<const N: usize>(bytes: &[u8; N], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0x811c_9dc5);
+        assert_eq!(fnv1a(b"a"), 0xe40c_292c);
+        assert_eq!(fnv1a(b"foobar"), 0xbf9c_f968);
+        assert_eq!(
+            fnv1a(b"Nobody inspects the spammish repetition"),
+            0xbe00_d8fb
+        );
+    }
+
+    #[test]
+    fn test_xxhash32() {
+        assert_eq!(xxhash32(b"", 0), 0x02cc_5d05);
+        assert_eq!(xxhash32(b"a", 0), 0x550d_7456);
+        assert_eq!(xxhash32(b"abc", 0), 0x32d1_53ff);
+        assert_eq!(xxhash32(b"foobar", 0), 0xeda3_4aaf);
+        // long enough for the 16 byte stripes
+        let long = b"Nobody inspects the spammish repetition";
+        assert_eq!(xxhash32(long, 0), 0xe229_3b2f);
+        assert_eq!(xxhash32(long, PRIME32_1), 0xc9e8_9e68);
+        assert_eq!(xxhash32(b"", PRIME32_1), 0x36b7_8ae7);
+    }
+
+    #[test]
+    fn test_flow_key() {
+        // 10.0.0.1:12345 -> 10.0.0.2:80
+        let key = [10, 0, 0, 1, 10, 0, 0, 2, 0x30, 0x39, 0, 80];
+        assert_eq!(fnv1a(&key), 0x5eef_79f3);
+        assert_eq!(xxhash32(&key, 0), 0x1076_ad49);
+        assert_eq!(xxhash32(&key, 42), 0x3e1f_8b53);
+    }
+}
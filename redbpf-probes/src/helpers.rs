@@ -31,6 +31,12 @@ pub fn bpf_ktime_get_ns() -> u64 {
     unsafe { gen::bpf_ktime_get_ns() }
 }
 
+/// Returns a pseudo-random number, which isn't suitable for cryptography.
+#[inline]
+pub fn bpf_get_prandom_u32() -> u32 {
+    unsafe { gen::bpf_get_prandom_u32() }
+}
+
 #[inline]
 pub fn bpf_probe_read<T>(src: *const T) -> T {
     unsafe {
@@ -45,9 +51,150 @@ pub fn bpf_probe_read<T>(src: *const T) -> T {
     }
 }
 
+/// Returns the cgroup v1 `net_cls` classid of the current task.
+///
+/// Only meaningful for programs handling packets on behalf of a task, such
+/// as TC programs on egress. Returns 0 if the task isn't in a `net_cls`
+/// cgroup, which is always the case on hosts that only mount cgroup v2.
+#[inline]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn bpf_get_cgroup_classid(skb: *mut __sk_buff) -> u32 {
+    unsafe { gen::bpf_get_cgroup_classid(skb) }
+}
+
+/// Returns the cgroup v1 `net_cls` classid of the socket `skb` belongs to.
+///
+/// Unlike `bpf_get_cgroup_classid`, the classid is taken from the socket
+/// rather than from the current task, so it also works on ingress and in
+/// softirq context. Only available to TC programs, on kernel 5.10 or later.
+/// Returns 0 if the socket isn't in a `net_cls` cgroup.
+///
+/// `skb` must be the program's context, which the verifier enforces.
+///
+/// # Example
+///
+/// Rate-limit egress traffic of the `net_cls` class `1:10` (`0x0001_0010`)
+/// to roughly 1000 packets per second, as an existing `tc` filter on the
+/// classid would:
+///
+/// ```
+/// #[map("class_budget")]
+/// static mut budget: HashMap<u32, u64> = HashMap::with_max_entries(1);
+///
+/// pub fn classify(skb: *mut __sk_buff) -> i32 {
+///     if bpf_skb_cgroup_classid(skb) != 0x0001_0010 {
+///         return TC_ACT_OK;
+///     }
+///     // one token every millisecond
+///     let now = bpf_ktime_get_ns() / 1_000_000;
+///     let next = unsafe { budget.get(0).copied().unwrap_or(0) };
+///     if next > now {
+///         return TC_ACT_SHOT;
+///     }
+///     unsafe { budget.set(0, now + 1) };
+///
+///     TC_ACT_OK
+/// }
+/// ```
+#[inline]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn bpf_skb_cgroup_classid(skb: *mut __sk_buff) -> u32 {
+    unsafe { gen::bpf_skb_cgroup_classid(skb) as u32 }
+}
+
 #[macro_export]
 macro_rules! bpf_probe_read {
     ( $x:expr ) => {
         bpf_probe_read(unsafe { $x })
     };
-}
\ No newline at end of file
+}
+
+/// Tests whether the running kernel provides the kernel function (kfunc) `f`.
+///
+/// `f` must be declared in an `extern "C"` block. The loader resolves the
+/// check to a constant, so on kernels without `f` the verifier removes the
+/// code the check guards. Unguarded calls to missing kfuncs fail the load.
+///
+/// # Example
+///
+/// ```
+/// extern "C" {
+///     fn bpf_rcu_read_lock();
+/// }
+///
+/// if kfunc_exists!(bpf_rcu_read_lock) {
+///     unsafe { bpf_rcu_read_lock() };
+/// }
+/// ```
+#[macro_export]
+macro_rules! kfunc_exists {
+    ( $f:ident ) => {{
+        extern "C" {
+            // Not a real object: the loader patches references to this
+            // symbol with whether the kernel has the function.
+            #[link_name = concat!("__redbpf_kfunc_exists:", stringify!($f))]
+            static EXISTS: u8;
+        }
+        unsafe { &EXISTS as *const u8 as usize == 1 }
+    }};
+}
+
+/// Tests whether the running kernel provides the BPF helper numbered `id`,
+/// in `enum bpf_func_id`, to programs of the type of the calling program.
+///
+/// Like `kfunc_exists!`, the loader resolves the check to a constant, by
+/// probing the kernel for the helper, and on kernels without the helper the
+/// verifier removes the code the check guards.
+///
+/// # Example
+///
+/// ```
+/// // BPF_FUNC_xdp_get_buff_len
+/// if helper_exists!(188) {
+///     len = unsafe { bpf_xdp_get_buff_len(ctx.inner()) };
+/// }
+/// ```
+#[macro_export]
+macro_rules! helper_exists {
+    ( $id:literal ) => {{
+        extern "C" {
+            // Not a real object: the loader patches references to this
+            // symbol with whether the kernel has the helper.
+            #[link_name = concat!("__redbpf_helper_exists:", $id)]
+            static EXISTS: u8;
+        }
+        unsafe { &EXISTS as *const u8 as usize == 1 }
+    }};
+}
+
+/// Returns the offset of a field of a kernel struct, in the layout of the
+/// running kernel.
+///
+/// The loader resolves the offset against the kernel's BTF (CO-RE), so that
+/// programs read fields of structs the bindings don't lay out, or that are
+/// laid out differently by other kernels. Fields of nested structs are
+/// reached through the members containing them. Loading fails if the kernel
+/// lacks the field.
+///
+/// # Example
+///
+/// ```
+/// let offset = core_offset!(task_struct, real_parent);
+/// let inum_offset = core_offset!(pid_namespace, ns.inum);
+/// ```
+#[macro_export]
+macro_rules! core_offset {
+    ( $ty:ident, $($field:ident).+ ) => {{
+        extern "C" {
+            // Not a real object: the loader patches references to this
+            // symbol with the offset of the field.
+            #[link_name = concat!(
+                "__redbpf_core_offset:",
+                stringify!($ty),
+                $(":", stringify!($field)),+
+            )]
+            static FIELD: u8;
+        }
+        unsafe { &FIELD as *const u8 as usize }
+    }};
+}
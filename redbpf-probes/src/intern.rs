@@ -0,0 +1,359 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+String interning
+
+Programs tracing paths or command names send the same few strings over and
+over. An `Interner` gives each distinct string an id, and sends the string
+to userspace along with its id the first time it is seen only, so events
+can carry the id instead of the string. On the userspace side,
+`redbpf::StringTable` collects the strings and resolves the ids.
+
+The ids of the strings are kept in a `HashMap` keyed by `BpfStr`, which
+bounds how many strings can be interned: once it is full, `intern` fails
+and the string has to be sent as is. Strings must be read into a fresh
+`BpfStr`, since the whole buffer, up to `N` bytes, is the key.
+
+# Example
+
+Trace the files opened through `do_sys_open`, by path id:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::helpers::bpf_get_current_pid_tgid;
+use redbpf_probes::intern::{InternedStr, Interner};
+use redbpf_probes::kprobe::*;
+use redbpf_probes::maps::{EventChannel, HashMap, PerfMap};
+use redbpf_probes::string::BpfStr;
+use redbpf_macros::{kprobe, map, program};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[repr(C)]
+pub struct OpenEvent {
+    pub pid: u32,
+    pub path_id: u32,
+}
+
+#[map("path_ids")]
+static mut path_ids: HashMap<BpfStr<128>, u32> = HashMap::with_max_entries(10240);
+
+#[map("paths")]
+static mut paths: EventChannel<InternedStr<128>> = EventChannel::with_max_entries(256 * 1024);
+
+#[map("open_events")]
+static mut open_events: PerfMap<OpenEvent> = PerfMap::with_max_entries(1024);
+
+#[kprobe("do_sys_open")]
+pub extern "C" fn enter_open(ctx: KProbeContext) -> i32 {
+    let mut path = InternedStr::<128>::new();
+    if path.string.read_user(ctx.regs().parm2() as *const u8).is_none() {
+        return 0;
+    }
+    let mut interner = unsafe { Interner::new(&mut path_ids, &mut paths) };
+    if let Some(path_id) = interner.intern(ctx.inner(), &mut path) {
+        let event = OpenEvent {
+            pid: (bpf_get_current_pid_tgid() >> 32) as u32,
+            path_id,
+        };
+        unsafe { open_events.insert(ctx.inner(), event) };
+    }
+
+    0
+}
+```
+ */
+use core::mem;
+use cty::*;
+
+use crate::bindings::*;
+use crate::helpers::{bpf_get_smp_processor_id, bpf_map_lookup_elem, bpf_map_update_elem};
+use crate::maps::{EventChannel, HashMap};
+use crate::string::BpfStr;
+
+/// Bits of an id counting the strings interned on a CPU, the CPU id taking
+/// up the rest.
+const ID_SEQ_BITS: u32 = 24;
+
+/// Number of CPUs the `string_ids` map can count ids for.
+const ID_CPUS_MAX: u32 = 1 << (32 - ID_SEQ_BITS);
+
+// The next id of every CPU. Atomically adding to a shared counter and
+// reading the result back takes BPF_ATOMIC instructions (kernel 5.12), so
+// each CPU hands out its own ids instead.
+#[link_section = "maps/string_ids"]
+static mut STRING_IDS: bpf_map_def = bpf_map_def {
+    type_: bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY,
+    key_size: mem::size_of::<u32>() as u32,
+    value_size: mem::size_of::<u32>() as u32,
+    max_entries: 1,
+    map_flags: 0,
+};
+
+/// A string sent to userspace along with the id it was interned as.
+///
+/// Read the string into `string`, then pass the whole `InternedStr` to
+/// `Interner::intern`, which fills in the id.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct InternedStr<const N: usize> {
+    pub id: u32,
+    pub string: BpfStr<N>,
+}
+
+impl<const N: usize> InternedStr<N> {
+    #[inline]
+    pub const fn new() -> InternedStr<N> {
+        InternedStr {
+            id: 0,
+            string: BpfStr::new(),
+        }
+    }
+}
+
+impl<const N: usize> Default for InternedStr<N> {
+    #[inline]
+    fn default() -> InternedStr<N> {
+        InternedStr::new()
+    }
+}
+
+/// Assigns ids to strings, see the module documentation.
+pub struct Interner<'a, const N: usize> {
+    ids: &'a mut HashMap<BpfStr<N>, u32>,
+    strings: &'a mut EventChannel<InternedStr<N>>,
+}
+
+impl<'a, const N: usize> Interner<'a, N> {
+    /// Creates an interner keeping ids in `ids`, and sending new strings to
+    /// userspace through `strings`.
+    #[inline]
+    pub fn new(
+        ids: &'a mut HashMap<BpfStr<N>, u32>,
+        strings: &'a mut EventChannel<InternedStr<N>>,
+    ) -> Interner<'a, N> {
+        Interner { ids, strings }
+    }
+
+    /// Returns the id of `s.string`, interning it if it's new, or `None` if
+    /// the map of ids is full.
+    ///
+    /// New strings are sent through the channel with their id, once: when
+    /// two CPUs intern the same new string at once, the one adding it to the
+    /// map first sends it, and the other one uses its id. Events referring
+    /// to the id may reach userspace before the string does, so the ids
+    /// that can't be resolved yet should be looked up again later.
+    ///
+    /// `ctx` is the context of the running program, see
+    /// `EventChannel::output`.
+    #[inline]
+    pub fn intern<C>(&mut self, ctx: *mut C, s: &mut InternedStr<N>) -> Option<u32> {
+        let map = self.ids.map_ptr();
+        let key = &mut s.string as *mut _ as *mut c_void;
+        let table = IdTable { map, key };
+        let id = intern_with(&table, next_id, |id| {
+            s.id = id;
+            self.strings.output(ctx, s);
+        })?;
+        s.id = id;
+        Some(id)
+    }
+}
+
+/// Where the ids of strings are kept.
+trait Ids {
+    /// Returns the id of the string, if it has one.
+    fn lookup(&self) -> Option<u32>;
+
+    /// Adds the string with `id`, unless it has an id already. Returns
+    /// `false` if the string couldn't be added.
+    fn insert_new(&self, id: u32) -> bool;
+}
+
+/// The entry of a string in the map of ids.
+struct IdTable {
+    map: *mut c_void,
+    key: *mut c_void,
+}
+
+impl Ids for IdTable {
+    #[inline]
+    fn lookup(&self) -> Option<u32> {
+        let id = unsafe { bpf_map_lookup_elem(self.map, self.key) as *const u32 };
+        if id.is_null() {
+            None
+        } else {
+            Some(unsafe { *id })
+        }
+    }
+
+    #[inline]
+    fn insert_new(&self, mut id: u32) -> bool {
+        let value = &mut id as *mut _ as *mut c_void;
+        unsafe { bpf_map_update_elem(self.map, self.key, value, BPF_NOEXIST.into()) == 0 }
+    }
+}
+
+/// Returns the id of a string, giving it the id `next_id` returns if it
+/// has none yet, and passing that to `emit` if the string was added.
+#[inline]
+fn intern_with<I: Ids>(
+    ids: &I,
+    next_id: impl FnOnce() -> Option<u32>,
+    emit: impl FnOnce(u32),
+) -> Option<u32> {
+    if let Some(id) = ids.lookup() {
+        return Some(id);
+    }
+    let id = next_id()?;
+    if ids.insert_new(id) {
+        emit(id);
+        return Some(id);
+    }
+    // added by another CPU meanwhile, or the map is full
+    ids.lookup()
+}
+
+/// Hands out the next id of the current CPU.
+#[inline]
+fn next_id() -> Option<u32> {
+    let cpu = unsafe { bpf_get_smp_processor_id() };
+    let mut key = 0u32;
+    let seq = unsafe {
+        bpf_map_lookup_elem(
+            &mut STRING_IDS as *mut _ as *mut c_void,
+            &mut key as *mut _ as *mut c_void,
+        ) as *mut u32
+    };
+    if seq.is_null() || cpu >= ID_CPUS_MAX {
+        return None;
+    }
+    // the count is per CPU, nothing else writes to it meanwhile
+    let id = unsafe {
+        let id = *seq;
+        *seq = id.wrapping_add(1);
+        id
+    };
+    Some(make_id(cpu, id))
+}
+
+/// Combines the id of a CPU with a sequence number counted on that CPU.
+#[inline]
+fn make_id(cpu: u32, seq: u32) -> u32 {
+    cpu << ID_SEQ_BITS | seq & ((1 << ID_SEQ_BITS) - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A map holding at most `max_entries` ids, shared between CPUs.
+    struct MockIds<'a> {
+        map: &'a RefCell<Vec<(&'static str, u32)>>,
+        max_entries: usize,
+        key: &'static str,
+    }
+
+    impl Ids for MockIds<'_> {
+        fn lookup(&self) -> Option<u32> {
+            let map = self.map.borrow();
+            map.iter().find(|(k, _)| *k == self.key).map(|&(_, id)| id)
+        }
+
+        fn insert_new(&self, id: u32) -> bool {
+            let mut map = self.map.borrow_mut();
+            if map.len() >= self.max_entries || map.iter().any(|(k, _)| *k == self.key) {
+                return false;
+            }
+            map.push((self.key, id));
+            true
+        }
+    }
+
+    #[test]
+    fn test_intern() {
+        let map = RefCell::new(vec![]);
+        let mut seq = 0;
+        let mut emitted = vec![];
+        let mut intern = |key| {
+            let ids = MockIds {
+                map: &map,
+                max_entries: 2,
+                key,
+            };
+            intern_with(
+                &ids,
+                || {
+                    seq += 1;
+                    Some(make_id(1, seq))
+                },
+                |id| emitted.push((id, key)),
+            )
+        };
+
+        let usr = intern("/usr");
+        let etc = intern("/etc");
+        assert_ne!(usr, etc);
+        assert_eq!(intern("/usr"), usr);
+        assert_eq!(intern("/etc"), etc);
+        assert_eq!(intern("/usr"), usr);
+        // the map is full
+        assert_eq!(intern("/var"), None);
+
+        // repeated strings are emitted once
+        assert_eq!(
+            emitted,
+            vec![(usr.unwrap(), "/usr"), (etc.unwrap(), "/etc")]
+        );
+        assert_eq!(usr, Some(1 << ID_SEQ_BITS | 1));
+    }
+
+    #[test]
+    fn test_intern_race() {
+        // another CPU adds the string between the lookup and the insert
+        struct Racing<'a>(MockIds<'a>, RefCell<bool>);
+
+        impl Ids for Racing<'_> {
+            fn lookup(&self) -> Option<u32> {
+                self.0.lookup()
+            }
+
+            fn insert_new(&self, id: u32) -> bool {
+                if !self.1.replace(true) {
+                    self.0.insert_new(make_id(2, 0));
+                }
+                self.0.insert_new(id)
+            }
+        }
+
+        let map = RefCell::new(vec![]);
+        let ids = Racing(
+            MockIds {
+                map: &map,
+                max_entries: 8,
+                key: "/usr",
+            },
+            RefCell::new(false),
+        );
+        let mut emitted = 0;
+        let id = intern_with(&ids, || Some(make_id(1, 0)), |_| emitted += 1);
+        assert_eq!(id, Some(make_id(2, 0)));
+        // the other CPU sends the string
+        assert_eq!(emitted, 0);
+    }
+
+    #[test]
+    fn test_make_id() {
+        assert_eq!(make_id(0, 5), 5);
+        assert_eq!(make_id(3, 5), 3 << 24 | 5);
+        // the sequence wraps around within its bits
+        assert_eq!(make_id(3, 1 << 24), 3 << 24);
+    }
+}
@@ -26,20 +26,216 @@ use redbpf_macros::{program, kprobe};
 program!(0xFFFFFFFE, "GPL");
 
 #[kprobe("__arm64_sys_execve")]
-pub extern "C" fn enter_execve(ctx: *mut c_void) -> i32 {
-    let regs = Registers::from(ctx);
+pub extern "C" fn enter_execve(ctx: KProbeContext) -> i32 {
+    let regs = ctx.regs();
 
     // do something here
     // ...
 
     0
 }
+```
+
+# Entry arguments in kretprobes
+
+A kretprobe only sees the return value of the function, since the argument
+registers are overwritten by the time it returns. Taking a
+`KRetProbeContext` instead of a `KProbeContext` makes the `kretprobe` macro
+generate a kprobe on the same function as well, which stashes the arguments
+in a map keyed by thread, from where `KRetProbeContext::entry_args` picks
+them up again.
+
+Report the bytes `tcp_sendmsg` was asked to send, with the bytes it sent:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::kprobe::*;
+use redbpf_probes::maps::PerfMap;
+use redbpf_macros::{map, program, kretprobe};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[repr(C)]
+pub struct Send {
+    size: u64,
+    sent: i64,
+}
+
+#[map("sends")]
+static mut SENDS: PerfMap<Send> = PerfMap::with_max_entries(1024);
+
+#[kretprobe("tcp_sendmsg")]
+pub extern "C" fn tcp_sendmsg_exit(ctx: KRetProbeContext) -> i32 {
+    // int tcp_sendmsg(struct sock *sk, struct msghdr *msg, size_t size)
+    if let Some(args) = ctx.entry_args() {
+        let send = Send {
+            size: args.arg(2),
+            sent: ctx.regs().rc() as i64,
+        };
+        unsafe { SENDS.insert(ctx.inner(), send) };
+    }
+
+    0
+}
 ```
  */
 
 use crate::bindings::*;
+use crate::helpers::{
+    bpf_get_current_pid_tgid, bpf_map_delete_elem, bpf_map_lookup_elem, bpf_map_update_elem,
+};
+use core::mem;
 use cty::*;
 
+/// Number of arguments stashed for kretprobes.
+pub const ENTRY_ARGS: usize = 5;
+/// Number of threads that can be inside a function with a
+/// `KRetProbeContext` kretprobe at the same time.
+pub const ENTRY_ARGS_MAX: u32 = 10240;
+
+/// Context object provided to kprobes and kretprobes.
+///
+/// The `kprobe` and `kretprobe` attribute macros wrap the raw `pt_regs`
+/// argument in a `KProbeContext`.
+pub struct KProbeContext {
+    pub ctx: *mut pt_regs,
+}
+
+impl KProbeContext {
+    /// Returns the raw `pt_regs` context.
+    #[inline]
+    pub fn inner(&self) -> *mut pt_regs {
+        self.ctx
+    }
+
+    /// Returns the registers of the probed function.
+    #[inline]
+    pub fn regs(&self) -> Registers {
+        Registers { ctx: self.ctx }
+    }
+}
+
+/// Context object provided to kretprobes taking it instead of a
+/// `KProbeContext`, which also holds the arguments the function was called
+/// with.
+pub struct KRetProbeContext {
+    pub ctx: *mut pt_regs,
+    entry: Option<EntryArgs>,
+}
+
+impl KRetProbeContext {
+    /// Wraps the `pt_regs` of a kretprobe, taking the arguments stashed on
+    /// entry out of `map`. Used by the `kretprobe` macro.
+    ///
+    /// # Safety
+    ///
+    /// `map` must be an entry args map, see `entry_args_map`.
+    #[doc(hidden)]
+    #[inline]
+    pub unsafe fn new(ctx: *mut pt_regs, map: *mut bpf_map_def) -> KRetProbeContext {
+        let mut key = bpf_get_current_pid_tgid();
+        let key = &mut key as *mut _ as *mut c_void;
+        let args = bpf_map_lookup_elem(map as *mut c_void, key) as *const EntryArgs;
+        let entry = if args.is_null() { None } else { Some(*args) };
+        bpf_map_delete_elem(map as *mut c_void, key);
+
+        KRetProbeContext { ctx, entry }
+    }
+
+    /// Returns the raw `pt_regs` context.
+    #[inline]
+    pub fn inner(&self) -> *mut pt_regs {
+        self.ctx
+    }
+
+    /// Returns the registers at the return of the probed function.
+    #[inline]
+    pub fn regs(&self) -> Registers {
+        Registers { ctx: self.ctx }
+    }
+
+    /// Returns the arguments the probed function was called with.
+    ///
+    /// Returns `None` if the call wasn't seen, because it started before
+    /// the programs were attached, or because the map ran full and the
+    /// entry was evicted. The arguments are kept per thread, so of
+    /// recursive calls only the innermost has them.
+    #[inline]
+    pub fn entry_args(&self) -> Option<&EntryArgs> {
+        self.entry.as_ref()
+    }
+}
+
+/// The arguments of a function, stashed on entry for its kretprobe.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EntryArgs {
+    args: [u64; ENTRY_ARGS],
+}
+
+impl EntryArgs {
+    /// Reads the arguments from the registers at the entry of a function.
+    #[inline]
+    pub fn from_regs(regs: &Registers) -> EntryArgs {
+        EntryArgs {
+            args: [
+                regs.parm1(),
+                regs.parm2(),
+                regs.parm3(),
+                regs.parm4(),
+                regs.parm5(),
+            ],
+        }
+    }
+
+    /// Returns the `n`th argument, or 0 if `n` is `ENTRY_ARGS` or more.
+    #[inline]
+    pub fn arg(&self, n: usize) -> u64 {
+        if n >= ENTRY_ARGS {
+            return 0;
+        }
+        self.args[n]
+    }
+}
+
+/// Returns the definition of the map the `kretprobe` macro stashes entry
+/// arguments in.
+///
+/// The map is an LRU hash, so the arguments of calls whose return is never
+/// seen, for example because the thread exited, are evicted eventually.
+#[doc(hidden)]
+pub const fn entry_args_map() -> bpf_map_def {
+    bpf_map_def {
+        type_: bpf_map_type_BPF_MAP_TYPE_LRU_HASH,
+        key_size: mem::size_of::<u64>() as u32,
+        value_size: mem::size_of::<EntryArgs>() as u32,
+        max_entries: ENTRY_ARGS_MAX,
+        map_flags: 0,
+    }
+}
+
+/// Stashes the arguments of the function `ctx` is the entry of in `map`.
+/// Used by the entry kprobes the `kretprobe` macro generates.
+///
+/// # Safety
+///
+/// `map` must be an entry args map, see `entry_args_map`.
+#[doc(hidden)]
+#[inline]
+pub unsafe fn stash_entry_args(ctx: *mut pt_regs, map: *mut bpf_map_def) {
+    let mut key = bpf_get_current_pid_tgid();
+    // a call whose return was missed leaves its arguments behind, which are
+    // overwritten here
+    let mut args = EntryArgs::from_regs(&Registers { ctx });
+    bpf_map_update_elem(
+        map as *mut c_void,
+        &mut key as *mut _ as *mut c_void,
+        &mut args as *mut _ as *mut c_void,
+        BPF_ANY.into(),
+    );
+}
+
 pub struct Registers {
     pub ctx: *mut pt_regs,
 }
@@ -193,3 +389,29 @@ impl Registers {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_args() {
+        let mut regs: pt_regs = unsafe { mem::zeroed() };
+        #[cfg(target_arch = "x86_64")]
+        {
+            regs.di = 1;
+            regs.si = 2;
+            regs.dx = 3;
+            regs.cx = 4;
+            regs.r8 = 5;
+        }
+        #[cfg(target_arch = "aarch64")]
+        regs.regs[..5].copy_from_slice(&[1, 2, 3, 4, 5]);
+
+        let args = EntryArgs::from_regs(&Registers { ctx: &mut regs });
+        assert_eq!(
+            (0..=ENTRY_ARGS).map(|n| args.arg(n)).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5, 0]
+        );
+    }
+}
@@ -51,9 +51,22 @@ pub extern "C" fn block_port_80(ctx: XdpContext) -> XdpAction {
 
 */
 #![deny(clippy::all)]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 pub mod bindings;
+pub mod byteorder;
+pub mod conntrack;
+pub mod fentry;
+pub mod hash;
 pub mod helpers;
+pub mod intern;
 pub mod kprobe;
 pub mod maps;
+pub mod net;
+pub mod sock;
+pub mod sock_addr;
+pub mod socket_filter;
+pub mod string;
+pub mod task;
+pub mod tc;
+pub mod tracepoint;
 pub mod xdp;
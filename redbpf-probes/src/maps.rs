@@ -14,7 +14,8 @@ kernel and user-space code.
  */
 use core::default::Default;
 use core::marker::PhantomData;
-use core::mem;
+use core::mem::{self, MaybeUninit};
+use core::ops::AddAssign;
 use cty::*;
 
 use crate::bindings::*;
@@ -33,13 +34,19 @@ pub struct HashMap<K, V> {
 impl<K, V> HashMap<K, V> {
     /// Creates a map with the specified maximum number of elements.
     pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self::with_flags(max_entries, 0)
+    }
+
+    /// Creates a map with the specified maximum number of elements and
+    /// `BPF_F_*` map flags, such as `BPF_F_NO_PREALLOC`.
+    pub const fn with_flags(max_entries: u32, map_flags: u32) -> Self {
         Self {
             def: bpf_map_def {
                 type_: bpf_map_type_BPF_MAP_TYPE_HASH,
                 key_size: mem::size_of::<K>() as u32,
                 value_size: mem::size_of::<V>() as u32,
                 max_entries,
-                map_flags: 0,
+                map_flags,
             },
             _k: PhantomData,
             _v: PhantomData,
@@ -85,6 +92,313 @@ impl<K, V> HashMap<K, V> {
             );
         }
     }
+
+    /// Returns the map to pass to helpers, for keys too large to copy around
+    /// on the stack.
+    #[inline]
+    pub(crate) fn map_ptr(&mut self) -> *mut c_void {
+        &mut self.def as *mut _ as *mut c_void
+    }
+}
+
+/// Array map.
+///
+/// High level API for BPF_MAP_TYPE_ARRAY maps, whose `max_entries` values
+/// are all allocated up front, and zeroed.
+///
+/// The kernel checks the index of every lookup, but the verifier may still
+/// lose track of the range of an index computed by the program, and reject
+/// accesses through the value. Size the array to a power of two, and use
+/// `get_masked` with a mask of `max_entries - 1`, so the index is in bounds
+/// by construction.
+///
+/// # Example
+///
+/// Count packets by RX queue:
+///
+/// ```
+/// const QUEUES: u32 = 64;
+///
+/// #[map("queue_packets")]
+/// static mut queue_packets: Array<u64> = Array::with_max_entries(QUEUES);
+///
+/// #[xdp]
+/// pub extern "C" fn count_queues(ctx: XdpContext) -> XdpAction {
+///     let queue = unsafe { (*ctx.inner()).rx_queue_index };
+///     if let Some(count) = unsafe { queue_packets.get_masked_mut(queue, QUEUES - 1) } {
+///         // not atomic, see PerCpuArray for per-CPU counts
+///         *count += 1;
+///     }
+///     XdpAction::Pass
+/// }
+/// ```
+#[repr(transparent)]
+pub struct Array<T> {
+    def: bpf_map_def,
+    _v: PhantomData<T>,
+}
+
+impl<T> Array<T> {
+    /// Creates an array of `max_entries` elements.
+    ///
+    /// Sizes that are powers of two allow masking indices, see `get_masked`.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self::with_flags(max_entries, 0)
+    }
+
+    /// Creates an array of `max_entries` elements with `BPF_F_*` map flags,
+    /// such as `BPF_F_MMAPABLE`.
+    pub const fn with_flags(max_entries: u32, map_flags: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_ARRAY,
+                key_size: mem::size_of::<u32>() as u32,
+                value_size: mem::size_of::<T>() as u32,
+                max_entries,
+                map_flags,
+            },
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the element at `index`, or `None` if `index`
+    /// is out of bounds.
+    #[inline]
+    pub fn get(&mut self, index: u32) -> Option<&T> {
+        self.get_mut(index).map(|value| &*value)
+    }
+
+    /// Returns a mutable reference to the element at `index`, or `None` if
+    /// `index` is out of bounds.
+    #[inline]
+    pub fn get_mut(&mut self, mut index: u32) -> Option<&mut T> {
+        unsafe {
+            let value = bpf_map_lookup_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                &mut index as *mut _ as *mut c_void,
+            );
+            if value.is_null() {
+                None
+            } else {
+                Some(&mut *(value as *mut T))
+            }
+        }
+    }
+
+    /// Returns a reference to the element at `index & mask`.
+    ///
+    /// With `mask` set to `max_entries - 1`, and `max_entries` a power of
+    /// two, every index lands in the array, and the verifier can tell.
+    /// Other masks only bound the index by `mask`, and larger indices are
+    /// still `None`.
+    #[inline]
+    pub fn get_masked(&mut self, index: u32, mask: u32) -> Option<&T> {
+        self.get(masked(index, mask))
+    }
+
+    /// Returns a mutable reference to the element at `index & mask`, see
+    /// `get_masked`.
+    #[inline]
+    pub fn get_masked_mut(&mut self, index: u32, mask: u32) -> Option<&mut T> {
+        self.get_mut(masked(index, mask))
+    }
+
+    /// Sets the element at `index` to `value`, if `index` is in bounds.
+    #[inline]
+    pub fn set(&mut self, mut index: u32, mut value: T) {
+        unsafe {
+            bpf_map_update_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                &mut index as *mut _ as *mut c_void,
+                &mut value as *mut _ as *mut c_void,
+                BPF_ANY.into(),
+            );
+        }
+    }
+}
+
+/// Bounds `index` by `mask`.
+#[inline]
+fn masked(index: u32, mask: u32) -> u32 {
+    index & mask
+}
+
+/// Per-CPU array map.
+///
+/// High level API for BPF_MAP_TYPE_PERCPU_ARRAY maps. Every CPU has its own
+/// copy of the `max_entries` values, and lookups return the copy of the
+/// current CPU, which nothing else writes to while the program runs. This
+/// makes for counters that never contend, which `redbpf::PerCpuArray` reads
+/// the copies of.
+///
+/// # Example
+///
+/// Count packets by the action taken on them:
+///
+/// ```
+/// #[map("action_packets")]
+/// static mut action_packets: PerCpuArray<u64> = PerCpuArray::with_max_entries(5);
+///
+/// #[xdp]
+/// pub extern "C" fn count_actions(ctx: XdpContext) -> XdpAction {
+///     let action = match ctx.transport() {
+///         Some(transport) if transport.dest() == 80 => XdpAction::Drop,
+///         _ => XdpAction::Pass,
+///     };
+///     unsafe { action_packets.increment(action as u32, 1) };
+///     action
+/// }
+/// ```
+#[repr(transparent)]
+pub struct PerCpuArray<T> {
+    def: bpf_map_def,
+    _v: PhantomData<T>,
+}
+
+impl<T> PerCpuArray<T> {
+    /// Creates an array of `max_entries` elements per CPU.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self::with_flags(max_entries, 0)
+    }
+
+    /// Creates an array of `max_entries` elements per CPU with `BPF_F_*` map
+    /// flags.
+    pub const fn with_flags(max_entries: u32, map_flags: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY,
+                key_size: mem::size_of::<u32>() as u32,
+                value_size: mem::size_of::<T>() as u32,
+                max_entries,
+                map_flags,
+            },
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the current CPU's element at `index`, or
+    /// `None` if `index` is out of bounds.
+    #[inline]
+    pub fn get(&mut self, index: u32) -> Option<&T> {
+        self.get_mut(index).map(|value| &*value)
+    }
+
+    /// Returns a mutable reference to the current CPU's element at `index`,
+    /// or `None` if `index` is out of bounds.
+    #[inline]
+    pub fn get_mut(&mut self, mut index: u32) -> Option<&mut T> {
+        unsafe {
+            let value = bpf_map_lookup_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                &mut index as *mut _ as *mut c_void,
+            );
+            if value.is_null() {
+                None
+            } else {
+                Some(&mut *(value as *mut T))
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the current CPU's element at
+    /// `index & mask`, see `Array::get_masked`.
+    #[inline]
+    pub fn get_masked_mut(&mut self, index: u32, mask: u32) -> Option<&mut T> {
+        self.get_mut(masked(index, mask))
+    }
+}
+
+impl<T: AddAssign> PerCpuArray<T> {
+    /// Adds `by` to the current CPU's element at `index`, if `index` is in
+    /// bounds.
+    #[inline]
+    pub fn increment(&mut self, index: u32, by: T) {
+        if let Some(value) = self.get_mut(index) {
+            *value += by;
+        }
+    }
+}
+
+/// Counters by key.
+///
+/// High level API for BPF_MAP_TYPE_PERCPU_HASH maps of `u64` counts. Every
+/// CPU counts in its own copy of the value, so increments never contend,
+/// and `redbpf::Counter` sums the copies up when reading a count.
+///
+/// # Example
+///
+/// Count packets by source address:
+///
+/// ```
+/// #[map("packets")]
+/// static mut packets: Counter<u32> = Counter::with_max_entries(10240);
+///
+/// #[xdp]
+/// pub extern "C" fn count_packets(ctx: XdpContext) -> XdpAction {
+///     if let Some(ip) = ctx.ip() {
+///         unsafe { packets.inc((*ip).saddr) };
+///     }
+///     XdpAction::Pass
+/// }
+/// ```
+#[repr(transparent)]
+pub struct Counter<K> {
+    def: bpf_map_def,
+    _k: PhantomData<K>,
+}
+
+impl<K> Counter<K> {
+    /// Creates a map with the specified maximum number of keys.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self::with_flags(max_entries, 0)
+    }
+
+    /// Creates a map with the specified maximum number of keys and
+    /// `BPF_F_*` map flags, such as `BPF_F_NO_PREALLOC`.
+    pub const fn with_flags(max_entries: u32, map_flags: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_PERCPU_HASH,
+                key_size: mem::size_of::<K>() as u32,
+                value_size: mem::size_of::<u64>() as u32,
+                max_entries,
+                map_flags,
+            },
+            _k: PhantomData,
+        }
+    }
+
+    /// Adds one to the count of `key`.
+    #[inline]
+    pub fn inc(&mut self, key: K) {
+        self.add(key, 1)
+    }
+
+    /// Adds `n` to the count of `key`, which starts at 0.
+    ///
+    /// Nothing is counted if the map is full.
+    #[inline]
+    pub fn add(&mut self, mut key: K, mut n: u64) {
+        let map = &mut self.def as *mut _ as *mut c_void;
+        let key = &mut key as *mut _ as *mut c_void;
+        unsafe {
+            let count = bpf_map_lookup_elem(map, key) as *mut u64;
+            // the count is per CPU, nothing else writes to it meanwhile
+            if !count.is_null() {
+                *count += n;
+                return;
+            }
+            let value = &mut n as *mut _ as *mut c_void;
+            if bpf_map_update_elem(map, key, value, BPF_NOEXIST.into()) == 0 {
+                return;
+            }
+            // added on another CPU since the lookup, with a count of 0 here
+            let count = bpf_map_lookup_elem(map, key) as *mut u64;
+            if !count.is_null() {
+                *count += n;
+            }
+        }
+    }
 }
 
 /// Flags that can be passed to `PerfMap::insert_with_flags`.
@@ -159,13 +473,19 @@ pub struct PerfMap<T> {
 impl<T> PerfMap<T> {
     /// Creates a perf map with the specified maximum number of elements.
     pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self::with_flags(max_entries, 0)
+    }
+
+    /// Creates a perf map with the specified maximum number of elements and
+    /// `BPF_F_*` map flags.
+    pub const fn with_flags(max_entries: u32, map_flags: u32) -> Self {
         Self {
             def: bpf_map_def {
                 type_: bpf_map_type_BPF_MAP_TYPE_PERF_EVENT_ARRAY,
                 key_size: mem::size_of::<u32>() as u32,
                 value_size: mem::size_of::<u32>() as u32,
                 max_entries,
-                map_flags: 0,
+                map_flags,
             },
             _event: PhantomData,
         }
@@ -196,3 +516,272 @@ impl<T> PerfMap<T> {
         };
     }
 }
+
+/// Map type of `EventChannel` definitions, swapped for a real map type by the
+/// loader. Mirrors `EVENT_CHANNEL_MAP_TYPE` in `redbpf`.
+pub const EVENT_CHANNEL_MAP_TYPE: u32 = 0x8000_001b;
+
+extern "C" {
+    // Not a real object: the loader patches references to this symbol with
+    // 1 if the running kernel supports ring buffers, and with 0 otherwise.
+    static __redbpf_ringbuf: u8;
+}
+
+/// Event channel.
+///
+/// Sends events to user-space through a `BPF_MAP_TYPE_RINGBUF` on kernels
+/// that support it (5.8 and later), and through a per-CPU
+/// `BPF_MAP_TYPE_PERF_EVENT_ARRAY` on older kernels.
+///
+/// The backing map type is chosen by the loader. The unused branch of
+/// `output` is a constant condition to the verifier, so programs using a
+/// channel load on kernels that don't know about ring buffer helpers. On the
+/// user-space side, bind the map with `redbpf::EventChannel`.
+///
+/// # Example
+///
+/// ```
+/// #[map("events")]
+/// static mut events: EventChannel<Event> = EventChannel::with_max_entries(256 * 1024);
+///
+/// #[xdp]
+/// pub extern "C" fn probe(ctx: XdpContext) -> XdpAction {
+///     let event = Event { len: ctx.len() };
+///     unsafe { events.output(ctx.ctx, &event) };
+///     XdpAction::Pass
+/// }
+/// ```
+#[repr(transparent)]
+pub struct EventChannel<T> {
+    def: bpf_map_def,
+    _event: PhantomData<T>,
+}
+
+impl<T> EventChannel<T> {
+    /// Creates a channel with a ring buffer of `size` bytes.
+    ///
+    /// `size` must be a power of two multiple of the page size. It is ignored
+    /// when the channel falls back to perf buffers.
+    pub const fn with_max_entries(size: u32) -> Self {
+        Self::with_flags(size, 0)
+    }
+
+    /// Creates a channel with a ring buffer of `size` bytes and `BPF_F_*`
+    /// map flags.
+    pub const fn with_flags(size: u32, map_flags: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: EVENT_CHANNEL_MAP_TYPE,
+                key_size: 0,
+                value_size: 0,
+                max_entries: size,
+                map_flags,
+            },
+            _event: PhantomData,
+        }
+    }
+
+    /// Sends `data` to user-space.
+    ///
+    /// `ctx` is the context of the running program. It is only used when the
+    /// channel is backed by perf buffers.
+    #[inline]
+    pub fn output<C>(&mut self, ctx: *mut C, data: &T) {
+        let map = &mut self.def as *mut _ as *mut c_void;
+        let data = data as *const T as *mut c_void;
+        let size = mem::size_of::<T>() as u64;
+        unsafe {
+            if &__redbpf_ringbuf as *const u8 as usize == 1 {
+                bpf_ringbuf_output(map, data, size, 0);
+            } else {
+                bpf_perf_event_output(
+                    ctx as *mut c_void,
+                    map,
+                    BPF_F_CURRENT_CPU.into(),
+                    data,
+                    size,
+                );
+            };
+        }
+    }
+}
+
+/// `BPF_MAP_TYPE_RINGBUF`, which the bindings of older kernel headers lack.
+const BPF_MAP_TYPE_RINGBUF: u32 = 27;
+
+/// Ring buffer map.
+///
+/// A single buffer shared by all CPUs, which keeps events in the order they
+/// were sent, for kernels 5.8 and later. This is a wrapper for
+/// `BPF_MAP_TYPE_RINGBUF`. Use `EventChannel` instead to fall back to perf
+/// buffers on older kernels. On the user-space side, bind the map with
+/// `redbpf::RingBuffer`.
+///
+/// Large events can be written in place with `reserve`, instead of being
+/// copied by `output`.
+///
+/// # Example
+///
+/// ```
+/// #[map("events")]
+/// static mut events: RingBuf<Event> = RingBuf::with_max_entries(256 * 1024);
+///
+/// #[kprobe("tcp_v4_connect")]
+/// pub extern "C" fn connect(ctx: KProbeContext) -> i32 {
+///     let mut event = match unsafe { events.reserve() } {
+///         Some(event) => event,
+///         None => return 0,
+///     };
+///     event.write(Event {
+///         pid: (bpf_get_current_pid_tgid() >> 32) as u32,
+///         comm: bpf_get_current_comm(),
+///     });
+///     event.submit();
+///
+///     0
+/// }
+/// ```
+#[repr(transparent)]
+pub struct RingBuf<T> {
+    def: bpf_map_def,
+    _event: PhantomData<T>,
+}
+
+impl<T> RingBuf<T> {
+    /// Creates a ring buffer of `size` bytes.
+    ///
+    /// `size` must be a power of two multiple of the page size.
+    pub const fn with_max_entries(size: u32) -> Self {
+        Self::with_flags(size, 0)
+    }
+
+    /// Creates a ring buffer of `size` bytes and `BPF_F_*` map flags.
+    pub const fn with_flags(size: u32, map_flags: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: BPF_MAP_TYPE_RINGBUF,
+                key_size: 0,
+                value_size: 0,
+                max_entries: size,
+                map_flags,
+            },
+            _event: PhantomData,
+        }
+    }
+
+    /// Copies `data` into the ring buffer.
+    ///
+    /// Fails if the buffer is full.
+    #[inline]
+    pub fn output(&mut self, data: &T) -> Result<(), ()> {
+        let ret = unsafe {
+            bpf_ringbuf_output(
+                &mut self.def as *mut _ as *mut c_void,
+                data as *const T as *mut c_void,
+                mem::size_of::<T>() as u64,
+                0,
+            )
+        };
+        if ret < 0 {
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reserves room for an event in the ring buffer, or returns `None` if
+    /// the buffer is full.
+    ///
+    /// The event isn't zeroed. Fill it in with `RingBufEntry::write`, or
+    /// field by field through `as_uninit_mut`, then `submit` it. Entries
+    /// that are dropped are discarded, which the verifier requires of every
+    /// entry reserved.
+    #[inline]
+    pub fn reserve(&mut self) -> Option<RingBufEntry<'_, T>> {
+        let data = unsafe {
+            bpf_ringbuf_reserve(
+                &mut self.def as *mut _ as *mut c_void,
+                mem::size_of::<T>() as u64,
+                0,
+            )
+        } as *mut T;
+        if data.is_null() {
+            None
+        } else {
+            Some(RingBufEntry {
+                data,
+                _map: PhantomData,
+            })
+        }
+    }
+}
+
+/// An event reserved in a `RingBuf`, returned by `RingBuf::reserve`.
+pub struct RingBufEntry<'a, T> {
+    data: *mut T,
+    _map: PhantomData<&'a mut RingBuf<T>>,
+}
+
+impl<T> RingBufEntry<'_, T> {
+    /// Sends the event to user-space.
+    #[inline]
+    pub fn submit(self) {
+        unsafe { bpf_ringbuf_submit(self.data as *mut c_void, 0) };
+        mem::forget(self);
+    }
+
+    /// Gives the room back without sending the event.
+    #[inline]
+    pub fn discard(self) {
+        // dropping discards
+    }
+
+    /// Returns a pointer to the reserved event.
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.data
+    }
+
+    /// Returns the reserved event, which isn't initialized.
+    #[inline]
+    pub fn as_uninit_mut(&mut self) -> &mut MaybeUninit<T> {
+        unsafe { &mut *(self.data as *mut MaybeUninit<T>) }
+    }
+
+    /// Sets the event to `value`, and returns it for further changes.
+    #[inline]
+    pub fn write(&mut self, value: T) -> &mut T {
+        unsafe {
+            self.data.write(value);
+            &mut *self.data
+        }
+    }
+}
+
+impl<T> Drop for RingBufEntry<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { bpf_ringbuf_discard(self.data as *mut c_void, 0) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_masked() {
+        for &max_entries in [1u32, 2, 64, 1 << 20].iter() {
+            let mask = max_entries - 1;
+            for &index in [0, 1, 63, 64, 65, max_entries, u32::MAX, 0xdead_beef].iter() {
+                let masked = masked(index, mask);
+                assert!(masked < max_entries);
+                if index < max_entries {
+                    assert_eq!(masked, index);
+                }
+            }
+        }
+        // masks that aren't max_entries - 1 only bound the index by them
+        assert_eq!(masked(u32::MAX, 0xff), 0xff);
+    }
+}
@@ -0,0 +1,687 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Packet parsing shared by XDP and TC programs.
+
+`XdpContext` and `SkBuffContext` both give programs direct access to the
+packet between the `data` and `data_end` fields of their context, which the
+verifier lets programs read once they checked the bounds. `NetworkBuffer`
+parses the headers of either, so the same parsing code runs on ingress in
+XDP, and on egress or on NICs without XDP support in TC.
+
+# Example
+
+Drop packets to a port, whichever hook the program runs at:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::net::NetworkBuffer;
+use redbpf_probes::tc::{SkBuffContext, TcAction};
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+use redbpf_macros::{program, tc_action, xdp};
+
+program!(0xFFFFFFFE, "GPL");
+
+fn blocked<B: NetworkBuffer>(buf: &B) -> bool {
+    match buf.transport() {
+        Some(transport) => transport.dest() == 6379,
+        None => false,
+    }
+}
+
+#[xdp]
+pub extern "C" fn block_ingress(ctx: XdpContext) -> XdpAction {
+    if blocked(&ctx) {
+        return XdpAction::Drop;
+    }
+    XdpAction::Pass
+}
+
+#[tc_action]
+pub extern "C" fn block_egress(skb: SkBuffContext) -> TcAction {
+    if blocked(&skb) {
+        return TcAction::Shot;
+    }
+    TcAction::Ok
+}
+```
+ */
+use core::mem;
+use cty::*;
+
+use crate::bindings::*;
+use crate::byteorder::{ntohs, tcp_doff};
+use crate::helpers::bpf_check_mtu;
+use crate::xdp::{
+    eth_payload, is_fragment, l4_header, ArpOp, Data, IpHeader, Transport, XdpParseError,
+};
+
+/// UDP destination port of VXLAN packets, as assigned by IANA.
+pub const VXLAN_PORT: u16 = 4789;
+/// Length of the VXLAN header.
+const VXLAN_HDR_LEN: usize = 8;
+/// Flag of VXLAN headers holding a network identifier.
+const VXLAN_FLAG_VNI: u8 = 0x08;
+/// GRE header flags: checksum, routing, key and sequence number present.
+const GRE_CSUM: u16 = 0x8000;
+const GRE_ROUTING: u16 = 0x4000;
+const GRE_KEY: u16 = 0x2000;
+const GRE_SEQ: u16 = 0x1000;
+const GRE_VERSION: u16 = 0x0007;
+/// Length of the addresses following the ARP header, for Ethernet/IPv4.
+const ARP_ETH_IPV4_LEN: usize = 20;
+/// Offset of the sender's MAC address after the ARP header.
+const ARP_SENDER_MAC: usize = 0;
+/// Offset of the sender's IPv4 address after the ARP header.
+const ARP_SENDER_IP: usize = 6;
+/// Offset of the target's IPv4 address after the ARP header.
+const ARP_TARGET_IP: usize = 16;
+
+/// Flag of `check_mtu` checking the size of the segments of GSO packets,
+/// rather than of the whole packet. Only for TC programs.
+pub const MTU_CHECK_SEGS: u64 = 1;
+/// Return values of `bpf_check_mtu` for packets that fit, that don't, and
+/// whose GSO segments don't.
+const MTU_CHECK_RET_SUCCESS: c_int = 0;
+const MTU_CHECK_RET_FRAG_NEEDED: c_int = 1;
+const MTU_CHECK_RET_SEGS_TOOBIG: c_int = 2;
+
+/// Contexts holding the bounds of the packet, in their `data` and
+/// `data_end` fields.
+pub trait PacketBounds {
+    /// Returns the start of the packet.
+    ///
+    /// # Safety
+    ///
+    /// `ctx` must point to the context the program was passed.
+    unsafe fn data(ctx: *const Self) -> *const u8;
+
+    /// Returns the end of the packet.
+    ///
+    /// # Safety
+    ///
+    /// `ctx` must point to the context the program was passed.
+    unsafe fn data_end(ctx: *const Self) -> *const u8;
+}
+
+impl PacketBounds for xdp_md {
+    #[inline]
+    unsafe fn data(ctx: *const xdp_md) -> *const u8 {
+        (*ctx).data as *const u8
+    }
+
+    #[inline]
+    unsafe fn data_end(ctx: *const xdp_md) -> *const u8 {
+        (*ctx).data_end as *const u8
+    }
+}
+
+impl PacketBounds for __sk_buff {
+    #[inline]
+    unsafe fn data(ctx: *const __sk_buff) -> *const u8 {
+        (*ctx).data as *const u8
+    }
+
+    #[inline]
+    unsafe fn data_end(ctx: *const __sk_buff) -> *const u8 {
+        (*ctx).data_end as *const u8
+    }
+}
+
+/// The outcome of checking the size of a packet against the MTU of an
+/// interface, with `XdpContext::check_mtu` or `SkBuffContext::check_mtu`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MtuResult {
+    /// Whether the packet, resized by the given delta, fits the MTU. When
+    /// checking segments, whether every segment does.
+    pub fits: bool,
+    /// The MTU of the interface. Like the MTU configured on the interface
+    /// it excludes the Ethernet header, unlike the lengths of packets.
+    pub mtu: u32,
+}
+
+impl MtuResult {
+    /// Interprets the return value of `bpf_check_mtu`, and the MTU it
+    /// stored, which it doesn't when it fails.
+    #[inline]
+    fn from_ret(ret: c_int, mtu: u32) -> Option<MtuResult> {
+        match ret {
+            MTU_CHECK_RET_SUCCESS => Some(MtuResult { fits: true, mtu }),
+            MTU_CHECK_RET_FRAG_NEEDED | MTU_CHECK_RET_SEGS_TOOBIG => {
+                Some(MtuResult { fits: false, mtu })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Checks the packet of `ctx`, resized by `len_delta`, against the MTU of
+/// the interface `ifindex`, or the one of the current interface if 0.
+///
+/// # Safety
+///
+/// `ctx` must point to the context the program was passed.
+#[inline]
+pub(crate) unsafe fn check_mtu<C>(
+    ctx: *mut C,
+    ifindex: u32,
+    len_delta: i32,
+    flags: u64,
+) -> Option<MtuResult> {
+    // 0 checks the length of the packet, rather than a given one
+    let mut mtu = 0u32;
+    let ret = bpf_check_mtu(ctx as *mut c_void, ifindex, &mut mtu, len_delta, flags);
+    MtuResult::from_ret(ret, mtu)
+}
+
+/// Header parsing of the packet of a program context.
+///
+/// The packet bounds are read from the context on every check, as the
+/// verifier loses track of them once they are stored elsewhere.
+pub trait NetworkBuffer {
+    /// The raw context, `xdp_md` or `__sk_buff`.
+    type Context: PacketBounds;
+
+    /// Returns the raw context.
+    fn context(&self) -> *mut Self::Context;
+
+    /// Returns the start of the packet.
+    #[inline]
+    fn data_start(&self) -> *const u8 {
+        unsafe { Self::Context::data(self.context()) }
+    }
+
+    /// Returns the end of the packet.
+    #[inline]
+    fn data_end(&self) -> *const u8 {
+        unsafe { Self::Context::data_end(self.context()) }
+    }
+
+    /// Returns the length of the packet that can be accessed directly.
+    ///
+    /// For TC programs, this may be less than the length of the packet, as
+    /// the rest of a non-linear `skb` is out of reach.
+    #[inline]
+    fn buffer_len(&self) -> usize {
+        self.data_end() as usize - self.data_start() as usize
+    }
+
+    /// Returns the packet's `Ethernet` header if present.
+    #[inline]
+    fn eth(&self) -> Option<*const ethhdr> {
+        self.try_eth().ok()
+    }
+
+    /// Returns the packet's `Ethernet` header, or `Truncated` if the packet
+    /// is too short to hold one.
+    #[inline]
+    fn try_eth(&self) -> Result<*const ethhdr, XdpParseError> {
+        let eth = self.data_start() as *const ethhdr;
+        unsafe {
+            if eth.add(1) as *const u8 > self.data_end() {
+                return Err(XdpParseError::Truncated);
+            }
+        }
+        Ok(eth)
+    }
+
+    /// Returns the offset of the network header from `data_start()`, along
+    /// with its EtherType, after up to `VLAN_TAGS_MAX` VLAN tags.
+    #[inline]
+    fn network_header(&self) -> Result<(usize, u16), XdpParseError> {
+        l3_offset(self)
+    }
+
+    /// Returns the packet's `IP` header if present, possibly VLAN tagged.
+    #[inline]
+    fn ip(&self) -> Option<*const iphdr> {
+        self.try_ip().ok()
+    }
+
+    /// Returns the packet's `IP` header, possibly VLAN tagged, or why there
+    /// is none.
+    #[inline]
+    fn try_ip(&self) -> Result<*const iphdr, XdpParseError> {
+        let (offset, proto) = self.network_header()?;
+        if proto != ETH_P_IP as u16 {
+            return Err(XdpParseError::UnsupportedL3);
+        }
+        unsafe {
+            let ip = self.data_start().add(offset) as *const iphdr;
+            if ip.add(1) as *const u8 > self.data_end() {
+                return Err(XdpParseError::Truncated);
+            }
+            Ok(ip)
+        }
+    }
+
+    /// Returns the packet's `IPv6` header if present, possibly VLAN tagged.
+    #[inline]
+    fn ipv6(&self) -> Option<*const ipv6hdr> {
+        self.try_ipv6().ok()
+    }
+
+    /// Returns the packet's `IPv6` header, possibly VLAN tagged, or why
+    /// there is none.
+    #[inline]
+    fn try_ipv6(&self) -> Result<*const ipv6hdr, XdpParseError> {
+        let (offset, proto) = self.network_header()?;
+        if proto != ETH_P_IPV6 as u16 {
+            return Err(XdpParseError::UnsupportedL3);
+        }
+        unsafe {
+            let ip6 = self.data_start().add(offset) as *const ipv6hdr;
+            if ip6.add(1) as *const u8 > self.data_end() {
+                return Err(XdpParseError::Truncated);
+            }
+            Ok(ip6)
+        }
+    }
+
+    /// Returns the packet's `IP` header, whichever the version.
+    #[inline]
+    fn ip_header(&self) -> Option<IpHeader> {
+        match self.ip() {
+            Some(ip) => Some(IpHeader::V4(ip)),
+            None => self.ipv6().map(IpHeader::V6),
+        }
+    }
+
+    /// Returns the packet's `ARP` header if present, possibly VLAN tagged.
+    ///
+    /// Only the fixed part of the header is checked to be in the packet. The
+    /// addresses following it are read by `arp_sender_mac()`,
+    /// `arp_sender_ip()` and `arp_target_ip()`, for Ethernet/IPv4 ARP.
+    ///
+    /// # Example
+    ///
+    /// Drop gratuitous ARP, announcing the sender's own address:
+    ///
+    /// ```
+    /// use redbpf_probes::net::NetworkBuffer;
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn drop_gratuitous_arp(ctx: XdpContext) -> XdpAction {
+    ///     match (ctx.arp_sender_ip(), ctx.arp_target_ip()) {
+    ///         (Some(sender), Some(target)) if sender == target => XdpAction::Drop,
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    fn arp(&self) -> Option<*const arphdr> {
+        let (offset, proto) = self.network_header().ok()?;
+        if proto != ETH_P_ARP as u16 {
+            return None;
+        }
+        unsafe {
+            let arp = self.data_start().add(offset) as *const arphdr;
+            if arp.add(1) as *const u8 > self.data_end() {
+                return None;
+            }
+            Some(arp)
+        }
+    }
+
+    /// Returns the operation of an `ARP` packet.
+    #[inline]
+    fn arp_opcode(&self) -> Option<ArpOp> {
+        let arp = self.arp()?;
+        let op = match ntohs(unsafe { (*arp).ar_op }) as u32 {
+            ARPOP_REQUEST => ArpOp::Request,
+            ARPOP_REPLY => ArpOp::Reply,
+            op => ArpOp::Other(op as u16),
+        };
+        Some(op)
+    }
+
+    /// Returns the sender's MAC address of an Ethernet/IPv4 `ARP` packet.
+    #[inline]
+    fn arp_sender_mac(&self) -> Option<[u8; 6]> {
+        let body = arp_eth_ipv4(self)?;
+        Some(unsafe { (body.add(ARP_SENDER_MAC) as *const [u8; 6]).read_unaligned() })
+    }
+
+    /// Returns the sender's address of an Ethernet/IPv4 `ARP` packet, in
+    /// host byte order.
+    #[inline]
+    fn arp_sender_ip(&self) -> Option<u32> {
+        let body = arp_eth_ipv4(self)?;
+        Some(unsafe { arp_ipv4(body, ARP_SENDER_IP) })
+    }
+
+    /// Returns the target's address of an Ethernet/IPv4 `ARP` packet, in
+    /// host byte order.
+    #[inline]
+    fn arp_target_ip(&self) -> Option<u32> {
+        let body = arp_eth_ipv4(self)?;
+        Some(unsafe { arp_ipv4(body, ARP_TARGET_IP) })
+    }
+
+    /// Returns the packet's transport header if present.
+    ///
+    /// In IPv6 packets, up to `IPV6_EXT_HEADERS_MAX` extension headers are
+    /// skipped to get to it. IP fragments other than the first have none.
+    #[inline]
+    fn transport(&self) -> Option<Transport> {
+        self.try_transport().ok()
+    }
+
+    /// Returns the packet's transport header, or why there is none.
+    #[inline]
+    fn try_transport(&self) -> Result<Transport, XdpParseError> {
+        let (offset, proto) = self.network_header()?;
+        unsafe {
+            let l3 = self.data_start().add(offset);
+            let (protocol, base) = l4_header(proto, l3, self.data_end())?;
+            let (transport, size) = match protocol as u32 {
+                IPPROTO_TCP => (Transport::TCP(base.cast()), mem::size_of::<tcphdr>()),
+                IPPROTO_UDP => (Transport::UDP(base.cast()), mem::size_of::<udphdr>()),
+                IPPROTO_ICMP => (Transport::ICMP(base.cast()), mem::size_of::<icmphdr>()),
+                IPPROTO_ICMPV6 => (Transport::ICMPv6(base.cast()), mem::size_of::<icmp6hdr>()),
+                _ => return Err(XdpParseError::UnsupportedL4),
+            };
+            if base.add(size) > self.data_end() {
+                return Err(XdpParseError::Truncated);
+            }
+            Ok(transport)
+        }
+    }
+
+    /// Returns whether the packet is an IP fragment, the first one
+    /// included, or `None` if it isn't IP or its headers are cut short.
+    #[inline]
+    fn is_fragment(&self) -> Option<bool> {
+        let (offset, proto) = self.network_header().ok()?;
+        unsafe { is_fragment(proto, self.data_start().add(offset), self.data_end()).ok() }
+    }
+
+    /// Returns the packet's data starting after the transport headers.
+    #[inline]
+    fn data(&self) -> Option<Data<Self::Context>> {
+        self.try_data().ok()
+    }
+
+    /// Returns the packet's data starting after the transport headers, or
+    /// why the headers couldn't be parsed.
+    #[inline]
+    fn try_data(&self) -> Result<Data<Self::Context>, XdpParseError> {
+        use Transport::*;
+        unsafe {
+            let base = match self.try_transport()? {
+                TCP(hdr) => {
+                    if hdr.add(1) as *const u8 > self.data_end() {
+                        return Err(XdpParseError::Truncated);
+                    }
+                    let mut base = hdr.add(1) as *const u8;
+                    let data_offset = tcp_doff(hdr);
+                    if data_offset > 5 {
+                        base = base.add(((data_offset - 5) * 4) as usize);
+                    }
+                    base
+                }
+                UDP(hdr) => hdr.add(1) as *const u8,
+                ICMP(hdr) => hdr.add(1) as *const u8,
+                ICMPv6(hdr) => hdr.add(1) as *const u8,
+            };
+            if base > self.data_end() {
+                return Err(XdpParseError::Truncated);
+            }
+            Ok(Data {
+                ctx: self.context(),
+                base,
+            })
+        }
+    }
+
+    /// Returns the packet encapsulated in the packet, if it is a VXLAN
+    /// packet to `VXLAN_PORT`, or a GRE packet carrying Ethernet or IP.
+    ///
+    /// The whole outer stack and the tunnel header are checked against the
+    /// end of the packet. Returns `None` for other packets, and for GRE
+    /// headers with routing information or another version than 0.
+    #[inline]
+    fn tunnel(&self) -> Option<Tunnel<Self::Context>> {
+        let (offset, proto) = self.network_header().ok()?;
+        unsafe {
+            let end = self.data_end();
+            let l3 = self.data_start().add(offset);
+            let (protocol, l4) = l4_header(proto, l3, end).ok()?;
+            let (inner, l3_proto, encap) = match protocol as u32 {
+                IPPROTO_UDP => vxlan(l4, end)?,
+                IPPROTO_GRE => gre(l4, end)?,
+                _ => return None,
+            };
+            let ctx = self.context();
+            Some(Tunnel {
+                ctx,
+                offset: inner as usize - Self::Context::data(ctx) as usize,
+                l3_proto,
+                encap,
+            })
+        }
+    }
+}
+
+/// The encapsulation of a `Tunnel`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encap {
+    /// VXLAN, with the 24 bit network identifier.
+    Vxlan { vni: u32 },
+    /// GRE, with the key, if any.
+    Gre { key: Option<u32> },
+}
+
+/// The packet encapsulated in a VXLAN or GRE packet, returned by
+/// `NetworkBuffer::tunnel()`.
+///
+/// It is parsed like the outer packet, through `NetworkBuffer`, and nested
+/// tunnels are found the same way. The inner packet of GRE tunnels carrying
+/// IP has no Ethernet header, for which `try_eth()` fails with
+/// `UnsupportedL2`.
+///
+/// # Example
+///
+/// Count the packets of the flows inside a VXLAN overlay:
+///
+/// ```
+/// #[map("inner_flows")]
+/// static mut inner_flows: Counter<(u32, u32, u16, u16)> = Counter::with_max_entries(10240);
+///
+/// #[xdp]
+/// pub extern "C" fn overlay_flows(ctx: XdpContext) -> XdpAction {
+///     let inner = match ctx.tunnel() {
+///         Some(inner) => inner,
+///         None => return XdpAction::Pass,
+///     };
+///     if let (Some(ip), Some(transport)) = (inner.ip(), inner.transport()) {
+///         let flow = unsafe { ((*ip).saddr, (*ip).daddr, transport.source(), transport.dest()) };
+///         unsafe { inner_flows.inc(flow) };
+///     }
+///     XdpAction::Pass
+/// }
+/// ```
+pub struct Tunnel<C = xdp_md> {
+    ctx: *mut C,
+    /// Offset of the inner packet from the start of the outer one.
+    offset: usize,
+    /// EtherType of the inner packet if it has no Ethernet header, or 0.
+    l3_proto: u16,
+    encap: Encap,
+}
+
+impl<C: PacketBounds> Tunnel<C> {
+    /// Returns how the packet is encapsulated.
+    #[inline]
+    pub fn encap(&self) -> Encap {
+        self.encap
+    }
+
+    /// Returns the offset of the inner packet from the start of the outer
+    /// one.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<C: PacketBounds> NetworkBuffer for Tunnel<C> {
+    type Context = C;
+
+    #[inline]
+    fn context(&self) -> *mut C {
+        self.ctx
+    }
+
+    #[inline]
+    fn data_start(&self) -> *const u8 {
+        unsafe { C::data(self.ctx).add(self.offset) }
+    }
+
+    #[inline]
+    fn try_eth(&self) -> Result<*const ethhdr, XdpParseError> {
+        if self.l3_proto != 0 {
+            return Err(XdpParseError::UnsupportedL2);
+        }
+        let eth = self.data_start() as *const ethhdr;
+        unsafe {
+            if eth.add(1) as *const u8 > self.data_end() {
+                return Err(XdpParseError::Truncated);
+            }
+        }
+        Ok(eth)
+    }
+
+    #[inline]
+    fn network_header(&self) -> Result<(usize, u16), XdpParseError> {
+        if self.l3_proto != 0 {
+            return Ok((0, self.l3_proto));
+        }
+        l3_offset(self)
+    }
+}
+
+/// Returns the start of the Ethernet frame in the VXLAN packet whose UDP
+/// header is `udp`, along with the encapsulation.
+#[inline]
+unsafe fn vxlan(udp: *const u8, end: *const u8) -> Option<(*const u8, u16, Encap)> {
+    let hdr = udp.add(mem::size_of::<udphdr>());
+    let inner = hdr.add(VXLAN_HDR_LEN);
+    if inner > end || ntohs((*(udp as *const udphdr)).dest) != VXLAN_PORT {
+        return None;
+    }
+    if *hdr & VXLAN_FLAG_VNI == 0 {
+        return None;
+    }
+    let vni = u32::from_be_bytes([0, *hdr.add(4), *hdr.add(5), *hdr.add(6)]);
+
+    Some((inner, 0, Encap::Vxlan { vni }))
+}
+
+/// Returns the start of the packet in the GRE packet whose GRE header is
+/// `hdr`, along with its EtherType if it has no Ethernet header, and the
+/// encapsulation.
+#[inline]
+unsafe fn gre(hdr: *const u8, end: *const u8) -> Option<(*const u8, u16, Encap)> {
+    if hdr.add(4) > end {
+        return None;
+    }
+    let flags = u16::from_be_bytes([*hdr, *hdr.add(1)]);
+    let proto = u16::from_be_bytes([*hdr.add(2), *hdr.add(3)]);
+    if flags & (GRE_ROUTING | GRE_VERSION) != 0 {
+        return None;
+    }
+    let l3_proto = match proto as u32 {
+        ETH_P_TEB => 0,
+        ETH_P_IP | ETH_P_IPV6 => proto,
+        _ => return None,
+    };
+
+    let mut len = 4;
+    if flags & GRE_CSUM != 0 {
+        len += 4;
+    }
+    let key_offset = len;
+    if flags & GRE_KEY != 0 {
+        len += 4;
+    }
+    if flags & GRE_SEQ != 0 {
+        len += 4;
+    }
+    let inner = hdr.add(len);
+    if inner > end {
+        return None;
+    }
+    let key = if flags & GRE_KEY != 0 {
+        let key = hdr.add(key_offset);
+        let key = [*key, *key.add(1), *key.add(2), *key.add(3)];
+        Some(u32::from_be_bytes(key))
+    } else {
+        None
+    };
+
+    Some((inner, l3_proto, Encap::Gre { key }))
+}
+
+/// Returns the addresses following the `ARP` header, if they are those of
+/// Ethernet and IPv4, and the packet holds all of them.
+#[inline]
+fn arp_eth_ipv4<B: NetworkBuffer + ?Sized>(buf: &B) -> Option<*const u8> {
+    let arp = buf.arp()?;
+    unsafe {
+        if ntohs((*arp).ar_hrd) != ARPHRD_ETHER as u16
+            || ntohs((*arp).ar_pro) != ETH_P_IP as u16
+            || (*arp).ar_hln != ETH_ALEN as u8
+            || (*arp).ar_pln != 4
+        {
+            return None;
+        }
+        let body = arp.add(1) as *const u8;
+        if body.add(ARP_ETH_IPV4_LEN) > buf.data_end() {
+            return None;
+        }
+        Some(body)
+    }
+}
+
+/// Reads the IPv4 address at `offset` into the body of an ARP packet, in
+/// host byte order.
+#[inline]
+unsafe fn arp_ipv4(body: *const u8, offset: usize) -> u32 {
+    u32::from_be_bytes((body.add(offset) as *const [u8; 4]).read_unaligned())
+}
+
+/// Returns the offset of the network header, after up to `VLAN_TAGS_MAX`
+/// VLAN tags, along with its EtherType.
+#[inline]
+pub(crate) fn l3_offset<B: NetworkBuffer + ?Sized>(buf: &B) -> Result<(usize, u16), XdpParseError> {
+    let eth = buf.try_eth()?;
+    unsafe {
+        let (proto, l3) = eth_payload(eth, buf.data_end()).ok_or(XdpParseError::Truncated)?;
+        Ok((l3 as usize - eth as usize, proto))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mtu_result() {
+        let fits = |ret, mtu| MtuResult::from_ret(ret, mtu).map(|res| res.fits);
+        assert_eq!(fits(MTU_CHECK_RET_SUCCESS, 1500), Some(true));
+        assert_eq!(fits(MTU_CHECK_RET_FRAG_NEEDED, 1500), Some(false));
+        assert_eq!(fits(MTU_CHECK_RET_SEGS_TOOBIG, 1500), Some(false));
+        // the MTU is reported whether the packet fits or not
+        let res = MtuResult::from_ret(MTU_CHECK_RET_FRAG_NEEDED, 9000).unwrap();
+        assert_eq!(res.mtu, 9000);
+        // -EINVAL, e.g. for `MTU_CHECK_SEGS` with a length delta
+        assert_eq!(MtuResult::from_ret(-22, 0), None);
+    }
+}
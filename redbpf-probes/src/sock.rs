@@ -0,0 +1,114 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Kernel sockets
+
+Networking programs can look up the kernel socket a packet is headed to,
+see `XdpContext::sk_lookup_tcp`. The lookup takes a reference on the socket,
+which the verifier insists is released before the program returns. A
+`SocketRef` releases it when dropped.
+
+# Example
+
+Fend off SYN floods: ACKs completing a handshake that the listener never
+saw the SYN of, because it was answered with a SYN cookie, are only let
+through if the cookie is valid.
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::byteorder::{tcp_flags, TcpFlags};
+use redbpf_probes::xdp::{Transport, XdpAction, XdpContext};
+use redbpf_macros::{program, xdp};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[xdp]
+pub extern "C" fn syncookie_guard(ctx: XdpContext) -> XdpAction {
+    let tcp = match ctx.transport() {
+        Some(Transport::TCP(tcp)) => tcp,
+        _ => return XdpAction::Pass,
+    };
+    let flags = unsafe { tcp_flags(tcp) };
+    let (syn, ack) = (flags.contains(TcpFlags::SYN), flags.contains(TcpFlags::ACK));
+    let sk = match ctx.sk_lookup_tcp() {
+        Some(sk) => sk,
+        None => return XdpAction::Pass,
+    };
+    // packets of established connections find their own socket
+    if !sk.is_listener() {
+        return XdpAction::Pass;
+    }
+
+    if syn && !ack {
+        // answering with a SYN-ACK built from the cookie, through
+        // `XdpAction::Tx`, keeps the SYN out of the listener's queue
+        if let Some((_cookie, _mss)) = ctx.tcp_gen_syncookie(&sk) {
+            return XdpAction::Pass;
+        }
+    } else if ack && !ctx.tcp_check_syncookie(&sk) {
+        return XdpAction::Drop;
+    }
+
+    XdpAction::Pass
+}
+```
+ */
+use crate::bindings::*;
+use crate::helpers::{bpf_sk_release, bpf_skc_lookup_tcp};
+
+/// A reference to a kernel socket, released when dropped.
+pub struct SocketRef {
+    sk: *mut bpf_sock,
+}
+
+impl SocketRef {
+    /// Looks up the TCP socket `tuple` belongs to, in the network namespace
+    /// of the program's context.
+    ///
+    /// Besides full sockets, the lookup returns request and time-wait
+    /// sockets (kernel 5.2 or later).
+    #[inline]
+    pub fn lookup_tcp<C>(ctx: *mut C, tuple: &mut bpf_sock_tuple, tuple_size: usize) -> Option<SocketRef> {
+        let sk = unsafe {
+            bpf_skc_lookup_tcp(
+                ctx as *mut _,
+                tuple,
+                tuple_size as u32,
+                BPF_F_CURRENT_NETNS as u64,
+                0,
+            )
+        };
+        if sk.is_null() {
+            return None;
+        }
+
+        Some(SocketRef { sk })
+    }
+
+    /// Returns the raw `bpf_sock`.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut bpf_sock {
+        self.sk
+    }
+
+    /// Returns `true` if the socket is listening for connections.
+    #[inline]
+    pub fn is_listener(&self) -> bool {
+        unsafe { (*self.sk).state == BPF_TCP_LISTEN }
+    }
+}
+
+impl Drop for SocketRef {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe {
+            bpf_sk_release(self.sk);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Cgroup socket address programs
+
+Programs defined with the `cgroup_sockaddr` attribute macro run when the
+sockets of a cgroup bind, connect, or send UDP datagrams to an address, see
+`redbpf::SockAddrHook`. They can rewrite the address before the kernel uses
+it, and return 1 to let the call go ahead, or 0 to fail it with `EPERM`.
+
+# Example
+
+Send the DNS queries of a cgroup to a local resolver, whatever server they
+were meant for. Clients that don't connect their UDP sockets are caught by
+the `sendmsg4` hook, so the same program is needed there too:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::sock_addr::SockAddrContext;
+use redbpf_macros::{cgroup_sockaddr, program};
+
+program!(0xFFFFFFFE, "GPL");
+
+const RESOLVER: u32 = 0x7f00_0035; // 127.0.0.53
+
+#[cgroup_sockaddr(connect4)]
+pub extern "C" fn redirect_dns(ctx: SockAddrContext) -> i32 {
+    if ctx.user_port() == 53 {
+        ctx.set_user_ip4(RESOLVER);
+    }
+    1
+}
+
+#[cgroup_sockaddr(sendmsg4)]
+pub extern "C" fn redirect_dns_datagrams(ctx: SockAddrContext) -> i32 {
+    if ctx.user_port() == 53 {
+        ctx.set_user_ip4(RESOLVER);
+    }
+    1
+}
+```
+ */
+use crate::bindings::*;
+use crate::byteorder::{htonl, htons, ntohl, ntohs};
+
+/// Context object provided to `cgroup_sock_addr` programs.
+///
+/// The addresses and ports are in host byte order. Only the address and
+/// the port can be written, the kernel rejects programs writing anything
+/// else.
+pub struct SockAddrContext {
+    pub ctx: *mut bpf_sock_addr,
+}
+
+impl SockAddrContext {
+    /// Returns the raw `bpf_sock_addr` context.
+    #[inline]
+    pub fn inner(&self) -> *mut bpf_sock_addr {
+        self.ctx
+    }
+
+    /// Returns the address family of the socket, `AF_INET` or `AF_INET6`.
+    #[inline]
+    pub fn family(&self) -> u32 {
+        unsafe { (*self.ctx).family }
+    }
+
+    /// Returns the protocol of the socket, such as `IPPROTO_TCP`.
+    #[inline]
+    pub fn protocol(&self) -> u32 {
+        unsafe { (*self.ctx).protocol }
+    }
+
+    /// Returns the IPv4 address passed to the call.
+    #[inline]
+    pub fn user_ip4(&self) -> u32 {
+        unsafe { ntohl((*self.ctx).user_ip4) }
+    }
+
+    /// Replaces the IPv4 address passed to the call.
+    #[inline]
+    pub fn set_user_ip4(&self, ip: u32) {
+        unsafe { (*self.ctx).user_ip4 = htonl(ip) }
+    }
+
+    /// Returns the IPv6 address passed to the call, as 4 words.
+    #[inline]
+    pub fn user_ip6(&self) -> [u32; 4] {
+        // the kernel only allows word-sized reads of the address
+        let ip6 = unsafe { &(*self.ctx).user_ip6 };
+        [ntohl(ip6[0]), ntohl(ip6[1]), ntohl(ip6[2]), ntohl(ip6[3])]
+    }
+
+    /// Replaces the IPv6 address passed to the call.
+    #[inline]
+    pub fn set_user_ip6(&self, ip: [u32; 4]) {
+        let ip6 = unsafe { &mut (*self.ctx).user_ip6 };
+        for (word, &ip) in ip6.iter_mut().zip(ip.iter()) {
+            *word = htonl(ip);
+        }
+    }
+
+    /// Returns the port passed to the call.
+    #[inline]
+    pub fn user_port(&self) -> u16 {
+        // the port is stored in network byte order in the low bytes
+        unsafe { ntohs((*self.ctx).user_port as u16) }
+    }
+
+    /// Replaces the port passed to the call.
+    #[inline]
+    pub fn set_user_port(&self, port: u16) {
+        unsafe { (*self.ctx).user_port = htons(port) as u32 }
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Socket filters
+
+Socket filters are attached to sockets and decide how much of each packet
+received on the socket is kept. Returning `0` drops the packet, while a
+larger value truncates it to that many bytes.
+
+Packet data can't be accessed directly from socket filters, so it is copied
+out of the buffer through `SocketFilterContext::load`.
+
+# Example
+
+Only keep IPv4 packets:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::bindings::*;
+use redbpf_probes::socket_filter::*;
+use redbpf_macros::{program, socket_filter};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[socket_filter]
+pub extern "C" fn only_ipv4(skb: SocketFilterContext) -> i32 {
+    if skb.protocol() == ETH_P_IP as u16 {
+        return skb.len() as i32;
+    }
+
+    0
+}
+```
+ */
+use core::mem::{self, MaybeUninit};
+use cty::*;
+
+use crate::bindings::*;
+use crate::byteorder::ntohs;
+use crate::helpers::bpf_skb_load_bytes;
+
+/// Context object provided to socket filters.
+///
+/// The `socket_filter` attribute macro wraps the raw `__sk_buff` argument in
+/// a `SocketFilterContext`. Unlike the `tc::SkBuffContext` of TC programs, it
+/// gives no direct access to the packet.
+pub struct SocketFilterContext {
+    pub skb: *mut __sk_buff,
+}
+
+impl SocketFilterContext {
+    /// Returns the raw `__sk_buff` context.
+    #[inline]
+    pub fn inner(&self) -> *mut __sk_buff {
+        self.skb
+    }
+
+    /// Returns the packet length.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        unsafe { (*self.skb).len }
+    }
+
+    /// Returns the link layer protocol of the packet, in host byte order.
+    #[inline]
+    pub fn protocol(&self) -> u16 {
+        unsafe { ntohs((*self.skb).protocol as u16) }
+    }
+
+    /// Returns the index of the interface the packet was received on.
+    #[inline]
+    pub fn ifindex(&self) -> u32 {
+        unsafe { (*self.skb).ifindex }
+    }
+
+    /// Copies a `T` out of the packet, starting `offset` bytes into it.
+    ///
+    /// Returns `None` if the packet is too short.
+    #[inline]
+    pub fn load<T>(&self, offset: usize) -> Option<T> {
+        unsafe {
+            let mut data = MaybeUninit::<T>::uninit();
+            let ret = bpf_skb_load_bytes(
+                self.skb as *const c_void,
+                offset as u32,
+                data.as_mut_ptr() as *mut c_void,
+                mem::size_of::<T>() as u32,
+            );
+            if ret < 0 {
+                return None;
+            }
+
+            Some(data.assume_init())
+        }
+    }
+}
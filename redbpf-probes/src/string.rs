@@ -0,0 +1,207 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Bounded strings
+
+Strings read out of kernel or user memory are copied into a fixed-size
+buffer on the BPF stack, which keeps track of how much of it the string
+takes up. Being a plain `#[repr(C)]` struct, a `BpfStr` can be sent to
+userspace as part of an event as is.
+
+# Example
+
+Report the files opened through `do_sys_open`:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::helpers::bpf_get_current_pid_tgid;
+use redbpf_probes::kprobe::*;
+use redbpf_probes::maps::PerfMap;
+use redbpf_probes::string::BpfStr;
+use redbpf_macros::{kprobe, map, program};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[repr(C)]
+pub struct OpenEvent {
+    pub pid: u32,
+    pub filename: BpfStr<128>,
+}
+
+#[map("open_events")]
+static mut open_events: PerfMap<OpenEvent> = PerfMap::with_max_entries(1024);
+
+#[kprobe("do_sys_open")]
+pub extern "C" fn enter_open(ctx: KProbeContext) -> i32 {
+    let mut event = OpenEvent {
+        pid: (bpf_get_current_pid_tgid() >> 32) as u32,
+        filename: BpfStr::new(),
+    };
+    let filename = ctx.regs().parm2() as *const u8;
+    if event.filename.read_user(filename).is_some() {
+        unsafe { open_events.insert(ctx.inner(), event) };
+    }
+
+    0
+}
+```
+ */
+use cty::*;
+
+use crate::helpers::{bpf_probe_read_kernel_str, bpf_probe_read_user_str};
+
+/// A NUL terminated string of at most `N - 1` bytes.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BpfStr<const N: usize> {
+    len: u32,
+    buf: [u8; N],
+}
+
+impl<const N: usize> BpfStr<N> {
+    #[inline]
+    pub const fn new() -> BpfStr<N> {
+        BpfStr { len: 0, buf: [0; N] }
+    }
+
+    /// Copies the NUL terminated string at `src` out of kernel memory,
+    /// through `bpf_probe_read_kernel_str` (kernel 5.5 or later).
+    ///
+    /// Returns the length of the string, which is cut short to `N - 1`
+    /// bytes if it doesn't fit, or `None` if `src` can't be read.
+    #[inline]
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn read_kernel(&mut self, src: *const u8) -> Option<usize> {
+        let ret = unsafe {
+            bpf_probe_read_kernel_str(
+                self.buf.as_mut_ptr() as *mut c_void,
+                N as u32,
+                src as *const c_void,
+            )
+        };
+        self.set_len(ret as i64)
+    }
+
+    /// Copies the NUL terminated string at `src` out of user memory,
+    /// through `bpf_probe_read_user_str` (kernel 5.5 or later).
+    ///
+    /// Returns the length of the string, which is cut short to `N - 1`
+    /// bytes if it doesn't fit, or `None` if `src` can't be read.
+    #[inline]
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn read_user(&mut self, src: *const u8) -> Option<usize> {
+        let ret = unsafe {
+            bpf_probe_read_user_str(
+                self.buf.as_mut_ptr() as *mut c_void,
+                N as u32,
+                src as *const c_void,
+            )
+        };
+        self.set_len(ret as i64)
+    }
+
+    /// Records the result of a `bpf_probe_read_*_str` call, which is the
+    /// number of bytes copied including the NUL terminator, or a negative
+    /// error.
+    #[inline]
+    fn set_len(&mut self, ret: i64) -> Option<usize> {
+        if ret <= 0 || N == 0 {
+            self.len = 0;
+            if N > 0 {
+                self.buf[0] = 0;
+            }
+            return None;
+        }
+
+        let len = (ret as usize).min(N) - 1;
+        self.len = len as u32;
+        // the helpers terminate the string, but don't trust the buffer
+        self.buf[len] = 0;
+        Some(len)
+    }
+
+    /// Returns the length of the string, without the NUL terminator.
+    #[inline]
+    pub fn len(&self) -> usize {
+        (self.len as usize).min(N.saturating_sub(1))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the string read didn't fit and was cut short.
+    ///
+    /// A string of exactly `N - 1` bytes is indistinguishable from a longer
+    /// one, and counts as truncated.
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        N > 0 && self.len() == N - 1
+    }
+
+    /// Returns the bytes of the string, without the NUL terminator.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len()]
+    }
+
+    /// Returns the bytes of the buffer up to the first NUL, regardless of
+    /// the recorded length.
+    ///
+    /// For strings read through `read_kernel` or `read_user`, this is the
+    /// same as `as_bytes`.
+    #[inline]
+    pub fn to_bytes_until_nul(&self) -> &[u8] {
+        let len = self.buf.iter().position(|&c| c == 0).unwrap_or(N);
+        &self.buf[..len]
+    }
+}
+
+impl<const N: usize> Default for BpfStr<N> {
+    #[inline]
+    fn default() -> BpfStr<N> {
+        BpfStr::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filled<const N: usize>(bytes: &[u8], ret: i64) -> BpfStr<N> {
+        let mut s = BpfStr::<N>::new();
+        s.buf[..bytes.len()].copy_from_slice(bytes);
+        s.set_len(ret);
+        s
+    }
+
+    #[test]
+    fn test_len() {
+        let s: BpfStr<8> = filled(b"abc\0", 4);
+        assert_eq!(s.as_bytes(), b"abc");
+        assert_eq!(s.to_bytes_until_nul(), b"abc");
+        assert!(!s.is_truncated());
+
+        // truncated reads fill the buffer and NUL terminate it
+        let s: BpfStr<4> = filled(b"abc\0", 4);
+        assert_eq!(s.as_bytes(), b"abc");
+        assert!(s.is_truncated());
+
+        // a bogus length can't reach past the terminator that is put in
+        let s: BpfStr<4> = filled(b"abcd", 100);
+        assert_eq!(s.as_bytes(), b"abc");
+        assert_eq!(s.to_bytes_until_nul(), b"abc");
+
+        let mut s: BpfStr<8> = filled(b"abc\0", 4);
+        assert_eq!(s.set_len(-14), None);
+        assert!(s.is_empty());
+        assert_eq!(s.to_bytes_until_nul(), b"");
+    }
+}
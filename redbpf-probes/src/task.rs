@@ -0,0 +1,180 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+The current task
+
+`bpf_get_current_task_btf` (kernel 5.11 or later) returns a pointer to the
+`task_struct` of the current task that the verifier knows the type of.
+Tracing programs such as fentry probes read its fields directly, rather
+than through `bpf_probe_read`, and follow the pointers it holds to other
+kernel structs the same way.
+
+The layout of `task_struct` changes with every kernel and configuration,
+so the fields are read at offsets resolved against the running kernel's
+BTF when the program is loaded, with `core_offset!`. Programs reading
+fields `Task` doesn't provide can do the same.
+
+# Example
+
+Count the processes started by every parent, by reading the parent pid of
+the tasks calling `execve`:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::fentry::*;
+use redbpf_probes::maps::HashMap;
+use redbpf_probes::task::Task;
+use redbpf_macros::{fentry, map, program};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[map("execs")]
+static mut EXECS: HashMap<i32, u64> = HashMap::with_max_entries(10240);
+
+#[fentry("do_execveat_common")]
+pub extern "C" fn count_execs(_ctx: FEntryContext) -> i32 {
+    let ppid = match Task::current().real_parent() {
+        Some(parent) => parent.tgid(),
+        None => return 0,
+    };
+    unsafe {
+        let count = EXECS.get(ppid).copied().unwrap_or(0);
+        EXECS.set(ppid, count + 1);
+    }
+
+    0
+}
+```
+ */
+use core::ptr;
+use cty::*;
+
+pub use crate::bindings::task_struct;
+use crate::core_offset;
+use crate::helpers::bpf_get_current_task_btf;
+
+/// Returns the BTF pointer to the `task_struct` of the current task.
+///
+/// Requires kernel 5.11 or later.
+#[inline]
+pub fn current_task_btf() -> *mut task_struct {
+    unsafe { bpf_get_current_task_btf() }
+}
+
+/// A task, read through its BTF pointer.
+#[derive(Clone, Copy)]
+pub struct Task {
+    task: *const task_struct,
+}
+
+impl Task {
+    /// Returns the current task.
+    #[inline]
+    pub fn current() -> Task {
+        Task {
+            task: current_task_btf(),
+        }
+    }
+
+    /// Wraps a BTF pointer to a `task_struct`, e.g. an argument of an
+    /// fentry probe.
+    ///
+    /// # Safety
+    ///
+    /// `task` must be a pointer the verifier knows is a `task_struct`.
+    #[inline]
+    pub unsafe fn from_ptr(task: *const task_struct) -> Task {
+        Task { task }
+    }
+
+    /// Returns the pointer to the `task_struct`.
+    #[inline]
+    pub fn as_ptr(&self) -> *const task_struct {
+        self.task
+    }
+
+    /// Returns the id of the thread, which the kernel calls the pid.
+    #[inline]
+    pub fn pid(&self) -> i32 {
+        self.read(core_offset!(task_struct, pid))
+    }
+
+    /// Returns the id of the thread group, which userspace calls the pid.
+    #[inline]
+    pub fn tgid(&self) -> i32 {
+        self.read(core_offset!(task_struct, tgid))
+    }
+
+    /// Returns the task that created this one, or `None` for the tasks
+    /// without a parent, such as `init`.
+    #[inline]
+    pub fn real_parent(&self) -> Option<Task> {
+        let parent: *const task_struct = self.read(core_offset!(task_struct, real_parent));
+        if parent.is_null() {
+            None
+        } else {
+            Some(Task { task: parent })
+        }
+    }
+
+    /// Returns the inode number of the pid namespace of the children of the
+    /// task, as found in `/proc/<pid>/ns/pid_for_children`, or `None` if the
+    /// task is exiting.
+    #[inline]
+    pub fn pid_ns_inum(&self) -> Option<u32> {
+        let nsproxy = self.nsproxy()?;
+        let offset = core_offset!(nsproxy, pid_ns_for_children);
+        let pid_ns: *const c_void = unsafe { read_field(nsproxy, offset) };
+        if pid_ns.is_null() {
+            return None;
+        }
+        let offset = core_offset!(pid_namespace, ns.inum);
+        Some(unsafe { read_field(pid_ns, offset) })
+    }
+
+    /// Returns the inode number of the network namespace of the task, as
+    /// found in `/proc/<pid>/ns/net`, or `None` if the task is exiting.
+    #[inline]
+    pub fn net_ns_inum(&self) -> Option<u32> {
+        let nsproxy = self.nsproxy()?;
+        let offset = core_offset!(nsproxy, net_ns);
+        let net_ns: *const c_void = unsafe { read_field(nsproxy, offset) };
+        if net_ns.is_null() {
+            return None;
+        }
+        let offset = core_offset!(net, ns.inum);
+        Some(unsafe { read_field(net_ns, offset) })
+    }
+
+    /// Returns the pointer to the `nsproxy` of the task, which is null once
+    /// the task exits.
+    #[inline]
+    fn nsproxy(&self) -> Option<*const c_void> {
+        let nsproxy: *const c_void = self.read(core_offset!(task_struct, nsproxy));
+        if nsproxy.is_null() {
+            None
+        } else {
+            Some(nsproxy)
+        }
+    }
+
+    #[inline(always)]
+    fn read<T>(&self, offset: usize) -> T {
+        unsafe { read_field(self.task as *const c_void, offset) }
+    }
+}
+
+/// Reads the field at `offset` of the struct at `base`.
+///
+/// The verifier checks the access against the type of `base`, which it has
+/// to know: reads of kernel memory through other pointers are rejected.
+#[inline(always)]
+unsafe fn read_field<T>(base: *const c_void, offset: usize) -> T {
+    ptr::read((base as *const u8).add(offset) as *const T)
+}
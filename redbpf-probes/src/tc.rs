@@ -0,0 +1,376 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Traffic control
+
+TC programs (`BPF_PROG_TYPE_SCHED_CLS`) are attached to the ingress and
+egress hooks of network interfaces, and are passed the packet's `__sk_buff`.
+Programs defined with the `tc_action` attribute macro get a `SkBuffContext`
+and return a `TcAction`.
+
+Packets are parsed the same way as in XDP programs, through the
+`NetworkBuffer` trait both contexts implement, so parsing code can be shared
+between the two: XDP only sees ingress traffic, and not every driver
+supports it, while TC programs run on egress as well, on any interface. See
+the `net` module.
+
+# Example
+
+Drop UDP traffic:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::tc::{SkBuffContext, TcAction};
+use redbpf_probes::xdp::Transport;
+use redbpf_macros::{program, tc_action};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[tc_action]
+pub extern "C" fn drop_udp(skb: SkBuffContext) -> TcAction {
+    match skb.transport() {
+        Some(Transport::UDP(_)) => TcAction::Shot,
+        _ => TcAction::Ok,
+    }
+}
+```
+
+Read the flow id an XDP program stored in the packet's metadata, see
+`XdpContext::meta_mut`:
+
+```
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FlowMeta {
+    pub flow_id: u32,
+}
+
+pub fn classify(skb: SkBuffContext) -> i32 {
+    match skb.meta::<FlowMeta>() {
+        Some(meta) => meta.flow_id as i32,
+        None => TC_ACT_OK,
+    }
+}
+```
+ */
+use core::convert::TryFrom;
+
+use crate::bindings::*;
+use crate::helpers::{bpf_clone_redirect, bpf_redirect_peer};
+use crate::net::{self, MtuResult, NetworkBuffer};
+use crate::xdp::{Data, MetadataLayout, Transport};
+
+/// The return type of TC programs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(i32)]
+pub enum TcAction {
+    /// Use the default action configured for the hook.
+    Unspec = TC_ACT_UNSPEC as i32,
+    /// Let the packet through.
+    Ok = TC_ACT_OK as i32,
+    /// Run the classification again from the start.
+    Reclassify = TC_ACT_RECLASSIFY as i32,
+    /// Drop the packet.
+    Shot = TC_ACT_SHOT as i32,
+    /// Go on with the next action or classifier.
+    Pipe = TC_ACT_PIPE as i32,
+    /// Consume the packet, which the program took over, e.g. by redirecting
+    /// a clone of it.
+    Stolen = TC_ACT_STOLEN as i32,
+    /// Redirect the packet as set up by `bpf_redirect` and related helpers,
+    /// see `SkBuffContext::redirect_peer`.
+    Redirect = TC_ACT_REDIRECT as i32,
+}
+
+const TC_ACTIONS: [TcAction; 7] = [
+    TcAction::Unspec,
+    TcAction::Ok,
+    TcAction::Reclassify,
+    TcAction::Shot,
+    TcAction::Pipe,
+    TcAction::Stolen,
+    TcAction::Redirect,
+];
+
+impl TcAction {
+    /// Returns the value of the action the kernel expects.
+    #[inline]
+    pub fn as_i32(self) -> i32 {
+        self as i32
+    }
+}
+
+impl TryFrom<i32> for TcAction {
+    type Error = i32;
+
+    /// Converts the return value of a program, failing with the value if it
+    /// isn't one of the actions.
+    #[inline]
+    fn try_from(value: i32) -> Result<TcAction, i32> {
+        TC_ACTIONS
+            .iter()
+            .copied()
+            .find(|action| action.as_i32() == value)
+            .ok_or(value)
+    }
+}
+
+/// Context object provided to TC programs.
+pub struct SkBuffContext {
+    pub skb: *mut __sk_buff,
+}
+
+impl SkBuffContext {
+    /// Returns the raw `__sk_buff` context.
+    #[inline]
+    pub fn inner(&self) -> *mut __sk_buff {
+        self.skb
+    }
+
+    /// Returns the packet's `Ethernet` header if present.
+    #[inline]
+    pub fn eth(&self) -> Option<*const ethhdr> {
+        NetworkBuffer::eth(self)
+    }
+
+    /// Returns the packet's `IP` header if present, possibly VLAN tagged.
+    #[inline]
+    pub fn ip(&self) -> Option<*const iphdr> {
+        NetworkBuffer::ip(self)
+    }
+
+    /// Returns the packet's `IPv6` header if present, possibly VLAN tagged.
+    #[inline]
+    pub fn ipv6(&self) -> Option<*const ipv6hdr> {
+        NetworkBuffer::ipv6(self)
+    }
+
+    /// Returns the packet's transport header if present.
+    #[inline]
+    pub fn transport(&self) -> Option<Transport> {
+        NetworkBuffer::transport(self)
+    }
+
+    /// Returns the packet's data starting after the transport headers.
+    ///
+    /// Only the linear part of the `skb` can be accessed directly, which
+    /// may end before the packet does.
+    #[inline]
+    pub fn data(&self) -> Option<Data<__sk_buff>> {
+        NetworkBuffer::data(self)
+    }
+
+    /// Sends a copy of the packet out of the interface `ifindex`, while the
+    /// original continues on its way. Returns `false` if the packet couldn't
+    /// be cloned.
+    ///
+    /// The copy enters the egress path of the target interface, or its
+    /// ingress path if `ingress` is set, as if it had been received there.
+    /// Cloning is comparatively expensive, so filter packets before
+    /// mirroring them where possible.
+    ///
+    /// XDP has no equivalent, as XDP programs can't clone packets: they can
+    /// only redirect the packet itself. Mirroring traffic that XDP programs
+    /// see therefore has to be done by a TC program on the same interface.
+    ///
+    /// # Example
+    ///
+    /// Port mirroring: attached to both the ingress and egress hooks of an
+    /// interface, copies all of its traffic to a capture interface.
+    ///
+    /// ```
+    /// const CAPTURE_IFINDEX: u32 = 4;
+    ///
+    /// pub fn mirror_port(skb: SkBuffContext) -> i32 {
+    ///     skb.mirror_to(CAPTURE_IFINDEX, false);
+    ///
+    ///     TC_ACT_OK
+    /// }
+    /// ```
+    #[inline]
+    pub fn mirror_to(&self, ifindex: u32, ingress: bool) -> bool {
+        let flags = if ingress { BPF_F_INGRESS as u64 } else { 0 };
+        unsafe { bpf_clone_redirect(self.skb, ifindex, flags) == 0 }
+    }
+
+    /// Redirects the packet to the ingress of the peer of the device
+    /// `ifindex`, in the peer's network namespace, and returns the action
+    /// the program must return for the redirect to happen: `TC_ACT_REDIRECT`,
+    /// or `TC_ACT_SHOT` if `flags` isn't 0.
+    ///
+    /// Only works for programs on the ingress hook, and for veth and netkit
+    /// devices, whose peer lives in another namespace (kernel 5.10 or later,
+    /// 6.7 for netkit). On other devices the packet is dropped once the
+    /// program returns. Unlike redirecting to the host side of the veth,
+    /// the packet skips the host stack and the per-CPU backlog queue, which
+    /// makes container ingress considerably faster.
+    ///
+    /// # Example
+    ///
+    /// Container ingress: attached to the ingress hook of the host's
+    /// physical interface, hands the traffic of each container straight to
+    /// the container side of its veth pair.
+    ///
+    /// ```
+    /// #[map("container_veths")]
+    /// static mut container_veths: HashMap<u32, u32> = HashMap::with_max_entries(1024);
+    ///
+    /// pub fn container_ingress(skb: SkBuffContext) -> i32 {
+    ///     let (data, data_end) = unsafe { ((*skb.skb).data, (*skb.skb).data_end) };
+    ///     // the IPv4 destination address, after the Ethernet header
+    ///     if data + 34 > data_end {
+    ///         return TC_ACT_OK as i32;
+    ///     }
+    ///     let daddr = unsafe { *((data as usize + 30) as *const u32) };
+    ///     // the host side of the container's veth pair
+    ///     match unsafe { container_veths.get(daddr) } {
+    ///         Some(&ifindex) => skb.redirect_peer(ifindex, 0),
+    ///         None => TC_ACT_OK as i32,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn redirect_peer(&self, ifindex: u32, flags: u64) -> i32 {
+        unsafe { bpf_redirect_peer(ifindex, flags) }
+    }
+
+    /// Checks whether the packet, grown by `len_delta` bytes or shrunk if
+    /// negative, fits the MTU of the interface `ifindex`, or of the current
+    /// interface if 0 (kernel 5.12 or later).
+    ///
+    /// With `net::MTU_CHECK_SEGS` in `flags`, GSO packets are checked by the
+    /// size of the segments they are split into on transmit, rather than by
+    /// their size, and `len_delta` must be 0. Returns `None` if the check
+    /// fails, e.g. for interfaces that don't exist.
+    #[inline]
+    pub fn check_mtu(&self, ifindex: u32, len_delta: i32, flags: u64) -> Option<MtuResult> {
+        unsafe { net::check_mtu(self.skb, ifindex, len_delta, flags) }
+    }
+
+    /// Returns the `M` stored in the metadata area in front of the packet by
+    /// an XDP program, see `XdpContext::meta_mut`.
+    ///
+    /// `M` is subject to the same compile-time size check as on the XDP
+    /// side. Returns `None` if the metadata area is shorter than `M`, e.g.
+    /// because no XDP program stored one.
+    #[inline]
+    pub fn meta<M: Copy>(&self) -> Option<&M> {
+        let size = MetadataLayout::<M>::size();
+        unsafe {
+            let meta = (*self.skb).data_meta as *const u8;
+            let data = (*self.skb).data as *const u8;
+            // the verifier only lets the area be read after this check
+            if meta.add(size) > data {
+                return None;
+            }
+            Some(&*(meta as *const M))
+        }
+    }
+}
+
+impl NetworkBuffer for SkBuffContext {
+    type Context = __sk_buff;
+
+    #[inline]
+    fn context(&self) -> *mut __sk_buff {
+        self.skb
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tc_action() {
+        for &action in TC_ACTIONS.iter() {
+            assert_eq!(TcAction::try_from(action.as_i32()), Ok(action));
+        }
+        assert_eq!(TcAction::Unspec.as_i32(), -1);
+        assert_eq!(TcAction::Ok.as_i32(), 0);
+        assert_eq!(TcAction::Shot.as_i32(), 2);
+        assert_eq!(TcAction::Redirect.as_i32(), 7);
+        // TC_ACT_QUEUED, not a verdict programs return
+        assert_eq!(TcAction::try_from(5), Err(5));
+        assert_eq!(TcAction::try_from(-2), Err(-2));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_parse_like_xdp() {
+        use crate::xdp::TestPacket;
+        use core::mem;
+
+        // Ethernet, IPv4, UDP to port 53, payload
+        let mut udp = vec![0u8; 12];
+        udp.extend_from_slice(&(ETH_P_IP as u16).to_be_bytes());
+        udp.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_UDP as u8]);
+        udp.extend_from_slice(&[0; 10]);
+        udp.extend_from_slice(&[0x30, 0x39, 0, 53, 0, 0, 0, 0]);
+        udp.extend_from_slice(b"query");
+
+        for len in 0..=udp.len() {
+            let mut packet = TestPacket::new(&udp[..len]).unwrap();
+            let xdp = packet.context();
+            let mut skb: __sk_buff = unsafe { mem::zeroed() };
+            unsafe {
+                skb.data = (*xdp.inner()).data;
+                skb.data_end = (*xdp.inner()).data_end;
+            }
+            let skb = SkBuffContext { skb: &mut skb };
+            assert_eq!(skb.buffer_len(), len);
+            assert_eq!(skb.eth(), xdp.eth());
+            assert_eq!(skb.ip(), xdp.ip());
+            assert_eq!(skb.ipv6(), None);
+            assert_eq!(
+                skb.transport().map(|t| t.dest()),
+                xdp.transport().map(|t| t.dest())
+            );
+            match (skb.data(), xdp.data()) {
+                (Some(skb_data), Some(xdp_data)) => {
+                    assert_eq!(skb_data.offset(), 42);
+                    assert_eq!(skb_data.slice(len - 42), xdp_data.slice(len - 42));
+                    assert!(skb_data.slice(len - 41).is_none());
+                }
+                (skb_data, xdp_data) => {
+                    assert!(skb_data.is_none() && xdp_data.is_none());
+                    assert!(len < 42);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_arp() {
+        use crate::xdp::{ArpOp, TestPacket};
+        use core::mem;
+
+        // Ethernet, ARP request from 10.0.0.1 for 10.0.0.2
+        let mut arp = vec![0u8; 12];
+        arp.extend_from_slice(&(ETH_P_ARP as u16).to_be_bytes());
+        arp.extend_from_slice(&[0, 1, 8, 0, 6, 4, 0, ARPOP_REQUEST as u8]);
+        arp.extend_from_slice(&[1, 2, 3, 4, 5, 6, 10, 0, 0, 1]);
+        arp.extend_from_slice(&[0, 0, 0, 0, 0, 0, 10, 0, 0, 2]);
+
+        let mut packet = TestPacket::new(&arp).unwrap();
+        let xdp = packet.context();
+        let mut skb: __sk_buff = unsafe { mem::zeroed() };
+        unsafe {
+            skb.data = (*xdp.inner()).data;
+            skb.data_end = (*xdp.inner()).data_end;
+        }
+        let skb = SkBuffContext { skb: &mut skb };
+        assert_eq!(skb.arp(), xdp.arp());
+        assert_eq!(skb.arp_opcode(), Some(ArpOp::Request));
+        assert_eq!(skb.arp_sender_mac(), Some([1, 2, 3, 4, 5, 6]));
+        assert_eq!(skb.arp_sender_ip(), Some(0x0a00_0001));
+        assert_eq!(skb.arp_target_ip(), Some(0x0a00_0002));
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Tracepoints
+
+Tracepoints are static hooks placed in the kernel source. Programs attached
+to a tracepoint receive the tracepoint's record, whose layout is described
+in `/sys/kernel/debug/tracing/events/<category>/<name>/format`.
+
+# Example
+
+Read the `fd` argument of `write()`, at offset 16 of the
+`syscalls/sys_enter_write` record:
+
+```
+#![no_std]
+#![no_main]
+use redbpf_probes::tracepoint::*;
+use redbpf_macros::{program, tracepoint};
+
+program!(0xFFFFFFFE, "GPL");
+
+#[tracepoint("sys_enter_write")]
+pub extern "C" fn enter_write(ctx: TracePointContext) -> i32 {
+    let fd: u64 = unsafe { ctx.read_field(16) };
+
+    // do something here
+    // ...
+
+    0
+}
+```
+
+Programs attached to the generic `raw_syscalls/sys_enter` tracepoint see
+every syscall, and read its number and arguments through
+`RawSyscallContext`. Counting the calls of each syscall, which
+`redbpf::syscall_name()` names in userspace:
+
+```
+use redbpf_probes::maps::HashMap;
+
+#[map("syscall_counts")]
+static mut SYSCALL_COUNTS: HashMap<i64, u64> = HashMap::with_max_entries(512);
+
+#[tracepoint("sys_enter")]
+pub extern "C" fn count_syscalls(ctx: TracePointContext) -> i32 {
+    let nr = RawSyscallContext::from(ctx).syscall_nr();
+    unsafe {
+        let count = SYSCALL_COUNTS.get(nr).copied().unwrap_or(0);
+        SYSCALL_COUNTS.set(nr, count + 1);
+    }
+
+    0
+}
+```
+ */
+use core::ptr;
+use cty::*;
+
+/// Context object provided to tracepoint programs.
+///
+/// The `tracepoint` attribute macro wraps the raw record pointer in a
+/// `TracePointContext`.
+pub struct TracePointContext {
+    pub ctx: *mut c_void,
+}
+
+impl TracePointContext {
+    /// Returns the raw pointer to the tracepoint record.
+    #[inline]
+    pub fn inner(&self) -> *mut c_void {
+        self.ctx
+    }
+
+    /// Reads the field of the record at `offset`.
+    ///
+    /// # Safety
+    ///
+    /// `offset` and `T` must match a field in the tracepoint's `format`.
+    #[inline]
+    pub unsafe fn read_field<T>(&self, offset: usize) -> T {
+        ptr::read_unaligned((self.ctx as *const u8).add(offset) as *const T)
+    }
+}
+
+/// Offset of the syscall number in the `raw_syscalls/sys_enter` record,
+/// after the common fields.
+const RAW_SYSCALL_ID_OFFSET: usize = 8;
+/// Offset of the syscall arguments in the `raw_syscalls/sys_enter` record.
+const RAW_SYSCALL_ARGS_OFFSET: usize = 16;
+/// Number of arguments in the `raw_syscalls/sys_enter` record.
+const RAW_SYSCALL_ARGS: usize = 6;
+
+/// Context of programs attached to the `raw_syscalls/sys_enter` tracepoint.
+///
+/// The record holds the number of the syscall, which depends on the
+/// architecture, and its six arguments, whether the syscall takes them or
+/// not.
+pub struct RawSyscallContext {
+    ctx: TracePointContext,
+}
+
+impl From<TracePointContext> for RawSyscallContext {
+    fn from(ctx: TracePointContext) -> RawSyscallContext {
+        RawSyscallContext { ctx }
+    }
+}
+
+impl RawSyscallContext {
+    /// Returns the raw pointer to the tracepoint record.
+    #[inline]
+    pub fn inner(&self) -> *mut c_void {
+        self.ctx.inner()
+    }
+
+    /// Returns the number of the syscall.
+    #[inline]
+    pub fn syscall_nr(&self) -> i64 {
+        unsafe { self.ctx.read_field(RAW_SYSCALL_ID_OFFSET) }
+    }
+
+    /// Returns the `n`th argument of the syscall, or 0 if `n` is 6 or more.
+    #[inline]
+    pub fn arg(&self, n: usize) -> u64 {
+        if n >= RAW_SYSCALL_ARGS {
+            return 0;
+        }
+        unsafe { self.ctx.read_field(RAW_SYSCALL_ARGS_OFFSET + n * 8) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_raw_syscall() {
+        // the record of write(1, buf, 12): common fields, id, args[6]
+        let mut record = [0u64; 8];
+        record[1] = 1;
+        record[2] = 1;
+        record[3] = 0xdead_beef;
+        record[4] = 12;
+        let ctx = RawSyscallContext::from(TracePointContext {
+            ctx: record.as_mut_ptr() as *mut c_void,
+        });
+
+        assert_eq!(ctx.syscall_nr(), 1);
+        assert_eq!(ctx.arg(0), 1);
+        assert_eq!(ctx.arg(1), 0xdead_beef);
+        assert_eq!(ctx.arg(2), 12);
+        assert_eq!(ctx.arg(5), 0);
+        assert_eq!(ctx.arg(6), 0);
+    }
+}
@@ -37,14 +37,69 @@ pub extern "C" fn block_port_80(ctx: XdpContext) -> XdpAction {
 }
 ```
  */
+use core::marker::PhantomData;
 use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
 use core::slice;
 use cty::*;
 
 use crate::bindings::*;
-use crate::maps::{PerfMap as PerfMapBase, PerfMapFlags};
+use crate::byteorder::{htons, ip_ihl, ntohl, ntohs, tcp_doff, tcp_flags, TcpFlags};
+use crate::conntrack::ConntrackEntry;
+use crate::helper_exists;
+use crate::helpers::{
+    bpf_get_prandom_u32, bpf_map_lookup_elem, bpf_redirect, bpf_redirect_map,
+    bpf_tcp_check_syncookie, bpf_tcp_gen_syncookie, bpf_xdp_adjust_head, bpf_xdp_adjust_meta,
+    bpf_xdp_adjust_tail,
+};
+use crate::kfunc_exists;
+use crate::maps::{PerfMap as PerfMapBase, PerfMapFlags, RingBuf as RingBufBase};
+use crate::net::{self, MtuResult, NetworkBuffer, PacketBounds, Tunnel};
+use crate::sock::SocketRef;
+
+extern "C" {
+    // XDP RX metadata kfuncs. They return -EOPNOTSUPP unless the program is
+    // bound to a device whose driver implements them.
+    fn bpf_xdp_metadata_rx_vlan_tag(
+        ctx: *const xdp_md,
+        vlan_proto: *mut __be16,
+        vlan_tci: *mut u16,
+    ) -> c_int;
+    fn bpf_xdp_metadata_rx_timestamp(ctx: *const xdp_md, timestamp: *mut u64) -> c_int;
+    fn bpf_xdp_metadata_rx_hash(ctx: *const xdp_md, hash: *mut u32, rss_type: *mut u32) -> c_int;
+}
+
+/// Number of reasons `XdpContext::drop_with_reason` keeps count of.
+pub const DROP_REASONS_MAX: u32 = 64;
+
+#[link_section = "maps/xdp_drop_reasons"]
+static mut DROP_REASONS: bpf_map_def = bpf_map_def {
+    type_: bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY,
+    key_size: mem::size_of::<u32>() as u32,
+    value_size: mem::size_of::<u64>() as u32,
+    max_entries: DROP_REASONS_MAX,
+    map_flags: 0,
+};
+
+/// Index of the number of calls to `PerfMap::insert_sampled` in the
+/// `xdp_samples` map.
+pub const SAMPLES_SEEN: u32 = 0;
+/// Index of the number of events `PerfMap::insert_sampled` inserted in the
+/// `xdp_samples` map.
+pub const SAMPLES_EXPORTED: u32 = 1;
+
+#[link_section = "maps/xdp_samples"]
+static mut SAMPLES: bpf_map_def = bpf_map_def {
+    type_: bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY,
+    key_size: mem::size_of::<u32>() as u32,
+    value_size: mem::size_of::<u64>() as u32,
+    max_entries: 2,
+    map_flags: 0,
+};
 
 /// The return type of XDP probes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u32)]
 pub enum XdpAction {
     /// Signals that the program had an unexpected anomaly. Should only be used
@@ -63,258 +118,3703 @@ pub enum XdpAction {
     /// before returning.
     Tx = xdp_action_XDP_TX,
     /// Similar to `Tx`, but through another NIC.
+    ///
+    /// The target is set up beforehand by `XdpContext::redirect`,
+    /// `DevMap::redirect` or `CpuMap::redirect`, which return this action
+    /// when they succeed.
     Redirect = xdp_action_XDP_REDIRECT,
 }
 
+/// Converts the return value of the redirect helpers to an action. Codes
+/// that aren't actions are errors, and abort.
+#[inline]
+fn redirect_action(code: c_int) -> XdpAction {
+    match code as u32 {
+        xdp_action_XDP_REDIRECT => XdpAction::Redirect,
+        xdp_action_XDP_PASS => XdpAction::Pass,
+        xdp_action_XDP_DROP => XdpAction::Drop,
+        xdp_action_XDP_TX => XdpAction::Tx,
+        _ => XdpAction::Aborted,
+    }
+}
+
 /// The packet transport header.
 ///
-/// Currently only `TCP` and `UDP` transports are supported.
+/// Currently only `TCP`, `UDP`, `ICMP` and `ICMPv6` transports are
+/// supported.
 pub enum Transport {
     TCP(*const tcphdr),
     UDP(*const udphdr),
+    ICMP(*const icmphdr),
+    ICMPv6(*const icmp6hdr),
 }
 
+/// End of the option list, in the kinds of `Transport::tcp_options()`.
+pub const TCP_OPTION_EOL: u8 = 0;
+/// Padding between options.
+pub const TCP_OPTION_NOP: u8 = 1;
+/// Maximum segment size, a 16 bit value in network byte order.
+pub const TCP_OPTION_MSS: u8 = 2;
+/// Window scale, the shift count as a single byte.
+pub const TCP_OPTION_WINDOW_SCALE: u8 = 3;
+/// Selective acknowledgments are permitted.
+pub const TCP_OPTION_SACK_PERMITTED: u8 = 4;
+/// Selective acknowledgment blocks.
+pub const TCP_OPTION_SACK: u8 = 5;
+/// Timestamp value and echo reply, two 32 bit values in network byte order.
+pub const TCP_OPTION_TIMESTAMPS: u8 = 8;
+
+/// Maximum number of options `TcpOptions` steps through, `NOP`s included,
+/// to keep the loop bounded for the verifier.
+pub const TCP_OPTIONS_MAX: usize = 10;
+
 impl Transport {
-    /// Returns the source port.
+    /// Returns the source port, or 0 for ICMP, which has no ports.
     #[inline]
     pub fn source(&self) -> u16 {
         let source = match *self {
             Transport::TCP(hdr) => unsafe { (*hdr).source },
             Transport::UDP(hdr) => unsafe { (*hdr).source },
+            Transport::ICMP(_) | Transport::ICMPv6(_) => 0,
         };
-        u16::from_be(source)
+        ntohs(source)
     }
 
-    /// Returns the destination port.
+    /// Returns the destination port, or 0 for ICMP, which has no ports.
     #[inline]
     pub fn dest(&self) -> u16 {
         let dest = match *self {
             Transport::TCP(hdr) => unsafe { (*hdr).dest },
             Transport::UDP(hdr) => unsafe { (*hdr).dest },
+            Transport::ICMP(_) | Transport::ICMPv6(_) => 0,
         };
-        u16::from_be(dest)
+        ntohs(dest)
     }
-}
 
-/// Context object provided to XDP programs.
-///
-/// XDP programs are passed a `XdpContext` instance as their argument. Through
-/// the context, programs can inspect and modify the packet.
-pub struct XdpContext {
-    pub ctx: *mut xdp_md,
-}
+    /// Returns the type of an ICMP or ICMPv6 message, such as
+    /// `ICMP_ECHO`, or `None` for other transports.
+    ///
+    /// # Example
+    ///
+    /// Drop pings, whatever the IP version:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn drop_pings(ctx: XdpContext) -> XdpAction {
+    ///     match ctx.transport().and_then(|transport| transport.icmp_type()) {
+    ///         // ICMP_ECHO and ICMPV6_ECHO_REQUEST
+    ///         Some(8) | Some(128) => XdpAction::Drop,
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn icmp_type(&self) -> Option<u8> {
+        match *self {
+            Transport::ICMP(hdr) => Some(unsafe { (*hdr).type_ }),
+            Transport::ICMPv6(hdr) => Some(unsafe { (*hdr).icmp6_type }),
+            _ => None,
+        }
+    }
 
-impl XdpContext {
-    /// Returns the raw `xdp_md` context.
+    /// Returns the code of an ICMP or ICMPv6 message, which refines its
+    /// type, or `None` for other transports.
     #[inline]
-    pub fn inner(&self) -> *mut xdp_md {
-        self.ctx
+    pub fn icmp_code(&self) -> Option<u8> {
+        match *self {
+            Transport::ICMP(hdr) => Some(unsafe { (*hdr).code }),
+            Transport::ICMPv6(hdr) => Some(unsafe { (*hdr).icmp6_code }),
+            _ => None,
+        }
     }
 
-    /// Returns the packet length.
+    /// Returns the sequence number of a TCP segment, or `None` for other
+    /// transports.
+    ///
+    /// # Example
+    ///
+    /// Track the highest sequence number seen in each TCP flow, telling
+    /// retransmissions apart from new data:
+    ///
+    /// ```
+    /// #[repr(C)]
+    /// #[derive(Clone, Copy)]
+    /// pub struct Flow {
+    ///     pub saddr: u32,
+    ///     pub daddr: u32,
+    ///     pub source: u16,
+    ///     pub dest: u16,
+    /// }
+    ///
+    /// #[map("max_seqs")]
+    /// static mut max_seqs: HashMap<Flow, u32> = HashMap::with_max_entries(10240);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn track_seqs(ctx: XdpContext) -> XdpAction {
+    ///     let (ip, transport) = match (ctx.ip(), ctx.transport()) {
+    ///         (Some(ip), Some(transport)) => (ip, transport),
+    ///         _ => return XdpAction::Pass,
+    ///     };
+    ///     let seq = match transport.seq() {
+    ///         Some(seq) => seq,
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     let flow = unsafe {
+    ///         Flow {
+    ///             saddr: (*ip).saddr,
+    ///             daddr: (*ip).daddr,
+    ///             source: transport.source(),
+    ///             dest: transport.dest(),
+    ///         }
+    ///     };
+    ///     unsafe {
+    ///         match max_seqs.get(flow) {
+    ///             // sequence numbers wrap around
+    ///             Some(&max) if (seq.wrapping_sub(max) as i32) <= 0 => (),
+    ///             _ => max_seqs.set(flow, seq),
+    ///         }
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
     #[inline]
-    pub fn len(&self) -> u32 {
-        unsafe {
-            let ctx = *self.ctx;
-            ctx.data_end - ctx.data
+    pub fn seq(&self) -> Option<u32> {
+        match *self {
+            Transport::TCP(hdr) => Some(ntohl(unsafe { (*hdr).seq })),
+            _ => None,
         }
     }
 
-    /// Returns the packet's `Ethernet` header if present.
+    /// Returns the acknowledgment number of a TCP segment, or `None` for
+    /// other transports.
+    ///
+    /// The number is only meaningful if the ACK flag is set.
     #[inline]
-    pub fn eth(&self) -> Option<*const ethhdr> {
-        let ctx = unsafe { *self.ctx };
-        let eth = ctx.data as *const ethhdr;
-        let end = ctx.data_end as *const c_void;
-        unsafe {
-            if eth.add(1) as *const c_void > end {
-                return None;
-            }
+    pub fn ack_seq(&self) -> Option<u32> {
+        match *self {
+            Transport::TCP(hdr) => Some(ntohl(unsafe { (*hdr).ack_seq })),
+            _ => None,
+        }
+    }
+
+    /// Returns the flags of a TCP segment, see `byteorder::TcpFlags`, or
+    /// `None` for other transports.
+    ///
+    /// # Example
+    ///
+    /// Drop connection attempts, letting established connections through:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn drop_syns(ctx: XdpContext) -> XdpAction {
+    ///     let flags = match ctx.transport().and_then(|transport| transport.tcp_flags()) {
+    ///         Some(flags) => flags,
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     if flags.contains(TcpFlags::SYN) && !flags.contains(TcpFlags::ACK) {
+    ///         return XdpAction::Drop;
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn tcp_flags(&self) -> Option<TcpFlags> {
+        match *self {
+            Transport::TCP(hdr) => Some(unsafe { tcp_flags(hdr) }),
+            _ => None,
         }
-        Some(eth)
     }
 
-    /// Returns the packet's `IP` header if present.
+    /// Returns whether this is a TCP segment with the SYN flag set.
     #[inline]
-    pub fn ip(&self) -> Option<*const iphdr> {
-        let eth = self.eth()?;
-        unsafe {
-            if (*eth).h_proto != u16::from_be(ETH_P_IP as u16) {
-                return None;
-            }
+    pub fn is_syn(&self) -> bool {
+        self.has_tcp_flag(TcpFlags::SYN)
+    }
 
-            let ip = eth.add(1) as *const iphdr;
-            if ip.add(1) as *const c_void > (*self.ctx).data_end as *const c_void {
-                return None;
-            }
-            Some(ip)
+    /// Returns whether this is a TCP segment with the ACK flag set.
+    #[inline]
+    pub fn is_ack(&self) -> bool {
+        self.has_tcp_flag(TcpFlags::ACK)
+    }
+
+    /// Returns whether this is a TCP segment with the FIN flag set.
+    #[inline]
+    pub fn is_fin(&self) -> bool {
+        self.has_tcp_flag(TcpFlags::FIN)
+    }
+
+    /// Returns whether this is a TCP segment with the RST flag set.
+    #[inline]
+    pub fn is_rst(&self) -> bool {
+        self.has_tcp_flag(TcpFlags::RST)
+    }
+
+    #[inline]
+    fn has_tcp_flag(&self, flag: u8) -> bool {
+        self.tcp_flags().map_or(false, |flags| flags.contains(flag))
+    }
+
+    /// Returns the receive window of a TCP segment, or `None` for other
+    /// transports.
+    ///
+    /// This is the raw 16 bit field, before scaling by the window scale
+    /// negotiated in the handshake.
+    #[inline]
+    pub fn window(&self) -> Option<u16> {
+        match *self {
+            Transport::TCP(hdr) => Some(ntohs(unsafe { (*hdr).window })),
+            _ => None,
         }
     }
 
-    /// Returns the packet's transport header if present.
+    /// Returns an iterator over the options of a TCP segment, or `None` for
+    /// other transports.
+    ///
+    /// `buf` is the context the transport header was parsed from, whose end
+    /// of packet every option is checked against. See `TcpOptions`.
+    ///
+    /// # Example
+    ///
+    /// Record the MSS clients announce in their SYNs:
+    ///
+    /// ```
+    /// #[map("client_mss")]
+    /// static mut client_mss: HashMap<u32, u16> = HashMap::with_max_entries(10240);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn record_mss(ctx: XdpContext) -> XdpAction {
+    ///     let (saddr, transport) = match (ctx.source_ipv4(), ctx.transport()) {
+    ///         (Some(saddr), Some(transport)) if transport.is_syn() && !transport.is_ack() => {
+    ///             (saddr, transport)
+    ///         }
+    ///         _ => return XdpAction::Pass,
+    ///     };
+    ///     let options = match transport.tcp_options(&ctx) {
+    ///         Some(options) => options,
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     for (kind, value) in options {
+    ///         if kind == TCP_OPTION_MSS && value.len() == 2 {
+    ///             let mss = u16::from_be_bytes([value[0], value[1]]);
+    ///             unsafe { client_mss.set(saddr, mss) };
+    ///         }
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
     #[inline]
-    pub fn transport(&self) -> Option<Transport> {
+    pub fn tcp_options<'a, B: NetworkBuffer>(
+        &self,
+        buf: &'a B,
+    ) -> Option<TcpOptions<'a, B::Context>> {
+        let hdr = match *self {
+            Transport::TCP(hdr) => hdr,
+            _ => return None,
+        };
+        let ctx = buf.context();
         unsafe {
-            let ip = self.ip()?;
-            let base = (ip as *const u8).add(((*ip).ihl() * 4) as usize);
-            let (transport, size) = match (*ip).protocol as u32 {
-                IPPROTO_TCP => (Transport::TCP(base.cast()), mem::size_of::<tcphdr>()),
-                IPPROTO_UDP => (Transport::UDP(base.cast()), mem::size_of::<udphdr>()),
-                _ => return None,
-            };
-            if base.add(size) > (*self.ctx).data_end as *const u8 {
+            let start = hdr.add(1) as *const u8;
+            if start > B::Context::data_end(ctx) {
                 return None;
             }
-            Some(transport)
+            let len = (tcp_doff(hdr) as usize * 4).saturating_sub(mem::size_of::<tcphdr>());
+            Some(TcpOptions {
+                ctx,
+                next: start,
+                end: start.add(len),
+                steps: 0,
+                _buf: PhantomData,
+            })
         }
     }
+}
 
-    /// Returns the packet's data starting after the transport headers.
+/// Iterator over the options of a TCP segment, returned by
+/// `Transport::tcp_options()`, yielding their kind and value.
+///
+/// The value leaves out the kind and length bytes, e.g. it is the 2 bytes of
+/// the MSS for `TCP_OPTION_MSS`. `NOP`s are skipped, and the iterator stops
+/// at the `EOL` option, at the end of the options the data offset covers,
+/// at an option whose length is invalid or runs past the end of the packet,
+/// and after `TCP_OPTIONS_MAX` options, returning what it found until then.
+pub struct TcpOptions<'a, C = xdp_md> {
+    ctx: *const C,
+    next: *const u8,
+    end: *const u8,
+    steps: usize,
+    _buf: PhantomData<&'a C>,
+}
+
+impl<'a, C: PacketBounds> TcpOptions<'a, C> {
+    /// Returns whether `len` bytes starting at the next option lie within
+    /// both the options and the packet.
     #[inline]
-    pub fn data(&self) -> Option<Data> {
-        use Transport::*;
+    fn has(&self, len: usize) -> bool {
         unsafe {
-            let base = match self.transport()? {
-                TCP(hdr) => {
-                    if hdr.add(1) as *const u8 > (*self.ctx).data_end as *const u8 {
-                        return None;
-                    }
-                    let mut base = hdr.add(1) as *const u8;
-                    let data_offset = (*hdr).doff();
-                    if data_offset > 5 {
-                        base = base.add(((data_offset - 5) * 4) as usize);
-                    }
-                    base
-                }
-                UDP(hdr) => hdr.add(1) as *const u8,
-            };
-            if base > (*self.ctx).data_end as *const u8 {
-                return None;
+            let end = self.next.add(len);
+            // checked against data_end right before reading, for the verifier
+            end <= self.end && end <= C::data_end(self.ctx)
+        }
+    }
+}
+
+impl<'a, C: PacketBounds> Iterator for TcpOptions<'a, C> {
+    type Item = (u8, &'a [u8]);
+
+    #[inline]
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        while self.steps < TCP_OPTIONS_MAX {
+            self.steps += 1;
+            if !self.has(1) {
+                break;
+            }
+            let kind = unsafe { *self.next };
+            if kind == TCP_OPTION_EOL {
+                break;
+            }
+            if kind == TCP_OPTION_NOP {
+                self.next = unsafe { self.next.add(1) };
+                continue;
+            }
+            if !self.has(2) {
+                break;
+            }
+            let len = unsafe { *self.next.add(1) } as usize;
+            if len < 2 || !self.has(len) {
+                break;
+            }
+            unsafe {
+                let value = slice::from_raw_parts(self.next.add(2), len - 2);
+                self.next = self.next.add(len);
+                return Some((kind, value));
             }
-            Some(Data {
-                ctx: self.ctx,
-                base,
-            })
         }
+        // stays done
+        self.steps = TCP_OPTIONS_MAX;
+        None
     }
 }
 
-/// Data type returned by calling `XdpContext::data()`
-pub struct Data {
-    ctx: *const xdp_md,
-    base: *const u8,
+/// Updates the checksum `csum` for a 16 bit field of the packet changing from
+/// `old` to `new`, as per RFC 1624.
+///
+/// All three are taken as they are stored in the packet, in network byte
+/// order, which the ones' complement sum doesn't depend on. Unlike the
+/// `bpf_l3_csum_replace` and `bpf_l4_csum_replace` helpers, this works in
+/// XDP programs as well as TC ones.
+///
+/// UDP over IPv4 may leave the checksum out, by setting it to 0, in which
+/// case it must stay 0.
+///
+/// # Example
+///
+/// Move DNS queries over to port 5353:
+///
+/// ```
+/// #[xdp]
+/// pub extern "C" fn redirect_dns(ctx: XdpContext) -> XdpAction {
+///     let udp = match ctx.transport() {
+///         Some(Transport::UDP(udp)) => udp as *mut udphdr,
+///         _ => return XdpAction::Pass,
+///     };
+///     unsafe {
+///         let old = (*udp).dest;
+///         if old != htons(53) || (*udp).check == 0 {
+///             return XdpAction::Pass;
+///         }
+///         let new = htons(5353);
+///         (*udp).dest = new;
+///         csum_replace_u16(old, new, &mut (*udp).check);
+///     }
+///     XdpAction::Pass
+/// }
+/// ```
+#[inline]
+pub fn csum_replace_u16(old: u16, new: u16, csum: &mut u16) {
+    // HC' = ~(~HC + ~m + m')
+    let mut sum = !*csum as u32 + !old as u32 + new as u32;
+    sum = (sum & 0xffff) + (sum >> 16);
+    sum = (sum & 0xffff) + (sum >> 16);
+    *csum = !(sum as u16);
+}
+
+/// Updates the checksum `csum` for a 32 bit field of the packet, such as an
+/// IPv4 address, changing from `old` to `new`, see `csum_replace_u16`.
+///
+/// A changed IPv4 address is covered by both the checksum of the IP header
+/// and the one of the TCP or UDP header, through its pseudo-header, so both
+/// need updating.
+#[inline]
+pub fn csum_replace_u32(old: u32, new: u32, csum: &mut u16) {
+    csum_replace_u16((old >> 16) as u16, (new >> 16) as u16, csum);
+    csum_replace_u16(old as u16, new as u16, csum);
+}
+
+/// The IP header of a packet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IpHeader {
+    V4(*const iphdr),
+    V6(*const ipv6hdr),
 }
 
-impl Data {
-    /// Returns the offset from the first byte of the packet.
+/// The operation of an ARP packet, returned by `NetworkBuffer::arp_opcode()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArpOp {
+    Request,
+    Reply,
+    /// Another operation, such as those of RARP.
+    Other(u16),
+}
+
+/// Why a header couldn't be parsed, returned by the `try_*` methods of
+/// `XdpContext`.
+///
+/// The values start at 1, so that 0 can stand for success in maps.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum XdpParseError {
+    /// The headers run past the end of the packet.
+    Truncated = 1,
+    /// The network header isn't the one asked for, or isn't IP.
+    UnsupportedL3 = 2,
+    /// The transport header isn't TCP, UDP, ICMP or ICMPv6, or follows more
+    /// than `IPV6_EXT_HEADERS_MAX` IPv6 extension headers.
+    UnsupportedL4 = 3,
+    /// The packet has no Ethernet header, like the IP packets of GRE
+    /// tunnels, see `net::Tunnel`.
+    UnsupportedL2 = 4,
+    /// The packet is an IP fragment other than the first, which doesn't
+    /// hold the transport header.
+    Fragment = 5,
+}
+
+/// Maximum length of an IPv4 header with options, in 16 bit words.
+const IPV4_HDR_WORDS_MAX: usize = 30;
+/// Index of the `check` field in the IPv4 header, in 16 bit words.
+const IPV4_CHECK_WORD: usize = 5;
+
+/// The IPv4 header of a packet, options included, returned by
+/// `XdpContext::ipv4_header()`.
+pub struct Ipv4Header {
+    ip: *const iphdr,
+    end: *const u8,
+}
+
+impl Ipv4Header {
+    /// Returns the raw header.
     #[inline]
-    pub fn offset(&self) -> usize {
-        unsafe { (self.base as u32 - (*self.ctx).data) as usize }
+    pub fn inner(&self) -> *const iphdr {
+        self.ip
     }
 
-    /// Returns the length of the data.
-    ///
-    /// This is equivalent to the length of the packet minus the length of the headers.
+    /// Returns the length of the header, options included, in bytes.
     #[inline]
-    pub fn len(&self) -> usize {
-        unsafe { ((*self.ctx).data_end - self.base as u32) as usize }
+    pub fn header_len(&self) -> usize {
+        unsafe { ip_ihl(self.ip) as usize * 4 }
     }
 
-    /// Returns a `slice` of `len` bytes from the data.
+    /// Returns `true` if the `check` field matches the header.
     #[inline]
-    pub fn slice(&self, len: usize) -> Option<&[u8]> {
-        unsafe {
-            if self.base.add(len) > (*self.ctx).data_end as *const u8 {
-                return None;
-            }
-            let s = slice::from_raw_parts(self.base, len);
-            Some(s)
-        }
+    pub fn checksum_valid(&self) -> bool {
+        self.sum(usize::MAX) == Some(0xffff)
     }
 
+    /// Computes the checksum of the header, leaving out the `check` field.
+    ///
+    /// The checksum is in network byte order, ready to be stored in `check`
+    /// after changing the header. Returns `None` if the options run past the
+    /// end of the packet.
     #[inline]
-    pub fn read<T>(&self) -> Option<T> {
-        unsafe {
-            let len = mem::size_of::<T>();
-            if self.base.add(len) as *const u8 > (*self.ctx).data_end as *const u8 {
-                return None;
+    pub fn compute_checksum(&self) -> Option<u16> {
+        self.sum(IPV4_CHECK_WORD).map(|sum| !sum)
+    }
+
+    /// Folds the 16 bit words of the header into their ones' complement
+    /// sum, skipping the word at index `skip`.
+    #[inline]
+    fn sum(&self, skip: usize) -> Option<u16> {
+        let words = self.header_len() / 2;
+        let mut word = self.ip as *const u16;
+        let mut sum = 0u32;
+        // bounded for the verifier, the length is checked per word
+        for i in 0..IPV4_HDR_WORDS_MAX {
+            if i >= words {
+                break;
+            }
+            unsafe {
+                if word.add(1) as *const u8 > self.end {
+                    return None;
+                }
+                if i != skip {
+                    sum += word.read_unaligned() as u32;
+                }
+                word = word.add(1);
             }
-            Some((self.base as *const T).read_unaligned())
         }
+        sum = (sum & 0xffff) + (sum >> 16);
+        sum = (sum & 0xffff) + (sum >> 16);
+        Some(sum as u16)
     }
 }
 
-/// Convenience data type to exchange payload data.
-#[repr(C)]
-pub struct MapData<T> {
-    /// The custom data type to be exchanged with user space.
-    pub data: T,
-    offset: u32,
-    size: u32,
-    payload: [u8; 0],
+/// The IPv6 header of a packet, returned by `XdpContext::ipv6_header()`.
+///
+/// The kernel headers lay the traffic class out across the `priority`
+/// bitfield and `flow_lbl`, in an order depending on the byte order of the
+/// target. The accessors read the fields from their place in the first 32
+/// bits of the header instead, which is right on any target.
+pub struct Ipv6Header {
+    ip: *const ipv6hdr,
 }
 
-impl<T> MapData<T> {
-    /// Create a new `MapData` value that includes only `data` and no packet
-    /// payload.
-    pub fn new(&self, data: T) -> Self {
-        MapData::<T>::with_payload(data, 0, 0)
+impl Ipv6Header {
+    /// Returns the raw header.
+    #[inline]
+    pub fn inner(&self) -> *const ipv6hdr {
+        self.ip
     }
 
-    /// Create a new `MapData` value that includes `data` and `size` payload
-    /// bytes, where the interesting part of the payload starts at `offset`.
+    /// Returns the traffic class of the packet, whose upper 6 bits are the
+    /// DSCP and lower 2 bits the ECN field.
     ///
-    /// The payload can then be retrieved calling `MapData::payload()`.
-    pub fn with_payload(data: T, offset: u32, size: u32) -> Self {
-        Self {
-            data,
-            payload: [],
-            offset,
-            size
-        }
+    /// # Example
+    ///
+    /// Count packets by DSCP:
+    ///
+    /// ```
+    /// #[map("dscp")]
+    /// static mut dscp: Counter<u8> = Counter::with_max_entries(64);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn count_dscp(ctx: XdpContext) -> XdpAction {
+    ///     if let Some(ip) = ctx.ipv6_header() {
+    ///         unsafe { dscp.inc(ip.traffic_class() >> 2) };
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn traffic_class(&self) -> u8 {
+        (self.first_word() >> 20) as u8
     }
 
-    /// Return the payload if any, skipping the initial `offset` bytes.
-    pub fn payload(&self) -> &[u8] {
-        unsafe {
-            let base = self.payload.as_ptr().add(self.offset as usize);
-            slice::from_raw_parts(base, (self.size - self.offset) as usize)
-        }
+    /// Returns the 20 bit flow label of the packet.
+    #[inline]
+    pub fn flow_label(&self) -> u32 {
+        self.first_word() & 0xf_ffff
+    }
+
+    /// Returns the length of the packet following the header, extension
+    /// headers included, in bytes.
+    #[inline]
+    pub fn payload_length(&self) -> u16 {
+        ntohs(unsafe { (*self.ip).payload_len })
+    }
+
+    /// Returns the version, traffic class and flow label word.
+    #[inline]
+    fn first_word(&self) -> u32 {
+        ntohl(unsafe { (self.ip as *const u32).read_unaligned() })
     }
 }
 
-/// Perf events map.
+/// An IEEE 802.1Q VLAN tag.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VlanTag {
+    /// The tag protocol identifier, `ETH_P_8021Q` or `ETH_P_8021AD`.
+    pub proto: u16,
+    /// The tag control information.
+    pub tci: u16,
+}
+
+impl VlanTag {
+    /// Returns the VLAN identifier.
+    #[inline]
+    pub fn id(&self) -> u16 {
+        self.tci & 0x0fff
+    }
+
+    /// Returns the priority code point.
+    #[inline]
+    pub fn priority(&self) -> u8 {
+        (self.tci >> 13) as u8
+    }
+}
+
+/// The kind of RSS hash reported by the NIC, `enum xdp_rss_hash_type`.
 ///
-/// Similar to `PerfMap`, with additional XDP-only API.
-#[repr(transparent)]
-pub struct PerfMap<T>(PerfMapBase<MapData<T>>);
+/// Made up of bits describing which headers went into the hash.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RssHashType(pub u32);
 
-impl<T> PerfMap<T> {
-    /// Creates a perf map with the specified maximum number of elements.
-    pub const fn with_max_entries(max_entries: u32) -> Self {
-        Self(PerfMapBase::with_max_entries(max_entries))
+impl RssHashType {
+    pub const L3_IPV4: u32 = 1 << 0;
+    pub const L3_IPV6: u32 = 1 << 1;
+    pub const L3_DYNHDR: u32 = 1 << 2;
+    pub const L4: u32 = 1 << 3;
+    pub const L4_TCP: u32 = 1 << 4;
+    pub const L4_UDP: u32 = 1 << 5;
+    pub const L4_SCTP: u32 = 1 << 6;
+    pub const L4_IPSEC: u32 = 1 << 7;
+    pub const L4_ICMP: u32 = 1 << 8;
+
+    /// Returns `true` if the hash covers the IPv4 addresses.
+    #[inline]
+    pub fn is_ipv4(&self) -> bool {
+        self.0 & Self::L3_IPV4 != 0
     }
 
-    /// Insert a new event in the perf events array keyed by the current CPU number.
-    ///
-    /// Each array can hold up to `max_entries` events, see `with_max_entries`.
-    /// If you want to use a key other than the current CPU, see
-    /// `insert_with_flags`.
-    ///
-    /// `packet_size` specifies the number of bytes from the current packet that
-    /// the kernel should append to the event data.
+    /// Returns `true` if the hash covers the IPv6 addresses.
     #[inline]
-    pub fn insert(&mut self, ctx: &XdpContext, data: MapData<T>) {
-        let size = data.size;
-        self.0
-            .insert_with_flags(ctx.inner(), data, PerfMapFlags::with_xdp_size(size))
+    pub fn is_ipv6(&self) -> bool {
+        self.0 & Self::L3_IPV6 != 0
     }
 
-    /// Insert a new event in the perf events array keyed by the index and with
-    /// the additional xdp payload data specified in the given `PerfMapFlags`.
+    /// Returns `true` if the hash covers the transport header, e.g. ports.
     #[inline]
-    pub fn insert_with_flags(&mut self, ctx: &XdpContext, data: MapData<T>, mut flags: PerfMapFlags) {
-        flags.xdp_size = data.size;
-        self.0.insert_with_flags(ctx.inner(), data, flags)
+    pub fn is_l4(&self) -> bool {
+        self.0 & Self::L4 != 0
+    }
+}
+
+/// Maximum number of VLAN tags `XdpContext` skips to get to the network
+/// header, enough for 802.1ad QinQ frames.
+pub const VLAN_TAGS_MAX: usize = 2;
+
+/// Maximum number of IPv6 extension headers `XdpContext::l4_protocol` and
+/// `XdpContext::transport` walk through.
+pub const IPV6_EXT_HEADERS_MAX: usize = 8;
+
+// IPv6 extension headers, the `NEXTHDR_*` numbers of the kernel
+const NEXTHDR_HOP: u8 = 0;
+const NEXTHDR_ROUTING: u8 = 43;
+const NEXTHDR_FRAGMENT: u8 = 44;
+const NEXTHDR_AUTH: u8 = 51;
+const NEXTHDR_DEST: u8 = 60;
+const NEXTHDR_MOBILITY: u8 = 135;
+
+/// The more fragments flag and the offset, in the `frag_off` field of the
+/// IPv4 header.
+const IP_MF: u16 = 0x2000;
+const IP_OFFSET: u16 = 0x1fff;
+
+/// Where an IP packet stands in the fragments of its datagram.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum IpFragment {
+    /// Not fragmented.
+    Whole,
+    /// The first fragment, with the transport header.
+    First,
+    /// Any other fragment.
+    Rest,
+}
+
+impl IpFragment {
+    /// Returns where the fragment at `offset` stands, `more` telling
+    /// whether other fragments follow.
+    #[inline]
+    fn new(offset: u16, more: bool) -> IpFragment {
+        match (offset, more) {
+            (0, false) => IpFragment::Whole,
+            (0, true) => IpFragment::First,
+            _ => IpFragment::Rest,
+        }
+    }
+}
+
+/// Maximum size of the metadata area in front of the packet data.
+pub const XDP_METADATA_MAX: usize = 32;
+
+/// Headroom in front of the packet data that the kernel reserves for XDP
+/// programs, which `bpf_xdp_adjust_head` can grow the packet into.
+///
+/// The metadata area is carved out of the same room, so programs pushing
+/// headers and storing metadata must fit both. Native drivers are asked to
+/// reserve this much, see `redbpf::xdp_max_headroom` to check an interface
+/// from userspace.
+///
+/// # Example
+///
+/// Encapsulate packets in an outer IP header, failing to compile if the
+/// header doesn't fit:
+///
+/// ```
+/// const ENCAP_LEN: usize = mem::size_of::<iphdr>();
+/// const _: () = assert!(ENCAP_LEN + XDP_METADATA_MAX <= XDP_PACKET_HEADROOM);
+///
+/// #[xdp]
+/// pub extern "C" fn encap(mut ctx: XdpContext) -> XdpAction {
+///     if ctx.ip().is_none() {
+///         return XdpAction::Pass;
+///     }
+///     // the driver may still have left less room
+///     if ctx.adjust_head(-(ENCAP_LEN as i32)).is_err() {
+///         return XdpAction::Aborted;
+///     }
+///     // move the Ethernet header to the front, and fill in the outer header
+///     // ...
+///
+///     XdpAction::Tx
+/// }
+/// ```
+pub const XDP_PACKET_HEADROOM: usize = 256;
+
+/// Compile-time layout checks of types stored in the metadata area.
+pub(crate) struct MetadataLayout<M>(PhantomData<M>);
+
+impl<M> MetadataLayout<M> {
+    const CHECK: () = assert!(
+        mem::size_of::<M>() <= XDP_METADATA_MAX && mem::size_of::<M>() & 3 == 0,
+        "XDP metadata must be at most 32 bytes long, in multiples of 4 bytes"
+    );
+
+    /// Returns the size of `M`, failing to compile if `M` doesn't fit.
+    #[inline]
+    #[allow(clippy::let_unit_value)]
+    pub(crate) fn size() -> usize {
+        let _ = Self::CHECK;
+        mem::size_of::<M>()
+    }
+}
+
+/// Context object provided to XDP programs.
+///
+/// XDP programs are passed a `XdpContext` instance as their argument. Through
+/// the context, programs can inspect and modify the packet.
+pub struct XdpContext {
+    pub ctx: *mut xdp_md,
+}
+
+impl XdpContext {
+    /// Returns the raw `xdp_md` context.
+    #[inline]
+    pub fn inner(&self) -> *mut xdp_md {
+        self.ctx
+    }
+
+    /// Returns the packet length.
+    ///
+    /// For multi-buffer packets, this is only the length of the linear part,
+    /// which the program can access directly. See `frame_len()`.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        unsafe {
+            let ctx = *self.ctx;
+            ctx.data_end - ctx.data
+        }
+    }
+
+    /// Returns the length of the whole frame, including the fragments
+    /// following the linear part of multi-buffer packets.
+    ///
+    /// Drivers only pass multi-buffer packets, e.g. jumbo frames or those
+    /// aggregated by GRO, to programs loaded with
+    /// `redbpf::Program::set_xdp_frags()`. Kernels before 5.18 don't report
+    /// the length of the fragments, and don't pass such packets either, so
+    /// the length of the linear part is returned.
+    #[inline]
+    pub fn frame_len(&self) -> usize {
+        // BPF_FUNC_xdp_get_buff_len
+        let buff_len = if helper_exists!(188) {
+            unsafe { crate::helpers::bpf_xdp_get_buff_len(self.ctx) }
+        } else {
+            0
+        };
+        frame_len(self.len() as usize, buff_len)
+    }
+
+    /// Redirects the packet out of the interface `ifindex`, and returns the
+    /// action the program must return for the redirect to happen:
+    /// `XdpAction::Redirect`, or `XdpAction::Aborted` if `flags` isn't 0.
+    ///
+    /// To pick among several interfaces, `DevMap::redirect` is faster, as
+    /// the kernel sends the packets of a map out in bulk.
+    #[inline]
+    pub fn redirect(&self, ifindex: u32, flags: u64) -> XdpAction {
+        redirect_action(unsafe { bpf_redirect(ifindex, flags) })
+    }
+
+    /// Checks whether the packet, grown by `len_delta` bytes or shrunk if
+    /// negative, fits the MTU of the interface `ifindex`, or of the
+    /// interface it was received on if 0 (kernel 5.12 or later).
+    ///
+    /// Packets redirected to an interface they don't fit are dropped
+    /// silently, so forwarding programs check before redirecting, and
+    /// before adding headers with the size of the headers as `len_delta`.
+    /// `flags` must be 0 for XDP programs. Returns `None` if the check
+    /// fails, e.g. for interfaces that don't exist.
+    ///
+    /// # Example
+    ///
+    /// Forward packets to the interface of their destination, leaving those
+    /// too large for it to the network stack, which replies with the ICMP
+    /// errors path MTU discovery relies on:
+    ///
+    /// ```
+    /// #[map("routes")]
+    /// static mut routes: HashMap<u32, u32> = HashMap::with_max_entries(1024);
+    ///
+    /// #[map("interfaces")]
+    /// static mut interfaces: DevMap = DevMap::with_max_entries(64);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn forward(ctx: XdpContext) -> XdpAction {
+    ///     let ifindex = match ctx.dest_ipv4().and_then(|daddr| unsafe { routes.get(daddr) }) {
+    ///         Some(&ifindex) => ifindex,
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     match ctx.check_mtu(ifindex, 0, 0) {
+    ///         Some(mtu) if mtu.fits => unsafe {
+    ///             interfaces.redirect(ifindex, xdp_action_XDP_PASS as u64)
+    ///         },
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn check_mtu(&self, ifindex: u32, len_delta: i32, flags: u64) -> Option<MtuResult> {
+        unsafe { net::check_mtu(self.ctx, ifindex, len_delta, flags) }
+    }
+
+    /// Returns the index of the interface the packet is being sent out of.
+    ///
+    /// The egress interface is only known to programs attached to the
+    /// entries of a `BPF_MAP_TYPE_DEVMAP`, which run once a packet has been
+    /// redirected through the map (kernel 5.8 or later). Elsewhere, the
+    /// verifier rejects the access. Such programs are loaded after calling
+    /// `redbpf::Program::set_xdp_devmap`.
+    ///
+    /// # Example
+    ///
+    /// Set the source MAC of redirected packets to the one of the device
+    /// they leave through:
+    ///
+    /// ```
+    /// #[map("egress_macs")]
+    /// static mut egress_macs: HashMap<u32, [u8; 6]> = HashMap::with_max_entries(64);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn set_source_mac(ctx: XdpContext) -> XdpAction {
+    ///     let eth = match ctx.eth() {
+    ///         Some(eth) => eth as *mut ethhdr,
+    ///         None => return XdpAction::Drop,
+    ///     };
+    ///     match unsafe { egress_macs.get(ctx.egress_ifindex()) } {
+    ///         Some(mac) => unsafe { (*eth).h_source = *mac },
+    ///         None => return XdpAction::Drop,
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn egress_ifindex(&self) -> u32 {
+        unsafe { (*self.ctx).egress_ifindex }
+    }
+
+    /// Returns the packet's `Ethernet` header if present.
+    #[inline]
+    pub fn eth(&self) -> Option<*const ethhdr> {
+        self.try_eth().ok()
+    }
+
+    /// Returns the packet's `Ethernet` header, or `Truncated` if the packet
+    /// is too short to hold one.
+    #[inline]
+    pub fn try_eth(&self) -> Result<*const ethhdr, XdpParseError> {
+        NetworkBuffer::try_eth(self)
+    }
+
+    /// Returns the packet's VLAN tag.
+    ///
+    /// If the NIC strips VLAN tags, the tag is read from the RX metadata
+    /// through the `bpf_xdp_metadata_rx_vlan_tag` kfunc (kernel 6.8 or
+    /// later). The program must then be bound to the device, see
+    /// `redbpf::Program::set_xdp_dev_bound`. Otherwise, or if the driver
+    /// doesn't report the tag, the outer tag in the packet is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn only_vlan_10(ctx: XdpContext) -> XdpAction {
+    ///     match ctx.hw_vlan() {
+    ///         Some(tag) if tag.id() == 10 => XdpAction::Pass,
+    ///         _ => XdpAction::Drop,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn hw_vlan(&self) -> Option<VlanTag> {
+        if kfunc_exists!(bpf_xdp_metadata_rx_vlan_tag) {
+            let mut proto: __be16 = 0;
+            let mut tci: u16 = 0;
+            if unsafe { bpf_xdp_metadata_rx_vlan_tag(self.ctx, &mut proto, &mut tci) } == 0 {
+                return Some(VlanTag {
+                    proto: ntohs(proto),
+                    tci,
+                });
+            }
+        }
+
+        let eth = self.eth()?;
+        unsafe {
+            let end = (*self.ctx).data_end as *const u8;
+            vlan_tag(ntohs((*eth).h_proto), eth.add(1) as *const u8, end).map(|(tag, _)| tag)
+        }
+    }
+
+    /// Returns the EtherType of the packet, in host byte order, skipping up
+    /// to `VLAN_TAGS_MAX` VLAN tags.
+    ///
+    /// Returns `None` if the packet is too short for its headers. A frame
+    /// with more tags than that returns the protocol of the innermost tag
+    /// skipped.
+    ///
+    /// # Example
+    ///
+    /// Count packets by EtherType, whether they are tagged or not:
+    ///
+    /// ```
+    /// #[map("eth_protos")]
+    /// static mut eth_protos: HashMap<u16, u64> = HashMap::with_max_entries(256);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn count_eth_protos(ctx: XdpContext) -> XdpAction {
+    ///     if let Some(proto) = ctx.eth_proto() {
+    ///         unsafe {
+    ///             let count = eth_protos.get(proto).copied().unwrap_or(0);
+    ///             eth_protos.set(proto, count + 1);
+    ///         }
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn eth_proto(&self) -> Option<u16> {
+        let eth = self.eth()?;
+        unsafe { eth_proto(eth, (*self.ctx).data_end as *const u8) }
+    }
+
+    /// Returns the protocol of the transport header of an IPv4 or IPv6
+    /// packet, possibly VLAN tagged, such as `IPPROTO_TCP`.
+    ///
+    /// For IPv4 this is the `protocol` field. For IPv6 it's the next header
+    /// following up to `IPV6_EXT_HEADERS_MAX` extension headers, which may
+    /// be `IPPROTO_NONE` or `IPPROTO_ESP`, whose payload isn't parsed.
+    /// Returns `None` for other packets, if the headers are truncated, or if
+    /// the extension headers don't end within that many headers.
+    ///
+    /// # Example
+    ///
+    /// Count packets by transport protocol, whatever the IP version:
+    ///
+    /// ```
+    /// #[map("l4_protos")]
+    /// static mut l4_protos: HashMap<u8, u64> = HashMap::with_max_entries(256);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn count_l4_protos(ctx: XdpContext) -> XdpAction {
+    ///     if let Some(proto) = ctx.l4_protocol() {
+    ///         unsafe {
+    ///             let count = l4_protos.get(proto).copied().unwrap_or(0);
+    ///             l4_protos.set(proto, count + 1);
+    ///         }
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn l4_protocol(&self) -> Option<u8> {
+        let eth = self.eth()?;
+        unsafe {
+            let end = (*self.ctx).data_end as *const u8;
+            let (proto, l3) = eth_payload(eth, end)?;
+            l4_protocol(proto, l3, end)
+        }
+    }
+
+    /// Returns `true` if the packet is IPv4, possibly VLAN tagged.
+    #[inline]
+    pub fn is_ip(&self) -> bool {
+        self.eth_proto() == Some(ETH_P_IP as u16)
+    }
+
+    /// Returns `true` if the packet is IPv6, possibly VLAN tagged.
+    #[inline]
+    pub fn is_ipv6(&self) -> bool {
+        self.eth_proto() == Some(ETH_P_IPV6 as u16)
+    }
+
+    /// Returns `true` if the packet is ARP, possibly VLAN tagged.
+    #[inline]
+    pub fn is_arp(&self) -> bool {
+        self.eth_proto() == Some(ETH_P_ARP as u16)
+    }
+
+    /// Returns the hardware RX timestamp of the packet, in nanoseconds.
+    ///
+    /// Returns `None` if the running kernel or the driver doesn't support RX
+    /// timestamp metadata, which is available from kernel 6.3. Like with
+    /// `hw_vlan()`, the program must be bound to the device.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[map("timestamps")]
+    /// static mut timestamps: EventChannel<u64> = EventChannel::with_max_entries(64 * 1024);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn rx_timestamps(ctx: XdpContext) -> XdpAction {
+    ///     if let Some(ts) = ctx.rx_timestamp() {
+    ///         unsafe { timestamps.output(ctx.ctx, &ts) };
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn rx_timestamp(&self) -> Option<u64> {
+        if !kfunc_exists!(bpf_xdp_metadata_rx_timestamp) {
+            return None;
+        }
+        let mut timestamp = 0u64;
+        if unsafe { bpf_xdp_metadata_rx_timestamp(self.ctx, &mut timestamp) } != 0 {
+            return None;
+        }
+        Some(timestamp)
+    }
+
+    /// Returns the RSS hash the NIC computed for the packet, and what it
+    /// covers.
+    ///
+    /// Returns `None` if the running kernel or the driver doesn't support RX
+    /// hash metadata. The program must be bound to the device, and the hash
+    /// type is reported from kernel 6.4.
+    #[inline]
+    pub fn rx_hash(&self) -> Option<(u32, RssHashType)> {
+        if !kfunc_exists!(bpf_xdp_metadata_rx_hash) {
+            return None;
+        }
+        let mut hash = 0u32;
+        let mut rss_type = 0u32;
+        if unsafe { bpf_xdp_metadata_rx_hash(self.ctx, &mut hash, &mut rss_type) } != 0 {
+            return None;
+        }
+        Some((hash, RssHashType(rss_type)))
+    }
+
+    /// Reserves room for an `M` in the metadata area in front of the packet
+    /// and returns it for writing.
+    ///
+    /// TC programs handling the packet afterwards read it back with
+    /// `redbpf_probes::tc::SkBuffContext::meta`. Both sides should use the
+    /// same `#[repr(C)]` type, which must be at most `XDP_METADATA_MAX` bytes
+    /// long in multiples of 4 bytes, or the program fails to compile.
+    ///
+    /// Growing the metadata area invalidates pointers into the packet, so
+    /// headers must be looked up again afterwards. Returns `None` if the
+    /// driver doesn't support metadata.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[repr(C)]
+    /// #[derive(Clone, Copy)]
+    /// pub struct FlowMeta {
+    ///     pub flow_id: u32,
+    /// }
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn tag_flows(mut ctx: XdpContext) -> XdpAction {
+    ///     let flow_id = match ctx.transport() {
+    ///         Some(transport) => u32::from(transport.source()) << 16 | u32::from(transport.dest()),
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     if let Some(meta) = ctx.meta_mut::<FlowMeta>() {
+    ///         meta.flow_id = flow_id;
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn meta_mut<M: Copy>(&mut self) -> Option<&mut M> {
+        let size = MetadataLayout::<M>::size();
+        let meta = self.reserve_metadata(size as u32).ok()?;
+        Some(unsafe { &mut *(meta.as_mut_ptr() as *mut M) })
+    }
+
+    /// Returns the first `len` bytes of the metadata area in front of the
+    /// packet, `[data_meta, data)`, which is empty unless a program reserved
+    /// room in it.
+    ///
+    /// AF_XDP sockets receive the area along with the packet, right in front
+    /// of it in the UMEM frame. Returns `None` if the area is shorter than
+    /// `len` bytes, or if a driver left `data_meta`, `data` and `data_end`
+    /// out of order.
+    ///
+    /// `len` rather than the length of the area bounds the slice because the
+    /// verifier only learns how many bytes at `data_meta` are safe to read
+    /// from comparing `data_meta + len` against `data`. It doesn't relate the
+    /// length `data - data_meta` to either pointer, so it rejects reads
+    /// through a slice of that length, as well as reads when `len` isn't a
+    /// constant.
+    #[inline]
+    pub fn metadata(&self, len: usize) -> Option<&[u8]> {
+        unsafe {
+            let ctx = *self.ctx;
+            let meta = ctx.data_meta as *const u8;
+            if !metadata_fits(meta, ctx.data as *const u8, ctx.data_end as *const u8, len) {
+                return None;
+            }
+            Some(slice::from_raw_parts(meta, len))
+        }
+    }
+
+    /// Grows the metadata area by `len` bytes and returns them for writing.
+    ///
+    /// The area grows at the front, so the bytes returned come before those
+    /// reserved earlier. `len` must be a multiple of 4, and the whole area
+    /// at most `XDP_METADATA_MAX` bytes long. Returns an error if the driver
+    /// doesn't support metadata, or if there's no room left. See `meta_mut`
+    /// to store a typed value, and its notes on invalidated pointers.
+    ///
+    /// # Example
+    ///
+    /// Tag packets for a TC program or an AF_XDP consumer to read:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn tag_packets(mut ctx: XdpContext) -> XdpAction {
+    ///     let tag: u32 = if ctx.is_ipv6() { 6 } else { 4 };
+    ///     if let Ok(meta) = ctx.reserve_metadata(4) {
+    ///         meta.copy_from_slice(&tag.to_ne_bytes());
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn reserve_metadata(&mut self, len: u32) -> Result<&mut [u8], ()> {
+        if len as usize > XDP_METADATA_MAX {
+            return Err(());
+        }
+        unsafe {
+            if bpf_xdp_adjust_meta(self.ctx, -(len as i32)) != 0 {
+                return Err(());
+            }
+            let ctx = *self.ctx;
+            let meta = ctx.data_meta as *mut u8;
+            let (data, end) = (ctx.data as *const u8, ctx.data_end as *const u8);
+            if !metadata_fits(meta, data, end, len as usize) {
+                return Err(());
+            }
+            Ok(slice::from_raw_parts_mut(meta, len as usize))
+        }
+    }
+
+    /// Moves the start of the packet by `delta` bytes, growing the packet
+    /// at the front when `delta` is negative, and shrinking it otherwise.
+    ///
+    /// Pointers from `eth()`, `ip()`, `transport()` and the like, as well
+    /// as `Data` and `DataMut`, are invalid after a successful call, and
+    /// headers must be looked up again. The verifier rejects programs
+    /// using them. The bytes a negative `delta` adds are uninitialized.
+    ///
+    /// Returns an error if the packet can't be moved that far, e.g. because
+    /// there isn't enough headroom, see `XDP_PACKET_HEADROOM`.
+    ///
+    /// # Example
+    ///
+    /// Push an extra Ethernet header in front of the packet:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn push_eth(mut ctx: XdpContext) -> XdpAction {
+    ///     let eth = match ctx.eth() {
+    ///         Some(eth) => unsafe { *eth },
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     if ctx.adjust_head(-(mem::size_of::<ethhdr>() as i32)).is_err() {
+    ///         return XdpAction::Pass;
+    ///     }
+    ///     // `eth()` now points at the new header
+    ///     let mut packet = match ctx.packet_mut() {
+    ///         Some(packet) => packet,
+    ///         None => return XdpAction::Aborted,
+    ///     };
+    ///     if packet.write(&eth).is_none() {
+    ///         return XdpAction::Aborted;
+    ///     }
+    ///     XdpAction::Tx
+    /// }
+    /// ```
+    #[inline]
+    pub fn adjust_head(&mut self, delta: i32) -> Result<(), ()> {
+        match unsafe { bpf_xdp_adjust_head(self.ctx, delta) } {
+            0 => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    /// Moves the end of the packet by `delta` bytes, growing the packet at
+    /// the back when `delta` is positive, and shrinking it otherwise.
+    ///
+    /// As with `adjust_head()`, pointers into the packet are invalid after a
+    /// successful call, and headers must be looked up again.
+    ///
+    /// Returns an error if the packet can't be resized that much, e.g.
+    /// because the driver leaves no tailroom.
+    #[inline]
+    pub fn adjust_tail(&mut self, delta: i32) -> Result<(), ()> {
+        match unsafe { bpf_xdp_adjust_tail(self.ctx, delta) } {
+            0 => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    /// Returns the VLAN ID of the outer VLAN tag in the packet.
+    ///
+    /// Unlike `hw_vlan()`, tags the NIC stripped aren't seen.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn drop_vlan_10(ctx: XdpContext) -> XdpAction {
+    ///     match ctx.vlan() {
+    ///         Some(10) => XdpAction::Drop,
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn vlan(&self) -> Option<u16> {
+        let eth = self.eth()?;
+        unsafe {
+            let end = (*self.ctx).data_end as *const u8;
+            vlan_tag(ntohs((*eth).h_proto), eth.add(1) as *const u8, end).map(|(tag, _)| tag.id())
+        }
+    }
+
+    /// Returns the offset of the network header, after up to
+    /// `VLAN_TAGS_MAX` VLAN tags, along with its EtherType.
+    #[inline]
+    fn l3_offset(&self) -> Result<(usize, u16), XdpParseError> {
+        net::l3_offset(self)
+    }
+
+    /// Returns the packet's `IP` header if present, possibly VLAN tagged.
+    #[inline]
+    pub fn ip(&self) -> Option<*const iphdr> {
+        self.try_ip().ok()
+    }
+
+    /// Returns the packet's `IP` header, possibly VLAN tagged, or why there
+    /// is none.
+    ///
+    /// # Example
+    ///
+    /// Count the packets cut short, apart from those that aren't IPv4:
+    ///
+    /// ```
+    /// #[map("parse_errors")]
+    /// static mut parse_errors: HashMap<u32, u64> = HashMap::with_max_entries(4);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn count_truncated(ctx: XdpContext) -> XdpAction {
+    ///     match ctx.try_ip() {
+    ///         Ok(_) | Err(XdpParseError::UnsupportedL3) => (),
+    ///         Err(err) => unsafe {
+    ///             let count = parse_errors.get(err as u32).copied().unwrap_or(0);
+    ///             parse_errors.set(err as u32, count + 1);
+    ///         },
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn try_ip(&self) -> Result<*const iphdr, XdpParseError> {
+        NetworkBuffer::try_ip(self)
+    }
+
+    /// Returns the packet's `IPv6` header if present, possibly VLAN tagged.
+    #[inline]
+    pub fn ipv6(&self) -> Option<*const ipv6hdr> {
+        self.try_ipv6().ok()
+    }
+
+    /// Returns the packet's `IPv6` header, possibly VLAN tagged, or why
+    /// there is none.
+    #[inline]
+    pub fn try_ipv6(&self) -> Result<*const ipv6hdr, XdpParseError> {
+        NetworkBuffer::try_ipv6(self)
+    }
+
+    /// Returns the packet's `IP` header, whichever the version.
+    ///
+    /// # Example
+    ///
+    /// Only let IPv6 traffic through to port 22:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn ssh_over_ipv6(ctx: XdpContext) -> XdpAction {
+    ///     match (ctx.ip_header(), ctx.transport()) {
+    ///         (Some(IpHeader::V4(_)), Some(transport)) if transport.dest() == 22 => {
+    ///             XdpAction::Drop
+    ///         }
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn ip_header(&self) -> Option<IpHeader> {
+        NetworkBuffer::ip_header(self)
+    }
+
+    /// Returns the source MAC address of the packet.
+    #[inline]
+    pub fn source_mac(&self) -> Option<[u8; 6]> {
+        self.eth().map(|eth| unsafe { (*eth).h_source })
+    }
+
+    /// Returns the destination MAC address of the packet.
+    #[inline]
+    pub fn dest_mac(&self) -> Option<[u8; 6]> {
+        self.eth().map(|eth| unsafe { (*eth).h_dest })
+    }
+
+    /// Returns a copy of the first `N` bytes of the packet, or `None` if the
+    /// packet is shorter.
+    ///
+    /// The bytes are checked against the end of the packet at once, then
+    /// copied to the stack, which the verifier accepts for small `N`, and
+    /// is cheaper than reading them one at a time. The stack of programs is
+    /// 512 bytes, which `N` has to fit in along with everything else.
+    ///
+    /// # Example
+    ///
+    /// Spread packets over 4 CPUs by the FNV-1a hash of their first 40
+    /// bytes, as computed by `hash::fnv1a`:
+    ///
+    /// ```
+    /// #[map("cpus")]
+    /// static mut cpus: CpuMap = CpuMap::with_max_entries(4);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn spread(ctx: XdpContext) -> XdpAction {
+    ///     match ctx.copy_header::<40>() {
+    ///         Some(header) => unsafe { cpus.redirect(fnv1a(&header) % 4, 0) },
+    ///         None => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn copy_header<const N: usize>(&self) -> Option<[u8; N]> {
+        let start = self.data_start();
+        unsafe {
+            if start.add(N) > self.data_end() {
+                return None;
+            }
+            Some(ptr::read_unaligned(start as *const [u8; N]))
+        }
+    }
+
+    /// Returns the byte at offset `off` from the start of the packet, or
+    /// `None` if the packet is shorter.
+    ///
+    /// Like `load_half` and `load_word`, this reads the packet the way the
+    /// `ldb`, `ldh` and `ld` instructions of classic BPF do, which makes it
+    /// easy to port filters written for them, e.g. the output of
+    /// `tcpdump -d`. Offsets are relative to the Ethernet header.
+    ///
+    /// # Example
+    ///
+    /// Drop IPv4 packets with options, as `tcpdump 'ip[0] & 0xf != 5'`
+    /// would match them:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn drop_ip_options(ctx: XdpContext) -> XdpAction {
+    ///     match (ctx.load_half(12), ctx.load_byte(14)) {
+    ///         (Some(0x0800), Some(ver_ihl)) if ver_ihl & 0xf != 5 => XdpAction::Drop,
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn load_byte(&self, off: usize) -> Option<u8> {
+        self.load::<1>(off).map(|[byte]| byte)
+    }
+
+    /// Returns the 16 bit word at offset `off` from the start of the packet,
+    /// in host byte order, or `None` if the packet is shorter.
+    ///
+    /// The packet is read in network byte order.
+    #[inline]
+    pub fn load_half(&self, off: usize) -> Option<u16> {
+        self.load(off).map(u16::from_be_bytes)
+    }
+
+    /// Returns the 32 bit word at offset `off` from the start of the packet,
+    /// in host byte order, or `None` if the packet is shorter.
+    ///
+    /// The packet is read in network byte order.
+    #[inline]
+    pub fn load_word(&self, off: usize) -> Option<u32> {
+        self.load(off).map(u32::from_be_bytes)
+    }
+
+    #[inline]
+    fn load<const N: usize>(&self, off: usize) -> Option<[u8; N]> {
+        let end = off.checked_add(N)?;
+        if end > self.len() as usize {
+            return None;
+        }
+        unsafe {
+            let start = self.data_start();
+            // checked again against `data_end` for the verifier, which
+            // doesn't know what `len()` means
+            if start.add(end) > self.data_end() {
+                return None;
+            }
+            Some(ptr::read_unaligned(start.add(off) as *const [u8; N]))
+        }
+    }
+
+    /// Returns the source address of an IPv4 packet, in host byte order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn block_10_8(ctx: XdpContext) -> XdpAction {
+    ///     match ctx.source_ipv4() {
+    ///         Some(addr) if addr >> 24 == 10 => XdpAction::Drop,
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn source_ipv4(&self) -> Option<u32> {
+        self.ip().map(|ip| ntohl(unsafe { (*ip).saddr }))
+    }
+
+    /// Returns the destination address of an IPv4 packet, in host byte
+    /// order.
+    #[inline]
+    pub fn dest_ipv4(&self) -> Option<u32> {
+        self.ip().map(|ip| ntohl(unsafe { (*ip).daddr }))
+    }
+
+    /// Returns the source address of an IPv6 packet.
+    #[inline]
+    pub fn source_ipv6(&self) -> Option<[u8; 16]> {
+        self.ipv6().map(|ip| unsafe { ipv6_addr(ip, IPV6_SADDR) })
+    }
+
+    /// Returns the destination address of an IPv6 packet.
+    #[inline]
+    pub fn dest_ipv6(&self) -> Option<[u8; 16]> {
+        self.ipv6().map(|ip| unsafe { ipv6_addr(ip, IPV6_DADDR) })
+    }
+
+    /// Returns the packet's IPv6 header.
+    #[inline]
+    pub fn ipv6_header(&self) -> Option<Ipv6Header> {
+        self.ipv6().map(|ip| Ipv6Header { ip })
+    }
+
+    /// Returns the packet's `IP` header along with its options, if the
+    /// packet is long enough to hold them.
+    ///
+    /// # Example
+    ///
+    /// Drop packets with corrupt IP headers:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn drop_bad_checksums(ctx: XdpContext) -> XdpAction {
+    ///     match ctx.ipv4_header() {
+    ///         Some(ip) if !ip.checksum_valid() => XdpAction::Drop,
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn ipv4_header(&self) -> Option<Ipv4Header> {
+        let ip = self.ip()?;
+        unsafe {
+            let end = (*self.ctx).data_end as *const u8;
+            let len = ip_ihl(ip) as usize * 4;
+            if len < mem::size_of::<iphdr>() || (ip as *const u8).add(len) > end {
+                return None;
+            }
+            Some(Ipv4Header { ip, end })
+        }
+    }
+
+    /// Returns the packet's transport header if present.
+    ///
+    /// In IPv6 packets, up to `IPV6_EXT_HEADERS_MAX` extension headers are
+    /// skipped to get to it. IP fragments other than the first have none,
+    /// see `is_fragment()`.
+    #[inline]
+    pub fn transport(&self) -> Option<Transport> {
+        self.try_transport().ok()
+    }
+
+    /// Returns whether the packet is an IP fragment, the first one
+    /// included, or `None` if it isn't IP or its headers are cut short.
+    ///
+    /// Only the first fragment of a datagram holds the transport header,
+    /// which the others can't be matched against.
+    ///
+    /// # Example
+    ///
+    /// Drop fragmented traffic, which port filters can't tell apart:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn no_fragments(ctx: XdpContext) -> XdpAction {
+    ///     match ctx.is_fragment() {
+    ///         Some(true) => XdpAction::Drop,
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn is_fragment(&self) -> Option<bool> {
+        NetworkBuffer::is_fragment(self)
+    }
+
+    /// Returns the packet's transport header, or why there is none.
+    #[inline]
+    pub fn try_transport(&self) -> Result<Transport, XdpParseError> {
+        NetworkBuffer::try_transport(self)
+    }
+
+    /// Returns the packet's data starting after the transport headers.
+    #[inline]
+    pub fn data(&self) -> Option<Data> {
+        self.try_data().ok()
+    }
+
+    /// Returns the packet's data starting after the transport headers, or
+    /// why the headers couldn't be parsed.
+    #[inline]
+    pub fn try_data(&self) -> Result<Data, XdpParseError> {
+        NetworkBuffer::try_data(self)
+    }
+
+    /// Returns the packet encapsulated in the packet, if it is a VXLAN or
+    /// GRE packet, see `NetworkBuffer::tunnel()`.
+    #[inline]
+    pub fn tunnel(&self) -> Option<Tunnel> {
+        NetworkBuffer::tunnel(self)
+    }
+
+    /// Returns the packet's data starting after the transport headers, for
+    /// writing.
+    #[inline]
+    pub fn data_mut(&mut self) -> Option<DataMut<'_>> {
+        self.data().map(|data| DataMut {
+            data,
+            _ctx: PhantomData,
+        })
+    }
+
+    /// Returns the whole packet, starting at the Ethernet header, for
+    /// writing.
+    ///
+    /// Programs bouncing packets back with `XdpAction::Tx` use it to swap
+    /// the addresses and ports around.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn swap_macs(mut ctx: XdpContext) -> XdpAction {
+    ///     let mut packet = match ctx.packet_mut() {
+    ///         Some(packet) => packet,
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     let eth = match packet.slice_mut(12) {
+    ///         Some(eth) => eth,
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     let (dest, source) = eth.split_at_mut(6);
+    ///     dest.swap_with_slice(source);
+    ///     XdpAction::Tx
+    /// }
+    /// ```
+    #[inline]
+    pub fn packet_mut(&mut self) -> Option<DataMut<'_>> {
+        unsafe {
+            let base = (*self.ctx).data as *const u8;
+            if base > (*self.ctx).data_end as *const u8 {
+                return None;
+            }
+            Some(DataMut {
+                data: Data {
+                    ctx: self.ctx,
+                    base,
+                },
+                _ctx: PhantomData,
+            })
+        }
+    }
+
+    /// Looks up the TCP socket an IPv4 packet is headed to, see
+    /// `SocketRef::lookup_tcp`.
+    #[inline]
+    pub fn sk_lookup_tcp(&self) -> Option<SocketRef> {
+        let (mut tuple, size, proto) = self.ipv4_tuple()?;
+        if proto != IPPROTO_TCP as u8 {
+            return None;
+        }
+        SocketRef::lookup_tcp(self.ctx, &mut tuple, size)
+    }
+
+    /// Looks up the conntrack entry of an IPv4 TCP or UDP packet, see
+    /// `ConntrackEntry::lookup_xdp`.
+    #[inline]
+    pub fn ct_lookup(&self) -> Option<ConntrackEntry> {
+        let (mut tuple, size, proto) = self.ipv4_tuple()?;
+        ConntrackEntry::lookup_xdp(self.ctx, &mut tuple, size, proto)
+    }
+
+    /// Returns the tuple of an IPv4 TCP or UDP packet, along with the size
+    /// of the tuple and the transport protocol.
+    #[inline]
+    fn ipv4_tuple(&self) -> Option<(bpf_sock_tuple, usize, u8)> {
+        let ip = self.ip()?;
+        let transport = self.transport()?;
+        let mut tuple: bpf_sock_tuple = unsafe { mem::zeroed() };
+        unsafe {
+            let v4 = &mut tuple.__bindgen_anon_1.ipv4;
+            v4.saddr = (*ip).saddr;
+            v4.daddr = (*ip).daddr;
+            v4.sport = htons(transport.source());
+            v4.dport = htons(transport.dest());
+        }
+        let size = unsafe { mem::size_of_val(&tuple.__bindgen_anon_1.ipv4) };
+
+        Some((tuple, size, unsafe { (*ip).protocol }))
+    }
+
+    /// Generates a SYN cookie for the TCP SYN in the packet, to be answered
+    /// on behalf of the listening socket `sk` (kernel 5.3 or later).
+    ///
+    /// Returns the cookie and the MSS encoded in it, or `None` if the packet
+    /// isn't a TCP SYN or `sk` isn't a listener with SYN cookies enabled.
+    #[inline]
+    pub fn tcp_gen_syncookie(&self, sk: &SocketRef) -> Option<(u32, u16)> {
+        let (ip, tcp, tcp_len) = self.tcp_headers()?;
+        let ret = unsafe {
+            bpf_tcp_gen_syncookie(
+                sk.as_ptr(),
+                ip as *mut c_void,
+                mem::size_of::<iphdr>() as u32,
+                tcp as *mut tcphdr,
+                tcp_len,
+            )
+        };
+        if ret < 0 {
+            return None;
+        }
+
+        Some((ret as u32, (ret >> 32) as u16))
+    }
+
+    /// Checks whether the TCP ACK in the packet carries a valid SYN cookie
+    /// of the listening socket `sk` (kernel 5.2 or later).
+    #[inline]
+    pub fn tcp_check_syncookie(&self, sk: &SocketRef) -> bool {
+        let (ip, tcp, tcp_len) = match self.tcp_headers() {
+            Some(headers) => headers,
+            None => return false,
+        };
+        unsafe {
+            bpf_tcp_check_syncookie(
+                sk.as_ptr(),
+                ip as *mut c_void,
+                mem::size_of::<iphdr>() as u32,
+                tcp as *mut tcphdr,
+                tcp_len,
+            ) == 0
+        }
+    }
+
+    /// Counts a dropped packet under `reason`, and returns `XdpAction::Drop`.
+    ///
+    /// The drops are counted per CPU in the `xdp_drop_reasons` map, which is
+    /// part of every program using this, and summed up in userspace by
+    /// `redbpf::Module::drop_reasons`. Reasons from `DROP_REASONS_MAX` on
+    /// are dropped without being counted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// const DROP_BAD_PORT: u32 = 1;
+    /// const DROP_NOT_IP: u32 = 2;
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn firewall(ctx: XdpContext) -> XdpAction {
+    ///     let transport = match ctx.transport() {
+    ///         Some(transport) => transport,
+    ///         None => return ctx.drop_with_reason(DROP_NOT_IP),
+    ///     };
+    ///     if transport.dest() != 80 && transport.dest() != 443 {
+    ///         return ctx.drop_with_reason(DROP_BAD_PORT);
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn drop_with_reason(&self, reason: u32) -> XdpAction {
+        unsafe { count(core::ptr::addr_of_mut!(DROP_REASONS), reason) };
+
+        XdpAction::Drop
+    }
+
+    /// Returns the IPv4 and TCP headers, and the length of the TCP header
+    /// including options, checked against the end of the packet.
+    #[inline]
+    fn tcp_headers(&self) -> Option<(*const iphdr, *const tcphdr, u32)> {
+        let ip = self.ip()?;
+        let tcp = match self.transport()? {
+            Transport::TCP(tcp) => tcp,
+            _ => return None,
+        };
+        unsafe {
+            let len = tcp_doff(tcp) as u32 * 4;
+            if len < mem::size_of::<tcphdr>() as u32
+                || (tcp as *const u8).add(len as usize) > (*self.ctx).data_end as *const u8
+            {
+                return None;
+            }
+            Some((ip, tcp, len))
+        }
+    }
+}
+
+/// A packet the `XdpContext` parsers can run on in userspace, to test and
+/// fuzz them without a kernel.
+///
+/// `xdp_md` holds 32-bit packet pointers, so the packet is copied to memory
+/// mapped below 4 GiB. It ends right before an inaccessible page, which
+/// turns reads past the end of the packet into a crash rather than a read
+/// of whatever lies next to it.
+///
+/// Only the parsers can run: calling BPF helpers or kfuncs outside of the
+/// kernel crashes.
+///
+/// `redbpf-probes/fuzz` runs the parsers on random packets with
+/// `cargo fuzz run xdp_parsers`.
+///
+/// # Example
+///
+/// ```
+/// use redbpf_probes::xdp::TestPacket;
+///
+/// let mut packet = TestPacket::new(&[0; 14]).unwrap();
+/// let ctx = packet.context();
+/// assert!(ctx.eth().is_some());
+/// assert!(ctx.ip().is_none());
+/// ```
+#[cfg(feature = "test-utils")]
+pub struct TestPacket {
+    md: xdp_md,
+    map: *mut c_void,
+    map_len: usize,
+}
+
+#[cfg(feature = "test-utils")]
+impl TestPacket {
+    /// Copies `packet` to memory `XdpContext` can point to, or returns
+    /// `None` if there's none left below 4 GiB.
+    pub fn new(packet: &[u8]) -> Option<TestPacket> {
+        use libc::{mmap, mprotect, munmap, sysconf, MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE};
+        use libc::{_SC_PAGESIZE, PROT_NONE, PROT_READ, PROT_WRITE};
+
+        #[cfg(target_arch = "x86_64")]
+        let low = libc::MAP_32BIT;
+        // elsewhere, hint at a low address and check where the mapping ended up
+        #[cfg(not(target_arch = "x86_64"))]
+        let low = 0;
+        let hint = 0x1000_0000 as *mut c_void;
+
+        let page = unsafe { sysconf(_SC_PAGESIZE) } as usize;
+        let data_len = (packet.len() + page - 1) / page * page;
+        let map_len = data_len + page;
+        unsafe {
+            let map = mmap(
+                hint,
+                map_len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS | low,
+                -1,
+                0,
+            );
+            if map == MAP_FAILED {
+                return None;
+            }
+            let guard = (map as *mut u8).add(data_len);
+            if guard as usize > u32::MAX as usize
+                || mprotect(guard as *mut c_void, page, PROT_NONE) != 0
+            {
+                munmap(map, map_len);
+                return None;
+            }
+            let data = guard.sub(packet.len());
+            data.copy_from_nonoverlapping(packet.as_ptr(), packet.len());
+
+            let mut md: xdp_md = mem::zeroed();
+            md.data = data as u32;
+            md.data_end = guard as u32;
+            md.data_meta = md.data;
+            Some(TestPacket { md, map, map_len })
+        }
+    }
+
+    /// Returns a context for the packet, which borrows it.
+    pub fn context(&mut self) -> TestContext<'_> {
+        TestContext {
+            ctx: XdpContext { ctx: &mut self.md },
+            _packet: PhantomData,
+        }
+    }
+}
+
+/// The `XdpContext` of a `TestPacket`, see `TestPacket::context`.
+#[cfg(feature = "test-utils")]
+pub struct TestContext<'a> {
+    ctx: XdpContext,
+    _packet: PhantomData<&'a mut TestPacket>,
+}
+
+#[cfg(feature = "test-utils")]
+impl Deref for TestContext<'_> {
+    type Target = XdpContext;
+
+    fn deref(&self) -> &XdpContext {
+        &self.ctx
+    }
+}
+
+#[cfg(feature = "test-utils")]
+impl DerefMut for TestContext<'_> {
+    fn deref_mut(&mut self) -> &mut XdpContext {
+        &mut self.ctx
+    }
+}
+
+#[cfg(feature = "test-utils")]
+impl Drop for TestPacket {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.map, self.map_len) };
+    }
+}
+
+impl NetworkBuffer for XdpContext {
+    type Context = xdp_md;
+
+    #[inline]
+    fn context(&self) -> *mut xdp_md {
+        self.ctx
+    }
+}
+
+/// Data type returned by calling `XdpContext::data()`, or
+/// `NetworkBuffer::data()` for other contexts
+pub struct Data<C = xdp_md> {
+    pub(crate) ctx: *const C,
+    pub(crate) base: *const u8,
+}
+
+impl<C: PacketBounds> Data<C> {
+    /// Returns the length of the linear part of the packet, from its start,
+    /// which the program can access directly.
+    ///
+    /// Multi-buffer packets continue past it, see `frame_len()`.
+    #[inline]
+    pub fn linear_len(&self) -> usize {
+        unsafe { C::data_end(self.ctx) as usize - C::data(self.ctx) as usize }
+    }
+
+    /// Returns the offset from the first byte of the packet.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        unsafe { self.base as usize - C::data(self.ctx) as usize }
+    }
+
+    /// Returns the length of the data.
+    ///
+    /// This is equivalent to the length of the packet minus the length of the headers.
+    #[inline]
+    pub fn len(&self) -> usize {
+        unsafe { C::data_end(self.ctx) as usize - self.base as usize }
+    }
+
+    /// Returns a `slice` of `len` bytes from the data.
+    #[inline]
+    pub fn slice(&self, len: usize) -> Option<&[u8]> {
+        unsafe {
+            if self.base.add(len) > C::data_end(self.ctx) {
+                return None;
+            }
+            let s = slice::from_raw_parts(self.base, len);
+            Some(s)
+        }
+    }
+
+    #[inline]
+    pub fn read<T>(&self) -> Option<T> {
+        unsafe {
+            let len = mem::size_of::<T>();
+            if self.base.add(len) > C::data_end(self.ctx) {
+                return None;
+            }
+            Some((self.base as *const T).read_unaligned())
+        }
+    }
+
+    /// Returns a `slice` of `len` bytes from the data, starting `offset`
+    /// bytes into it.
+    ///
+    /// # Example
+    ///
+    /// Read the question count of DNS queries, from their 12 byte header:
+    ///
+    /// ```
+    /// #[xdp]
+    /// pub extern "C" fn dns_questions(ctx: XdpContext) -> XdpAction {
+    ///     let data = match ctx.data() {
+    ///         Some(data) => data,
+    ///         None => return XdpAction::Pass,
+    ///     };
+    ///     match data.read_at::<u16>(4).map(ntohs) {
+    ///         Some(0) => XdpAction::Drop,
+    ///         _ => XdpAction::Pass,
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn slice_at(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        let start = self.checked_at(offset, len)?;
+        Some(unsafe { slice::from_raw_parts(start, len) })
+    }
+
+    /// Reads a `T` at `offset` bytes into the data, unaligned.
+    #[inline]
+    pub fn read_at<T>(&self, offset: usize) -> Option<T> {
+        let start = self.checked_at(offset, mem::size_of::<T>())?;
+        Some(unsafe { (start as *const T).read_unaligned() })
+    }
+
+    /// Returns a pointer `offset` bytes into the data, if `len` bytes
+    /// starting there are in the packet.
+    #[inline]
+    fn checked_at(&self, offset: usize, len: usize) -> Option<*const u8> {
+        // checked first, so large offsets can't wrap around
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len() => (),
+            _ => return None,
+        }
+        unsafe {
+            let start = self.base.add(offset);
+            // and against the end of the packet again, for the verifier
+            if start.add(len) > C::data_end(self.ctx) {
+                return None;
+            }
+            Some(start)
+        }
+    }
+}
+
+impl Data<xdp_md> {
+    /// Returns the length of the whole frame the data is part of, from the
+    /// start of the packet, including the fragments of multi-buffer packets
+    /// that `len()` and `linear_len()` leave out. See
+    /// `XdpContext::frame_len()`.
+    ///
+    /// The data spans `frame_len() - offset()` bytes of the frame.
+    ///
+    /// # Example
+    ///
+    /// Log the length of the frames along with their linear part, to see
+    /// how often the program misses out on data:
+    ///
+    /// ```
+    /// #[repr(C)]
+    /// pub struct FrameLen {
+    ///     frame_len: u32,
+    ///     linear_len: u32,
+    /// }
+    ///
+    /// #[map("frame_lens")]
+    /// static mut frame_lens: PerfMap<FrameLen> = PerfMap::with_max_entries(1024);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn log_frame_lens(ctx: XdpContext) -> XdpAction {
+    ///     if let Some(data) = ctx.data() {
+    ///         let lens = FrameLen {
+    ///             frame_len: data.frame_len(&ctx) as u32,
+    ///             linear_len: data.linear_len() as u32,
+    ///         };
+    ///         unsafe { frame_lens.insert(&ctx, MapData::with_payload(lens, 0, 0)) };
+    ///     }
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn frame_len(&self, ctx: &XdpContext) -> usize {
+        ctx.frame_len()
+    }
+}
+
+/// Data type returned by calling `XdpContext::data_mut()` and
+/// `XdpContext::packet_mut()`, which can be written to as well as read.
+///
+/// The verifier only lets a program write to the packet at offsets it has
+/// just checked against `data_end`, and loses track of the check once the
+/// pointers are spilled or recomputed. `slice_mut()` and `write()` do the
+/// check right before handing out the bytes, so write to the slice they
+/// return rather than keeping pointers to it around.
+///
+/// It borrows the context mutably, so that no two of them write to the
+/// same bytes.
+pub struct DataMut<'a, C = xdp_md> {
+    pub(crate) data: Data<C>,
+    pub(crate) _ctx: PhantomData<&'a mut C>,
+}
+
+impl<C> Deref for DataMut<'_, C> {
+    type Target = Data<C>;
+
+    fn deref(&self) -> &Data<C> {
+        &self.data
+    }
+}
+
+impl<C: PacketBounds> DataMut<'_, C> {
+    /// Returns a mutable `slice` of `len` bytes from the data.
+    #[inline]
+    pub fn slice_mut(&mut self, len: usize) -> Option<&mut [u8]> {
+        unsafe {
+            let base = self.data.base as *mut u8;
+            if base.add(len) as *const u8 > C::data_end(self.data.ctx) {
+                return None;
+            }
+            Some(slice::from_raw_parts_mut(base, len))
+        }
+    }
+
+    /// Writes `value` at the start of the data, unaligned.
+    #[inline]
+    pub fn write<T>(&mut self, value: &T) -> Option<()> {
+        unsafe {
+            let base = self.data.base as *mut u8;
+            let len = mem::size_of::<T>();
+            if base.add(len) as *const u8 > C::data_end(self.data.ctx) {
+                return None;
+            }
+            ptr::copy_nonoverlapping(value as *const T as *const u8, base, len);
+            Some(())
+        }
+    }
+}
+
+/// Offset of the source address in the IPv6 header.
+const IPV6_SADDR: usize = 8;
+/// Offset of the destination address in the IPv6 header.
+const IPV6_DADDR: usize = 24;
+
+/// Returns the length of a frame, given the length of its linear part and
+/// the one `bpf_xdp_get_buff_len` returned, or 0 if it's not available.
+#[inline]
+fn frame_len(linear_len: usize, buff_len: u64) -> usize {
+    linear_len.max(buff_len as usize)
+}
+
+/// Returns whether the metadata area `[meta, data)` holds `len` bytes, in
+/// front of a packet `[data, end)`.
+///
+/// The pointers are compared rather than the length of the area, for the
+/// verifier to learn that the `len` bytes at `meta` can be accessed.
+#[inline]
+fn metadata_fits(meta: *const u8, data: *const u8, end: *const u8, len: usize) -> bool {
+    meta.wrapping_add(len) <= data && data <= end
+}
+
+/// Reads the address at `offset` of the IPv6 header `ip`, whose layout in
+/// the bindings changes with the kernel version.
+#[inline]
+unsafe fn ipv6_addr(ip: *const ipv6hdr, offset: usize) -> [u8; 16] {
+    ((ip as *const u8).add(offset) as *const [u8; 16]).read_unaligned()
+}
+
+/// Reads the VLAN tag at `tag`, if the EtherType `proto` in front of it is
+/// a tag protocol, along with the EtherType the tag is followed by.
+#[inline]
+unsafe fn vlan_tag(proto: u16, tag: *const u8, end: *const u8) -> Option<(VlanTag, u16)> {
+    if proto != ETH_P_8021Q as u16 && proto != ETH_P_8021AD as u16 {
+        return None;
+    }
+    // the TCI, then the encapsulated protocol
+    let fields = tag as *const __be16;
+    if fields.add(2) as *const u8 > end {
+        return None;
+    }
+    let tci = ntohs(fields.read_unaligned());
+    let inner = ntohs(fields.add(1).read_unaligned());
+
+    Some((VlanTag { proto, tci }, inner))
+}
+
+/// Returns the EtherType of the frame at `eth`, after skipping up to
+/// `VLAN_TAGS_MAX` VLAN tags, or `None` if the headers run past `end`.
+#[inline]
+unsafe fn eth_proto(eth: *const ethhdr, end: *const u8) -> Option<u16> {
+    eth_payload(eth, end).map(|(proto, _)| proto)
+}
+
+/// Like `eth_proto`, along with the start of the header the EtherType is
+/// the protocol of.
+#[inline]
+pub(crate) unsafe fn eth_payload(eth: *const ethhdr, end: *const u8) -> Option<(u16, *const u8)> {
+    if eth.add(1) as *const u8 > end {
+        return None;
+    }
+    let mut proto = ntohs((*eth).h_proto);
+    let mut tag = eth.add(1) as *const u8;
+    for _ in 0..VLAN_TAGS_MAX {
+        if proto != ETH_P_8021Q as u16 && proto != ETH_P_8021AD as u16 {
+            break;
+        }
+        proto = vlan_tag(proto, tag, end)?.1;
+        tag = tag.add(2 * mem::size_of::<__be16>());
+    }
+
+    Some((proto, tag))
+}
+
+/// Returns the transport protocol of the IP header `l3` of EtherType
+/// `proto`, or `None` if it isn't IP or the headers run past `end`.
+///
+/// Fragments other than the first have the protocol of their datagram,
+/// though not its transport header.
+#[inline]
+unsafe fn l4_protocol(proto: u16, l3: *const u8, end: *const u8) -> Option<u8> {
+    ip_payload(proto, l3, end)
+        .ok()
+        .map(|(protocol, _, _)| protocol)
+}
+
+/// Like `l4_protocol`, along with the start of the transport header, which
+/// may lie past `end`.
+///
+/// Fails with `Fragment` for fragments other than the first, whose payload
+/// continues the one of the previous fragment.
+#[inline]
+pub(crate) unsafe fn l4_header(
+    proto: u16,
+    l3: *const u8,
+    end: *const u8,
+) -> Result<(u8, *const u8), XdpParseError> {
+    let (protocol, base, fragment) = ip_payload(proto, l3, end)?;
+    if fragment == IpFragment::Rest {
+        return Err(XdpParseError::Fragment);
+    }
+    Ok((protocol, base))
+}
+
+/// Returns whether the IP packet `l3` of EtherType `proto` is a fragment.
+#[inline]
+pub(crate) unsafe fn is_fragment(
+    proto: u16,
+    l3: *const u8,
+    end: *const u8,
+) -> Result<bool, XdpParseError> {
+    let (_, _, fragment) = ip_payload(proto, l3, end)?;
+    Ok(fragment != IpFragment::Whole)
+}
+
+/// Like `l4_header`, for any fragment, along with where the packet stands
+/// in the fragments of its datagram.
+#[inline]
+unsafe fn ip_payload(
+    proto: u16,
+    l3: *const u8,
+    end: *const u8,
+) -> Result<(u8, *const u8, IpFragment), XdpParseError> {
+    use XdpParseError::*;
+    if proto == ETH_P_IP as u16 {
+        let ip = l3 as *const iphdr;
+        if ip.add(1) as *const u8 > end {
+            return Err(Truncated);
+        }
+        let frag_off = ntohs((*ip).frag_off);
+        let fragment = IpFragment::new(frag_off & IP_OFFSET, frag_off & IP_MF != 0);
+        return Ok(((*ip).protocol, l3.add(ip_ihl(ip) as usize * 4), fragment));
+    }
+    let ip6 = l3 as *const ipv6hdr;
+    if proto != ETH_P_IPV6 as u16 {
+        return Err(UnsupportedL3);
+    }
+    if ip6.add(1) as *const u8 > end {
+        return Err(Truncated);
+    }
+
+    let mut next = (*ip6).nexthdr;
+    let mut hdr = ip6.add(1) as *const u8;
+    let mut fragment = IpFragment::Whole;
+    for _ in 0..IPV6_EXT_HEADERS_MAX {
+        let len = match next {
+            NEXTHDR_FRAGMENT => 8,
+            NEXTHDR_HOP | NEXTHDR_ROUTING | NEXTHDR_DEST | NEXTHDR_MOBILITY => {
+                if hdr.add(2) > end {
+                    return Err(Truncated);
+                }
+                (*hdr.add(1) as usize + 1) * 8
+            }
+            NEXTHDR_AUTH => {
+                if hdr.add(2) > end {
+                    return Err(Truncated);
+                }
+                (*hdr.add(1) as usize + 2) * 4
+            }
+            _ => return Ok((next, hdr, fragment)),
+        };
+        // the next header field comes first in all of them
+        if hdr.add(len) > end {
+            return Err(Truncated);
+        }
+        if next == NEXTHDR_FRAGMENT {
+            // the offset in 8 byte units, then the more fragments flag
+            let offset = u16::from_be_bytes([*hdr.add(2), *hdr.add(3)]);
+            fragment = IpFragment::new(offset >> 3, offset & 1 != 0);
+        }
+        next = *hdr;
+        hdr = hdr.add(len);
+    }
+
+    Err(UnsupportedL4)
+}
+
+/// Increments the counter at `index` of the per-CPU array `map`.
+#[inline]
+unsafe fn count(map: *mut bpf_map_def, index: u32) {
+    let mut key = index;
+    let count =
+        bpf_map_lookup_elem(map as *mut c_void, &mut key as *mut _ as *mut c_void) as *mut u64;
+    // the count is per CPU, nothing else writes to it meanwhile
+    if !count.is_null() {
+        *count += 1;
+    }
+}
+
+/// Decides whether to sample an event, given a random number, for one in
+/// `rate_inv` events to be sampled.
+#[inline]
+fn sample(random: u32, rate_inv: u32) -> bool {
+    rate_inv <= 1 || random % rate_inv == 0
+}
+
+/// Convenience data type to exchange payload data.
+#[repr(C)]
+pub struct MapData<T> {
+    /// The custom data type to be exchanged with user space.
+    pub data: T,
+    offset: u32,
+    size: u32,
+    payload: [u8; 0],
+}
+
+impl<T> MapData<T> {
+    /// Create a new `MapData` value that includes only `data` and no packet
+    /// payload.
+    pub fn new(data: T) -> Self {
+        MapData::<T>::with_payload(data, 0, 0)
+    }
+
+    /// Create a new `MapData` value that includes `data` and `size` payload
+    /// bytes, where the interesting part of the payload starts at `offset`.
+    ///
+    /// The payload can then be retrieved calling `MapData::payload()`. An
+    /// `offset` past `size` leaves the payload empty, see
+    /// `try_with_payload` to reject it instead.
+    pub fn with_payload(data: T, offset: u32, size: u32) -> Self {
+        Self {
+            data,
+            payload: [],
+            offset,
+            size,
+        }
+    }
+
+    /// Like `with_payload`, but returns `None` if `offset` lies past the
+    /// `size` payload bytes.
+    pub fn try_with_payload(data: T, offset: u32, size: u32) -> Option<Self> {
+        if offset > size {
+            return None;
+        }
+        Some(MapData::with_payload(data, offset, size))
+    }
+
+    /// Return the payload if any, skipping the initial `offset` bytes.
+    ///
+    /// The payload is empty if `offset` lies past the payload bytes.
+    pub fn payload(&self) -> &[u8] {
+        let len = self.size.saturating_sub(self.offset) as usize;
+        if len == 0 {
+            return &[];
+        }
+        unsafe {
+            let base = self.payload.as_ptr().add(self.offset as usize);
+            slice::from_raw_parts(base, len)
+        }
+    }
+}
+
+/// Perf events map.
+///
+/// Similar to `PerfMap`, with additional XDP-only API.
+#[repr(transparent)]
+pub struct PerfMap<T>(PerfMapBase<MapData<T>>);
+
+impl<T> PerfMap<T> {
+    /// Creates a perf map with the specified maximum number of elements.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self(PerfMapBase::with_max_entries(max_entries))
+    }
+
+    /// Creates a perf map with the specified maximum number of elements and
+    /// `BPF_F_*` map flags.
+    pub const fn with_flags(max_entries: u32, map_flags: u32) -> Self {
+        Self(PerfMapBase::with_flags(max_entries, map_flags))
+    }
+
+    /// Insert a new event in the perf events array keyed by the current CPU number.
+    ///
+    /// Each array can hold up to `max_entries` events, see `with_max_entries`.
+    /// If you want to use a key other than the current CPU, see
+    /// `insert_with_flags`.
+    ///
+    /// `packet_size` specifies the number of bytes from the current packet that
+    /// the kernel should append to the event data.
+    #[inline]
+    pub fn insert(&mut self, ctx: &XdpContext, data: MapData<T>) {
+        let size = data.size;
+        self.0
+            .insert_with_flags(ctx.inner(), data, PerfMapFlags::with_xdp_size(size))
+    }
+
+    /// Insert a new event in the perf events array keyed by the index and with
+    /// the additional xdp payload data specified in the given `PerfMapFlags`.
+    #[inline]
+    pub fn insert_with_flags(&mut self, ctx: &XdpContext, data: MapData<T>, mut flags: PerfMapFlags) {
+        flags.xdp_size = data.size;
+        self.0.insert_with_flags(ctx.inner(), data, flags)
+    }
+
+    /// Inserts a new event like `insert`, for one in `rate_inv` calls on
+    /// average, picked at random. A `rate_inv` of 0 or 1 inserts every
+    /// event.
+    ///
+    /// Returns whether the event was inserted. The calls and the inserted
+    /// events are counted per CPU in the `xdp_samples` map, which is part of
+    /// every program using this, so that userspace can scale up what it
+    /// derives from the events, see `redbpf::Module::sample_counts`.
+    ///
+    /// # Example
+    ///
+    /// Export the headers of one in 100 packets:
+    ///
+    /// ```
+    /// #[map("packets")]
+    /// static mut PACKETS: PerfMap<u32> = PerfMap::with_max_entries(1024);
+    ///
+    /// #[xdp]
+    /// pub extern "C" fn sampled_capture(ctx: XdpContext) -> XdpAction {
+    ///     let len = ctx.len();
+    ///     let data = MapData::with_payload(len, 0, len.min(128));
+    ///     unsafe { PACKETS.insert_sampled(&ctx, data, 100) };
+    ///
+    ///     XdpAction::Pass
+    /// }
+    /// ```
+    #[inline]
+    pub fn insert_sampled(&mut self, ctx: &XdpContext, data: MapData<T>, rate_inv: u32) -> bool {
+        let samples = core::ptr::addr_of_mut!(SAMPLES);
+        unsafe { count(samples, SAMPLES_SEEN) };
+        if !sample(bpf_get_prandom_u32(), rate_inv) {
+            return false;
+        }
+        unsafe { count(samples, SAMPLES_EXPORTED) };
+        self.insert(ctx, data);
+
+        true
+    }
+}
+
+/// An event of an XDP `RingBuf`, followed by room for `N` bytes of packet.
+#[repr(C)]
+struct PacketEvent<T, const N: usize> {
+    data: MapData<T>,
+    payload: [u8; N],
+}
+
+/// Ring buffer map.
+///
+/// Similar to `maps::RingBuf`, with events carrying up to `N` bytes of the
+/// packet, like the events of `PerfMap` do. The bytes follow the `MapData`
+/// in the event, and `MapData::payload()` returns them on the user-space
+/// side.
+///
+/// # Example
+///
+/// Export the first 64 bytes of every packet:
+///
+/// ```
+/// #[map("packets")]
+/// static mut PACKETS: RingBuf<u32, 64> = RingBuf::with_max_entries(1024 * 1024);
+///
+/// #[xdp]
+/// pub extern "C" fn capture(ctx: XdpContext) -> XdpAction {
+///     let len = ctx.len();
+///     let _ = unsafe { PACKETS.insert(&ctx, MapData::with_payload(len, 0, len)) };
+///
+///     XdpAction::Pass
+/// }
+/// ```
+#[repr(transparent)]
+pub struct RingBuf<T, const N: usize>(RingBufBase<PacketEvent<T, N>>);
+
+impl<T, const N: usize> RingBuf<T, N> {
+    /// Creates a ring buffer of `size` bytes.
+    ///
+    /// `size` must be a power of two multiple of the page size.
+    pub const fn with_max_entries(size: u32) -> Self {
+        Self(RingBufBase::with_max_entries(size))
+    }
+
+    /// Creates a ring buffer of `size` bytes and `BPF_F_*` map flags.
+    pub const fn with_flags(size: u32, map_flags: u32) -> Self {
+        Self(RingBufBase::with_flags(size, map_flags))
+    }
+
+    /// Sends `data` to user-space, along with the first `data.size` bytes of
+    /// the packet, cut down to `N` bytes and to the length of the packet.
+    ///
+    /// Fails if the buffer is full.
+    #[inline]
+    pub fn insert(&mut self, ctx: &XdpContext, mut data: MapData<T>) -> Result<(), ()> {
+        let mut event = self.0.reserve().ok_or(())?;
+        let event_ptr = event.as_mut_ptr();
+        let payload = unsafe { ptr::addr_of_mut!((*event_ptr).payload) } as *mut u8;
+        let md = unsafe { *ctx.ctx };
+        let start = md.data as *const u8;
+        let end = md.data_end as *const u8;
+        let mut size = 0;
+        // one byte at a time, which the verifier can bound
+        for i in 0..N {
+            let byte = unsafe { start.add(i) };
+            if i >= data.size as usize || byte >= end {
+                break;
+            }
+            unsafe { payload.add(i).write(*byte) };
+            size = i + 1;
+        }
+        data.size = size as u32;
+        data.offset = data.offset.min(data.size);
+        unsafe { ptr::addr_of_mut!((*event_ptr).data).write(data) };
+        event.submit();
+
+        Ok(())
+    }
+}
+
+/// Map of network devices to redirect packets to, `BPF_MAP_TYPE_DEVMAP`.
+///
+/// The values are the indices of the interfaces, set from user space, e.g.
+/// with `redbpf::Array`. Redirecting through the map rather than with
+/// `XdpContext::redirect` lets the kernel send packets out in bulk.
+///
+/// # Example
+///
+/// Round-robin packets across two interfaces:
+///
+/// ```
+/// #[map("tx_ports")]
+/// static mut TX_PORTS: DevMap = DevMap::with_max_entries(2);
+///
+/// #[map("next_port")]
+/// static mut NEXT_PORT: Array<u32> = Array::with_max_entries(1);
+///
+/// #[xdp]
+/// pub extern "C" fn round_robin(_ctx: XdpContext) -> XdpAction {
+///     let port = match unsafe { NEXT_PORT.get_mut(0) } {
+///         Some(next) => {
+///             *next = (*next + 1) % 2;
+///             *next
+///         }
+///         None => return XdpAction::Aborted,
+///     };
+///     // pass the packet on to the stack if the port isn't set
+///     unsafe { TX_PORTS.redirect(port, xdp_action_XDP_PASS as u64) }
+/// }
+/// ```
+#[repr(transparent)]
+pub struct DevMap {
+    def: bpf_map_def,
+}
+
+impl DevMap {
+    /// Creates a map of `max_entries` devices.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self::with_flags(max_entries, 0)
+    }
+
+    /// Creates a map of `max_entries` devices with `BPF_F_*` map flags.
+    pub const fn with_flags(max_entries: u32, map_flags: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_DEVMAP,
+                key_size: mem::size_of::<u32>() as u32,
+                value_size: mem::size_of::<u32>() as u32,
+                max_entries,
+                map_flags,
+            },
+        }
+    }
+
+    /// Redirects the packet out of the device at `key`, and returns the
+    /// action the program must return for the redirect to happen.
+    ///
+    /// Returns `XdpAction::Redirect` on success. If there's no device at
+    /// `key`, returns the action in the lower two bits of `flags` instead,
+    /// e.g. `xdp_action_XDP_PASS`, or `XdpAction::Aborted` for 0. From
+    /// kernel 5.15 on, `BPF_F_BROADCAST` sends the packet out of every
+    /// device of the map, except the one it came in through with
+    /// `BPF_F_EXCLUDE_INGRESS`.
+    #[inline]
+    pub fn redirect(&mut self, key: u32, flags: u64) -> XdpAction {
+        let code = unsafe { bpf_redirect_map(&mut self.def as *mut _ as *mut c_void, key, flags) };
+        redirect_action(code)
+    }
+}
+
+/// Map of CPUs to hand packets over to, `BPF_MAP_TYPE_CPUMAP`.
+///
+/// The values are the sizes of the queues of the CPUs, set from user space
+/// with `redbpf::CpuMap`. The packets are taken up by the network stack on
+/// the target CPU, which lets programs spread the load of the stack over
+/// other CPUs than the one the NIC interrupted.
+#[repr(transparent)]
+pub struct CpuMap {
+    def: bpf_map_def,
+}
+
+impl CpuMap {
+    /// Creates a map of `max_entries` CPUs.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self::with_flags(max_entries, 0)
+    }
+
+    /// Creates a map of `max_entries` CPUs with `BPF_F_*` map flags.
+    pub const fn with_flags(max_entries: u32, map_flags: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_CPUMAP,
+                key_size: mem::size_of::<u32>() as u32,
+                value_size: mem::size_of::<u32>() as u32,
+                max_entries,
+                map_flags,
+            },
+        }
+    }
+
+    /// Redirects the packet to the CPU at `key`, and returns the action the
+    /// program must return for the redirect to happen.
+    ///
+    /// Returns `XdpAction::Redirect` on success. If the CPU isn't in the
+    /// map, returns the action in the lower two bits of `flags` instead,
+    /// or `XdpAction::Aborted` for 0.
+    #[inline]
+    pub fn redirect(&mut self, key: u32, flags: u64) -> XdpAction {
+        let code = unsafe { bpf_redirect_map(&mut self.def as *mut _ as *mut c_void, key, flags) };
+        redirect_action(code)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_packet_event_layout() {
+        // the packet bytes follow the event, as with perf buffers
+        let event = mem::MaybeUninit::<PacketEvent<u64, 7>>::uninit();
+        let base = event.as_ptr() as usize;
+        let payload = unsafe { ptr::addr_of!((*event.as_ptr()).payload) } as usize;
+        assert_eq!(payload - base, mem::size_of::<MapData<u64>>());
+        assert_eq!(mem::size_of::<MapData<u64>>(), 16);
+    }
+
+    #[test]
+    fn test_map_data_payload() {
+        let event = |offset, size| PacketEvent {
+            data: MapData::with_payload(1u32, offset, size),
+            payload: [0, 1, 2, 3, 4, 5, 6, 7],
+        };
+        let full = event(0, 8);
+        assert_eq!(full.data.payload(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+        let skipped = event(3, 8);
+        assert_eq!(skipped.data.payload(), &[3, 4, 5, 6, 7]);
+        assert!(event(0, 0).data.payload().is_empty());
+        assert!(event(8, 8).data.payload().is_empty());
+        // the offset lies past the payload bytes
+        assert!(event(9, 8).data.payload().is_empty());
+        assert!(event(u32::MAX, 0).data.payload().is_empty());
+
+        assert!(MapData::try_with_payload(1u32, 0, 0).is_some());
+        assert!(MapData::try_with_payload(1u32, 8, 8).is_some());
+        assert!(MapData::try_with_payload(1u32, 9, 8).is_none());
+        assert!(MapData::new(1u32).payload().is_empty());
+    }
+
+    #[test]
+    fn test_redirect_action() {
+        let action = |code: u32| redirect_action(code as c_int);
+        assert_eq!(action(xdp_action_XDP_REDIRECT), XdpAction::Redirect);
+        // the fallback in the flags of bpf_redirect_map
+        assert_eq!(action(xdp_action_XDP_PASS), XdpAction::Pass);
+        assert_eq!(action(xdp_action_XDP_DROP), XdpAction::Drop);
+        assert_eq!(action(xdp_action_XDP_TX), XdpAction::Tx);
+        assert_eq!(action(xdp_action_XDP_ABORTED), XdpAction::Aborted);
+        assert_eq!(redirect_action(-22), XdpAction::Aborted);
+    }
+
+    #[test]
+    fn test_sample() {
+        // xorshift, standing in for bpf_get_prandom_u32
+        let mut state = 0x2545_f491u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        for &rate_inv in [2u32, 10, 100].iter() {
+            let calls = 100_000;
+            let sampled = (0..calls).filter(|_| sample(random(), rate_inv)).count();
+            let expected = calls / rate_inv as usize;
+            assert!(
+                sampled > expected * 9 / 10 && sampled < expected * 11 / 10,
+                "sampled {} of {} calls at 1/{}",
+                sampled,
+                calls,
+                rate_inv
+            );
+        }
+        assert!((0..1000).all(|_| sample(random(), 0)));
+        assert!((0..1000).all(|_| sample(random(), 1)));
+    }
+
+    /// Builds a frame with the VLAN `tags`, the EtherType `proto` and a few
+    /// bytes of payload.
+    fn frame(tags: &[(u16, u16)], proto: u16) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        for &(tpid, tci) in tags {
+            frame.extend_from_slice(&tpid.to_be_bytes());
+            frame.extend_from_slice(&tci.to_be_bytes());
+        }
+        frame.extend_from_slice(&proto.to_be_bytes());
+        frame.extend_from_slice(&[0xaa; 4]);
+        frame
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_truncated_packets() {
+        // Ethernet, IPv4 with 20 bytes of options, TCP to port 80, payload
+        let mut tcp = frame(&[], ETH_P_IP as u16);
+        tcp.truncate(14);
+        tcp.extend_from_slice(&[0x4a, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_TCP as u8]);
+        tcp.extend_from_slice(&[0; 30]);
+        tcp.extend_from_slice(&[0x30, 0x39, 0, 80, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x02]);
+        tcp.extend_from_slice(&[0; 6]);
+        tcp.extend_from_slice(b"hello");
+
+        for len in 0..=tcp.len() {
+            let mut packet = TestPacket::new(&tcp[..len]).unwrap();
+            let ctx = packet.context();
+            assert_eq!(ctx.len() as usize, len);
+            assert_eq!(ctx.eth().is_some(), len >= 14);
+            assert_eq!(ctx.eth_proto().is_some(), len >= 14);
+            assert_eq!(ctx.ip().is_some(), len >= 34);
+            assert_eq!(ctx.transport().is_some(), len >= 74);
+            match ctx.data() {
+                Some(data) => {
+                    assert_eq!(data.offset(), 74);
+                    assert_eq!(data.slice(len - 74), Some(&tcp[74..len]));
+                    assert!(data.slice(len - 73).is_none());
+                }
+                None => assert!(len < 74),
+            }
+        }
+        let mut packet = TestPacket::new(&tcp).unwrap();
+        assert_eq!(packet.context().transport().unwrap().dest(), 80);
+    }
+
+    /// Builds a TCP SYN over IPv4 with `options`, padded with `EOL`s.
+    #[cfg(feature = "test-utils")]
+    fn tcp_syn(options: &[u8]) -> Vec<u8> {
+        let mut syn = frame(&[], ETH_P_IP as u16);
+        syn.truncate(14);
+        syn.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_TCP as u8]);
+        syn.extend_from_slice(&[0; 10]);
+        let doff = 5 + (options.len() as u8 + 3) / 4;
+        syn.extend_from_slice(&[0x30, 0x39, 0, 80, 0, 0, 0, 0, 0, 0, 0, 0, doff << 4, 0x02]);
+        syn.extend_from_slice(&[0; 6]);
+        syn.extend_from_slice(options);
+        while syn.len() < 34 + doff as usize * 4 {
+            syn.push(TCP_OPTION_EOL);
+        }
+        syn
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_tcp_options() {
+        fn options(packet: &[u8]) -> Vec<(u8, Vec<u8>)> {
+            let mut packet = TestPacket::new(packet).unwrap();
+            let ctx = packet.context();
+            let transport = ctx.transport().unwrap();
+            let options = transport.tcp_options(&*ctx).unwrap();
+            options.map(|(kind, v)| (kind, v.to_vec())).collect()
+        }
+
+        // MSS 1460, SACK permitted, timestamps, NOP, window scale 7
+        let mut syn_options = vec![2, 4, 0x05, 0xb4, 4, 2, 8, 10, 0, 0, 0, 1, 0, 0, 0, 0];
+        syn_options.extend_from_slice(&[1, 3, 3, 7]);
+        let syn = tcp_syn(&syn_options);
+        let expected = vec![
+            (TCP_OPTION_MSS, vec![0x05, 0xb4]),
+            (TCP_OPTION_SACK_PERMITTED, vec![]),
+            (TCP_OPTION_TIMESTAMPS, vec![0, 0, 0, 1, 0, 0, 0, 0]),
+            (TCP_OPTION_WINDOW_SCALE, vec![7]),
+        ];
+        assert_eq!(options(&syn), expected);
+
+        // the packet ends within the timestamps
+        assert_eq!(options(&syn[..69]), &expected[..2]);
+        assert_eq!(options(&syn[..54]), vec![]);
+
+        // nothing past EOL, or an option with an invalid length
+        let mss = &expected[..1];
+        assert_eq!(options(&tcp_syn(&[2, 4, 0x05, 0xb4, 0, 1, 3, 3, 7])), mss);
+        assert_eq!(options(&tcp_syn(&[4, 1, 2, 4, 0x05, 0xb4])), vec![]);
+        assert_eq!(options(&tcp_syn(&[2, 4, 0x05, 0xb4, 3, 5, 7])), mss);
+        assert_eq!(options(&tcp_syn(&[])), vec![]);
+
+        // NOPs count towards TCP_OPTIONS_MAX
+        let mut nops = vec![TCP_OPTION_NOP; TCP_OPTIONS_MAX - 1];
+        nops.extend_from_slice(&[2, 4, 0x05, 0xb4, 1, 3, 3, 7]);
+        assert_eq!(options(&tcp_syn(&nops)), mss);
+
+        let mut packet = TestPacket::new(&frame(&[], ETH_P_IP as u16)).unwrap();
+        let ctx = packet.context();
+        let udp = Transport::UDP(ctx.data_start() as *const udphdr);
+        assert!(udp.tcp_options(&*ctx).is_none());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_metadata() {
+        // 8 bytes of metadata in front of the packet
+        let mut frame = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        frame.extend(self::frame(&[], ETH_P_IP as u16));
+        let mut packet = TestPacket::new(&frame).unwrap();
+        assert_eq!(packet.context().metadata(0), Some(&[][..]));
+        assert_eq!(packet.context().metadata(4), None);
+
+        packet.md.data += 8;
+        assert_eq!(packet.context().metadata(8), Some(&frame[..8]));
+        assert_eq!(packet.context().metadata(4), Some(&frame[..4]));
+        assert_eq!(packet.context().metadata(9), None);
+        assert_eq!(packet.context().len() as usize, frame.len() - 8);
+        assert!(packet.context().eth().is_some());
+
+        // drivers setting data_meta past data, or data past data_end
+        packet.md.data_meta = packet.md.data + 1;
+        assert_eq!(packet.context().metadata(0), None);
+        packet.md.data_meta = packet.md.data_end + 1;
+        packet.md.data = packet.md.data_end + 1;
+        assert_eq!(packet.context().metadata(0), None);
+    }
+
+    #[test]
+    fn test_metadata_fits() {
+        let buf = [0u8; 16];
+        let at = |i: usize| buf[i..].as_ptr();
+        assert!(metadata_fits(at(0), at(4), at(8), 4));
+        assert!(metadata_fits(at(0), at(4), at(8), 2));
+        assert!(!metadata_fits(at(0), at(4), at(8), 5));
+        assert!(metadata_fits(at(4), at(4), at(4), 0));
+        assert!(!metadata_fits(at(5), at(4), at(8), 0));
+        assert!(!metadata_fits(at(0), at(4), at(3), 4));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_parse_errors() {
+        use XdpParseError::*;
+        // the errors of try_ip, try_transport and try_data
+        let parse = |frame: &[u8]| {
+            let mut packet = TestPacket::new(frame).unwrap();
+            let ctx = packet.context();
+            [
+                ctx.try_ip().err(),
+                ctx.try_transport().err(),
+                ctx.try_data().err(),
+            ]
+        };
+
+        assert_eq!(parse(&[0; 13]), [Some(Truncated); 3]);
+        let arp = frame(&[], ETH_P_ARP as u16);
+        assert_eq!(parse(&arp), [Some(UnsupportedL3); 3]);
+        let mut packet = TestPacket::new(&arp).unwrap();
+        assert_eq!(packet.context().try_ipv6().err(), Some(UnsupportedL3));
+
+        // GRE
+        let mut gre = frame(&[], ETH_P_IP as u16);
+        gre.truncate(14);
+        gre.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, 47]);
+        gre.extend_from_slice(&[0; 18]);
+        let unsupported = Some(UnsupportedL4);
+        assert_eq!(parse(&gre), [None, unsupported, unsupported]);
+        assert_eq!(parse(&gre[..30]), [Some(Truncated); 3]);
+
+        let mut udp = gre.clone();
+        udp[23] = IPPROTO_UDP as u8;
+        assert_eq!(parse(&udp[..40]), [None, Some(Truncated), Some(Truncated)]);
+        assert_eq!(parse(&udp), [None; 3]);
+
+        let hop: &[u8] = &[0; 7];
+        let exts = [(NEXTHDR_DEST, hop); IPV6_EXT_HEADERS_MAX + 1];
+        let v6 = ipv6_frame(&[], &exts, IPPROTO_TCP as u8);
+        assert_eq!(parse(&v6), [Some(UnsupportedL3), unsupported, unsupported]);
+        let mut packet = TestPacket::new(&v6[..40]).unwrap();
+        assert_eq!(packet.context().try_ipv6().err(), Some(Truncated));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_addresses() {
+        let mut v4 = frame(&[], ETH_P_IP as u16);
+        v4.truncate(14);
+        v4[..12].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        v4.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_UDP as u8, 0, 0]);
+        v4.extend_from_slice(&[192, 168, 0, 1, 10, 0, 0, 2]);
+
+        let mut packet = TestPacket::new(&v4).unwrap();
+        let ctx = packet.context();
+        assert_eq!(ctx.dest_mac(), Some([1, 2, 3, 4, 5, 6]));
+        assert_eq!(ctx.source_mac(), Some([7, 8, 9, 10, 11, 12]));
+        assert_eq!(ctx.source_ipv4(), Some(0xc0a8_0001));
+        assert_eq!(ctx.dest_ipv4(), Some(0x0a00_0002));
+        assert_eq!(ctx.source_ipv6(), None);
+
+        let mut packet = TestPacket::new(&v4[..33]).unwrap();
+        let ctx = packet.context();
+        assert!(ctx.source_mac().is_some());
+        assert_eq!(ctx.source_ipv4(), None);
+        assert_eq!(ctx.dest_ipv4(), None);
+
+        let mut v6 = ipv6_frame(&[], &[], IPPROTO_UDP as u8);
+        let source: Vec<u8> = (0..16).collect();
+        let dest: Vec<u8> = (16..32).collect();
+        v6[14 + 8..14 + 24].copy_from_slice(&source);
+        v6[14 + 24..14 + 40].copy_from_slice(&dest);
+
+        let mut packet = TestPacket::new(&v6).unwrap();
+        let ctx = packet.context();
+        assert_eq!(ctx.source_ipv6().unwrap()[..], source[..]);
+        assert_eq!(ctx.dest_ipv6().unwrap()[..], dest[..]);
+        assert_eq!(ctx.source_ipv4(), None);
+    }
+
+    /// An Ethernet frame holding an ARP packet with the operation `op`,
+    /// from 1:2:3:4:5:6 at 10.0.0.1 for 10.0.0.2.
+    #[cfg(feature = "test-utils")]
+    fn arp_frame(tags: &[(u16, u16)], op: u16) -> Vec<u8> {
+        let mut arp = frame(tags, ETH_P_ARP as u16);
+        arp.truncate(arp.len() - 4);
+        arp.extend_from_slice(&[0, 1, 8, 0, 6, 4]);
+        arp.extend_from_slice(&op.to_be_bytes());
+        arp.extend_from_slice(&[1, 2, 3, 4, 5, 6, 10, 0, 0, 1]);
+        arp.extend_from_slice(&[0, 0, 0, 0, 0, 0, 10, 0, 0, 2]);
+        arp
+    }
+
+    /// Returns an IPv4 header of protocol `protocol`, without options.
+    #[cfg(feature = "test-utils")]
+    fn ipv4(protocol: u32) -> Vec<u8> {
+        let mut ip = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, protocol as u8, 0, 0];
+        ip.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        ip
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_tunnel() {
+        use crate::net::{Encap, VXLAN_PORT};
+
+        let tunneled = |frame: &[u8]| TestPacket::new(frame).unwrap().context().tunnel().is_some();
+
+        // the inner frame: Ethernet, IPv4, TCP from 12345 to 80
+        let mut inner = frame(&[], ETH_P_IP as u16);
+        inner.truncate(14);
+        inner.extend_from_slice(&ipv4(IPPROTO_TCP));
+        inner.extend_from_slice(&[0x30, 0x39, 0, 80, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x02]);
+        inner.extend_from_slice(&[0; 6]);
+        let outer = |protocol: u32| {
+            let mut outer = frame(&[], ETH_P_IP as u16);
+            outer.truncate(14);
+            outer.extend_from_slice(&ipv4(protocol));
+            outer
+        };
+
+        let mut vxlan = outer(IPPROTO_UDP);
+        vxlan.extend_from_slice(&[0xc0, 0x01, 0x12, 0xb5, 0, 0, 0, 0]);
+        vxlan.extend_from_slice(&[0x08, 0, 0, 0, 0, 0, 42, 0]);
+        vxlan.extend_from_slice(&inner);
+        let mut packet = TestPacket::new(&vxlan).unwrap();
+        let ctx = packet.context();
+        let tunnel = ctx.tunnel().unwrap();
+        assert_eq!(tunnel.encap(), Encap::Vxlan { vni: 42 });
+        assert_eq!(tunnel.offset(), 50);
+        assert_eq!(tunnel.buffer_len(), inner.len());
+        assert!(tunnel.eth().is_some());
+        let ip = tunnel.ip().unwrap();
+        assert_eq!(unsafe { (*ip).daddr }, u32::from_ne_bytes([10, 0, 0, 2]));
+        let transport = tunnel.transport().unwrap();
+        assert_eq!((transport.source(), transport.dest()), (12345, 80));
+        assert!(tunnel.tunnel().is_none());
+        // the outer packet is left alone
+        assert_eq!(ctx.transport().unwrap().dest(), VXLAN_PORT);
+
+        // the outer stack and the VXLAN header are cut short
+        for len in 0..vxlan.len() {
+            let mut packet = TestPacket::new(&vxlan[..len]).unwrap();
+            let ctx = packet.context();
+            match ctx.tunnel() {
+                Some(tunnel) => {
+                    assert!(len >= 50);
+                    assert!(tunnel.transport().is_none());
+                }
+                None => assert!(len < 50),
+            }
+        }
+        // not the VXLAN port, or no network identifier
+        let mut other = vxlan.clone();
+        other[37] = 0xb6;
+        assert!(!tunneled(&other));
+        let mut no_vni = vxlan.clone();
+        no_vni[42] = 0;
+        assert!(!tunneled(&no_vni));
+
+        // GRE with a key, carrying Ethernet
+        let mut gretap = outer(IPPROTO_GRE);
+        gretap.extend_from_slice(&[0x20, 0, 0x65, 0x58, 0, 0, 0x01, 0x02]);
+        gretap.extend_from_slice(&inner);
+        let mut packet = TestPacket::new(&gretap).unwrap();
+        let tunnel = packet.context().tunnel().unwrap();
+        assert_eq!(tunnel.encap(), Encap::Gre { key: Some(0x0102) });
+        assert_eq!(tunnel.transport().unwrap().dest(), 80);
+
+        // GRE with a checksum, carrying IPv4
+        let mut gre = outer(IPPROTO_GRE);
+        gre.extend_from_slice(&[0x80, 0, 0x08, 0, 0, 0, 0, 0]);
+        gre.extend_from_slice(&inner[14..]);
+        let mut packet = TestPacket::new(&gre).unwrap();
+        let tunnel = packet.context().tunnel().unwrap();
+        assert_eq!(tunnel.encap(), Encap::Gre { key: None });
+        assert_eq!(tunnel.try_eth(), Err(XdpParseError::UnsupportedL2));
+        assert!(tunnel.ip().is_some());
+        assert_eq!(tunnel.transport().unwrap().source(), 12345);
+        for len in 0..42 {
+            assert!(!tunneled(&gre[..len]));
+        }
+
+        // GRE with routing, or carrying something else
+        let mut routing = gre.clone();
+        routing[34] = 0x40;
+        assert!(!tunneled(&routing));
+        let mut mpls = gre.clone();
+        mpls[36..38].copy_from_slice(&[0x88, 0x47]);
+        assert!(!tunneled(&mpls));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_copy_header() {
+        let bytes: Vec<u8> = (0..64).collect();
+        let mut packet = TestPacket::new(&bytes).unwrap();
+        let ctx = packet.context();
+        assert_eq!(ctx.copy_header::<6>(), Some([0, 1, 2, 3, 4, 5]));
+        assert_eq!(&ctx.copy_header::<40>().unwrap()[..], &bytes[..40]);
+        assert_eq!(&ctx.copy_header::<64>().unwrap()[..], &bytes[..]);
+        assert_eq!(ctx.copy_header::<65>(), None);
+
+        for len in 0..40 {
+            let mut packet = TestPacket::new(&bytes[..len]).unwrap();
+            assert_eq!(packet.context().copy_header::<40>(), None);
+        }
+        let mut packet = TestPacket::new(&bytes[..40]).unwrap();
+        assert!(packet.context().copy_header::<40>().is_some());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_load() {
+        let bytes: Vec<u8> = (0..64).collect();
+        let mut packet = TestPacket::new(&bytes).unwrap();
+        let ctx = packet.context();
+        assert_eq!(ctx.load_byte(0), Some(0));
+        assert_eq!(ctx.load_byte(63), Some(63));
+        assert_eq!(ctx.load_byte(64), None);
+        assert_eq!(ctx.load_half(12), Some(0x0c0d));
+        assert_eq!(ctx.load_half(62), Some(0x3e3f));
+        assert_eq!(ctx.load_half(63), None);
+        assert_eq!(ctx.load_word(1), Some(0x0102_0304));
+        assert_eq!(ctx.load_word(60), Some(0x3c3d_3e3f));
+        assert_eq!(ctx.load_word(61), None);
+
+        // off + size overflows
+        assert_eq!(ctx.load_byte(usize::MAX), None);
+        assert_eq!(ctx.load_half(usize::MAX - 1), None);
+        assert_eq!(ctx.load_word(usize::MAX - 2), None);
+
+        let mut packet = TestPacket::new(&[]).unwrap();
+        assert_eq!(packet.context().load_byte(0), None);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_arp() {
+        let request = arp_frame(&[], ARPOP_REQUEST as u16);
+        let mut packet = TestPacket::new(&request).unwrap();
+        let ctx = packet.context();
+        assert!(ctx.arp().is_some());
+        assert_eq!(ctx.arp_opcode(), Some(ArpOp::Request));
+        assert_eq!(ctx.arp_sender_mac(), Some([1, 2, 3, 4, 5, 6]));
+        assert_eq!(ctx.arp_sender_ip(), Some(0x0a00_0001));
+        assert_eq!(ctx.arp_target_ip(), Some(0x0a00_0002));
+        assert_eq!(ctx.ip(), None);
+
+        let parse = |frame: &[u8]| {
+            let mut packet = TestPacket::new(frame).unwrap();
+            let ctx = packet.context();
+            (ctx.arp_opcode(), ctx.arp_target_ip())
+        };
+        let reply = arp_frame(&[(ETH_P_8021Q as u16, 10)], ARPOP_REPLY as u16);
+        assert_eq!(parse(&reply), (Some(ArpOp::Reply), Some(0x0a00_0002)));
+        let other = arp_frame(&[], 3);
+        assert_eq!(parse(&other), (Some(ArpOp::Other(3)), Some(0x0a00_0002)));
+
+        // the fixed header, then the addresses, are cut short
+        for len in 14..request.len() {
+            let op = if len < 22 { None } else { Some(ArpOp::Request) };
+            assert_eq!(parse(&request[..len]), (op, None));
+        }
+
+        // not Ethernet/IPv4
+        let mut ipv6 = request.clone();
+        ipv6[16..18].copy_from_slice(&(ETH_P_IPV6 as u16).to_be_bytes());
+        assert_eq!(parse(&ipv6), (Some(ArpOp::Request), None));
+        let mut hln = request.clone();
+        hln[18] = 8;
+        assert_eq!(parse(&hln), (Some(ArpOp::Request), None));
+
+        let mut packet = TestPacket::new(&frame(&[], ETH_P_IP as u16)).unwrap();
+        let ctx = packet.context();
+        assert!(ctx.arp().is_none());
+        assert_eq!(ctx.arp_opcode(), None);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_vlan_tagged_packets() {
+        let q = ETH_P_8021Q as u16;
+        let ad = ETH_P_8021AD as u16;
+        for tags in [&[][..], &[(q, 10)], &[(ad, 10), (q, 20)]].iter() {
+            let mut frame = frame(tags, ETH_P_IP as u16);
+            frame.truncate(frame.len() - 4);
+            let l3 = frame.len();
+            frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_UDP as u8]);
+            frame.extend_from_slice(&[0; 10]);
+            frame.extend_from_slice(&[0x30, 0x39, 0, 53, 0, 8, 0, 0]);
+
+            let mut packet = TestPacket::new(&frame).unwrap();
+            let ctx = packet.context();
+            assert_eq!(ctx.vlan(), tags.first().map(|&(_, tci)| tci));
+            assert_eq!(ctx.l3_offset(), Ok((l3, ETH_P_IP as u16)));
+            assert_eq!(ctx.ip().unwrap() as usize - ctx.eth().unwrap() as usize, l3);
+            assert!(ctx.ipv6().is_none());
+            assert_eq!(ctx.transport().unwrap().dest(), 53);
+            assert_eq!(ctx.data().unwrap().offset(), frame.len());
+
+            // truncated right after the tags
+            let mut packet = TestPacket::new(&frame[..l3]).unwrap();
+            let ctx = packet.context();
+            assert_eq!(ctx.l3_offset(), Ok((l3, ETH_P_IP as u16)));
+            assert!(ctx.ip().is_none());
+        }
+
+        // truncated in the tag
+        let frame = frame(&[(q, 10)], ETH_P_IP as u16);
+        let mut packet = TestPacket::new(&frame[..16]).unwrap();
+        let ctx = packet.context();
+        assert_eq!(ctx.vlan(), None);
+        assert_eq!(ctx.l3_offset(), Err(XdpParseError::Truncated));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_read_at() {
+        let mut frame = frame(&[], ETH_P_IP as u16);
+        frame.truncate(frame.len() - 4);
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_UDP as u8]);
+        frame.extend_from_slice(&[0; 10]);
+        frame.extend_from_slice(&[0x30, 0x39, 0, 53, 0, 16, 0, 0]);
+        // DNS header with one question
+        frame.extend_from_slice(&[0x12, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+        let mut packet = TestPacket::new(&frame).unwrap();
+        let ctx = packet.context();
+        let data = ctx.data().unwrap();
+        assert_eq!(data.len(), 12);
+        assert_eq!(data.read_at::<u16>(4).map(ntohs), Some(1));
+        assert_eq!(data.read_at::<[u8; 2]>(10), Some([0, 0]));
+        assert_eq!(data.read_at::<u16>(11), None);
+        assert_eq!(data.slice_at(2, 4), Some(&[1, 0, 0, 1][..]));
+        assert_eq!(data.slice_at(12, 0), Some(&[][..]));
+        assert_eq!(data.slice_at(12, 1), None);
+        assert_eq!(data.slice_at(13, 0), None);
+
+        // offsets that would wrap around
+        assert_eq!(data.read_at::<u32>(usize::MAX), None);
+        assert_eq!(data.read_at::<u32>(usize::MAX - 2), None);
+        assert_eq!(data.slice_at(usize::MAX, 2), None);
+        assert_eq!(data.slice_at(2, usize::MAX), None);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_frame_len() {
+        // the linear part of a 9000 byte UDP frame, as a driver splits it
+        let mut frame = frame(&[], ETH_P_IP as u16);
+        frame.truncate(frame.len() - 4);
+        frame.extend_from_slice(&[0x45, 0, 0x23, 0x1a, 0, 0, 0, 0, 64, IPPROTO_UDP as u8]);
+        frame.extend_from_slice(&[0; 10]);
+        frame.extend_from_slice(&[0x30, 0x39, 0, 53, 0x23, 0x06, 0, 0]);
+        frame.resize(4096, 0xaa);
+
+        let mut packet = TestPacket::new(&frame).unwrap();
+        let ctx = packet.context();
+        let data = ctx.data().unwrap();
+        assert_eq!(data.offset(), 42);
+        assert_eq!(data.linear_len(), 4096);
+        assert_eq!(data.len(), 4096 - 42);
+        // with the length of the fragments reported by the kernel
+        assert_eq!(super::frame_len(data.linear_len(), 9000), 9000);
+        // and without
+        assert_eq!(super::frame_len(data.linear_len(), 0), 4096);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_data_mut() {
+        let mut frame = frame(&[], ETH_P_IP as u16);
+        frame.truncate(frame.len() - 4);
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_UDP as u8]);
+        frame.extend_from_slice(&[0; 10]);
+        frame.extend_from_slice(&[0x30, 0x39, 0, 53, 0, 12, 0, 0, 1, 2, 3, 4]);
+        let payload = frame.len() - 4;
+
+        let mut packet = TestPacket::new(&frame).unwrap();
+        let mut ctx = packet.context();
+        let mut data = ctx.data_mut().unwrap();
+        assert_eq!(data.offset(), payload);
+        assert!(data.slice_mut(5).is_none());
+        assert!(data.write(&0u64).is_none());
+        data.slice_mut(2).unwrap().copy_from_slice(&[5, 6]);
+        assert_eq!(data.read::<[u8; 4]>(), Some([5, 6, 3, 4]));
+        data.write(&[7u8, 8, 9, 10]).unwrap();
+        assert_eq!(data.slice(4), Some(&[7, 8, 9, 10][..]));
+
+        // swap the ports
+        let mut packet_data = ctx.packet_mut().unwrap();
+        assert_eq!(packet_data.offset(), 0);
+        let ports = &mut packet_data.slice_mut(payload - 4).unwrap()[payload - 8..];
+        let (source, dest) = ports.split_at_mut(2);
+        source.swap_with_slice(dest);
+        let transport = ctx.transport().unwrap();
+        assert_eq!((transport.source(), transport.dest()), (53, 12345));
+    }
+
+    fn frame_proto(frame: &[u8]) -> Option<u16> {
+        let range = frame.as_ptr_range();
+        unsafe { eth_proto(range.start as *const ethhdr, range.end) }
+    }
+
+    #[test]
+    fn test_eth_proto() {
+        let q = ETH_P_8021Q as u16;
+        let ad = ETH_P_8021AD as u16;
+        for &proto in [ETH_P_IP, ETH_P_IPV6, ETH_P_ARP].iter() {
+            let proto = proto as u16;
+            assert_eq!(frame_proto(&frame(&[], proto)), Some(proto));
+            assert_eq!(frame_proto(&frame(&[(q, 10)], proto)), Some(proto));
+            assert_eq!(
+                frame_proto(&frame(&[(ad, 10), (q, 20)], proto)),
+                Some(proto)
+            );
+        }
+        // more tags than are skipped
+        let tags = [(ad, 10), (q, 20), (q, 30)];
+        assert_eq!(frame_proto(&frame(&tags, ETH_P_IP as u16)), Some(q));
+
+        // truncated in the Ethernet header, then in the tag
+        let untagged = frame(&[], ETH_P_IP as u16);
+        assert_eq!(frame_proto(&untagged[..13]), None);
+        assert_eq!(frame_proto(&untagged[..14]), Some(ETH_P_IP as u16));
+        let tagged = frame(&[(q, 10)], ETH_P_ARP as u16);
+        assert_eq!(frame_proto(&tagged[..16]), None);
+        assert_eq!(frame_proto(&tagged[..18]), Some(ETH_P_ARP as u16));
+
+        let (tag, inner) = unsafe {
+            let range = tagged.as_ptr_range();
+            vlan_tag(q, range.start.add(14), range.end).unwrap()
+        };
+        assert_eq!(tag, VlanTag { proto: q, tci: 10 });
+        assert_eq!(inner, ETH_P_ARP as u16);
+    }
+
+    fn ipv4_header(header: &[u8]) -> Ipv4Header {
+        let range = header.as_ptr_range();
+        Ipv4Header {
+            ip: range.start as *const iphdr,
+            end: range.end,
+        }
+    }
+
+    #[test]
+    fn test_tcp_fields() {
+        let segment: [u8; 20] = [
+            0x30, 0x39, 0, 80, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 0x50, 0x10, 0xfa,
+            0xf0, 0, 0, 0, 0,
+        ];
+        // keep the headers aligned
+        let segment: [u32; 5] = unsafe { mem::transmute(segment) };
+        let tcp = Transport::TCP(segment.as_ptr() as *const tcphdr);
+        assert_eq!(tcp.source(), 12345);
+        assert_eq!(tcp.dest(), 80);
+        assert_eq!(tcp.seq(), Some(0x1234_5678));
+        assert_eq!(tcp.ack_seq(), Some(0x9abc_def0));
+        assert_eq!(tcp.window(), Some(64240));
+        assert_eq!(tcp.tcp_flags(), Some(TcpFlags(TcpFlags::ACK)));
+        assert!(tcp.is_ack());
+        assert!(!tcp.is_syn());
+        assert!(!tcp.is_fin());
+        assert!(!tcp.is_rst());
+
+        // SYN with ECN setup, then RST
+        let mut flags = segment;
+        unsafe { *(flags.as_mut_ptr() as *mut u8).add(13) = 0xc2 };
+        let syn = Transport::TCP(flags.as_ptr() as *const tcphdr);
+        assert_eq!(
+            syn.tcp_flags(),
+            Some(TcpFlags(TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR))
+        );
+        assert!(syn.is_syn());
+        assert!(!syn.is_ack());
+        unsafe { *(flags.as_mut_ptr() as *mut u8).add(13) = 0x14 };
+        let rst = Transport::TCP(flags.as_ptr() as *const tcphdr);
+        assert!(rst.is_rst() && rst.is_ack());
+        // the flag bit fields of tcphdr agree
+        let hdr = unsafe { &*(flags.as_ptr() as *const tcphdr) };
+        assert_eq!((hdr.rst(), hdr.ack(), hdr.syn()), (1, 1, 0));
+
+        let datagram: [u8; 8] = [0x30, 0x39, 0, 53, 0, 8, 0, 0];
+        let datagram: [u16; 4] = unsafe { mem::transmute(datagram) };
+        let udp = Transport::UDP(datagram.as_ptr() as *const udphdr);
+        assert_eq!(udp.dest(), 53);
+        assert_eq!(udp.seq(), None);
+        assert_eq!(udp.ack_seq(), None);
+        assert_eq!(udp.window(), None);
+        assert_eq!(udp.tcp_flags(), None);
+        assert!(!udp.is_syn());
+        assert_eq!(udp.icmp_type(), None);
+        assert_eq!(tcp.icmp_code(), None);
+    }
+
+    #[test]
+    fn test_ipv6_fields() {
+        // version 6, traffic class 0xb8, flow label 0xabcde, 1280 bytes
+        let mut ip = [0u8; 40];
+        ip[..6].copy_from_slice(&[0x6b, 0x8a, 0xbc, 0xde, 0x05, 0x00]);
+        // keep the header aligned
+        let ip: [u32; 10] = unsafe { mem::transmute(ip) };
+        let hdr = Ipv6Header {
+            ip: ip.as_ptr() as *const ipv6hdr,
+        };
+        assert_eq!(hdr.traffic_class(), 0xb8);
+        assert_eq!(hdr.traffic_class() >> 2, 46);
+        assert_eq!(hdr.flow_label(), 0xabcde);
+        assert_eq!(hdr.payload_length(), 1280);
+
+        // fields across byte boundaries, from the bytes on the wire
+        let headers: [([u8; 6], u8, u32, u16); 4] = [
+            ([0x60, 0x00, 0x00, 0x00, 0x00, 0x00], 0x00, 0x00000, 0),
+            ([0x60, 0x1f, 0x00, 0x01, 0x00, 0x28], 0x01, 0xf0001, 40),
+            ([0x6f, 0xe0, 0x00, 0x00, 0x01, 0x00], 0xfe, 0x00000, 256),
+            ([0x6f, 0xff, 0xff, 0xff, 0xff, 0xff], 0xff, 0xfffff, 0xffff),
+        ];
+        for &(bytes, class, label, len) in headers.iter() {
+            let mut ip = [0u32; 10];
+            unsafe { (ip.as_mut_ptr() as *mut u8).copy_from_nonoverlapping(bytes.as_ptr(), 6) };
+            let hdr = Ipv6Header {
+                ip: ip.as_ptr() as *const ipv6hdr,
+            };
+            assert_eq!(hdr.traffic_class(), class);
+            assert_eq!(hdr.flow_label(), label);
+            assert_eq!(hdr.payload_length(), len);
+        }
+
+        // the generated accessors agree on the host
+        unsafe {
+            let raw = hdr.inner();
+            assert_eq!((*raw).version(), 6);
+            let class = ((*raw).priority() as u8) << 4 | (*raw).flow_lbl[0] >> 4;
+            assert_eq!(class, hdr.traffic_class());
+        }
+    }
+
+    #[test]
+    fn test_ipv4_checksum() {
+        let mut ip = [
+            0x45, 0, 0, 0x73, 0, 0, 0x40, 0, 0x40, 0x11, 0xb8, 0x61, 192, 168, 0, 1, 192, 168, 0,
+            0xc7,
+        ];
+        assert!(ipv4_header(&ip).checksum_valid());
+        assert_eq!(ipv4_header(&ip).compute_checksum(), Some(0xb861u16.to_be()));
+        ip[8] = 0x3f;
+        assert!(!ipv4_header(&ip).checksum_valid());
+        assert_eq!(ipv4_header(&ip).compute_checksum(), Some(0xb961u16.to_be()));
+
+        // record route option, then padding
+        let mut options = vec![0x47, 0, 0, 0x7b, 0, 0, 0x40, 0, 0x40, 0x06, 0, 0];
+        options.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        options.extend_from_slice(&[7, 7, 4, 10, 0, 0, 1, 0]);
+        let check = ipv4_header(&options).compute_checksum().unwrap();
+        options[10..12].copy_from_slice(&check.to_ne_bytes());
+        assert_eq!(ipv4_header(&options).header_len(), 28);
+        assert!(ipv4_header(&options).checksum_valid());
+        options[25] ^= 0x80;
+        assert!(!ipv4_header(&options).checksum_valid());
+        options[25] ^= 0x80;
+
+        // the options run past the end of the packet
+        assert!(!ipv4_header(&options[..26]).checksum_valid());
+        assert_eq!(ipv4_header(&options[..26]).compute_checksum(), None);
+    }
+
+    /// Computes the UDP checksum of an IPv4 packet without options from
+    /// scratch, pseudo-header included, in network byte order.
+    fn udp_checksum(packet: &[u8]) -> u16 {
+        let (ip, udp) = packet.split_at(20);
+        let mut words = ip[12..20].to_vec();
+        words.extend_from_slice(&[0, ip[9]]);
+        words.extend_from_slice(&(udp.len() as u16).to_be_bytes());
+        words.extend_from_slice(&udp[..6]);
+        words.extend_from_slice(&udp[8..]);
+        if words.len() % 2 == 1 {
+            words.push(0);
+        }
+        let mut sum = words
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]) as u32)
+            .sum::<u32>();
+        sum = (sum & 0xffff) + (sum >> 16);
+        sum = (sum & 0xffff) + (sum >> 16);
+        (!(sum as u16)).to_be()
+    }
+
+    #[test]
+    fn test_csum_replace() {
+        let word = |packet: &[u8], at: usize| u16::from_ne_bytes([packet[at], packet[at + 1]]);
+        let dword = |packet: &[u8], at: usize| {
+            u32::from_ne_bytes([packet[at], packet[at + 1], packet[at + 2], packet[at + 3]])
+        };
+
+        // IPv4, UDP from port 12345 to 53, payload
+        let mut packet = vec![0x45, 0, 0, 33, 0, 0, 0x40, 0, 0x40, 0x11, 0, 0];
+        packet.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 0xc7]);
+        packet.extend_from_slice(&[0x30, 0x39, 0, 53, 0, 13, 0, 0]);
+        packet.extend_from_slice(b"query");
+        let check = ipv4_header(&packet).compute_checksum().unwrap();
+        packet[10..12].copy_from_slice(&check.to_ne_bytes());
+        let check = udp_checksum(&packet);
+        packet[26..28].copy_from_slice(&check.to_ne_bytes());
+
+        // flip the destination port
+        let old = word(&packet, 22);
+        packet[22..24].copy_from_slice(&5353u16.to_be_bytes());
+        let mut check = word(&packet, 26);
+        csum_replace_u16(old, word(&packet, 22), &mut check);
+        assert_eq!(check, udp_checksum(&packet));
+        packet[26..28].copy_from_slice(&check.to_ne_bytes());
+
+        // the destination address is in both checksums
+        let old = dword(&packet, 16);
+        packet[16..20].copy_from_slice(&[10, 255, 0, 2]);
+        let new = dword(&packet, 16);
+        let mut ip_check = word(&packet, 10);
+        csum_replace_u32(old, new, &mut ip_check);
+        let mut udp_check = word(&packet, 26);
+        csum_replace_u32(old, new, &mut udp_check);
+        assert_eq!(Some(ip_check), ipv4_header(&packet).compute_checksum());
+        assert_eq!(udp_check, udp_checksum(&packet));
+
+        // changing a field to its value leaves the checksum alone
+        let mut check = 0x1234;
+        csum_replace_u16(0xabcd, 0xabcd, &mut check);
+        assert_eq!(check, 0x1234);
+    }
+
+    fn frame_l4_protocol(frame: &[u8]) -> Option<u8> {
+        let range = frame.as_ptr_range();
+        unsafe {
+            let (proto, l3) = eth_payload(range.start as *const ethhdr, range.end)?;
+            l4_protocol(proto, l3, range.end)
+        }
+    }
+
+    /// Builds an IPv6 frame with the extension headers `exts`, each given
+    /// as its number and its bytes after the next header field.
+    fn ipv6_frame(tags: &[(u16, u16)], exts: &[(u8, &[u8])], l4: u8) -> Vec<u8> {
+        let mut frame = frame(tags, ETH_P_IPV6 as u16);
+        frame.truncate(frame.len() - 4);
+        let mut ip = [0u8; mem::size_of::<ipv6hdr>()];
+        ip[0] = 0x60;
+        ip[6] = exts.first().map_or(l4, |&(nexthdr, _)| nexthdr);
+        frame.extend_from_slice(&ip);
+        for (i, &(_, ext)) in exts.iter().enumerate() {
+            frame.push(exts.get(i + 1).map_or(l4, |&(nexthdr, _)| nexthdr));
+            frame.extend_from_slice(ext);
+        }
+        frame.extend_from_slice(&[0xaa; 8]);
+        frame
+    }
+
+    #[test]
+    fn test_l4_protocol() {
+        let tcp = IPPROTO_TCP as u8;
+        let udp = IPPROTO_UDP as u8;
+
+        let mut v4 = frame(&[], ETH_P_IP as u16);
+        v4.truncate(14);
+        v4.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, tcp]);
+        v4.extend_from_slice(&[0; 10]);
+        assert_eq!(frame_l4_protocol(&v4), Some(tcp));
+        assert_eq!(frame_l4_protocol(&v4[..33]), None);
+
+        let v6 = ipv6_frame(&[], &[], udp);
+        assert_eq!(frame_l4_protocol(&v6), Some(udp));
+        assert_eq!(frame_l4_protocol(&v6[..53]), None);
+        let tagged = ipv6_frame(&[(ETH_P_8021Q as u16, 10)], &[], udp);
+        assert_eq!(frame_l4_protocol(&tagged), Some(udp));
+
+        // hop-by-hop options, 16 bytes of routing header, a fragment header
+        let hop: &[u8] = &[0; 7];
+        let routing: &[u8] = &[1; 15];
+        let fragment: &[u8] = &[0; 7];
+        let exts = [
+            (NEXTHDR_HOP, hop),
+            (NEXTHDR_ROUTING, routing),
+            (NEXTHDR_FRAGMENT, fragment),
+        ];
+        let v6_exts = ipv6_frame(&[], &exts, tcp);
+        assert_eq!(frame_l4_protocol(&v6_exts), Some(tcp));
+        // truncated in the routing header
+        assert_eq!(frame_l4_protocol(&v6_exts[..14 + 40 + 8 + 15]), None);
+        // authentication header, 12 bytes long
+        let auth: &[u8] = &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let v6_auth = ipv6_frame(&[], &[(NEXTHDR_AUTH, auth)], tcp);
+        assert_eq!(frame_l4_protocol(&v6_auth), Some(tcp));
+
+        // more extension headers than are walked
+        let exts = [(NEXTHDR_DEST, hop); IPV6_EXT_HEADERS_MAX + 1];
+        assert_eq!(frame_l4_protocol(&ipv6_frame(&[], &exts, tcp)), None);
+        assert_eq!(frame_l4_protocol(&frame(&[], ETH_P_ARP as u16)), None);
+    }
+
+    /// Returns the offset of the transport header of the untagged `frame`.
+    fn frame_l4_offset(frame: &[u8]) -> Option<(u8, usize)> {
+        let range = frame.as_ptr_range();
+        unsafe {
+            let proto = u16::from_be_bytes([frame[12], frame[13]]);
+            let (protocol, l4) = l4_header(proto, range.start.add(14), range.end).ok()?;
+            Some((protocol, l4 as usize - range.start as usize))
+        }
+    }
+
+    #[test]
+    fn test_l4_header() {
+        let tcp = IPPROTO_TCP as u8;
+        let udp = IPPROTO_UDP as u8;
+
+        // IPv4 with 4 bytes of options
+        let mut v4 = frame(&[], ETH_P_IP as u16);
+        v4.truncate(14);
+        v4.extend_from_slice(&[0x46, 0, 0, 0, 0, 0, 0, 0, 64, udp]);
+        v4.extend_from_slice(&[0; 14]);
+        assert_eq!(frame_l4_offset(&v4), Some((udp, 38)));
+
+        assert_eq!(frame_l4_offset(&ipv6_frame(&[], &[], tcp)), Some((tcp, 54)));
+        let hop: &[u8] = &[0; 7];
+        let fragment: &[u8] = &[0; 7];
+        let exts = [(NEXTHDR_HOP, hop), (NEXTHDR_FRAGMENT, fragment)];
+        assert_eq!(
+            frame_l4_offset(&ipv6_frame(&[], &exts, udp)),
+            Some((udp, 70))
+        );
+    }
+
+    #[test]
+    fn test_l4_header_fragments() {
+        let udp = IPPROTO_UDP as u8;
+        let v4 = |frag_off: [u8; 2]| {
+            let mut v4 = frame(&[], ETH_P_IP as u16);
+            v4.truncate(14);
+            v4.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, frag_off[0], frag_off[1], 64, udp]);
+            v4.extend_from_slice(&[0; 18]);
+            v4
+        };
+        // the first fragment, with more fragments to come
+        assert_eq!(frame_l4_offset(&v4([0x20, 0])), Some((udp, 34)));
+        // at 8 bytes in, with and without more to come
+        assert_eq!(frame_l4_offset(&v4([0x20, 1])), None);
+        assert_eq!(frame_l4_offset(&v4([0, 1])), None);
+        assert_eq!(frame_l4_protocol(&v4([0, 1])), Some(udp));
+        // don't fragment
+        assert_eq!(frame_l4_offset(&v4([0x40, 0])), Some((udp, 34)));
+
+        let v6 = |offset: u16, more: bool| {
+            let frag_off = ((offset << 3) | more as u16).to_be_bytes();
+            let fragment: &[u8] = &[0, frag_off[0], frag_off[1], 0, 0, 0, 1];
+            ipv6_frame(&[], &[(NEXTHDR_FRAGMENT, fragment)], udp)
+        };
+        assert_eq!(frame_l4_offset(&v6(0, true)), Some((udp, 62)));
+        assert_eq!(frame_l4_offset(&v6(1, true)), None);
+        assert_eq!(frame_l4_offset(&v6(185, false)), None);
+        assert_eq!(frame_l4_protocol(&v6(185, false)), Some(udp));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_fragment_transport() {
+        // UDP from 12345 to 53
+        let mut whole = frame(&[], ETH_P_IP as u16);
+        whole.truncate(14);
+        whole.extend_from_slice(&ipv4(IPPROTO_UDP));
+        whole.extend_from_slice(&[0x30, 0x39, 0, 53, 0, 8, 0, 0]);
+        let parse = |frame: &[u8]| {
+            let mut packet = TestPacket::new(frame).unwrap();
+            let ctx = packet.context();
+            let ports = ctx.transport().map(|t| (t.source(), t.dest()));
+            (ctx.is_fragment(), ports)
+        };
+        assert_eq!(parse(&whole), (Some(false), Some((12345, 53))));
+
+        let mut first = whole.clone();
+        first[20] = 0x20;
+        assert_eq!(parse(&first), (Some(true), Some((12345, 53))));
+        // the payload at 1480 bytes in, which isn't a UDP header
+        let mut rest = whole.clone();
+        rest[20..22].copy_from_slice(&185u16.to_be_bytes());
+        assert_eq!(parse(&rest), (Some(true), None));
+        let mut packet = TestPacket::new(&rest).unwrap();
+        let ctx = packet.context();
+        assert_eq!(ctx.try_transport().err(), Some(XdpParseError::Fragment));
+        assert!(ctx.data().is_none());
+        assert_eq!(ctx.l4_protocol(), Some(IPPROTO_UDP as u8));
+
+        assert_eq!(parse(&frame(&[], ETH_P_ARP as u16)), (None, None));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_ipv6_transport() {
+        let hop: &[u8] = &[0; 7];
+        let mut frame = ipv6_frame(&[], &[(NEXTHDR_HOP, hop)], IPPROTO_UDP as u8);
+        frame.truncate(14 + 40 + 8);
+        frame.extend_from_slice(&[0x30, 0x39, 0, 53, 0, 8, 0, 0]);
+
+        let mut packet = TestPacket::new(&frame).unwrap();
+        let ctx = packet.context();
+        assert!(ctx.ip().is_none());
+        assert!(ctx.ipv6().is_some());
+        assert!(matches!(ctx.ip_header(), Some(IpHeader::V6(_))));
+        let transport = ctx.transport().unwrap();
+        assert!(matches!(transport, Transport::UDP(_)));
+        assert_eq!((transport.source(), transport.dest()), (12345, 53));
+        assert_eq!(ctx.data().unwrap().offset(), frame.len());
+
+        let mut packet = TestPacket::new(&frame[..frame.len() - 1]).unwrap();
+        assert!(packet.context().transport().is_none());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_icmp_transport() {
+        // echo request with 4 bytes of payload
+        let mut v4 = frame(&[], ETH_P_IP as u16);
+        v4.truncate(14);
+        v4.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_ICMP as u8]);
+        v4.extend_from_slice(&[0; 10]);
+        v4.extend_from_slice(&[8, 0, 0, 0, 0, 1, 0, 2, 1, 2, 3, 4]);
+
+        let mut packet = TestPacket::new(&v4).unwrap();
+        let ctx = packet.context();
+        let transport = ctx.transport().unwrap();
+        assert!(matches!(transport, Transport::ICMP(_)));
+        assert_eq!(transport.icmp_type(), Some(8));
+        assert_eq!(transport.icmp_code(), Some(0));
+        assert_eq!((transport.source(), transport.dest()), (0, 0));
+        assert_eq!(transport.seq(), None);
+        let data = ctx.data().unwrap();
+        assert_eq!(data.offset(), v4.len() - 4);
+        assert_eq!(data.slice(4), Some(&[1, 2, 3, 4][..]));
+
+        let mut packet = TestPacket::new(&v4[..v4.len() - 5]).unwrap();
+        assert!(packet.context().transport().is_none());
+
+        // destination unreachable, port unreachable
+        let mut v6 = ipv6_frame(&[], &[], IPPROTO_ICMPV6 as u8);
+        v6.truncate(14 + 40);
+        v6.extend_from_slice(&[1, 4, 0, 0, 0, 0, 0, 0]);
+
+        let mut packet = TestPacket::new(&v6).unwrap();
+        let ctx = packet.context();
+        let transport = ctx.transport().unwrap();
+        assert!(matches!(transport, Transport::ICMPv6(_)));
+        assert_eq!(transport.icmp_type(), Some(1));
+        assert_eq!(transport.icmp_code(), Some(4));
+        assert_eq!(ctx.data().unwrap().offset(), v6.len());
     }
 }
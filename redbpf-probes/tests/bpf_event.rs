@@ -0,0 +1,83 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::mem;
+use std::slice;
+
+use redbpf_macros::BpfEvent;
+use redbpf_probes::byteorder::htons;
+
+/// An event as a program would output it, addresses and ports copied from
+/// the packet as they are.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, BpfEvent)]
+struct Flow {
+    ifindex: u32,
+    #[bpf_event(big_endian)]
+    dport: u16,
+    proto: u8,
+    // padded to 8
+    bytes: u64,
+    saddr: [u8; 4],
+    ingress: bool,
+    rtt: f32,
+    counts: [[u16; 2]; 2],
+    stats: Stats,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, BpfEvent)]
+struct Stats(u64, i32);
+
+/// Returns the bytes of `value`, as the program outputs them.
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+fn flow() -> Flow {
+    Flow {
+        ifindex: 2,
+        dport: htons(5353),
+        proto: 17,
+        bytes: 1 << 40,
+        saddr: [10, 0, 0, 1],
+        ingress: true,
+        rtt: 0.5,
+        counts: [[1, 2], [3, 4]],
+        stats: Stats(u64::MAX, -7),
+    }
+}
+
+#[test]
+fn test_bpf_event_round_trip() {
+    let emitted = flow();
+    let mut parsed = Flow::from_bytes(as_bytes(&emitted)).unwrap();
+    assert_eq!(parsed.dport, 5353);
+    parsed.dport = htons(parsed.dport);
+    assert_eq!(parsed, emitted);
+
+    // perf samples are padded, and their data needn't be aligned
+    let mut sample = vec![0xaa];
+    sample.extend_from_slice(as_bytes(&emitted));
+    sample.extend_from_slice(&[0; 4]);
+    let parsed = Flow::from_bytes(&sample[1..]).unwrap();
+    assert_eq!(parsed.stats, emitted.stats);
+    assert_eq!(parsed.counts, emitted.counts);
+}
+
+#[test]
+fn test_bpf_event_invalid() {
+    let emitted = flow();
+    let bytes = as_bytes(&emitted);
+    assert_eq!(Flow::from_bytes(&bytes[..bytes.len() - 1]), None);
+    assert_eq!(Flow::from_bytes(&[]), None);
+
+    let mut bytes = bytes.to_vec();
+    let ingress = &emitted.ingress as *const _ as usize - &emitted as *const _ as usize;
+    bytes[ingress] = 2;
+    assert_eq!(Flow::from_bytes(&bytes), None);
+}
@@ -0,0 +1,44 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#[test]
+fn test_context_types() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/kprobe_regs.rs");
+    t.pass("tests/ui/kretprobe_entry_args.rs");
+    t.pass("tests/ui/fentry_module.rs");
+    t.pass("tests/ui/fentry_task.rs");
+    t.pass("tests/ui/xdp_syncookie.rs");
+    t.pass("tests/ui/xdp_swap_ports.rs");
+    t.pass("tests/ui/xdp_nat_port.rs");
+    t.pass("tests/ui/xdp_adjust_head.rs");
+    t.pass("tests/ui/xdp_syn_filter.rs");
+    t.pass("tests/ui/xdp_tcp_mss.rs");
+    t.pass("tests/ui/xdp_arp.rs");
+    t.pass("tests/ui/xdp_redirect.rs");
+    t.pass("tests/ui/xdp_tunnel.rs");
+    t.pass("tests/ui/xdp_consistent_hash.rs");
+    t.pass("tests/ui/tc_mirror.rs");
+    t.pass("tests/ui/tc_redirect_peer.rs");
+    t.pass("tests/ui/tc_action.rs");
+    t.pass("tests/ui/tc_parse.rs");
+    t.pass("tests/ui/cgroup_sockaddr.rs");
+    t.pass("tests/ui/xdp_egress.rs");
+    t.pass("tests/ui/xdp_drop_reason.rs");
+    t.pass("tests/ui/xdp_counter.rs");
+    t.pass("tests/ui/xdp_action_counts.rs");
+    t.pass("tests/ui/xdp_ringbuf.rs");
+    t.pass("tests/ui/xdp_queue_array.rs");
+    t.pass("tests/ui/declare_map.rs");
+    t.pass("tests/ui/map_pinning.rs");
+    t.pass("tests/ui/bpf_event.rs");
+    t.compile_fail("tests/ui/kprobe_eth.rs");
+    t.compile_fail("tests/ui/xdp_meta_oversized.rs");
+    t.compile_fail("tests/ui/declare_map_bad_attr.rs");
+    t.compile_fail("tests/ui/bpf_event_bad.rs");
+    t.compile_fail("tests/ui/xdp_data_mut_alias.rs");
+}
@@ -0,0 +1,52 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use redbpf::{HashMap, IpKey, Map};
+use redbpf_probes::bindings::{bpf_map_def, bpf_map_type_BPF_MAP_TYPE_HASH};
+use redbpf_probes::byteorder::{htonl, IpKey as ProbeIpKey};
+
+#[test]
+fn test_ip_key_layout() {
+    assert_eq!(mem::size_of::<IpKey>(), mem::size_of::<ProbeIpKey>());
+
+    // the address as it is stored in iphdr::saddr
+    let saddr = u32::from_ne_bytes([10, 0, 0, 1]);
+    let key = IpKey::from(Ipv4Addr::new(10, 0, 0, 1));
+    assert_eq!(key.octets(), ProbeIpKey::from_ipv4(saddr).octets());
+
+    let addr = [htonl(0x2001_0db8), 0, 0, htonl(0x42)];
+    let key = IpKey::from("2001:db8::42".parse::<Ipv6Addr>().unwrap());
+    assert_eq!(key.octets(), ProbeIpKey::from_ipv6(addr).octets());
+}
+
+#[test]
+#[ignore = "needs root"]
+fn test_ip_key_lookup() {
+    let def = bpf_map_def {
+        type_: bpf_map_type_BPF_MAP_TYPE_HASH,
+        key_size: mem::size_of::<IpKey>() as u32,
+        value_size: mem::size_of::<u8>() as u32,
+        max_entries: 16,
+        map_flags: 0,
+    };
+    let code = unsafe {
+        std::slice::from_raw_parts(&def as *const _ as *const u8, mem::size_of_val(&def))
+    };
+    let map = Map::load("blocklist", code).unwrap();
+    let blocklist = HashMap::<IpKey, u8>::new(&map).unwrap();
+    blocklist.set(Ipv4Addr::new(10, 0, 0, 1).into(), 1).unwrap();
+
+    // what the probe looks up for packets from 10.0.0.1 and 1.0.0.10
+    let lookup = HashMap::<ProbeIpKey, u8>::new(&map).unwrap();
+    let source = ProbeIpKey::from_ipv4(u32::from_ne_bytes([10, 0, 0, 1]));
+    assert_eq!(lookup.get(source), Some(1));
+    let source = ProbeIpKey::from_ipv4(u32::from_ne_bytes([1, 0, 0, 10]));
+    assert_eq!(lookup.get(source), None);
+}
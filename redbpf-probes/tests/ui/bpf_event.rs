@@ -0,0 +1,40 @@
+use redbpf_macros::{map, xdp, BpfEvent};
+use redbpf_probes::maps::PerfMap;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+/// Shared by the program, which outputs it, and userspace, which parses it
+/// with the generated `from_bytes`.
+#[repr(C)]
+#[derive(Clone, Copy, BpfEvent)]
+pub struct Packet {
+    pub ifindex: u32,
+    #[bpf_event(big_endian)]
+    pub dport: u16,
+    pub saddr: [u8; 4],
+}
+
+#[map("packets")]
+static mut PACKETS: PerfMap<Packet> = PerfMap::with_max_entries(1024);
+
+#[xdp]
+pub extern "C" fn export_packets(ctx: XdpContext) -> XdpAction {
+    let (ip, transport) = match (ctx.ip(), ctx.transport()) {
+        (Some(ip), Some(transport)) => (ip, transport),
+        _ => return XdpAction::Pass,
+    };
+    let packet = Packet {
+        ifindex: unsafe { (*ctx.inner()).ingress_ifindex },
+        // as in the header, in network byte order
+        dport: transport.dest().to_be(),
+        saddr: unsafe { (*ip).saddr }.to_ne_bytes(),
+    };
+    unsafe { PACKETS.insert(ctx.inner(), packet) };
+    XdpAction::Pass
+}
+
+fn main() {
+    let sample = [0u8; 12];
+    if let Some(packet) = Packet::from_bytes(&sample) {
+        let _ = (packet.ifindex, packet.dport, packet.saddr);
+    }
+}
@@ -0,0 +1,30 @@
+use redbpf_macros::BpfEvent;
+
+#[derive(Clone, Copy, BpfEvent)]
+pub struct Unordered {
+    pub pid: u32,
+    pub comm: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, BpfEvent)]
+pub struct Task {
+    pub pid: u32,
+    pub task: *const u8,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, BpfEvent)]
+pub struct Port {
+    #[bpf_event(network_order)]
+    pub dport: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, BpfEvent)]
+pub struct Direction {
+    #[bpf_event(big_endian)]
+    pub ingress: bool,
+}
+
+fn main() {}
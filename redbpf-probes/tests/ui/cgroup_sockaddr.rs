@@ -0,0 +1,21 @@
+use redbpf_macros::cgroup_sockaddr;
+use redbpf_probes::sock_addr::SockAddrContext;
+
+#[cgroup_sockaddr(connect4)]
+pub extern "C" fn redirect_dns(ctx: SockAddrContext) -> i32 {
+    if ctx.user_port() == 53 {
+        ctx.set_user_ip4(0x7f00_0035);
+        ctx.set_user_port(5353);
+    }
+    1
+}
+
+#[cgroup_sockaddr(connect6)]
+pub extern "C" fn redirect_dns6(ctx: SockAddrContext) -> i32 {
+    if ctx.user_port() == 53 {
+        ctx.set_user_ip6([0, 0, 0, 1]);
+    }
+    1
+}
+
+fn main() {}
@@ -0,0 +1,39 @@
+use redbpf_macros::declare_map;
+use redbpf_probes::bindings::BPF_F_NO_PREALLOC;
+use redbpf_probes::maps::{EventChannel, HashMap, PerfMap};
+use redbpf_probes::xdp;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FlowKey {
+    saddr: u32,
+    daddr: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Stats {
+    packets: u64,
+    bytes: u64,
+}
+
+declare_map!(CONNS: HashMap<FlowKey, Stats> = 10240);
+declare_map!(SESSIONS: HashMap<u64, Stats> = 4096, flags = BPF_F_NO_PREALLOC);
+declare_map!(BLOCKLIST: HashMap<u32, u8> = 1024, pinning = by_name);
+declare_map!(SHARED: HashMap<u32, u64> = 64, flags = BPF_F_NO_PREALLOC, pinning = by_name,);
+declare_map!(LOCAL: HashMap<u32, u64> = 64, pinning = none);
+declare_map!(EVENTS: PerfMap<Stats> = 1024);
+declare_map!(PACKETS: xdp::PerfMap<Stats> = 1024);
+declare_map!(LOG: EventChannel<Stats> = 256 * 1024);
+
+fn main() {
+    unsafe {
+        CONNS.set(FlowKey { saddr: 1, daddr: 2 }, Stats { packets: 1, bytes: 64 });
+        SESSIONS.delete(1);
+        BLOCKLIST.get(1);
+        SHARED.get(1);
+        LOCAL.get(1);
+        let _ = (&EVENTS, &PACKETS, &LOG);
+    }
+    let _: u32 = BLOCKLIST_PINNING + SHARED_PINNING;
+}
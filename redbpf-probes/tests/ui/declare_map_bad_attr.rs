@@ -0,0 +1,7 @@
+use redbpf_macros::declare_map;
+use redbpf_probes::maps::HashMap;
+
+declare_map!(CONNS: HashMap<u32, u64> = 1024, pining = by_name);
+declare_map!(FLOWS: HashMap<u32, u64> = 1024, pinning = by_path);
+
+fn main() {}
@@ -0,0 +1,15 @@
+use redbpf_macros::{fentry, kprobe};
+use redbpf_probes::fentry::FEntryContext;
+use redbpf_probes::kprobe::KProbeContext;
+
+#[fentry("i915:intel_irq_handler")]
+pub extern "C" fn irq_fentry(ctx: FEntryContext) -> i32 {
+    ctx.arg(0) as i32
+}
+
+#[kprobe("i915:intel_irq_handler")]
+pub extern "C" fn irq_kprobe(ctx: KProbeContext) -> i32 {
+    ctx.regs().parm1() as i32
+}
+
+fn main() {}
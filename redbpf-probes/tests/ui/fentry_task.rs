@@ -0,0 +1,24 @@
+use redbpf_macros::{fentry, map};
+use redbpf_probes::fentry::FEntryContext;
+use redbpf_probes::maps::HashMap;
+use redbpf_probes::task::Task;
+
+#[map("execs")]
+static mut EXECS: HashMap<i32, u64> = HashMap::with_max_entries(10240);
+
+/// Counts the processes started by every parent.
+#[fentry("do_execveat_common")]
+pub extern "C" fn count_execs(_ctx: FEntryContext) -> i32 {
+    let ppid = match Task::current().real_parent() {
+        Some(parent) => parent.tgid(),
+        None => return 0,
+    };
+    unsafe {
+        let count = EXECS.get(ppid).copied().unwrap_or(0);
+        EXECS.set(ppid, count + 1);
+    }
+
+    0
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use redbpf_macros::kprobe;
+use redbpf_probes::kprobe::KProbeContext;
+
+#[kprobe("__x64_sys_clone")]
+pub extern "C" fn clone_enter(ctx: KProbeContext) -> i32 {
+    ctx.eth();
+    0
+}
+
+fn main() {}
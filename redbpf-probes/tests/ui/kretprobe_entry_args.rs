@@ -0,0 +1,17 @@
+use redbpf_macros::kretprobe;
+use redbpf_probes::kprobe::KRetProbeContext;
+
+#[kretprobe("tcp_sendmsg")]
+pub extern "C" fn tcp_sendmsg_exit(ctx: KRetProbeContext) -> i32 {
+    let sent = ctx.regs().rc() as i64;
+    match ctx.entry_args() {
+        Some(args) if args.arg(2) as i64 == sent => 0,
+        _ => 1,
+    }
+}
+
+fn main() {
+    // the paired kprobe stashes the arguments in the map the kretprobe reads
+    let _: extern "C" fn(*mut redbpf_probes::bindings::pt_regs) -> i32 = tcp_sendmsg_exit_entry;
+    let _ = unsafe { &tcp_sendmsg_exit_entry_args };
+}
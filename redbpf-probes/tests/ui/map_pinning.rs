@@ -0,0 +1,19 @@
+use redbpf_macros::map;
+use redbpf_probes::maps::HashMap;
+
+#[map("blocklist", pinning = by_name)]
+static mut BLOCKLIST: HashMap<u32, u8> = HashMap::with_max_entries(1024);
+
+#[map("counts", pinning = none)]
+static mut COUNTS: HashMap<u32, u64> = HashMap::with_max_entries(64);
+
+#[map("events")]
+static mut EVENTS: HashMap<u32, u64> = HashMap::with_max_entries(64);
+
+fn main() {
+    unsafe {
+        BLOCKLIST.set(0x0a00_0001, 1);
+        let _ = (&COUNTS, &EVENTS);
+    }
+    let _: u32 = BLOCKLIST_PINNING;
+}
@@ -0,0 +1,13 @@
+use redbpf_macros::tc_action;
+use redbpf_probes::tc::{SkBuffContext, TcAction};
+
+#[tc_action]
+pub extern "C" fn drop_short(skb: SkBuffContext) -> TcAction {
+    let skb = unsafe { *skb.inner() };
+    if skb.len < 64 {
+        return TcAction::Shot;
+    }
+    TcAction::Ok
+}
+
+fn main() {}
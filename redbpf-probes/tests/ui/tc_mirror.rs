@@ -0,0 +1,8 @@
+use redbpf_probes::tc::SkBuffContext;
+
+pub fn mirror_port(skb: SkBuffContext) -> i32 {
+    skb.mirror_to(4, false);
+    0
+}
+
+fn main() {}
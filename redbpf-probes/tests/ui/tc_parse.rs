@@ -0,0 +1,32 @@
+use redbpf_macros::{tc_action, xdp};
+use redbpf_probes::net::NetworkBuffer;
+use redbpf_probes::tc::{SkBuffContext, TcAction};
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+fn is_dns<B: NetworkBuffer>(buf: &B) -> bool {
+    match (buf.ip(), buf.transport()) {
+        (Some(_), Some(transport)) => transport.dest() == 53,
+        _ => false,
+    }
+}
+
+#[xdp]
+pub extern "C" fn dns_ingress(ctx: XdpContext) -> XdpAction {
+    if is_dns(&ctx) {
+        return XdpAction::Drop;
+    }
+    XdpAction::Pass
+}
+
+#[tc_action]
+pub extern "C" fn dns_egress(skb: SkBuffContext) -> TcAction {
+    if !is_dns(&skb) {
+        return TcAction::Ok;
+    }
+    match skb.data().and_then(|data| data.read::<u16>()) {
+        Some(_) => TcAction::Shot,
+        None => TcAction::Ok,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use redbpf_probes::bindings::*;
+use redbpf_probes::tc::SkBuffContext;
+
+pub fn container_ingress(skb: SkBuffContext) -> i32 {
+    if skb.redirect_peer(3, 0) != TC_ACT_REDIRECT as i32 {
+        return TC_ACT_SHOT as i32;
+    }
+    TC_ACT_REDIRECT as i32
+}
+
+fn main() {}
@@ -0,0 +1,27 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::maps::PerCpuArray;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+/// Packets by the action taken on them, read with `redbpf::PerCpuArray`.
+#[map("action_packets")]
+static mut ACTION_PACKETS: PerCpuArray<u64> = PerCpuArray::with_max_entries(5);
+
+#[map("action_bytes")]
+static mut ACTION_BYTES: PerCpuArray<u64> = PerCpuArray::with_max_entries(5);
+
+#[xdp]
+pub extern "C" fn count_actions(ctx: XdpContext) -> XdpAction {
+    let action = match ctx.transport() {
+        Some(transport) if transport.dest() == 80 => XdpAction::Drop,
+        _ => XdpAction::Pass,
+    };
+    unsafe {
+        ACTION_PACKETS.increment(action as u32, 1);
+        if let Some(bytes) = ACTION_BYTES.get_mut(action as u32) {
+            *bytes += ctx.len() as u64;
+        }
+    }
+    action
+}
+
+fn main() {}
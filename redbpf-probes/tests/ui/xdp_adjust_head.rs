@@ -0,0 +1,42 @@
+use core::mem;
+use redbpf_macros::xdp;
+use redbpf_probes::bindings::*;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+/// Pushes a copy of the Ethernet header in front of IPv4 packets, then
+/// parses the packet again, which now starts with two Ethernet headers.
+#[xdp]
+pub extern "C" fn push_eth(mut ctx: XdpContext) -> XdpAction {
+    let eth = match (ctx.eth(), ctx.ip()) {
+        (Some(eth), Some(_)) => unsafe { *eth },
+        _ => return XdpAction::Pass,
+    };
+    if ctx.adjust_head(-(mem::size_of::<ethhdr>() as i32)).is_err() {
+        return XdpAction::Pass;
+    }
+    let mut packet = match ctx.packet_mut() {
+        Some(packet) => packet,
+        None => return XdpAction::Aborted,
+    };
+    if packet.write(&eth).is_none() {
+        return XdpAction::Aborted;
+    }
+
+    // the old pointers are gone, look the headers up again
+    match ctx.eth() {
+        Some(outer) if unsafe { (*outer).h_proto } == eth.h_proto => (),
+        _ => return XdpAction::Aborted,
+    }
+    // the copy is followed by the original Ethernet header, which is now
+    // where the IP header is looked for
+    match ctx.ip() {
+        Some(ip) if unsafe { *(ip as *const ethhdr) }.h_proto == eth.h_proto => (),
+        _ => return XdpAction::Aborted,
+    }
+    if ctx.adjust_tail(-4).is_err() {
+        return XdpAction::Aborted;
+    }
+    XdpAction::Tx
+}
+
+fn main() {}
@@ -0,0 +1,32 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::maps::HashMap;
+use redbpf_probes::net::NetworkBuffer;
+use redbpf_probes::xdp::{ArpOp, XdpAction, XdpContext};
+
+#[map("arp_table")]
+static mut ARP_TABLE: HashMap<u32, [u8; 6]> = HashMap::with_max_entries(1024);
+
+/// Drops ARP replies claiming an address for another MAC than the one that
+/// first claimed it.
+#[xdp]
+pub extern "C" fn arp_guard(ctx: XdpContext) -> XdpAction {
+    if ctx.arp_opcode() != Some(ArpOp::Reply) {
+        return XdpAction::Pass;
+    }
+    let (ip, mac) = match (ctx.arp_sender_ip(), ctx.arp_sender_mac()) {
+        (Some(ip), Some(mac)) => (ip, mac),
+        _ => return XdpAction::Pass,
+    };
+    unsafe {
+        match ARP_TABLE.get(ip) {
+            Some(known) if *known != mac => XdpAction::Drop,
+            Some(_) => XdpAction::Pass,
+            None => {
+                ARP_TABLE.set(ip, mac);
+                XdpAction::Pass
+            }
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,47 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::bindings::xdp_action_XDP_PASS;
+use redbpf_probes::hash::{fnv1a, xxhash32};
+use redbpf_probes::maps::HashMap;
+use redbpf_probes::xdp::{CpuMap, DevMap, XdpAction, XdpContext};
+
+const SLOTS: u32 = 256;
+
+#[map("slots")]
+static mut SLOTS_MAP: HashMap<u32, u32> = HashMap::with_max_entries(SLOTS);
+
+#[map("backends")]
+static mut BACKENDS: DevMap = DevMap::with_max_entries(64);
+
+#[map("cpus")]
+static mut CPUS: CpuMap = CpuMap::with_max_entries(4);
+
+/// Sends the packets of a flow to the backend of the slot it hashes to.
+#[xdp]
+pub extern "C" fn consistent_hash(ctx: XdpContext) -> XdpAction {
+    let (saddr, daddr, transport) = match (ctx.source_ipv4(), ctx.dest_ipv4(), ctx.transport()) {
+        (Some(saddr), Some(daddr), Some(transport)) => (saddr, daddr, transport),
+        _ => return XdpAction::Pass,
+    };
+    let mut key = [0u8; 12];
+    key[0..4].copy_from_slice(&saddr.to_be_bytes());
+    key[4..8].copy_from_slice(&daddr.to_be_bytes());
+    key[8..10].copy_from_slice(&transport.source().to_be_bytes());
+    key[10..12].copy_from_slice(&transport.dest().to_be_bytes());
+
+    let slot = xxhash32(&key, 0) % SLOTS;
+    match unsafe { SLOTS_MAP.get(slot) } {
+        Some(&backend) => unsafe { BACKENDS.redirect(backend, xdp_action_XDP_PASS as u64) },
+        None => XdpAction::Pass,
+    }
+}
+
+/// Spreads packets over CPUs by the hash of their first bytes.
+#[xdp]
+pub extern "C" fn spread_cpus(ctx: XdpContext) -> XdpAction {
+    match ctx.copy_header::<40>() {
+        Some(header) => unsafe { CPUS.redirect(fnv1a(&header) % 4, 0) },
+        None => XdpAction::Pass,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,17 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::maps::Counter;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+/// Packets by source address, read with `redbpf::Counter`.
+#[map("packets")]
+static mut PACKETS: Counter<u32> = Counter::with_max_entries(10240);
+
+#[xdp]
+pub extern "C" fn count_packets(ctx: XdpContext) -> XdpAction {
+    if let Some(ip) = ctx.ip() {
+        unsafe { PACKETS.inc((*ip).saddr) };
+    }
+    XdpAction::Pass
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use redbpf_macros::xdp;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+#[xdp]
+pub extern "C" fn alias(mut ctx: XdpContext) -> XdpAction {
+    let mut data = ctx.data_mut().unwrap();
+    let mut packet = ctx.packet_mut().unwrap();
+    data.slice_mut(1).unwrap()[0] = 1;
+    packet.slice_mut(1).unwrap()[0] = 2;
+    XdpAction::Pass
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use redbpf_macros::xdp;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+const DROP_NOT_IP: u32 = 1;
+
+#[xdp]
+pub extern "C" fn firewall(ctx: XdpContext) -> XdpAction {
+    match ctx.ip() {
+        Some(_) => XdpAction::Pass,
+        None => ctx.drop_with_reason(DROP_NOT_IP),
+    }
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+use redbpf_macros::xdp;
+use redbpf_probes::bindings::ethhdr;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+#[xdp]
+pub extern "C" fn devmap_egress(ctx: XdpContext) -> XdpAction {
+    let eth = match ctx.eth() {
+        Some(eth) => eth as *mut ethhdr,
+        None => return XdpAction::Drop,
+    };
+    let ifindex = ctx.egress_ifindex();
+    unsafe { (*eth).h_source[5] = ifindex as u8 };
+    XdpAction::Pass
+}
+
+fn main() {}
@@ -0,0 +1,18 @@
+use redbpf_macros::xdp;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Meta {
+    pub id: [u32; 9],
+}
+
+#[xdp]
+pub extern "C" fn tag(mut ctx: XdpContext) -> XdpAction {
+    if let Some(meta) = ctx.meta_mut::<Meta>() {
+        meta.id[0] = 1;
+    }
+    XdpAction::Pass
+}
+
+fn main() {}
@@ -0,0 +1,29 @@
+use redbpf_macros::xdp;
+use redbpf_probes::bindings::*;
+use redbpf_probes::byteorder::htons;
+use redbpf_probes::xdp::{csum_replace_u16, csum_replace_u32, Transport, XdpAction, XdpContext};
+
+/// Forwards TCP port 8080 of the host to port 80 of a backend.
+#[xdp]
+pub extern "C" fn nat_port(ctx: XdpContext) -> XdpAction {
+    let (ip, tcp) = match (ctx.ip(), ctx.transport()) {
+        (Some(ip), Some(Transport::TCP(tcp))) => (ip as *mut iphdr, tcp as *mut tcphdr),
+        _ => return XdpAction::Pass,
+    };
+    unsafe {
+        if (*tcp).dest != htons(8080) {
+            return XdpAction::Pass;
+        }
+        let backend = u32::from_ne_bytes([10, 0, 0, 2]);
+        let daddr = (*ip).daddr;
+        (*ip).daddr = backend;
+        csum_replace_u32(daddr, backend, &mut (*ip).check);
+        csum_replace_u32(daddr, backend, &mut (*tcp).check);
+        let dest = (*tcp).dest;
+        (*tcp).dest = htons(80);
+        csum_replace_u16(dest, htons(80), &mut (*tcp).check);
+    }
+    XdpAction::Pass
+}
+
+fn main() {}
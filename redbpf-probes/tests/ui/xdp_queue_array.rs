@@ -0,0 +1,27 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::maps::Array;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+const QUEUES: u32 = 64;
+
+#[repr(C)]
+pub struct QueueStats {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Packets and bytes by RX queue, masking the queue index into the array.
+#[map("queue_stats")]
+static mut QUEUE_STATS: Array<QueueStats> = Array::with_max_entries(QUEUES);
+
+#[xdp]
+pub extern "C" fn count_queues(ctx: XdpContext) -> XdpAction {
+    let queue = unsafe { (*ctx.inner()).rx_queue_index };
+    if let Some(stats) = unsafe { QUEUE_STATS.get_masked_mut(queue, QUEUES - 1) } {
+        stats.packets += 1;
+        stats.bytes += ctx.len() as u64;
+    }
+    XdpAction::Pass
+}
+
+fn main() {}
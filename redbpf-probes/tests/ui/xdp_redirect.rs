@@ -0,0 +1,37 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::bindings::xdp_action_XDP_PASS;
+use redbpf_probes::maps::Array;
+use redbpf_probes::xdp::{CpuMap, DevMap, XdpAction, XdpContext};
+
+#[map("tx_ports")]
+static mut TX_PORTS: DevMap = DevMap::with_max_entries(2);
+
+#[map("next_port")]
+static mut NEXT_PORT: Array<u32> = Array::with_max_entries(1);
+
+#[map("cpus")]
+static mut CPUS: CpuMap = CpuMap::with_max_entries(4);
+
+/// Round-robins packets across the two interfaces of `tx_ports`.
+#[xdp]
+pub extern "C" fn round_robin(_ctx: XdpContext) -> XdpAction {
+    let port = match unsafe { NEXT_PORT.get_mut(0) } {
+        Some(next) => {
+            *next = (*next + 1) % 2;
+            *next
+        }
+        None => return XdpAction::Aborted,
+    };
+    unsafe { TX_PORTS.redirect(port, xdp_action_XDP_PASS as u64) }
+}
+
+/// Hands packets over to the CPU picked by their source port.
+#[xdp]
+pub extern "C" fn spread_cpus(ctx: XdpContext) -> XdpAction {
+    match ctx.transport() {
+        Some(transport) => unsafe { CPUS.redirect(transport.source() as u32 % 4, 0) },
+        None => ctx.redirect(1, 0),
+    }
+}
+
+fn main() {}
@@ -0,0 +1,48 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::maps::RingBuf;
+use redbpf_probes::xdp::{self, MapData, XdpAction, XdpContext};
+
+#[repr(C)]
+pub struct Event {
+    pub len: u32,
+    pub saddr: u32,
+}
+
+#[map("events")]
+static mut EVENTS: RingBuf<Event> = RingBuf::with_max_entries(256 * 1024);
+
+#[map("packets")]
+static mut PACKETS: xdp::RingBuf<u32, 64> = xdp::RingBuf::with_max_entries(1024 * 1024);
+
+/// Sends the IPv4 packets through one ring buffer, copying their headers,
+/// and the others through another one, along with their first bytes.
+#[xdp]
+pub extern "C" fn capture(ctx: XdpContext) -> XdpAction {
+    let len = ctx.len();
+    let ip = match ctx.ip() {
+        Some(ip) => ip,
+        None => {
+            let _ = unsafe { PACKETS.insert(&ctx, MapData::with_payload(len, 0, len)) };
+            return XdpAction::Pass;
+        }
+    };
+    let saddr = unsafe { (*ip).saddr };
+    if saddr == 0 {
+        let _ = unsafe { EVENTS.output(&Event { len, saddr }) };
+        return XdpAction::Pass;
+    }
+    let mut event = match unsafe { EVENTS.reserve() } {
+        Some(event) => event,
+        None => return XdpAction::Pass,
+    };
+    if len < 64 {
+        event.discard();
+        return XdpAction::Pass;
+    }
+    event.write(Event { len, saddr });
+    event.submit();
+
+    XdpAction::Pass
+}
+
+fn main() {}
@@ -0,0 +1,34 @@
+use redbpf_macros::xdp;
+use redbpf_probes::byteorder::ip_ihl;
+use redbpf_probes::xdp::{Transport, XdpAction, XdpContext};
+
+const ETH_LEN: usize = 14;
+const IP_LEN: usize = 20;
+
+/// Bounces UDP datagrams back to where they came from.
+#[xdp]
+pub extern "C" fn udp_echo(mut ctx: XdpContext) -> XdpAction {
+    match (ctx.vlan(), ctx.ip(), ctx.transport()) {
+        (None, Some(ip), Some(Transport::UDP(_))) if unsafe { ip_ihl(ip) } == 5 => (),
+        _ => return XdpAction::Pass,
+    }
+    let mut packet = match ctx.packet_mut() {
+        Some(packet) => packet,
+        None => return XdpAction::Pass,
+    };
+    let headers = match packet.slice_mut(ETH_LEN + IP_LEN + 4) {
+        Some(headers) => headers,
+        None => return XdpAction::Pass,
+    };
+    let (eth, rest) = headers.split_at_mut(ETH_LEN);
+    let (dest, source) = eth[..12].split_at_mut(6);
+    dest.swap_with_slice(source);
+    let (ip, ports) = rest.split_at_mut(IP_LEN);
+    let (source, dest) = ip[12..].split_at_mut(4);
+    source.swap_with_slice(dest);
+    let (source, dest) = ports.split_at_mut(2);
+    source.swap_with_slice(dest);
+    XdpAction::Tx
+}
+
+fn main() {}
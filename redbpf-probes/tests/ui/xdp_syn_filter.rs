@@ -0,0 +1,27 @@
+use redbpf_macros::xdp;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+/// Drops TCP connection attempts, SYN without ACK, to a listening port,
+/// and lets the rest of the handshake and established connections through.
+#[xdp]
+pub extern "C" fn drop_syns(ctx: XdpContext) -> XdpAction {
+    let transport = match ctx.transport() {
+        Some(transport) => transport,
+        None => return XdpAction::Pass,
+    };
+    if transport.dest() != 8080 {
+        return XdpAction::Pass;
+    }
+    if transport.is_syn() && !transport.is_ack() {
+        return XdpAction::Drop;
+    }
+    if transport.is_rst() || transport.is_fin() {
+        return XdpAction::Pass;
+    }
+    match (transport.seq(), transport.ack_seq()) {
+        (Some(_), Some(_)) => XdpAction::Pass,
+        _ => XdpAction::Aborted,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+use redbpf_macros::xdp;
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+#[xdp]
+pub extern "C" fn syncookie(ctx: XdpContext) -> XdpAction {
+    let sk = match ctx.sk_lookup_tcp() {
+        Some(sk) => sk,
+        None => return XdpAction::Pass,
+    };
+    if sk.is_listener() && ctx.tcp_gen_syncookie(&sk).is_none() && !ctx.tcp_check_syncookie(&sk) {
+        return XdpAction::Drop;
+    }
+    XdpAction::Pass
+}
+
+fn main() {}
@@ -0,0 +1,31 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::maps::HashMap;
+use redbpf_probes::xdp::{XdpAction, XdpContext, TCP_OPTION_MSS};
+
+#[map("client_mss")]
+static mut CLIENT_MSS: HashMap<u32, u16> = HashMap::with_max_entries(10240);
+
+/// Records the MSS clients announce in their SYNs, by source address.
+#[xdp]
+pub extern "C" fn record_mss(ctx: XdpContext) -> XdpAction {
+    let (saddr, transport) = match (ctx.source_ipv4(), ctx.transport()) {
+        (Some(saddr), Some(transport)) if transport.is_syn() && !transport.is_ack() => {
+            (saddr, transport)
+        }
+        _ => return XdpAction::Pass,
+    };
+    let options = match transport.tcp_options(&ctx) {
+        Some(options) => options,
+        None => return XdpAction::Pass,
+    };
+    for (kind, value) in options {
+        if kind == TCP_OPTION_MSS && value.len() == 2 {
+            let mss = u16::from_be_bytes([value[0], value[1]]);
+            unsafe { CLIENT_MSS.set(saddr, mss) };
+            break;
+        }
+    }
+    XdpAction::Pass
+}
+
+fn main() {}
@@ -0,0 +1,27 @@
+use redbpf_macros::{map, xdp};
+use redbpf_probes::maps::Counter;
+use redbpf_probes::net::{Encap, NetworkBuffer};
+use redbpf_probes::xdp::{XdpAction, XdpContext};
+
+/// Packets by the addresses and ports of the flows inside VXLAN tunnels.
+#[map("inner_flows")]
+static mut INNER_FLOWS: Counter<(u32, u32, u16, u16)> = Counter::with_max_entries(10240);
+
+#[xdp]
+pub extern "C" fn overlay_flows(ctx: XdpContext) -> XdpAction {
+    let inner = match ctx.tunnel() {
+        Some(inner) => inner,
+        None => return XdpAction::Pass,
+    };
+    if let Encap::Gre { .. } = inner.encap() {
+        return XdpAction::Pass;
+    }
+    if let (Some(ip), Some(transport)) = (inner.ip(), inner.transport()) {
+        let (saddr, daddr) = unsafe { ((*ip).saddr, (*ip).daddr) };
+        let flow = (saddr, daddr, transport.source(), transport.dest());
+        unsafe { INNER_FLOWS.inc(flow) };
+    }
+    XdpAction::Pass
+}
+
+fn main() {}
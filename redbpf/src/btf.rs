@@ -0,0 +1,817 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # BTF
+//!
+//! A small reader for the BPF Type Format, the type information the kernel
+//! exports for itself under `/sys/kernel/btf/vmlinux` and for every loaded
+//! module under `/sys/kernel/btf/<module>`.
+//!
+//! Only what the loader needs is implemented: walking the type section and
+//! finding types by kind and name. Module BTF is "split" BTF, its type ids
+//! and string offsets continue where those of `vmlinux` end, so a module has
+//! to be parsed on top of the kernel's own BTF.
+//!
+//! ```no_run
+//! use redbpf::btf::{Btf, BTF_KIND_FUNC};
+//!
+//! let vmlinux = Btf::vmlinux().unwrap();
+//! let id = vmlinux.find(BTF_KIND_FUNC, "bpf_rcu_read_lock");
+//! ```
+//!
+//! fentry programs are attached to functions by BTF id, looked up in the BTF
+//! of the module for `module:function` targets, and in `vmlinux` otherwise.
+//!
+//! Objects built with libbpf's conventions declare their maps as variables
+//! of the `.maps` section, whose types describe the maps. `Btf::maps` reads
+//! them back:
+//!
+//! ```c
+//! struct {
+//!     __uint(type, BPF_MAP_TYPE_HASH);
+//!     __uint(max_entries, 1024);
+//!     __type(key, __u32);
+//!     __type(value, __u64);
+//! } counts SEC(".maps");
+//! ```
+
+use crate::kprobe::split_target;
+use crate::sys::bpf::{btf_load, module_btf_fd};
+use crate::{LoadError, Result};
+use bpf_sys::bpf_map_def;
+use lazy_static::lazy_static;
+use std::fs;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::str;
+use std::sync::{Arc, Mutex};
+
+const BTF_MAGIC: u16 = 0xeb9f;
+const SYS_BTF_DIR: &str = "/sys/kernel/btf";
+
+pub const BTF_KIND_INT: u32 = 1;
+pub const BTF_KIND_PTR: u32 = 2;
+pub const BTF_KIND_ARRAY: u32 = 3;
+pub const BTF_KIND_STRUCT: u32 = 4;
+pub const BTF_KIND_UNION: u32 = 5;
+pub const BTF_KIND_ENUM: u32 = 6;
+pub const BTF_KIND_FWD: u32 = 7;
+pub const BTF_KIND_TYPEDEF: u32 = 8;
+pub const BTF_KIND_VOLATILE: u32 = 9;
+pub const BTF_KIND_CONST: u32 = 10;
+pub const BTF_KIND_RESTRICT: u32 = 11;
+pub const BTF_KIND_FUNC: u32 = 12;
+pub const BTF_KIND_FUNC_PROTO: u32 = 13;
+pub const BTF_KIND_VAR: u32 = 14;
+pub const BTF_KIND_DATASEC: u32 = 15;
+pub const BTF_KIND_FLOAT: u32 = 16;
+pub const BTF_KIND_DECL_TAG: u32 = 17;
+pub const BTF_KIND_TYPE_TAG: u32 = 18;
+pub const BTF_KIND_ENUM64: u32 = 19;
+
+/// The section libbpf-style map declarations are placed in.
+pub const MAPS_SECTION: &str = ".maps";
+/// The `pinning` of a `.maps` declaration pinning the map by its name.
+pub const LIBBPF_PIN_BY_NAME: u32 = 1;
+/// How many typedefs and qualifiers are followed before giving up on a
+/// type, which protects against cycles.
+const MODIFIERS_MAX: usize = 32;
+
+/// A single entry of the type section.
+///
+/// The kind-specific data following the common header is kept as raw words
+/// in `data`, e.g. `(name_off, type, offset)` triples for struct members.
+#[derive(Debug, Clone)]
+pub struct Type {
+    pub name_off: u32,
+    pub info: u32,
+    pub size_or_type: u32,
+    pub data: Vec<u32>,
+}
+
+pub struct Btf {
+    types: Vec<Type>,
+    strings: Vec<u8>,
+    start_id: u32,
+}
+
+lazy_static! {
+    static ref VMLINUX: Mutex<Option<Arc<Btf>>> = Mutex::new(None);
+}
+
+/// Returns the running kernel's BTF, which is only parsed the first time it
+/// is needed, by the first program loaded.
+pub(crate) fn vmlinux() -> Result<Arc<Btf>> {
+    let mut vmlinux = VMLINUX.lock().unwrap();
+    if let Some(btf) = &*vmlinux {
+        return Ok(btf.clone());
+    }
+    let btf = Arc::new(Btf::vmlinux()?);
+    *vmlinux = Some(btf.clone());
+    Ok(btf)
+}
+
+/// A map declared in the `.maps` section.
+///
+/// Each member of the declaration's struct is a pointer. `__uint(name, n)`
+/// declares `int (*name)[n]`, storing the value in the array length, and
+/// `__type(name, T)` declares `T *name`.
+#[derive(Debug, Clone)]
+pub struct BtfMapDef {
+    pub name: String,
+    pub def: bpf_map_def,
+    /// `LIBBPF_PIN_BY_NAME` if the map is shared through a pin.
+    pub pinning: u32,
+    pub key_type_id: Option<u32>,
+    pub value_type_id: Option<u32>,
+}
+
+impl Type {
+    #[inline]
+    pub fn kind(&self) -> u32 {
+        (self.info >> 24) & 0x1f
+    }
+
+    #[inline]
+    pub fn vlen(&self) -> usize {
+        (self.info & 0xffff) as usize
+    }
+
+    #[inline]
+    pub fn kind_flag(&self) -> bool {
+        self.info >> 31 == 1
+    }
+}
+
+impl Btf {
+    /// Parses the running kernel's BTF.
+    pub fn vmlinux() -> Result<Btf> {
+        Btf::parse(&fs::read(Path::new(SYS_BTF_DIR).join("vmlinux"))?)
+    }
+
+    /// Parses the BTF of the loaded kernel module `name` on top of `vmlinux`.
+    pub fn module(name: &str, vmlinux: &Btf) -> Result<Btf> {
+        Btf::parse_split(&fs::read(Path::new(SYS_BTF_DIR).join(name))?, vmlinux)
+    }
+
+    /// Lists the modules that have BTF available.
+    pub fn modules() -> Result<Vec<String>> {
+        let mut modules = vec![];
+        for entry in fs::read_dir(SYS_BTF_DIR)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name != "vmlinux" {
+                modules.push(name);
+            }
+        }
+        Ok(modules)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Btf> {
+        Btf::parse_with_base(data, 1, vec![])
+    }
+
+    /// Parses split BTF, which extends the types and strings of `base`.
+    pub fn parse_split(data: &[u8], base: &Btf) -> Result<Btf> {
+        Btf::parse_with_base(
+            data,
+            base.start_id + base.types.len() as u32,
+            base.strings.clone(),
+        )
+    }
+
+    fn parse_with_base(data: &[u8], start_id: u32, mut strings: Vec<u8>) -> Result<Btf> {
+        let magic = read_u16(data, 0)?;
+        if magic != BTF_MAGIC {
+            return Err(LoadError::BTF);
+        }
+        let hdr_len = read_u32(data, 4)? as usize;
+        let type_off = read_u32(data, 8)? as usize;
+        let type_len = read_u32(data, 12)? as usize;
+        let str_off = read_u32(data, 16)? as usize;
+        let str_len = read_u32(data, 20)? as usize;
+
+        let type_section = section(data, hdr_len + type_off, type_len)?;
+        strings.extend_from_slice(section(data, hdr_len + str_off, str_len)?);
+
+        let mut types = vec![];
+        let mut pos = 0;
+        while pos < type_section.len() {
+            let name_off = read_u32(type_section, pos)?;
+            let info = read_u32(type_section, pos + 4)?;
+            let size_or_type = read_u32(type_section, pos + 8)?;
+            pos += 12;
+
+            let mut ty = Type {
+                name_off,
+                info,
+                size_or_type,
+                data: vec![],
+            };
+            let words = match ty.kind() {
+                BTF_KIND_INT | BTF_KIND_VAR | BTF_KIND_DECL_TAG => 1,
+                BTF_KIND_ARRAY => 3,
+                BTF_KIND_STRUCT | BTF_KIND_UNION | BTF_KIND_DATASEC => 3 * ty.vlen(),
+                BTF_KIND_ENUM | BTF_KIND_FUNC_PROTO => 2 * ty.vlen(),
+                BTF_KIND_ENUM64 => 3 * ty.vlen(),
+                BTF_KIND_PTR | BTF_KIND_FWD | BTF_KIND_TYPEDEF | BTF_KIND_VOLATILE
+                | BTF_KIND_CONST | BTF_KIND_RESTRICT | BTF_KIND_FUNC | BTF_KIND_FLOAT
+                | BTF_KIND_TYPE_TAG => 0,
+                _ => return Err(LoadError::BTF),
+            };
+            for _ in 0..words {
+                ty.data.push(read_u32(type_section, pos)?);
+                pos += 4;
+            }
+            types.push(ty);
+        }
+
+        Ok(Btf {
+            types,
+            strings,
+            start_id,
+        })
+    }
+
+    /// Returns the type with the given id. Id `0` is `void` and has no entry.
+    pub fn type_by_id(&self, id: u32) -> Option<&Type> {
+        if id < self.start_id {
+            return None;
+        }
+        self.types.get((id - self.start_id) as usize)
+    }
+
+    /// Returns the ids of all types.
+    pub(crate) fn type_ids(&self) -> std::ops::Range<u32> {
+        self.start_id..self.start_id + self.types.len() as u32
+    }
+
+    /// Resolves a string offset, as found in `name_off` fields.
+    pub fn name(&self, offset: u32) -> Option<&str> {
+        let bytes = self.strings.get(offset as usize..)?;
+        let end = bytes.iter().position(|&c| c == 0)?;
+        str::from_utf8(&bytes[..end]).ok()
+    }
+
+    /// Finds the id of the first type of `kind` called `name`.
+    pub fn find(&self, kind: u32, name: &str) -> Option<u32> {
+        self.types
+            .iter()
+            .position(|ty| ty.kind() == kind && self.name(ty.name_off) == Some(name))
+            .map(|idx| self.start_id + idx as u32)
+    }
+
+    /// Returns the key and value type ids of the map `name`.
+    ///
+    /// Maps defined through `bpf_map_def` have no type information of their
+    /// own. Instead, the types are declared as the `key` and `value` members
+    /// of a struct called `____btf_map_<name>`, following libbpf.
+    ///
+    /// Maps declared in the `.maps` section carry their types along.
+    pub fn map_type_ids(&self, name: &str) -> Option<(u32, u32)> {
+        let id = match self.find(BTF_KIND_STRUCT, &format!("____btf_map_{}", name)) {
+            Some(id) => id,
+            None => {
+                let map = self.maps().ok()?.into_iter().find(|map| map.name == name)?;
+                return Some((map.key_type_id?, map.value_type_id?));
+            }
+        };
+        let members = &self.type_by_id(id)?.data;
+        let member = |name| {
+            members
+                .chunks(3)
+                .find(|m| self.name(m[0]) == Some(name))
+                .map(|m| m[1])
+        };
+        Some((member("key")?, member("value")?))
+    }
+
+    /// Returns the maps declared in the `.maps` section, or none if there's
+    /// no such section.
+    ///
+    /// Members the loader doesn't know are ignored, as libbpf does outside
+    /// of its strict mode. Fails on declarations it can't make sense of,
+    /// such as the `values` of maps of maps.
+    pub fn maps(&self) -> Result<Vec<BtfMapDef>> {
+        let datasec = match self.find(BTF_KIND_DATASEC, MAPS_SECTION) {
+            Some(id) => self.type_by_id(id).ok_or(LoadError::BTF)?,
+            None => return Ok(vec![]),
+        };
+        datasec
+            .data
+            .chunks(3)
+            .map(|var| self.map_def(var[0]))
+            .collect()
+    }
+
+    fn map_def(&self, var_id: u32) -> Result<BtfMapDef> {
+        let var = self
+            .type_by_id(var_id)
+            .filter(|ty| ty.kind() == BTF_KIND_VAR)
+            .ok_or(LoadError::BTF)?;
+        let decl = self
+            .type_by_id(self.skip_modifiers(var.size_or_type))
+            .filter(|ty| ty.kind() == BTF_KIND_STRUCT)
+            .ok_or(LoadError::BTF)?;
+        let mut map = BtfMapDef {
+            name: self.name(var.name_off).ok_or(LoadError::BTF)?.to_string(),
+            def: bpf_map_def {
+                type_: 0,
+                key_size: 0,
+                value_size: 0,
+                max_entries: 0,
+                map_flags: 0,
+            },
+            pinning: 0,
+            key_type_id: None,
+            value_type_id: None,
+        };
+
+        for member in decl.data.chunks(3) {
+            let pointee = || self.pointee(member[1]).ok_or(LoadError::BTF);
+            let uint = || self.array_len(pointee()?).ok_or(LoadError::BTF);
+            let size = || self.type_size(pointee()?).ok_or(LoadError::BTF);
+            match self.name(member[0]) {
+                Some("type") => map.def.type_ = uint()?,
+                Some("max_entries") => map.def.max_entries = uint()?,
+                Some("map_flags") => map.def.map_flags = uint()?,
+                Some("key_size") => map.def.key_size = uint()?,
+                Some("value_size") => map.def.value_size = uint()?,
+                Some("pinning") => map.pinning = uint()?,
+                Some("numa_node") => {}
+                Some("key") => {
+                    map.def.key_size = size()?;
+                    map.key_type_id = Some(pointee()?);
+                }
+                Some("value") => {
+                    map.def.value_size = size()?;
+                    map.value_type_id = Some(pointee()?);
+                }
+                Some("values") | None => return Err(LoadError::BTF),
+                Some(_) => {}
+            }
+        }
+
+        Ok(map)
+    }
+
+    /// Returns the size in bytes of the type `id`.
+    pub fn type_size(&self, id: u32) -> Option<u32> {
+        let ty = self.type_by_id(self.skip_modifiers(id))?;
+        match ty.kind() {
+            BTF_KIND_INT | BTF_KIND_STRUCT | BTF_KIND_UNION | BTF_KIND_ENUM | BTF_KIND_ENUM64
+            | BTF_KIND_FLOAT | BTF_KIND_DATASEC => Some(ty.size_or_type),
+            BTF_KIND_PTR => Some(8),
+            BTF_KIND_ARRAY => self.type_size(ty.data[0])?.checked_mul(ty.data[2]),
+            _ => None,
+        }
+    }
+
+    /// Follows typedefs and qualifiers to the type they stand for.
+    pub(crate) fn skip_modifiers(&self, mut id: u32) -> u32 {
+        for _ in 0..MODIFIERS_MAX {
+            match self.type_by_id(id) {
+                Some(ty)
+                    if [
+                        BTF_KIND_TYPEDEF,
+                        BTF_KIND_VOLATILE,
+                        BTF_KIND_CONST,
+                        BTF_KIND_RESTRICT,
+                        BTF_KIND_TYPE_TAG,
+                    ]
+                    .contains(&ty.kind()) =>
+                {
+                    id = ty.size_or_type
+                }
+                _ => break,
+            }
+        }
+        id
+    }
+
+    fn pointee(&self, id: u32) -> Option<u32> {
+        let ty = self.type_by_id(self.skip_modifiers(id))?;
+        if ty.kind() != BTF_KIND_PTR {
+            return None;
+        }
+        Some(ty.size_or_type)
+    }
+
+    fn array_len(&self, id: u32) -> Option<u32> {
+        let ty = self.type_by_id(self.skip_modifiers(id))?;
+        if ty.kind() != BTF_KIND_ARRAY {
+            return None;
+        }
+        Some(ty.data[2])
+    }
+
+    /// Fills in the section sizes and variable offsets that compilers leave
+    /// at zero in the `DATASEC` types of object files, as the kernel rejects
+    /// them otherwise.
+    pub(crate) fn fixup_datasecs<S, V>(&mut self, section_size: S, var_offset: V)
+    where
+        S: Fn(&str) -> Option<u32>,
+        V: Fn(&str) -> Option<u32>,
+    {
+        for idx in 0..self.types.len() {
+            if self.types[idx].kind() != BTF_KIND_DATASEC {
+                continue;
+            }
+            let size = self.name(self.types[idx].name_off).and_then(&section_size);
+            let offsets: Vec<Option<u32>> = self.types[idx]
+                .data
+                .chunks(3)
+                .map(|var| {
+                    let var = self.type_by_id(var[0])?;
+                    var_offset(self.name(var.name_off)?)
+                })
+                .collect();
+
+            let ty = &mut self.types[idx];
+            if let Some(size) = size {
+                ty.size_or_type = size;
+            }
+            for (var, offset) in ty.data.chunks_mut(3).zip(offsets) {
+                if let Some(offset) = offset {
+                    var[1] = offset;
+                }
+            }
+        }
+    }
+
+    /// Encodes the types and strings back into the BTF format.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut types = vec![];
+        for ty in self.types.iter() {
+            for word in [ty.name_off, ty.info, ty.size_or_type]
+                .iter()
+                .chain(ty.data.iter())
+            {
+                types.extend_from_slice(&word.to_ne_bytes());
+            }
+        }
+
+        let mut buf = vec![];
+        buf.extend_from_slice(&BTF_MAGIC.to_ne_bytes());
+        buf.extend_from_slice(&[1, 0]);
+        for word in &[24, 0, types.len() as u32, types.len() as u32, self.strings.len() as u32] {
+            buf.extend_from_slice(&word.to_ne_bytes());
+        }
+        buf.extend_from_slice(&types);
+        buf.extend_from_slice(&self.strings);
+        buf
+    }
+}
+
+/// The BTF of a BPF object, loaded into the kernel so that maps can be
+/// created with typed keys and values.
+pub(crate) struct ObjectBtf {
+    pub btf: Btf,
+    pub fd: RawFd,
+}
+
+impl ObjectBtf {
+    pub fn load(btf: Btf, token: Option<RawFd>) -> Result<ObjectBtf> {
+        let fd = btf_load(&btf.to_bytes(), token)?;
+        Ok(ObjectBtf { btf, fd })
+    }
+}
+
+impl Drop for ObjectBtf {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Looks kernel functions up in `vmlinux` first, then in module BTF.
+///
+/// Module BTF is only parsed on the first miss. The fds to the BTF objects
+/// of modules that functions were found in are kept open until the resolver
+/// is dropped, since the kernel needs them when loading the program.
+pub(crate) struct KfuncResolver {
+    vmlinux: Arc<Btf>,
+    modules: Option<Vec<(String, Btf)>>,
+    module_fds: Vec<(String, RawFd)>,
+}
+
+impl KfuncResolver {
+    pub fn new() -> Result<KfuncResolver> {
+        Ok(KfuncResolver {
+            vmlinux: vmlinux()?,
+            modules: None,
+            module_fds: vec![],
+        })
+    }
+
+    /// Returns the BTF id of `name` and the index of the module BTF fd it
+    /// belongs to in `fd_array()`, or `0` for functions of `vmlinux`.
+    pub fn resolve(&mut self, name: &str) -> Result<Option<(u32, i16)>> {
+        if let Some(id) = self.vmlinux.find(BTF_KIND_FUNC, name) {
+            return Ok(Some((id, 0)));
+        }
+
+        if self.modules.is_none() {
+            let mut modules = vec![];
+            for module in Btf::modules()? {
+                let btf = Btf::module(&module, &self.vmlinux)?;
+                modules.push((module, btf));
+            }
+            self.modules = Some(modules);
+        }
+        let found = self
+            .modules
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find_map(|(module, btf)| btf.find(BTF_KIND_FUNC, name).map(|id| (module, id)));
+        let (module, id) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let idx = match self.module_fds.iter().position(|(m, _)| m == module) {
+            Some(idx) => idx,
+            None => {
+                let fd = module_btf_fd(module)?;
+                self.module_fds.push((module.clone(), fd));
+                self.module_fds.len() - 1
+            }
+        };
+        Ok(Some((id, idx as i16 + 1)))
+    }
+
+    /// Returns the `fd_array` to load the program with, if any modules are
+    /// referenced. Offset `0` in kfunc calls stands for `vmlinux`, so the
+    /// first slot is unused.
+    pub fn fd_array(&self) -> Option<Vec<RawFd>> {
+        if self.module_fds.is_empty() {
+            return None;
+        }
+        Some(Some(0).into_iter().chain(self.module_fds.iter().map(|(_, fd)| *fd)).collect())
+    }
+}
+
+impl Drop for KfuncResolver {
+    fn drop(&mut self) {
+        for (_, fd) in self.module_fds.iter() {
+            unsafe { libc::close(*fd) };
+        }
+    }
+}
+
+/// The function an fentry program is loaded for.
+pub(crate) struct AttachTarget {
+    pub btf_id: u32,
+    /// The BTF object of the module defining the function, which the
+    /// kernel needs when loading the program.
+    pub module_fd: Option<RawFd>,
+}
+
+impl AttachTarget {
+    /// Finds the BTF id of the function `target`, which is
+    /// `module:function` for functions of loadable modules.
+    pub fn resolve(target: &str) -> Result<AttachTarget> {
+        let vmlinux = vmlinux()?;
+        let (module, name) = split_target(target);
+        let module = match module {
+            Some(module) => module,
+            None => {
+                return match vmlinux.find(BTF_KIND_FUNC, name) {
+                    Some(btf_id) => Ok(AttachTarget {
+                        btf_id,
+                        module_fd: None,
+                    }),
+                    None => Err(not_found(target)),
+                }
+            }
+        };
+
+        let btf = Btf::module(module, &vmlinux)?;
+        let btf_id = btf
+            .find(BTF_KIND_FUNC, name)
+            .ok_or_else(|| not_found(target))?;
+        Ok(AttachTarget {
+            btf_id,
+            module_fd: Some(module_btf_fd(module)?),
+        })
+    }
+}
+
+impl Drop for AttachTarget {
+    fn drop(&mut self) {
+        if let Some(fd) = self.module_fd {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+fn not_found(target: &str) -> LoadError {
+    let msg = format!("no BTF for function {}", target);
+    io::Error::new(io::ErrorKind::NotFound, msg).into()
+}
+
+#[inline]
+pub(crate) fn section(data: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    data.get(offset..offset + len).ok_or(LoadError::BTF)
+}
+
+#[inline]
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = section(data, offset, 2)?;
+    Ok(u16::from_ne_bytes([bytes[0], bytes[1]]))
+}
+
+#[inline]
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = section(data, offset, 4)?;
+    Ok(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::map_def_bytes;
+    use crate::test_util::map_def;
+
+    fn push(buf: &mut Vec<u8>, words: &[u32]) {
+        for w in words {
+            buf.extend_from_slice(&w.to_ne_bytes());
+        }
+    }
+
+    pub(crate) fn encode(types: &[u32], strings: &[u8]) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&BTF_MAGIC.to_ne_bytes());
+        buf.extend_from_slice(&[1, 0]);
+        let type_len = types.len() as u32 * 4;
+        push(&mut buf, &[24, 0, type_len, type_len, strings.len() as u32]);
+        push(&mut buf, types);
+        buf.extend_from_slice(strings);
+        buf
+    }
+
+    #[test]
+    fn test_find() {
+        // [1] INT "int" size 4, [2] FUNC_PROTO void (int), [3] FUNC "foo"
+        let types = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            0, BTF_KIND_FUNC_PROTO << 24 | 1, 0, 0, 1,
+            5, BTF_KIND_FUNC << 24, 2,
+        ];
+        let btf = Btf::parse(&encode(&types, b"\0int\0foo\0")).unwrap();
+        assert_eq!(btf.find(BTF_KIND_FUNC, "foo"), Some(3));
+        assert_eq!(btf.find(BTF_KIND_INT, "foo"), None);
+        assert_eq!(btf.type_by_id(2).unwrap().vlen(), 1);
+
+        let module = Btf::parse_split(&encode(&[9, BTF_KIND_FUNC << 24, 2], b"bar\0"), &btf).unwrap();
+        assert_eq!(module.find(BTF_KIND_FUNC, "bar"), Some(4));
+        assert_eq!(module.name(1), Some("int"));
+    }
+
+    /// The BTF of a hash map `counts` of ints, as BCC declares it.
+    #[rustfmt::skip]
+    fn counts_btf() -> Btf {
+        // [1] INT "int" size 4, [2] STRUCT "____btf_map_counts" { int key; int value; }
+        let types = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            5, BTF_KIND_STRUCT << 24 | 2, 8, 24, 1, 0, 28, 1, 32,
+        ];
+        Btf::parse(&encode(&types, b"\0int\0____btf_map_counts\0key\0value\0")).unwrap()
+    }
+
+    #[test]
+    fn test_map_type_ids() {
+        let btf = counts_btf();
+        assert_eq!(btf.map_type_ids("counts"), Some((1, 1)));
+        assert_eq!(btf.map_type_ids("other"), None);
+        let btf = Btf::parse(&btf.to_bytes()).unwrap();
+        assert_eq!(btf.map_type_ids("counts"), Some((1, 1)));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_load_map_btf() {
+        let btf = ObjectBtf::load(counts_btf(), None).unwrap();
+        let def = map_def(bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH, 4, 4, 16);
+        let map =
+            crate::Map::load_with_btf("counts", map_def_bytes(&def), Some(&btf), None).unwrap();
+        let info = map.info().unwrap();
+        assert_eq!((info.btf_key_type_id, info.btf_value_type_id), (1, 1));
+    }
+
+    /// The BTF of `counts` in the example of the module documentation, with
+    /// `pinning` set to `LIBBPF_PIN_BY_NAME`.
+    #[rustfmt::skip]
+    pub(crate) fn maps_section() -> Vec<u8> {
+        // [1] INT "int", [2] int[BPF_MAP_TYPE_HASH], [3] PTR 2,
+        // [4] int[1024], [5] PTR 4, [6] INT "long", [7] PTR 1, [8] PTR 6,
+        // [9] int[1], [10] PTR 9,
+        // [11] STRUCT { type, max_entries, key, value, pinning }
+        // [12] VAR "counts", [13] DATASEC ".maps"
+        let types = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            0, BTF_KIND_ARRAY << 24, 0, 1, 1, 1,
+            0, BTF_KIND_PTR << 24, 2,
+            0, BTF_KIND_ARRAY << 24, 0, 1, 1, 1024,
+            0, BTF_KIND_PTR << 24, 4,
+            5, BTF_KIND_INT << 24, 8, 64,
+            0, BTF_KIND_PTR << 24, 1,
+            0, BTF_KIND_PTR << 24, 6,
+            0, BTF_KIND_ARRAY << 24, 0, 1, 1, 1,
+            0, BTF_KIND_PTR << 24, 9,
+            0, BTF_KIND_STRUCT << 24 | 5, 40, 10, 3, 0, 15, 5, 64, 27, 7, 128, 31, 8, 192,
+            37, 10, 256,
+            45, BTF_KIND_VAR << 24, 11, 1,
+            52, BTF_KIND_DATASEC << 24 | 1, 40, 12, 0, 40,
+        ];
+        encode(
+            &types,
+            b"\0int\0long\0type\0max_entries\0key\0value\0pinning\0counts\0.maps\0",
+        )
+    }
+
+    #[test]
+    fn test_maps() {
+        let btf = Btf::parse(&maps_section()).unwrap();
+        let maps = btf.maps().unwrap();
+        assert_eq!(maps.len(), 1);
+        let map = &maps[0];
+        assert_eq!(map.name, "counts");
+        assert_eq!(map.def.type_, bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH);
+        assert_eq!(map.def.max_entries, 1024);
+        assert_eq!((map.def.key_size, map.def.value_size), (4, 8));
+        assert_eq!(map.def.map_flags, 0);
+        assert_eq!(map.pinning, LIBBPF_PIN_BY_NAME);
+        assert_eq!(btf.map_type_ids("counts"), Some((1, 6)));
+        assert_eq!(btf.type_size(4), Some(4096));
+
+        // an unknown member is ignored
+        let mut types = maps_section();
+        let key = types.windows(4).position(|w| w == b"key\0").unwrap();
+        types[key..key + 3].copy_from_slice(b"kez");
+        let maps = Btf::parse(&types).unwrap().maps().unwrap();
+        assert_eq!(maps[0].def.key_size, 0);
+        assert_eq!(maps[0].key_type_id, None);
+        assert_eq!(maps[0].def.value_size, 8);
+
+        // the `values` of a map of maps aren't supported
+        let mut types = maps_section();
+        let name = types
+            .windows(12)
+            .position(|w| w == b"max_entries\0")
+            .unwrap();
+        types[name..name + 7].copy_from_slice(b"values\0");
+        assert!(Btf::parse(&types).unwrap().maps().is_err());
+
+        let empty = Btf::parse(&encode(&[], b"\0")).unwrap();
+        assert!(empty.maps().unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_resolve_module_kfunc() {
+        if !std::path::Path::new("/sys/kernel/btf/vmlinux").exists() {
+            // a kernel built without BTF
+            return;
+        }
+        let mut resolver = KfuncResolver::new().unwrap();
+        // nf_conntrack is either built in, loaded, or not there at all
+        if let Some((id, btf_idx)) = resolver.resolve("bpf_xdp_ct_lookup").unwrap() {
+            assert!(id > 0);
+            let fds = resolver.fd_array().map_or(1, |fds| fds.len());
+            assert!((btf_idx as usize) < fds);
+        }
+    }
+
+    #[test]
+    fn test_resolve_metadata_kfuncs() {
+        let vmlinux = match Btf::vmlinux() {
+            Ok(btf) => btf,
+            // a kernel built without BTF
+            Err(_) => return,
+        };
+        let rx_hash = vmlinux.find(BTF_KIND_FUNC, "bpf_xdp_metadata_rx_hash");
+        if rx_hash.is_none() {
+            // XDP RX metadata is exposed since kernel 6.3
+            return;
+        }
+        let mut resolver = KfuncResolver::new().unwrap();
+        for name in &["bpf_xdp_metadata_rx_timestamp", "bpf_xdp_metadata_rx_hash"] {
+            let (id, btf_idx) = resolver.resolve(name).unwrap().unwrap();
+            assert_eq!(vmlinux.type_by_id(id).unwrap().kind(), BTF_KIND_FUNC);
+            assert_eq!(vmlinux.find(BTF_KIND_FUNC, name), Some(id));
+            // built into vmlinux, rather than a module
+            assert_eq!(btf_idx, 0);
+        }
+        assert!(resolver.fd_array().is_none());
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        assert!(Btf::parse(&[0; 24]).is_err());
+    }
+}
@@ -111,6 +111,36 @@ pub const BUILD_FLAGS: [&str; 20] = [
     "-c",
 ];
 
+#[cfg(target_arch = "s390x")]
+pub const BUILD_FLAGS: [&str; 20] = [
+    "-D__BPF_TRACING__",
+    "-D__KERNEL__",
+    "-target", "s390x",
+    "-Wall",
+    "-Werror",
+    "-Wunused",
+    "-Wno-unused-value",
+    "-Wno-pointer-sign",
+    "-Wno-compare-distinct-pointer-types",
+    "-Wno-unused-parameter",
+    "-Wno-missing-field-initializers",
+    "-Wno-initializer-overrides",
+    "-Wno-unknown-pragmas",
+    "-fno-stack-protector",
+    "-Wno-unused-label",
+    "-Wno-unused-variable",
+    "-O2",
+    "-emit-llvm",
+    "-c",
+];
+
+/// The `llc` architecture of programs built on this host, whose byte order
+/// the programs share with the host.
+#[cfg(target_endian = "little")]
+pub const BPF_MARCH: &str = "-march=bpfel";
+#[cfg(target_endian = "big")]
+pub const BPF_MARCH: &str = "-march=bpfeb";
+
 #[derive(Debug)]
 pub enum Error {
     OSUnsupported,
@@ -142,7 +172,7 @@ fn link_target(out_dir: &Path, source: &Path) -> Option<PathBuf> {
 pub fn build(flags: &[String], out_dir: &Path, source: &Path) -> Result<PathBuf, Error> {
     println!("Building eBPF module: {:?} ", source);
 
-    let llc_args = ["-march=bpf", "-filetype=obj", "-o"];
+    let llc_args = [BPF_MARCH, "-filetype=obj", "-o"];
     let cc_target = compile_target(out_dir, source).unwrap();
     let elf_target = link_target(out_dir, source).unwrap();
 
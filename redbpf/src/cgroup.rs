@@ -0,0 +1,326 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Cgroup socket buffer programs
+//!
+//! `cgroup_skb` programs run on the packets sent and received by the
+//! sockets of the processes in a cgroup and its descendants, and decide
+//! whether to let them through. A cgroup can hold a stack of programs for
+//! each direction, attached with `CgroupAttachMode::Multi`, which all run
+//! in the order they were attached, and a packet is only let through if
+//! every program allows it.
+//!
+//! `CgroupSkb::query` lists the programs attached to a cgroup, in that
+//! order, which is what auditing the policy of a cgroup needs:
+//!
+//! ```no_run
+//! use redbpf::{CgroupAttachType, CgroupSkb};
+//! use std::fs::File;
+//! use std::os::unix::io::AsRawFd;
+//!
+//! let cgroup = File::open("/sys/fs/cgroup/system.slice").unwrap();
+//! for attach_type in &[CgroupAttachType::Ingress, CgroupAttachType::Egress] {
+//!     println!("{:?}:", attach_type);
+//!     for prog in CgroupSkb::query(cgroup.as_raw_fd(), *attach_type).unwrap() {
+//!         println!("  {} {} tag {:02x?}", prog.id, prog.name, prog.tag);
+//!     }
+//! }
+//! ```
+
+use crate::sys::bpf::{
+    obj_get_info_by_fd, prog_attach, prog_detach, prog_get_fd_by_id, prog_query, ProgAttachAttr,
+};
+use crate::{LoadError, Program, Result};
+use bpf_sys::bpf_prog_info;
+use libc::close;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+/// The hook of a cgroup `cgroup_skb` programs are attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupAttachType {
+    Ingress,
+    Egress,
+}
+
+impl CgroupAttachType {
+    fn to_attach_type(self) -> bpf_sys::bpf_attach_type {
+        match self {
+            CgroupAttachType::Ingress => bpf_sys::bpf_attach_type_BPF_CGROUP_INET_INGRESS,
+            CgroupAttachType::Egress => bpf_sys::bpf_attach_type_BPF_CGROUP_INET_EGRESS,
+        }
+    }
+}
+
+/// How a program shares a cgroup hook with the programs of the cgroup and
+/// of its ancestors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CgroupAttachMode {
+    /// The only program of the hook in the cgroup's subtree.
+    Exclusive = 0,
+    /// The only program of the hook in the cgroup, which descendants can
+    /// replace with their own.
+    Override = bpf_sys::BPF_F_ALLOW_OVERRIDE,
+    /// One of the programs of the hook, which run in the order they were
+    /// attached, after the ones of the ancestors.
+    Multi = bpf_sys::BPF_F_ALLOW_MULTI,
+}
+
+/// A program attached to a cgroup, which is detached when the `CgroupSkb`
+/// is dropped, even if the program was unloaded or the cgroup closed
+/// meanwhile.
+pub struct CgroupSkb {
+    _link: CgroupLink,
+}
+
+/// A program attached to a cgroup hook, which is detached when dropped.
+///
+/// The link holds fds of its own to the cgroup and to the program, as the
+/// ones it was attached with may be closed, and their numbers reused, by
+/// the time it is dropped.
+pub(crate) struct CgroupLink {
+    cgroup_fd: RawFd,
+    prog_fd: RawFd,
+    attach_type: bpf_sys::bpf_attach_type,
+}
+
+impl CgroupLink {
+    /// Attaches the program `prog_fd` to the hook `attach_type` of the
+    /// cgroup `cgroup_fd`, with the `BPF_F_ALLOW_*` `flags`.
+    pub(crate) fn attach(
+        cgroup_fd: RawFd,
+        prog_fd: RawFd,
+        attach_type: bpf_sys::bpf_attach_type,
+        flags: u32,
+    ) -> Result<CgroupLink> {
+        let cgroup_fd = dup(cgroup_fd)?;
+        let prog_fd = match dup(prog_fd) {
+            Ok(fd) => fd,
+            Err(e) => {
+                unsafe { close(cgroup_fd) };
+                return Err(e.into());
+            }
+        };
+        let mut attr = ProgAttachAttr {
+            target_fd: cgroup_fd as u32,
+            attach_bpf_fd: prog_fd as u32,
+            attach_type,
+            attach_flags: flags,
+            ..Default::default()
+        };
+        if let Err(e) = prog_attach(&mut attr) {
+            unsafe {
+                close(cgroup_fd);
+                close(prog_fd);
+            }
+            return Err(e.into());
+        }
+
+        Ok(CgroupLink {
+            cgroup_fd,
+            prog_fd,
+            attach_type,
+        })
+    }
+}
+
+impl Drop for CgroupLink {
+    fn drop(&mut self) {
+        let mut attr = ProgAttachAttr {
+            target_fd: self.cgroup_fd as u32,
+            attach_bpf_fd: self.prog_fd as u32,
+            attach_type: self.attach_type,
+            ..Default::default()
+        };
+        let _ = prog_detach(&mut attr);
+        unsafe {
+            close(self.cgroup_fd);
+            close(self.prog_fd);
+        }
+    }
+}
+
+fn dup(fd: RawFd) -> io::Result<RawFd> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(fd),
+    }
+}
+
+/// Information about a loaded program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgInfo {
+    pub id: u32,
+    pub prog_type: bpf_sys::bpf_prog_type,
+    /// The name the program was loaded with, cut to 15 bytes.
+    pub name: String,
+    /// The hash of the program's instructions.
+    pub tag: [u8; 8],
+}
+
+impl CgroupSkb {
+    /// Returns the programs attached to the cgroup `cgroup_fd` at
+    /// `attach_type`, in the order they run.
+    ///
+    /// Programs detached while they are being looked up are left out.
+    pub fn query(cgroup_fd: RawFd, attach_type: CgroupAttachType) -> Result<Vec<ProgInfo>> {
+        let ids = prog_query(cgroup_fd, attach_type.to_attach_type())?;
+
+        let mut progs = Vec::with_capacity(ids.len());
+        for id in ids {
+            let fd = match prog_get_fd_by_id(id) {
+                Ok(fd) => fd,
+                Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let mut info = unsafe { mem::zeroed::<bpf_prog_info>() };
+            let res = unsafe { obj_get_info_by_fd(fd, &mut info) };
+            unsafe { close(fd) };
+            res?;
+            progs.push(ProgInfo::from(&info));
+        }
+
+        Ok(progs)
+    }
+}
+
+impl From<&bpf_prog_info> for ProgInfo {
+    fn from(info: &bpf_prog_info) -> ProgInfo {
+        let name: Vec<u8> = info
+            .name
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        ProgInfo {
+            id: info.id,
+            prog_type: info.type_,
+            name: String::from_utf8_lossy(&name).into_owned(),
+            tag: info.tag,
+        }
+    }
+}
+
+impl Program {
+    /// Attaches a loaded `cgroup_skb` program to the `attach_type` hook of
+    /// the cgroup `cgroup_fd`.
+    pub fn attach_cgroup_skb(
+        &mut self,
+        cgroup_fd: RawFd,
+        attach_type: CgroupAttachType,
+        mode: CgroupAttachMode,
+    ) -> Result<CgroupSkb> {
+        let prog_fd = self.fd.ok_or(LoadError::BPF)?;
+        let link = CgroupLink::attach(
+            cgroup_fd,
+            prog_fd,
+            attach_type.to_attach_type(),
+            mode as u32,
+        )?;
+
+        Ok(CgroupSkb { _link: link })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::load_program;
+    use crate::Module;
+    use std::fs::{self, File};
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_query() {
+        // r0 = 1 (allow); exit
+        let code = [0xb7, 0, 0, 0, 1, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0];
+        let mut progs: Vec<Program> = ["cg_first", "cg_second"]
+            .iter()
+            .map(|name| load_program("cgroup_skb", name, &code))
+            .collect();
+
+        let path = format!("/sys/fs/cgroup/redbpf_query_test_{}", std::process::id());
+        if fs::create_dir(&path).is_err() {
+            // no cgroup v2 hierarchy to experiment in
+            return;
+        }
+        let cgroup = File::open(&path).unwrap();
+        let fd = cgroup.as_raw_fd();
+        let ingress = CgroupAttachType::Ingress;
+
+        assert!(CgroupSkb::query(fd, ingress).unwrap().is_empty());
+        let (first, second) = progs.split_at_mut(1);
+        let first = first[0]
+            .attach_cgroup_skb(fd, ingress, CgroupAttachMode::Multi)
+            .unwrap();
+        let second = second[0]
+            .attach_cgroup_skb(fd, ingress, CgroupAttachMode::Multi)
+            .unwrap();
+
+        let names: Vec<String> = CgroupSkb::query(fd, ingress)
+            .unwrap()
+            .into_iter()
+            .map(|prog| prog.name)
+            .collect();
+        assert_eq!(names, vec!["cg_first", "cg_second"]);
+        assert!(CgroupSkb::query(fd, CgroupAttachType::Egress)
+            .unwrap()
+            .is_empty());
+
+        drop(first);
+        let progs = CgroupSkb::query(fd, ingress).unwrap();
+        assert_eq!(progs.len(), 1);
+        assert_eq!(progs[0].name, "cg_second");
+        assert_eq!(
+            progs[0].prog_type,
+            bpf_sys::bpf_prog_type_BPF_PROG_TYPE_CGROUP_SKB
+        );
+
+        drop(second);
+        drop(cgroup);
+        fs::remove_dir(&path).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_outlive_program() {
+        // r0 = 1 (allow); exit
+        let code = [0xb7, 0, 0, 0, 1, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0];
+        let mut module = Module {
+            programs: vec![load_program("cgroup_skb", "cg_outlive", &code)],
+            maps: vec![],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+
+        let path = format!("/sys/fs/cgroup/redbpf_outlive_test_{}", std::process::id());
+        if fs::create_dir(&path).is_err() {
+            // no cgroup v2 hierarchy to experiment in
+            return;
+        }
+        let cgroup = File::open(&path).unwrap();
+        let egress = CgroupAttachType::Egress;
+        let attached = module.programs[0]
+            .attach_cgroup_skb(cgroup.as_raw_fd(), egress, CgroupAttachMode::Multi)
+            .unwrap();
+        let query = || CgroupSkb::query(cgroup.as_raw_fd(), egress).unwrap();
+
+        // the program stays attached after its fd is closed, and the fd
+        // number may be taken by anything
+        module.close().unwrap();
+        let _reused = File::open("/dev/null").unwrap();
+        assert_eq!(query().len(), 1);
+        drop(attached);
+        assert!(query().is_empty());
+
+        drop(cgroup);
+        fs::remove_dir(&path).unwrap();
+    }
+}
@@ -0,0 +1,607 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # CO-RE relocations
+//!
+//! Programs reading kernel structs with clang's
+//! `__builtin_preserve_access_index` don't depend on the layout of the
+//! kernel they were compiled against. For every instruction holding a field
+//! offset, the compiler records in the `.BTF.ext` section the path to the
+//! field in the program's own BTF, e.g. `0:1` for the second member of the
+//! struct a pointer points to. Before the program is loaded, the instruction
+//! is patched with the offset of the field of the same name in the target
+//! kernel's BTF.
+//!
+//! Only field relocations are supported: offsets, sizes and existence
+//! checks. Bitfields aren't. Relocations of other kinds, such as type and
+//! enum value existence checks, fail to load the program they belong to,
+//! but not to parse the module.
+//!
+//! rustc doesn't record field accesses, so programs written in Rust take
+//! the offsets of fields from `redbpf_probes::core_offset!` instead. It
+//! loads the address of an undefined symbol named after the field, e.g.
+//! `__redbpf_core_offset:task_struct:real_parent`, which is patched with
+//! the offset of the field like the relocations of `.BTF.ext`.
+
+use crate::btf::{
+    read_u16, read_u32, section, Btf, BTF_KIND_ARRAY, BTF_KIND_STRUCT, BTF_KIND_UNION,
+};
+use crate::{LoadError, Result};
+use bpf_sys::bpf_insn;
+
+const BTF_EXT_MAGIC: u16 = 0xeb9f;
+/// Size of the `.BTF.ext` header up to the CO-RE relocation section.
+const BTF_EXT_CORE_HDR_LEN: usize = 32;
+
+const CORE_FIELD_BYTE_OFFSET: u32 = 0;
+const CORE_FIELD_BYTE_SIZE: u32 = 1;
+const CORE_FIELD_EXISTS: u32 = 2;
+/// The last of the kinds of field relocations, the kinds after it relocate
+/// types and enum values.
+const CORE_FIELD_RSHIFT_U64: u32 = 5;
+
+/// Helper id that accesses of fields the target lacks are replaced with,
+/// following libbpf.
+const CORE_POISON_CALL: i32 = 0xbad_2310;
+
+/// Prefix of the symbols `redbpf_probes::core_offset!` loads the offsets of
+/// fields from, followed by the struct and the path to the field, separated
+/// by colons.
+pub(crate) const CORE_OFFSET_SYM_PREFIX: &str = "__redbpf_core_offset:";
+
+/// A step of the path to a field.
+#[derive(Debug, Clone, PartialEq)]
+enum Access {
+    /// A member of a struct or union, looked up by name.
+    Member(String),
+    /// An element of an array.
+    Index(u32),
+}
+
+/// An instruction to patch with a property of a field, as found in the
+/// target's BTF.
+#[derive(Debug, Clone)]
+pub(crate) struct CoreRelo {
+    pub insn_idx: usize,
+    kind: u32,
+    /// The kind of the root type, struct or union.
+    root_kind: u32,
+    /// The name of the root type, without its `___flavor` suffix.
+    root_name: String,
+    /// Index into the array of root types the access starts from.
+    root_index: u32,
+    path: Vec<Access>,
+}
+
+/// A field found in the target's BTF.
+struct Field {
+    bit_offset: u64,
+    type_id: u32,
+}
+
+/// Parses the CO-RE relocations of `.BTF.ext`, resolving the fields they
+/// access in `btf`, the BTF of the object. Returns them along with the name
+/// of the section of the instructions they patch.
+pub(crate) fn parse_core_relos(data: &[u8], btf: &Btf) -> Result<Vec<(String, CoreRelo)>> {
+    if read_u16(data, 0)? != BTF_EXT_MAGIC {
+        return Err(LoadError::BTF);
+    }
+    let hdr_len = read_u32(data, 4)? as usize;
+    if hdr_len < BTF_EXT_CORE_HDR_LEN {
+        // written by a compiler without CO-RE support
+        return Ok(vec![]);
+    }
+    let core_off = read_u32(data, 24)? as usize;
+    let core_len = read_u32(data, 28)? as usize;
+    let relos = section(data, hdr_len + core_off, core_len)?;
+    if relos.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let rec_size = read_u32(relos, 0)? as usize;
+    if rec_size < 16 {
+        return Err(LoadError::BTF);
+    }
+    let mut ret = vec![];
+    let mut pos = 4;
+    while pos < relos.len() {
+        let sec_name = btf.name(read_u32(relos, pos)?).ok_or(LoadError::BTF)?;
+        let num_info = read_u32(relos, pos + 4)? as usize;
+        pos += 8;
+        for _ in 0..num_info {
+            let insn_off = read_u32(relos, pos)? as usize;
+            let type_id = read_u32(relos, pos + 4)?;
+            let access = btf.name(read_u32(relos, pos + 8)?).ok_or(LoadError::BTF)?;
+            let kind = read_u32(relos, pos + 12)?;
+            let mut relo = if kind <= CORE_FIELD_RSHIFT_U64 {
+                CoreRelo::local(btf, type_id, access)?
+            } else {
+                CoreRelo::unsupported(btf, type_id)
+            };
+            relo.insn_idx = insn_off / std::mem::size_of::<bpf_insn>();
+            relo.kind = kind;
+            ret.push((sec_name.to_string(), relo));
+            pos += rec_size;
+        }
+    }
+
+    Ok(ret)
+}
+
+impl CoreRelo {
+    /// Turns the access string `access`, member and element indices starting
+    /// from the type `type_id` of `btf`, into a path of member names.
+    fn local(btf: &Btf, type_id: u32, access: &str) -> Result<CoreRelo> {
+        let invalid = || LoadError::CoreReloc(format!("invalid access {}", access));
+        let mut indices = access.split(':').map(|idx| idx.parse::<u32>());
+        let root_index = indices.next().ok_or_else(invalid)?.map_err(|_| invalid())?;
+        let root = btf
+            .type_by_id(btf.skip_modifiers(type_id))
+            .ok_or_else(invalid)?;
+        let root_kind = root.kind();
+        if root_kind != BTF_KIND_STRUCT && root_kind != BTF_KIND_UNION {
+            return Err(invalid());
+        }
+        let root_name = btf.name(root.name_off).ok_or_else(invalid)?;
+
+        let mut path = vec![];
+        let mut ty = root;
+        for idx in indices {
+            let idx = idx.map_err(|_| invalid())?;
+            let next = match ty.kind() {
+                BTF_KIND_STRUCT | BTF_KIND_UNION => {
+                    let member = ty.data.chunks(3).nth(idx as usize).ok_or_else(invalid)?;
+                    if ty.kind_flag() && member[2] >> 24 != 0 {
+                        return Err(LoadError::CoreReloc(format!(
+                            "{}: bitfields aren't supported",
+                            access
+                        )));
+                    }
+                    // members of anonymous structs and unions are looked up
+                    // through them in the target
+                    let name = btf.name(member[0]).ok_or_else(invalid)?;
+                    if !name.is_empty() {
+                        path.push(Access::Member(name.to_string()));
+                    }
+                    member[1]
+                }
+                BTF_KIND_ARRAY => {
+                    path.push(Access::Index(idx));
+                    ty.data[0]
+                }
+                _ => return Err(invalid()),
+            };
+            ty = btf
+                .type_by_id(btf.skip_modifiers(next))
+                .ok_or_else(invalid)?;
+        }
+
+        Ok(CoreRelo {
+            insn_idx: 0,
+            kind: CORE_FIELD_BYTE_OFFSET,
+            root_kind,
+            root_name: essential_name(root_name).to_string(),
+            root_index,
+            path,
+        })
+    }
+
+    /// A relocation of a type or enum value, rooted at `type_id`, which are
+    /// kept so that `apply` fails the load of the program with them.
+    fn unsupported(btf: &Btf, type_id: u32) -> CoreRelo {
+        let root = btf.type_by_id(btf.skip_modifiers(type_id));
+        let root_name = root.and_then(|ty| btf.name(ty.name_off)).unwrap_or("");
+        CoreRelo {
+            insn_idx: 0,
+            kind: CORE_FIELD_BYTE_OFFSET,
+            root_kind: root.map(|ty| ty.kind()).unwrap_or(0),
+            root_name: essential_name(root_name).to_string(),
+            root_index: 0,
+            path: vec![],
+        }
+    }
+
+    /// Turns the name of a symbol of `redbpf_probes::core_offset!` into the
+    /// relocation of the offset of its field, loaded by the instruction at
+    /// `insn_idx`. Returns `None` for other symbols.
+    pub fn from_symbol(name: &str, insn_idx: usize) -> Option<CoreRelo> {
+        let mut names = name.strip_prefix(CORE_OFFSET_SYM_PREFIX)?.split(':');
+        let root_name = names.next()?.to_string();
+        let path: Vec<Access> = names.map(|name| Access::Member(name.to_string())).collect();
+        if root_name.is_empty() || path.is_empty() {
+            return None;
+        }
+
+        Some(CoreRelo {
+            insn_idx,
+            kind: CORE_FIELD_BYTE_OFFSET,
+            root_kind: BTF_KIND_STRUCT,
+            root_name,
+            root_index: 0,
+            path,
+        })
+    }
+
+    /// Patches `code` with the field as found in `target`, starting over
+    /// from `insn`, the instruction as compiled.
+    pub fn apply(&self, code: &mut [bpf_insn], insn: bpf_insn, target: &Btf) -> Result<()> {
+        let field = self.resolve(target);
+        let value = match (self.kind, &field) {
+            (CORE_FIELD_EXISTS, _) => field.is_some() as u64,
+            (CORE_FIELD_BYTE_OFFSET, Some(field)) => {
+                if field.bit_offset % 8 != 0 {
+                    return Err(LoadError::CoreReloc(format!(
+                        "{}: bitfields aren't supported",
+                        self
+                    )));
+                }
+                field.bit_offset / 8
+            }
+            (CORE_FIELD_BYTE_SIZE, Some(field)) => target
+                .type_size(field.type_id)
+                .ok_or_else(|| LoadError::CoreReloc(format!("{}: unsized field", self)))?
+                as u64,
+            (CORE_FIELD_BYTE_OFFSET, None) | (CORE_FIELD_BYTE_SIZE, None) => {
+                return poison(code, self.insn_idx, insn, self)
+            }
+            (kind, _) => {
+                return Err(LoadError::CoreReloc(format!(
+                    "{}: unsupported relocation kind {}",
+                    self, kind
+                )))
+            }
+        };
+
+        patch(code, self.insn_idx, insn, value, self)
+    }
+
+    /// Finds the field in the first type of `target` with the root's name
+    /// and kind that has it.
+    fn resolve(&self, target: &Btf) -> Option<Field> {
+        target
+            .type_ids()
+            .filter(|&id| {
+                let name = target
+                    .type_by_id(id)
+                    .filter(|ty| ty.kind() == self.root_kind)
+                    .and_then(|ty| target.name(ty.name_off));
+                name.map(essential_name) == Some(self.root_name.as_str())
+            })
+            .find_map(|id| self.resolve_in(target, id))
+    }
+
+    fn resolve_in(&self, target: &Btf, root: u32) -> Option<Field> {
+        let size = target.type_size(root)? as u64;
+        let mut field = Field {
+            bit_offset: self.root_index as u64 * size * 8,
+            type_id: root,
+        };
+        for access in self.path.iter() {
+            let ty = target.type_by_id(target.skip_modifiers(field.type_id))?;
+            let (bit_offset, type_id) = match access {
+                Access::Member(name) => {
+                    find_member(target, ty.kind(), &ty.data, ty.kind_flag(), name)?
+                }
+                Access::Index(idx) => {
+                    if ty.kind() != BTF_KIND_ARRAY {
+                        return None;
+                    }
+                    let (elem, len) = (ty.data[0], ty.data[2]);
+                    // flexible array members have no length
+                    if *idx >= len && len != 0 {
+                        return None;
+                    }
+                    (*idx as u64 * target.type_size(elem)? as u64 * 8, elem)
+                }
+            };
+            field.bit_offset += bit_offset;
+            field.type_id = type_id;
+        }
+        Some(field)
+    }
+}
+
+impl std::fmt::Display for CoreRelo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.root_name)?;
+        for access in self.path.iter() {
+            match access {
+                Access::Member(name) => write!(f, ".{}", name)?,
+                Access::Index(idx) => write!(f, "[{}]", idx)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the member `name` of a struct or union, or of the anonymous structs
+/// and unions it contains, and returns its offset in bits and its type.
+fn find_member(
+    btf: &Btf,
+    kind: u32,
+    members: &[u32],
+    kind_flag: bool,
+    name: &str,
+) -> Option<(u64, u32)> {
+    if kind != BTF_KIND_STRUCT && kind != BTF_KIND_UNION {
+        return None;
+    }
+    for member in members.chunks(3) {
+        let (offset, bitfield_size) = if kind_flag {
+            (member[2] & 0xff_ffff, member[2] >> 24)
+        } else {
+            (member[2], 0)
+        };
+        match btf.name(member[0]) {
+            Some(member_name) if member_name == name => {
+                if bitfield_size != 0 {
+                    return None;
+                }
+                return Some((offset as u64, member[1]));
+            }
+            Some("") => {
+                let ty = btf.type_by_id(btf.skip_modifiers(member[1]))?;
+                if let Some((inner, type_id)) =
+                    find_member(btf, ty.kind(), &ty.data, ty.kind_flag(), name)
+                {
+                    return Some((offset as u64 + inner, type_id));
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Strips the `___flavor` suffix programs use to declare several versions
+/// of a type.
+fn essential_name(name: &str) -> &str {
+    match name.find("___") {
+        Some(idx) => &name[..idx],
+        None => name,
+    }
+}
+
+/// Writes `value` into the immediate or offset of the instruction at `idx`.
+fn patch(
+    code: &mut [bpf_insn],
+    idx: usize,
+    mut insn: bpf_insn,
+    value: u64,
+    relo: &CoreRelo,
+) -> Result<()> {
+    let unpatchable = || LoadError::CoreReloc(format!("{}: can't patch instruction {}", relo, idx));
+    let class = (insn.code & 0x07) as u32;
+    match class {
+        bpf_sys::BPF_ALU | bpf_sys::BPF_ALU64 if insn.code as u32 & bpf_sys::BPF_X == 0 => {
+            insn.imm = value as i32;
+        }
+        bpf_sys::BPF_LDX | bpf_sys::BPF_ST | bpf_sys::BPF_STX => {
+            if value > i16::MAX as u64 {
+                return Err(unpatchable());
+            }
+            insn.off = value as i16;
+        }
+        bpf_sys::BPF_LD
+            if insn.code == (bpf_sys::BPF_LD | bpf_sys::BPF_IMM | bpf_sys::BPF_DW) as u8 =>
+        {
+            insn.imm = value as i32;
+            code.get_mut(idx + 1).ok_or_else(unpatchable)?.imm = (value >> 32) as i32;
+        }
+        _ => return Err(unpatchable()),
+    }
+    *code.get_mut(idx).ok_or_else(unpatchable)? = insn;
+    Ok(())
+}
+
+/// Replaces the instruction at `idx` with an invalid helper call, which the
+/// verifier only rejects if the program can reach it, so that programs can
+/// guard accesses of fields with existence checks.
+fn poison(code: &mut [bpf_insn], idx: usize, mut insn: bpf_insn, relo: &CoreRelo) -> Result<()> {
+    if insn.code == (bpf_sys::BPF_LD | bpf_sys::BPF_IMM | bpf_sys::BPF_DW) as u8 {
+        return Err(LoadError::CoreReloc(format!("{}: no such field", relo)));
+    }
+    insn.code = (bpf_sys::BPF_JMP | bpf_sys::BPF_CALL) as u8;
+    insn.set_dst_reg(0);
+    insn.set_src_reg(0);
+    insn.off = 0;
+    insn.imm = CORE_POISON_CALL;
+    *code
+        .get_mut(idx)
+        .ok_or_else(|| LoadError::CoreReloc(format!("{}: no such field", relo)))? = insn;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::btf::test::encode;
+    use crate::btf::BTF_KIND_INT;
+
+    fn push(buf: &mut Vec<u8>, words: &[u32]) {
+        for w in words {
+            buf.extend_from_slice(&w.to_ne_bytes());
+        }
+    }
+
+    /// `.BTF.ext` with the CO-RE relocations `(insn_off, type_id,
+    /// access_str_off, kind)` of the section `sec_name_off`.
+    fn btf_ext(sec_name_off: u32, relos: &[[u32; 4]]) -> Vec<u8> {
+        let mut core = vec![];
+        push(&mut core, &[16, sec_name_off, relos.len() as u32]);
+        for relo in relos {
+            push(&mut core, relo);
+        }
+        let mut buf = vec![];
+        buf.extend_from_slice(&BTF_EXT_MAGIC.to_ne_bytes());
+        buf.extend_from_slice(&[1, 0]);
+        push(&mut buf, &[32, 0, 0, 0, 0, 0, core.len() as u32]);
+        buf.extend_from_slice(&core);
+        buf
+    }
+
+    fn insn(code: u32, off: i16, imm: i32) -> bpf_insn {
+        let mut insn = unsafe { std::mem::zeroed::<bpf_insn>() };
+        insn.code = code as u8;
+        insn.off = off;
+        insn.imm = imm;
+        insn
+    }
+
+    #[test]
+    fn test_field_offset_against_targets() {
+        // [1] INT "int", [2] STRUCT "task_struct___old" { int pid; int tgid; }
+        #[rustfmt::skip]
+        let local = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            5, BTF_KIND_STRUCT << 24 | 2, 8, 23, 1, 0, 27, 1, 32,
+        ];
+        let local = Btf::parse(&encode(
+            &local,
+            b"\0int\0task_struct___old\0pid\0tgid\0kprobe/foo\x000:1\0",
+        ))
+        .unwrap();
+        // r0 = *(u32 *)(r1 + 4), r0 = 1
+        let ext = btf_ext(
+            32,
+            &[
+                [0, 2, 43, CORE_FIELD_BYTE_OFFSET],
+                [8, 2, 43, CORE_FIELD_EXISTS],
+            ],
+        );
+        let relos = parse_core_relos(&ext, &local).unwrap();
+        assert_eq!(relos.len(), 2);
+        assert_eq!(relos[0].0, "kprobe/foo");
+        assert_eq!(relos[1].1.insn_idx, 1);
+        assert_eq!(relos[0].1.to_string(), "task_struct.tgid");
+
+        let ldx = bpf_sys::BPF_LDX | bpf_sys::BPF_MEM | bpf_sys::BPF_W;
+        let mov = bpf_sys::BPF_ALU64 | bpf_sys::BPF_MOV | bpf_sys::BPF_K;
+        let compiled = [insn(ldx, 4, 0), insn(mov, 0, 1)];
+        let relocate = |target: &Btf| {
+            let mut code = compiled.to_vec();
+            for (_, relo) in relos.iter() {
+                let compiled = compiled[relo.insn_idx];
+                relo.apply(&mut code, compiled, target).unwrap();
+            }
+            code
+        };
+
+        // [1] INT "long", [2] INT "int",
+        // [3] STRUCT "task_struct" { long state; union { int tgid; }; int pid; }
+        // [4] UNION { int tgid; }
+        #[rustfmt::skip]
+        let newer = [
+            1, BTF_KIND_INT << 24, 8, 64,
+            6, BTF_KIND_INT << 24, 4, 32,
+            10, BTF_KIND_STRUCT << 24 | 3, 16, 22, 1, 0, 0, 4, 64, 28, 2, 96,
+            0, BTF_KIND_UNION << 24 | 1, 4, 32, 2, 0,
+        ];
+        let strings = b"\0long\0int\0task_struct\0state\0pid\0tgid\0";
+        let newer = Btf::parse(&encode(&newer, strings)).unwrap();
+        let code = relocate(&newer);
+        assert_eq!(code[0].off, 8);
+        assert_eq!(code[1].imm, 1);
+
+        // [1] INT "int", [2] STRUCT "task_struct" { int tgid; int pid; }
+        #[rustfmt::skip]
+        let older = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            5, BTF_KIND_STRUCT << 24 | 2, 8, 17, 1, 0, 22, 1, 32,
+        ];
+        let older = Btf::parse(&encode(&older, b"\0int\0task_struct\0tgid\0pid\0")).unwrap();
+        let code = relocate(&older);
+        assert_eq!(code[0].off, 0);
+        assert_eq!(code[1].imm, 1);
+
+        // without the field, the load is poisoned and the check fails
+        #[rustfmt::skip]
+        let without = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            5, BTF_KIND_STRUCT << 24 | 1, 4, 17, 1, 0,
+        ];
+        let without = Btf::parse(&encode(&without, b"\0int\0task_struct\0pid\0")).unwrap();
+        let code = relocate(&without);
+        assert_eq!(code[0].code, (bpf_sys::BPF_JMP | bpf_sys::BPF_CALL) as u8);
+        assert_eq!(code[0].imm, CORE_POISON_CALL);
+        assert_eq!(code[1].imm, 0);
+
+        // relocating again starts over from the compiled instructions
+        let code = relocate(&newer);
+        assert_eq!((code[0].code, code[0].off), (ldx as u8, 8));
+    }
+
+    #[test]
+    fn test_offset_symbol() {
+        assert!(CoreRelo::from_symbol("__redbpf_ringbuf", 0).is_none());
+        assert!(CoreRelo::from_symbol("__redbpf_core_offset:task_struct", 0).is_none());
+        let relo = CoreRelo::from_symbol("__redbpf_core_offset:pid_namespace:ns:inum", 2).unwrap();
+        assert_eq!(relo.insn_idx, 2);
+        assert_eq!(relo.to_string(), "pid_namespace.ns.inum");
+
+        // [1] INT "int", [2] STRUCT "ns_common" { long stashed; int inum; }
+        // [3] STRUCT "pid_namespace" { int level; struct ns_common ns; }
+        // [4] INT "long"
+        #[rustfmt::skip]
+        let types = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            5, BTF_KIND_STRUCT << 24 | 2, 16, 29, 4, 0, 37, 1, 64,
+            15, BTF_KIND_STRUCT << 24 | 2, 24, 42, 1, 0, 48, 2, 64,
+            51, BTF_KIND_INT << 24, 8, 64,
+        ];
+        let strings = b"\0int\0ns_common\0pid_namespace\0stashed\0inum\0level\0ns\0long\0";
+        let target = Btf::parse(&encode(&types, strings)).unwrap();
+        // r1 = offset ll
+        let ld = bpf_sys::BPF_LD | bpf_sys::BPF_IMM | bpf_sys::BPF_DW;
+        let compiled = [insn(ld, 0, 0), insn(0, 0, 0), insn(ld, 0, 0), insn(0, 0, 0)];
+        let mut code = compiled.to_vec();
+        relo.apply(&mut code, compiled[2], &target).unwrap();
+        assert_eq!((code[2].imm, code[3].imm), (16, 0));
+
+        // the field of the running kernel, if it has BTF
+        let vmlinux = match Btf::vmlinux() {
+            Ok(vmlinux) => vmlinux,
+            Err(_) => return,
+        };
+        let relo =
+            CoreRelo::from_symbol("__redbpf_core_offset:task_struct:real_parent", 0).unwrap();
+        let mut code = compiled.to_vec();
+        relo.apply(&mut code, compiled[0], &vmlinux).unwrap();
+        assert!(code[0].imm > 0);
+        let relo =
+            CoreRelo::from_symbol("__redbpf_core_offset:task_struct:no_such_field", 0).unwrap();
+        assert!(relo.apply(&mut code, compiled[0], &vmlinux).is_err());
+    }
+
+    #[test]
+    fn test_bad_access() {
+        // [1] INT "int", [2] STRUCT "s" { int a; }
+        #[rustfmt::skip]
+        let types = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            5, BTF_KIND_STRUCT << 24 | 1, 4, 7, 1, 0,
+        ];
+        let btf = Btf::parse(&encode(&types, b"\0int\0s\0a\0x\x000:1\x000\0")).unwrap();
+        // no second member
+        assert!(parse_core_relos(&btf_ext(9, &[[0, 2, 11, 0]]), &btf).is_err());
+        // the root isn't a struct
+        assert!(parse_core_relos(&btf_ext(9, &[[0, 1, 15, 0]]), &btf).is_err());
+        assert!(parse_core_relos(&btf_ext(9, &[[0, 2, 15, 0]]), &btf).is_ok());
+        assert!(parse_core_relos(&[0; 32], &btf).is_err());
+
+        // type existence checks are parsed, but fail to be applied
+        let relos = parse_core_relos(&btf_ext(9, &[[0, 1, 15, 8]]), &btf).unwrap();
+        assert_eq!(relos[0].1.to_string(), "int");
+        let mov = bpf_sys::BPF_ALU64 | bpf_sys::BPF_MOV | bpf_sys::BPF_K;
+        let compiled = insn(mov, 0, 1);
+        let mut code = [compiled];
+        match relos[0].1.apply(&mut code, compiled, &btf) {
+            Err(LoadError::CoreReloc(msg)) => assert_eq!(msg, "int: unsupported relocation kind 8"),
+            _ => panic!("type existence check applied"),
+        }
+    }
+}
@@ -16,6 +16,39 @@ pub enum LoadError {
     IO(::std::io::Error),
     Uname,
     Reloc,
+    BTF,
+    Kfunc(String),
+    /// A map given a size, e.g. with `Loader::max_entries`, isn't declared
+    /// by the module.
+    UnknownMap(String),
+    /// A CO-RE relocation couldn't be applied, naming the field it accesses.
+    CoreReloc(String),
+    /// A program uses a map the running kernel couldn't create, such as a
+    /// ring buffer before kernel 5.8. See `Module::set_autoload` to skip
+    /// such programs.
+    MissingMap {
+        program: String,
+        map: String,
+    },
+    /// The running kernel doesn't support programs of this type, e.g. it is
+    /// too old, or built without the config option the type depends on.
+    UnsupportedProgramType {
+        ty: crate::ProgramKind,
+        /// The version of the kernel, as `major.minor.patch`.
+        kernel: String,
+    },
+    /// A program was rejected by the kernel, usually by the verifier.
+    ProgramLoad {
+        name: String,
+        error: ::std::io::Error,
+        log: String,
+        /// The index of the instruction the verifier rejected, if the log
+        /// names one. Multiplied by 8, it is the offset of the instruction
+        /// in the program's ELF section.
+        failed_insn: Option<usize>,
+        /// The verifier's explanation, from the end of the log.
+        reason: String,
+    },
 }
 
 pub type Result<T> = ::std::result::Result<T, LoadError>;
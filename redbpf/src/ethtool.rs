@@ -0,0 +1,252 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Kernel XDP statistics
+//!
+//! Drivers running XDP programs count the actions the programs return, and
+//! report the counts among their ethtool statistics. The kernel has no
+//! common format for these, so `xdp_stats` picks them out by name, which
+//! works for drivers following the usual `rx_xdp_drop`, `rx_xdp_redirect`,
+//! etc. naming, such as `veth`, `mlx5` or `virtio_net`.
+//!
+//! Comparing these with counters kept by the program itself shows packets
+//! the program never saw, or actions that failed in the driver.
+//!
+//! ```no_run
+//! let ifindex = 2;
+//! let stats = redbpf::xdp_stats(ifindex).unwrap();
+//! println!("drop: {:?}", stats.drop);
+//! println!("pass: {:?}", stats.pass);
+//! println!("tx: {:?}", stats.tx);
+//! println!("redirect: {:?}", stats.redirect);
+//! println!("aborted: {:?}", stats.aborted);
+//! ```
+
+use crate::Result;
+use libc::{c_char, c_int, c_void, close, if_indextoname, ioctl, socket, AF_INET, IF_NAMESIZE, SOCK_DGRAM};
+use std::io;
+use std::mem;
+
+const SIOCETHTOOL: u64 = 0x8946;
+const ETHTOOL_GDRVINFO: u32 = 0x03;
+const ETHTOOL_GSTRINGS: u32 = 0x1b;
+const ETHTOOL_GSTATS: u32 = 0x1d;
+const ETH_SS_STATS: u32 = 1;
+const ETH_GSTRING_LEN: usize = 32;
+
+/// Counts of XDP actions as seen by the driver.
+///
+/// Fields are `None` if the driver doesn't report the action.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct XdpStats {
+    pub aborted: Option<u64>,
+    pub drop: Option<u64>,
+    pub pass: Option<u64>,
+    pub tx: Option<u64>,
+    pub redirect: Option<u64>,
+}
+
+impl XdpStats {
+    /// Returns `true` if the driver reports any XDP action.
+    pub fn is_supported(&self) -> bool {
+        *self != XdpStats::default()
+    }
+}
+
+#[repr(C)]
+struct IfReq {
+    name: [c_char; IF_NAMESIZE],
+    data: *mut c_void,
+    _pad: [u8; 16],
+}
+
+#[repr(C)]
+struct DrvInfo {
+    cmd: u32,
+    driver: [u8; 32],
+    version: [u8; 32],
+    fw_version: [u8; 32],
+    bus_info: [u8; 32],
+    erom_version: [u8; 32],
+    reserved2: [u8; 12],
+    n_priv_flags: u32,
+    n_stats: u32,
+    testinfo_len: u32,
+    eedump_len: u32,
+    regdump_len: u32,
+}
+
+/// Reads the XDP action counts of the interface `ifindex` from its ethtool
+/// statistics.
+///
+/// Drivers that don't count XDP actions yield an `XdpStats` without any
+/// counts, see `XdpStats::is_supported`.
+pub fn xdp_stats(ifindex: u32) -> Result<XdpStats> {
+    let mut req = IfReq {
+        name: [0; IF_NAMESIZE],
+        data: std::ptr::null_mut(),
+        _pad: [0; 16],
+    };
+    if unsafe { if_indextoname(ifindex, req.name.as_mut_ptr()) }.is_null() {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let sock = unsafe { socket(AF_INET, SOCK_DGRAM, 0) };
+    if sock < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let res = read_stats(sock, &mut req);
+    unsafe { close(sock) };
+    let (names, values) = match res {
+        Ok(stats) => stats,
+        // drivers without ethtool support, such as the loopback device
+        Err(ref e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => return Ok(XdpStats::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let names: Vec<&str> = names
+        .chunks(ETH_GSTRING_LEN)
+        .map(|name| {
+            let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+            std::str::from_utf8(&name[..len]).unwrap_or("")
+        })
+        .collect();
+    Ok(xdp_stats_from(&names, &values))
+}
+
+fn ethtool(sock: c_int, req: &mut IfReq, data: *mut c_void) -> io::Result<()> {
+    req.data = data;
+    if unsafe { ioctl(sock, SIOCETHTOOL as _, req as *mut IfReq) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns the names, `ETH_GSTRING_LEN` bytes each, and values of the
+/// interface's statistics.
+fn read_stats(sock: c_int, req: &mut IfReq) -> io::Result<(Vec<u8>, Vec<u64>)> {
+    let mut info: DrvInfo = unsafe { mem::zeroed() };
+    info.cmd = ETHTOOL_GDRVINFO;
+    ethtool(sock, req, &mut info as *mut _ as *mut c_void)?;
+    let n_stats = info.n_stats as usize;
+
+    // struct ethtool_gstrings: cmd, string_set, len, data
+    let mut strings = vec![0u32; 3 + n_stats * ETH_GSTRING_LEN / 4];
+    strings[0] = ETHTOOL_GSTRINGS;
+    strings[1] = ETH_SS_STATS;
+    strings[2] = n_stats as u32;
+    ethtool(sock, req, strings.as_mut_ptr() as *mut c_void)?;
+
+    // struct ethtool_stats: cmd, n_stats, data
+    let mut stats = vec![0u64; 1 + n_stats];
+    unsafe {
+        let header = stats.as_mut_ptr() as *mut u32;
+        *header = ETHTOOL_GSTATS;
+        *header.add(1) = n_stats as u32;
+    }
+    ethtool(sock, req, stats.as_mut_ptr() as *mut c_void)?;
+
+    // the driver may have reported fewer statistics than it announced
+    let n_stats = n_stats.min(strings[2] as usize);
+    let names = strings[3..]
+        .iter()
+        .flat_map(|w| w.to_ne_bytes().to_vec())
+        .take(n_stats * ETH_GSTRING_LEN)
+        .collect();
+    Ok((names, stats[1..=n_stats].to_vec()))
+}
+
+/// Sums the statistics counting each XDP action.
+///
+/// Drivers report per-queue counts, totals, or both, so totals are used
+/// where present, and per-queue counts are summed otherwise.
+fn xdp_stats_from(names: &[&str], values: &[u64]) -> XdpStats {
+    #[derive(Default, Clone, Copy)]
+    struct Sum {
+        total: Option<u64>,
+        queues: Option<u64>,
+    }
+
+    let mut sums = [Sum::default(); 5];
+    for (name, &value) in names.iter().zip(values) {
+        let name = name.to_ascii_lowercase();
+        let tokens: Vec<&str> = name.split(&['_', '-', '.', ':'][..]).collect();
+        let xdp = match tokens.iter().position(|&t| t == "xdp") {
+            Some(xdp) => xdp,
+            None => continue,
+        };
+        let per_queue = tokens[..xdp]
+            .iter()
+            .any(|t| t.bytes().any(|c| c.is_ascii_digit()));
+        let tokens = &tokens[xdp + 1..];
+        if tokens.iter().any(|t| t.starts_with("err") || *t == "full") {
+            continue;
+        }
+        let action = match tokens.first() {
+            Some(&"aborted") | Some(&"abort") => 0,
+            Some(&"drop") | Some(&"drops") => 1,
+            Some(&"pass") => 2,
+            Some(&"tx") => 3,
+            Some(&"redirect") | Some(&"redirects") => 4,
+            _ => continue,
+        };
+        let sum = if per_queue {
+            &mut sums[action].queues
+        } else {
+            &mut sums[action].total
+        };
+        *sum = Some(sum.unwrap_or(0) + value);
+    }
+
+    let count = |action: usize| sums[action].total.or(sums[action].queues);
+    XdpStats {
+        aborted: count(0),
+        drop: count(1),
+        pass: count(2),
+        tx: count(3),
+        redirect: count(4),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_xdp_stats_from() {
+        // veth
+        let names = [
+            "peer_ifindex",
+            "rx_queue_0_xdp_packets",
+            "rx_queue_0_xdp_redirect",
+            "rx_queue_0_xdp_drops",
+            "rx_queue_0_xdp_tx",
+            "rx_queue_0_xdp_tx_errors",
+            "rx_queue_1_xdp_drops",
+            "tx_queue_0_xdp_xmit",
+        ];
+        let stats = xdp_stats_from(&names, &[3, 100, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(
+            stats,
+            XdpStats {
+                aborted: None,
+                drop: Some(7),
+                pass: None,
+                tx: Some(3),
+                redirect: Some(1),
+            }
+        );
+
+        // totals next to per-channel counts, as mlx5 reports them
+        let names = ["rx_xdp_drop", "rx_xdp_tx_xmit", "rx0_xdp_drop", "rx1_xdp_drop"];
+        let stats = xdp_stats_from(&names, &[10, 2, 4, 6]);
+        assert_eq!(stats.drop, Some(10));
+        assert_eq!(stats.tx, Some(2));
+
+        assert!(!xdp_stats_from(&["rx_packets"], &[1]).is_supported());
+    }
+}
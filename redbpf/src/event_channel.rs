@@ -0,0 +1,243 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Event channels
+//!
+//! User-space end of `redbpf_probes::maps::EventChannel`.
+//!
+//! When an ELF object is parsed, channel maps are created as ring buffers if
+//! the running kernel supports them, and as perf event arrays with one entry
+//! per possible CPU otherwise. `EventChannel` hides the difference, so the
+//! same tool runs unchanged on, say, a 5.4 and a 5.15 kernel:
+//!
+//! ```no_run
+//! use redbpf::{EventChannel, Module};
+//!
+//! let code = std::fs::read("bpf.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.load(module.version, module.license.clone()).unwrap();
+//! }
+//!
+//! let map = module.maps.iter_mut().find(|m| m.name == "events").unwrap();
+//! let channel = EventChannel::bind(map).unwrap();
+//! loop {
+//!     channel
+//!         .poll(1000, |event| println!("{} bytes", event.len()))
+//!         .unwrap();
+//! }
+//! ```
+//!
+//! Events read from perf buffers may carry up to 7 bytes of trailing
+//! padding, while ring buffer events have the exact size they were sent
+//! with.
+
+use crate::ringbuf::{RingBuffer, BPF_MAP_TYPE_RINGBUF};
+use crate::{cpus, Event, LoadError, Map, PerfMap, Result};
+use libc::{close, epoll_create1, epoll_ctl, epoll_event, epoll_wait, EPOLLIN, EPOLL_CLOEXEC, EPOLL_CTL_ADD};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::slice;
+
+/// Map type of channel definitions in ELF objects, replaced during
+/// `Map::load`. Mirrors the definition in `redbpf_probes::maps`.
+pub const EVENT_CHANNEL_MAP_TYPE: u32 = 0x8000_001b;
+
+/// Name of the symbol that channels test for ring buffer support.
+pub(crate) const RINGBUF_SUPPORTED_SYM: &str = "__redbpf_ringbuf";
+
+const PERF_PAGE_CNT: usize = 16;
+
+enum Backend {
+    RingBuf(RingBuffer),
+    Perf(Vec<PerfMap>),
+}
+
+pub struct EventChannel {
+    backend: Backend,
+    epoll: RawFd,
+}
+
+impl EventChannel {
+    /// Binds to a channel map, as created when parsing the ELF object.
+    pub fn bind(map: &mut Map) -> Result<EventChannel> {
+        let backend = match map.kind {
+            BPF_MAP_TYPE_RINGBUF => Backend::RingBuf(RingBuffer::bind(map)?),
+            bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERF_EVENT_ARRAY => {
+                let mut maps = vec![];
+                for cpu in cpus::get_online()? {
+                    maps.push(PerfMap::bind(map, -1, cpu, PERF_PAGE_CNT, -1, 0)?);
+                }
+                Backend::Perf(maps)
+            }
+            _ => return Err(LoadError::Map),
+        };
+
+        let epoll = unsafe { epoll_create1(EPOLL_CLOEXEC) };
+        if epoll < 0 {
+            return Err(LoadError::IO(io::Error::last_os_error()));
+        }
+        let channel = EventChannel { backend, epoll };
+        let fds = match &channel.backend {
+            Backend::RingBuf(ringbuf) => vec![ringbuf.fd],
+            Backend::Perf(maps) => maps.iter().map(|m| m.fd).collect(),
+        };
+        for fd in fds {
+            let mut event = epoll_event {
+                events: EPOLLIN as u32,
+                u64: fd as u64,
+            };
+            if unsafe { epoll_ctl(epoll, EPOLL_CTL_ADD, fd, &mut event) } < 0 {
+                return Err(LoadError::IO(io::Error::last_os_error()));
+            }
+        }
+
+        Ok(channel)
+    }
+
+    /// Returns `true` if the channel is backed by a ring buffer.
+    pub fn is_ringbuf(&self) -> bool {
+        match self.backend {
+            Backend::RingBuf(_) => true,
+            Backend::Perf(_) => false,
+        }
+    }
+
+    /// Waits up to `timeout` milliseconds for events, then calls `f` on all
+    /// pending events. A negative `timeout` waits indefinitely.
+    ///
+    /// Returns the number of events consumed.
+    pub fn poll<F: FnMut(&[u8])>(&self, timeout: i32, mut f: F) -> Result<usize> {
+        let mut events = [epoll_event { events: 0, u64: 0 }; 16];
+        let ready = unsafe { epoll_wait(self.epoll, events.as_mut_ptr(), events.len() as i32, timeout) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(0);
+            }
+            return Err(LoadError::IO(err));
+        }
+
+        Ok(self.consume(&mut f))
+    }
+
+    /// Calls `f` on all pending events without waiting.
+    pub fn consume<F: FnMut(&[u8])>(&self, mut f: F) -> usize {
+        match &self.backend {
+            Backend::RingBuf(ringbuf) => ringbuf.read(f),
+            Backend::Perf(maps) => {
+                let mut count = 0;
+                for map in maps {
+                    while let Some(event) = map.read() {
+                        if let Event::Sample(sample) = event {
+                            let data = unsafe {
+                                slice::from_raw_parts(sample.data.as_ptr(), sample.size as usize)
+                            };
+                            f(data);
+                            count += 1;
+                        }
+                    }
+                }
+                count
+            }
+        }
+    }
+}
+
+impl Drop for EventChannel {
+    fn drop(&mut self) {
+        unsafe { close(self.epoll) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{create_map, insn, load_program};
+    use crate::{page_size, ringbuf_supported, Module, XdpAction};
+
+    /// An XDP program sending the event `42u64` to the channel `map`, with
+    /// the helper of its backend.
+    fn send_event(map: &Map) -> Vec<u8> {
+        let map_fd = |dst| [insn(0x18, dst, 1, 0, map.fd), insn(0, 0, 0, 0, 0)].concat();
+        let output = if map.kind == BPF_MAP_TYPE_RINGBUF {
+            [
+                map_fd(1),                // r1 = map
+                insn(0xbf, 2, 10, 0, 0),  // r2 = r10
+                insn(0x07, 2, 0, 0, -8),  // r2 += -8
+                insn(0xb7, 3, 0, 0, 8),   // r3 = 8
+                insn(0xb7, 4, 0, 0, 0),   // r4 = 0
+                insn(0x85, 0, 0, 0, 130), // call bpf_ringbuf_output
+            ]
+            .concat()
+        } else {
+            [
+                map_fd(2),               // r2 = map
+                insn(0xb4, 3, 0, 0, -1), // w3 = BPF_F_CURRENT_CPU
+                insn(0xbf, 4, 10, 0, 0), // r4 = r10
+                insn(0x07, 4, 0, 0, -8), // r4 += -8
+                insn(0xb7, 5, 0, 0, 8),  // r5 = 8
+                insn(0x85, 0, 0, 0, 25), // call bpf_perf_event_output
+            ]
+            .concat()
+        };
+        [
+            insn(0x7a, 10, 0, -8, 42), // *(u64 *)(r10 - 8) = 42
+            output,
+            insn(0xb7, 0, 0, 0, 2), // r0 = XDP_PASS
+            insn(0x95, 0, 0, 0, 0), // exit
+        ]
+        .concat()
+    }
+
+    /// Sends three events to the channel `map`, and checks they all arrive.
+    fn check_delivery(map: &mut Map) {
+        let prog = load_program("xdp", "send_event", &send_event(map));
+        let channel = EventChannel::bind(map).unwrap();
+        let module = Module {
+            programs: vec![prog],
+            maps: vec![],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+        let run = module.xdp_test_runner("send_event").unwrap();
+        for _ in 0..3 {
+            assert_eq!(run(&[0u8; 64]).unwrap().0, XdpAction::Pass);
+        }
+
+        let mut events = vec![];
+        channel
+            .poll(1000, |event| events.push(event.to_vec()))
+            .unwrap();
+        assert_eq!(events.len(), 3);
+        for event in events {
+            assert_eq!(event[..8], 42u64.to_ne_bytes());
+            if channel.is_ringbuf() {
+                assert_eq!(event.len(), 8);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_channel() {
+        let size = 4 * page_size() as u32;
+        let mut map = create_map("events", EVENT_CHANNEL_MAP_TYPE, 0, 0, size);
+        let ringbuf = map.kind == BPF_MAP_TYPE_RINGBUF;
+        assert_eq!(ringbuf, ringbuf_supported());
+        check_delivery(&mut map);
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_perf_fallback() {
+        let cpus = cpus::get_possible().unwrap().len() as u32;
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERF_EVENT_ARRAY;
+        let mut map = create_map("events", type_, 4, 4, cpus);
+        check_delivery(&mut map);
+    }
+}
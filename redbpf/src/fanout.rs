@@ -0,0 +1,264 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Several readers of the same events
+//!
+//! Neither kind of event map can be read by several consumers on its own:
+//!
+//! * a perf event array holds one perf buffer per CPU. Binding a second
+//!   `PerfMap` to the same CPU replaces the buffer of the first in the map,
+//!   which then silently stops receiving events;
+//! * a ring buffer has a single consumer position, kept in the map. Every
+//!   `RingBuffer` bound to it advances the same position, so readers split
+//!   the events between them rather than each seeing all of them. They must
+//!   not read at the same time either, or they may both read an event.
+//!
+//! `EventFanout` reads the map once, through an `EventChannel`, and hands
+//! every event to each of its subscribers. Every `Subscriber` has its own
+//! queue, and thus its own position in the stream of events: a slow
+//! subscriber doesn't hold back the others, and loses the events its queue
+//! has no room for, which it counts.
+//!
+//! A tool sending events both to a low latency log, and to an aggregator
+//! running on its own thread:
+//!
+//! ```no_run
+//! use redbpf::{EventFanout, Module};
+//! use std::thread;
+//!
+//! let code = std::fs::read("bpf.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! module.load().unwrap();
+//!
+//! let map = module.maps.iter_mut().find(|m| m.name == "events").unwrap();
+//! let mut fanout = EventFanout::bind(map).unwrap();
+//! let log = fanout.subscribe(1024);
+//! let aggregator = fanout.subscribe(64 * 1024);
+//! thread::spawn(move || {
+//!     let mut bytes = 0;
+//!     while let Some(event) = aggregator.recv() {
+//!         bytes += event.len();
+//!     }
+//!     println!("{} bytes, {} events lost", bytes, aggregator.lost());
+//! });
+//!
+//! loop {
+//!     fanout.poll(1000).unwrap();
+//!     while let Some(event) = log.try_recv() {
+//!         println!("{:02x?}", event);
+//!     }
+//! }
+//! ```
+
+use crate::{EventChannel, Map, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Reads the events of a map once, for several subscribers.
+pub struct EventFanout {
+    channel: EventChannel,
+    subscriptions: Vec<Subscription>,
+}
+
+/// The sending end of a `Subscriber`.
+struct Subscription {
+    events: SyncSender<Arc<[u8]>>,
+    lost: Arc<AtomicU64>,
+}
+
+/// A reader of the events of an `EventFanout`.
+///
+/// Can be moved to another thread than the one polling the fanout.
+pub struct Subscriber {
+    events: Receiver<Arc<[u8]>>,
+    lost: Arc<AtomicU64>,
+}
+
+impl EventFanout {
+    /// Binds to an event channel, a ring buffer or a perf event array map.
+    ///
+    /// The map must not be read by anything else meanwhile.
+    pub fn bind(map: &mut Map) -> Result<EventFanout> {
+        Ok(EventFanout::new(EventChannel::bind(map)?))
+    }
+
+    /// Hands the events of `channel` to the subscribers.
+    pub fn new(channel: EventChannel) -> EventFanout {
+        EventFanout {
+            channel,
+            subscriptions: vec![],
+        }
+    }
+
+    /// Returns a new subscriber, which receives the events read from now
+    /// on, and keeps up to `capacity` of them until it reads them.
+    pub fn subscribe(&mut self, capacity: usize) -> Subscriber {
+        let (subscription, subscriber) = subscription(capacity);
+        self.subscriptions.push(subscription);
+        subscriber
+    }
+
+    /// Returns the number of subscribers that haven't been dropped, as of
+    /// the last event.
+    pub fn subscribers(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    /// Waits up to `timeout` milliseconds for events, then hands all pending
+    /// events to every subscriber. A negative `timeout` waits indefinitely.
+    ///
+    /// Returns the number of events read from the map.
+    pub fn poll(&mut self, timeout: i32) -> Result<usize> {
+        let subscriptions = &mut self.subscriptions;
+        self.channel
+            .poll(timeout, |event| dispatch(subscriptions, event))
+    }
+
+    /// Hands all pending events to every subscriber without waiting.
+    pub fn consume(&mut self) -> usize {
+        let subscriptions = &mut self.subscriptions;
+        self.channel.consume(|event| dispatch(subscriptions, event))
+    }
+}
+
+fn subscription(capacity: usize) -> (Subscription, Subscriber) {
+    let (events, receiver) = sync_channel(capacity);
+    let lost = Arc::new(AtomicU64::new(0));
+    let subscription = Subscription {
+        events,
+        lost: lost.clone(),
+    };
+    let subscriber = Subscriber {
+        events: receiver,
+        lost,
+    };
+    (subscription, subscriber)
+}
+
+/// Queues `event` for every subscriber, counting it as lost for those
+/// whose queue is full, and forgetting those that were dropped.
+fn dispatch(subscriptions: &mut Vec<Subscription>, event: &[u8]) {
+    let event: Arc<[u8]> = Arc::from(event);
+    subscriptions.retain(|sub| match sub.events.try_send(event.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            sub.lost.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    });
+}
+
+impl Subscriber {
+    /// Waits for the next event. Returns `None` once the `EventFanout` is
+    /// dropped and all events are read.
+    pub fn recv(&self) -> Option<Arc<[u8]>> {
+        self.events.recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next event.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Arc<[u8]>> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Returns the next event, if one is queued.
+    pub fn try_recv(&self) -> Option<Arc<[u8]>> {
+        self.events.try_recv().ok()
+    }
+
+    /// Returns the number of events this subscriber lost because its queue
+    /// was full.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{create_map, load_program};
+    use crate::{cpus, Module, XdpAction};
+
+    #[test]
+    fn test_dispatch() {
+        let (to_first, first) = subscription(8);
+        let (to_second, second) = subscription(1);
+        // the subscriber is dropped right away
+        let (to_dropped, _) = subscription(8);
+        let mut subscriptions = vec![to_first, to_second, to_dropped];
+
+        dispatch(&mut subscriptions, &[1, 2]);
+        dispatch(&mut subscriptions, &[3]);
+        assert_eq!(subscriptions.len(), 2);
+
+        assert_eq!(first.try_recv().as_deref(), Some(&[1, 2][..]));
+        assert_eq!(first.try_recv().as_deref(), Some(&[3][..]));
+        assert_eq!(first.try_recv(), None);
+        assert_eq!(first.lost(), 0);
+        // no room for the second event
+        assert_eq!(second.try_recv().as_deref(), Some(&[1, 2][..]));
+        assert_eq!(second.try_recv(), None);
+        assert_eq!(second.lost(), 1);
+
+        drop(subscriptions);
+        assert_eq!(first.recv(), None);
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_perf_fanout() {
+        let cpus = cpus::get_possible().unwrap().len() as u32;
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERF_EVENT_ARRAY;
+        let mut map = create_map("fanout_test", type_, 4, 4, cpus);
+
+        // *(u64 *)(r10 - 8) = 42;
+        // bpf_perf_event_output(ctx, events, BPF_F_CURRENT_CPU, r10 - 8, 8);
+        // return XDP_PASS
+        let fd = map.fd.to_le_bytes();
+        let code = [
+            0x7a, 0x0a, 0xf8, 0xff, 42, 0, 0, 0, //
+            0x18, 0x12, 0, 0, fd[0], fd[1], fd[2], fd[3], //
+            0, 0, 0, 0, 0, 0, 0, 0, //
+            0xb4, 0x03, 0, 0, 0xff, 0xff, 0xff, 0xff, //
+            0xbf, 0xa4, 0, 0, 0, 0, 0, 0, //
+            0x07, 0x04, 0, 0, 0xf8, 0xff, 0xff, 0xff, //
+            0xb7, 0x05, 0, 0, 8, 0, 0, 0, //
+            0x85, 0, 0, 0, 25, 0, 0, 0, //
+            0xb7, 0, 0, 0, 2, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let prog = load_program("xdp", "emit", &code);
+
+        let mut fanout = EventFanout::bind(&mut map).unwrap();
+        let first = fanout.subscribe(16);
+        let second = fanout.subscribe(16);
+        let module = Module {
+            programs: vec![prog],
+            maps: vec![],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+        let run = module.xdp_test_runner("emit").unwrap();
+        let packet = [0u8; 64];
+        for _ in 0..3 {
+            assert_eq!(run(&packet).unwrap().0, XdpAction::Pass);
+        }
+
+        assert_eq!(fanout.poll(1000).unwrap(), 3);
+        for subscriber in [first, second].iter() {
+            for _ in 0..3 {
+                let event = subscriber.try_recv().unwrap();
+                // perf pads the samples, for the records to be 8 byte aligned
+                assert_eq!(event[..8], 42u64.to_ne_bytes());
+            }
+            assert_eq!(subscriber.try_recv(), None);
+            assert_eq!(subscriber.lost(), 0);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Feature detection
+//!
+//! The helpers available to a program depend on the kernel and on the type
+//! of the program. `probe_helper` finds out whether a helper can be called,
+//! so that userspace can pick what the program should do before it is
+//! loaded, for example by setting a flag in a configuration map.
+//!
+//! Checking whether `socket_filter` programs can output to ring buffers:
+//!
+//! ```no_run
+//! use redbpf::features::probe_helper;
+//!
+//! const BPF_FUNC_RINGBUF_OUTPUT: u32 = 130;
+//!
+//! let prog_type = bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SOCKET_FILTER;
+//! if probe_helper(prog_type, BPF_FUNC_RINGBUF_OUTPUT) {
+//!     println!("events go through a ring buffer");
+//! } else {
+//!     println!("events go through a perf buffer");
+//! }
+//! ```
+//!
+//! Program types are probed the same way, see `probe_prog_type`.
+//! `Program::load` probes the type of the program before loading it, and
+//! fails with `LoadError::UnsupportedProgramType` if the kernel doesn't know
+//! it, rather than with the bare `EINVAL` the kernel returns:
+//!
+//! ```no_run
+//! use redbpf::{LoadError, Module};
+//!
+//! let code = std::fs::read("probe.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     match prog.load(module.version, module.license.clone()) {
+//!         Ok(_) => (),
+//!         Err(LoadError::UnsupportedProgramType { ty, kernel }) => {
+//!             eprintln!("{:?} programs aren't supported by kernel {}", ty, kernel);
+//!             std::process::exit(1);
+//!         }
+//!         Err(e) => panic!("{:?}", e),
+//!     }
+//! }
+//! ```
+
+use crate::load_with_log;
+use crate::sys::bpf::{prog_load, ProgLoadAttr};
+use crate::uname::get_kernel_internal_version;
+use bpf_sys::bpf_insn;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::sync::Mutex;
+
+/// Results of `probe_helper`, as `(prog_type, helper_id, available)`.
+static PROBED: Mutex<Vec<(u32, u32, bool)>> = Mutex::new(Vec::new());
+
+/// Results of `prog_type_supported`, as `(prog_type, expected_attach_type,
+/// supported)`.
+static PROBED_TYPES: Mutex<Vec<(u32, u32, Option<bool>)>> = Mutex::new(Vec::new());
+
+/// Size of the verifier log of probe programs, which only hold a call.
+const PROBE_LOG_SIZE: usize = 4096;
+
+/// Returns `true` if programs of type `prog_type` can call the helper with
+/// id `helper_id` on the running kernel.
+///
+/// This loads a program calling the helper, like libbpf does. The program
+/// isn't expected to pass the verifier, since the helper is called without
+/// proper arguments, but the verifier rejects calls to unknown helpers
+/// before it looks at them. The results are cached for the lifetime of the
+/// process.
+///
+/// Returns `false` if the program type itself can't be loaded, which
+/// includes running without the privileges to load programs.
+pub fn probe_helper(prog_type: bpf_sys::bpf_prog_type, helper_id: u32) -> bool {
+    let mut probed = PROBED.lock().unwrap();
+    if let Some(&(_, _, available)) = probed
+        .iter()
+        .find(|&&(t, id, _)| t == prog_type && id == helper_id)
+    {
+        return available;
+    }
+
+    let (res, log) = load_probe(prog_type, &helper_call(helper_id));
+    let available = match res {
+        Ok(fd) => {
+            unsafe { libc::close(fd) };
+            true
+        }
+        Err(_) => helper_known(&log),
+    };
+    probed.push((prog_type, helper_id, available));
+
+    available
+}
+
+/// Returns `true` if the running kernel supports programs of type
+/// `prog_type`.
+///
+/// This loads a program of the type that only returns 0. Like
+/// `probe_helper`, it returns `false` if the program couldn't be loaded for
+/// another reason, such as missing privileges. The results are cached for
+/// the lifetime of the process.
+pub fn probe_prog_type(prog_type: bpf_sys::bpf_prog_type) -> bool {
+    prog_type_supported(prog_type, 0).unwrap_or(false)
+}
+
+/// Returns whether the kernel supports programs of type `prog_type`
+/// expected to attach to `expected_attach_type`, or `None` if the probe
+/// program couldn't be loaded for another reason.
+pub(crate) fn prog_type_supported(
+    prog_type: bpf_sys::bpf_prog_type,
+    expected_attach_type: u32,
+) -> Option<bool> {
+    let mut probed = PROBED_TYPES.lock().unwrap();
+    if let Some(&(_, _, supported)) = probed
+        .iter()
+        .find(|&&(t, a, _)| t == prog_type && a == expected_attach_type)
+    {
+        return supported;
+    }
+
+    let (res, log) = load_probe_with(prog_type, expected_attach_type, &return_zero());
+    let supported = match res {
+        Ok(fd) => {
+            unsafe { libc::close(fd) };
+            Some(true)
+        }
+        Err(error) => prog_type_known(&error, &log, control_loads),
+    };
+    probed.push((prog_type, expected_attach_type, supported));
+
+    supported
+}
+
+/// Returns the version of the running kernel, as `major.minor.patch`.
+pub(crate) fn kernel_release() -> String {
+    match get_kernel_internal_version() {
+        Some(version) => format!(
+            "{}.{}.{}",
+            version >> 16,
+            (version >> 8) & 0xff,
+            version & 0xff
+        ),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Interprets the error and the verifier log of a probe program returning
+/// 0 that failed to load.
+///
+/// Unknown types are refused with `EINVAL` before the verifier runs, but so
+/// are attributes the kernel doesn't accept. `control_loads` tells them
+/// apart, by loading the same program as a socket filter, which every
+/// kernel knows, with the same attributes.
+fn prog_type_known<F: FnOnce() -> bool>(
+    error: &io::Error,
+    log: &str,
+    control_loads: F,
+) -> Option<bool> {
+    match error.raw_os_error() {
+        // the verifier logs why it refused programs of known types, such as
+        // tracing programs without a target
+        Some(libc::EINVAL) if !log.is_empty() => Some(true),
+        Some(libc::EINVAL) if control_loads() => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether the program `probe_prog_type` probes with loads as a socket
+/// filter.
+fn control_loads() -> bool {
+    let prog_type = bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SOCKET_FILTER;
+    match load_probe(prog_type, &return_zero()).0 {
+        Ok(fd) => {
+            unsafe { libc::close(fd) };
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Makes `prog_type_supported` report `supported` for `prog_type` and
+/// `expected_attach_type`, without probing the kernel.
+#[cfg(test)]
+pub(crate) fn set_prog_type_supported(
+    prog_type: bpf_sys::bpf_prog_type,
+    expected_attach_type: u32,
+    supported: Option<bool>,
+) {
+    let mut probed = PROBED_TYPES.lock().unwrap();
+    probed.retain(|&(t, a, _)| t != prog_type || a != expected_attach_type);
+    probed.push((prog_type, expected_attach_type, supported));
+}
+
+/// Interprets the verifier log of a probe program that failed to load.
+fn helper_known(log: &str) -> bool {
+    // an empty log means the program type isn't supported, or the load was
+    // refused before the verifier ran
+    !log.is_empty() && !log.contains("invalid func ") && !log.contains("unknown func ")
+}
+
+/// Returns the code of a program calling `helper_id`, and returning 0.
+fn helper_call(helper_id: u32) -> Vec<bpf_insn> {
+    let mut call = insn((bpf_sys::BPF_JMP | bpf_sys::BPF_CALL) as u8);
+    call.imm = helper_id as i32;
+
+    let mut code = vec![call];
+    code.extend_from_slice(&return_zero());
+    code
+}
+
+fn return_zero() -> [bpf_insn; 2] {
+    [
+        insn((bpf_sys::BPF_ALU64 | bpf_sys::BPF_MOV | bpf_sys::BPF_K) as u8),
+        insn((bpf_sys::BPF_JMP | bpf_sys::BPF_EXIT) as u8),
+    ]
+}
+
+fn insn(code: u8) -> bpf_insn {
+    let mut insn = unsafe { mem::zeroed::<bpf_insn>() };
+    insn.code = code;
+    insn
+}
+
+/// Loads `code` as a program of type `prog_type` with the verifier log on.
+fn load_probe(prog_type: bpf_sys::bpf_prog_type, code: &[bpf_insn]) -> (io::Result<RawFd>, String) {
+    load_probe_with(prog_type, 0, code)
+}
+
+fn load_probe_with(
+    prog_type: bpf_sys::bpf_prog_type,
+    expected_attach_type: u32,
+    code: &[bpf_insn],
+) -> (io::Result<RawFd>, String) {
+    let license = CString::new("GPL").unwrap();
+    let mut attr = ProgLoadAttr {
+        prog_type,
+        expected_attach_type,
+        insn_cnt: code.len() as u32,
+        insns: code.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        kern_version: get_kernel_internal_version().unwrap_or(0),
+        ..Default::default()
+    };
+
+    load_with_log(PROBE_LOG_SIZE, |log| {
+        attr.log_level = 1;
+        attr.log_size = log.len() as u32;
+        attr.log_buf = log.as_mut_ptr() as u64;
+        attr.log_true_size = 0;
+        let res = prog_load(&mut attr);
+        (res, attr.log_true_size as usize)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{LoadError, Program, ProgramKind, SockAddrHook};
+
+    const BPF_FUNC_GET_PRANDOM_U32: u32 = 7;
+
+    #[test]
+    fn test_helper_known() {
+        assert!(!helper_known(""));
+        assert!(!helper_known(
+            "0: (85) call unknown#100000\ninvalid func unknown#100000\n"
+        ));
+        assert!(helper_known(
+            "0: (85) call bpf_map_lookup_elem#1\nR1 type=ctx expected=map_ptr\n"
+        ));
+    }
+
+    #[test]
+    fn test_prog_type_known() {
+        let einval = io::Error::from_raw_os_error(libc::EINVAL);
+        assert_eq!(prog_type_known(&einval, "", || true), Some(false));
+        // the attributes are at fault, whatever the type
+        assert_eq!(prog_type_known(&einval, "", || false), None);
+        let log = "Tracing programs must provide btf_id\n";
+        assert_eq!(prog_type_known(&einval, log, || unreachable!()), Some(true));
+        let eperm = io::Error::from_raw_os_error(libc::EPERM);
+        assert_eq!(prog_type_known(&eperm, "", || unreachable!()), None);
+    }
+
+    #[test]
+    fn test_load_unsupported_prog_type() {
+        // no other test loads bind6 hooks
+        let mut prog = Program::new("cgroup_bind6", "bind6", &[]).unwrap();
+        let prog_type = prog.kind.to_prog_type();
+        let attach_type = SockAddrHook::Bind6.to_attach_type();
+        set_prog_type_supported(prog_type, attach_type, Some(false));
+        match prog.load(0, "GPL".to_string()) {
+            Err(LoadError::UnsupportedProgramType { ty, kernel }) => {
+                assert_eq!(ty, ProgramKind::CgroupSockAddr(SockAddrHook::Bind6));
+                assert_eq!(kernel, kernel_release());
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_probe_prog_type() {
+        let prog_type = bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SOCKET_FILTER;
+        assert!(probe_prog_type(prog_type));
+        assert_eq!(prog_type_supported(100_000, 0), Some(false));
+        assert!(!probe_prog_type(100_000));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_probe_helper() {
+        let prog_type = bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SOCKET_FILTER;
+        assert!(probe_helper(prog_type, BPF_FUNC_GET_PRANDOM_U32));
+        assert!(!probe_helper(prog_type, 100_000));
+        // cached
+        assert!(probe_helper(prog_type, BPF_FUNC_GET_PRANDOM_U32));
+    }
+}
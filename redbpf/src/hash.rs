@@ -0,0 +1,139 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Hashing keys like programs do
+//!
+//! User-space end of `redbpf_probes::hash`. Programs hash fixed-size keys,
+//! such as the addresses and ports of a flow, to pick a slot or a backend.
+//! The functions here compute the same hashes, on the same bytes, so that
+//! maps can be filled in before packets come in, e.g. to pin a flow to a
+//! backend.
+//!
+//! ```no_run
+//! use redbpf::{hash, HashMap, Module};
+//!
+//! const SLOTS: u32 = 256;
+//! const SEED: u32 = 0x5eed;
+//!
+//! let code = std::fs::read("balance.elf").unwrap();
+//! let module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter().find(|m| m.name == "slots").unwrap();
+//! let slots = HashMap::<u32, u32>::new(map).unwrap();
+//!
+//! // 10.0.0.1:12345 -> 10.0.0.2:80, in network byte order
+//! let key = [10, 0, 0, 1, 10, 0, 0, 2, 0x30, 0x39, 0, 80];
+//! let slot = hash::xxhash32(&key, SEED) % SLOTS;
+//! // send the flow to the interface with index 3
+//! slots.set(slot, 3).unwrap();
+//! ```
+
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+const PRIME32_1: u32 = 0x9e37_79b1;
+const PRIME32_2: u32 = 0x85eb_ca77;
+const PRIME32_3: u32 = 0xc2b2_ae3d;
+const PRIME32_4: u32 = 0x27d4_eb2f;
+const PRIME32_5: u32 = 0x1656_67b1;
+
+/// Returns the 32 bit FNV-1a hash of `bytes`, as
+/// `redbpf_probes::hash::fnv1a` does.
+pub fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Returns the 32 bit xxHash of `bytes`, with `seed`, as
+/// `redbpf_probes::hash::xxhash32` does.
+pub fn xxhash32(bytes: &[u8], seed: u32) -> u32 {
+    let mut stripes = bytes.chunks_exact(16);
+    let mut hash = if bytes.len() >= 16 {
+        let mut acc = [
+            seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2),
+            seed.wrapping_add(PRIME32_2),
+            seed,
+            seed.wrapping_sub(PRIME32_1),
+        ];
+        for stripe in &mut stripes {
+            for (acc, lane) in acc.iter_mut().zip(stripe.chunks_exact(4)) {
+                *acc = xxh32_round(*acc, read_u32(lane));
+            }
+        }
+        acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME32_5)
+    };
+    hash = hash.wrapping_add(bytes.len() as u32);
+
+    let mut words = stripes.remainder().chunks_exact(4);
+    for word in &mut words {
+        hash = hash.wrapping_add(read_u32(word).wrapping_mul(PRIME32_3));
+        hash = hash.rotate_left(17).wrapping_mul(PRIME32_4);
+    }
+    for &byte in words.remainder() {
+        hash = hash.wrapping_add((byte as u32).wrapping_mul(PRIME32_5));
+        hash = hash.rotate_left(11).wrapping_mul(PRIME32_1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME32_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME32_3);
+    hash ^ (hash >> 16)
+}
+
+fn xxh32_round(acc: u32, lane: u32) -> u32 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME32_2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME32_1)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // the same values as the tests of `redbpf_probes::hash`
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0x811c_9dc5);
+        assert_eq!(fnv1a(b"a"), 0xe40c_292c);
+        assert_eq!(fnv1a(b"foobar"), 0xbf9c_f968);
+        assert_eq!(
+            fnv1a(b"Nobody inspects the spammish repetition"),
+            0xbe00_d8fb
+        );
+    }
+
+    #[test]
+    fn test_xxhash32() {
+        assert_eq!(xxhash32(b"", 0), 0x02cc_5d05);
+        assert_eq!(xxhash32(b"a", 0), 0x550d_7456);
+        assert_eq!(xxhash32(b"abc", 0), 0x32d1_53ff);
+        assert_eq!(xxhash32(b"foobar", 0), 0xeda3_4aaf);
+        let long = b"Nobody inspects the spammish repetition";
+        assert_eq!(xxhash32(long, 0), 0xe229_3b2f);
+        assert_eq!(xxhash32(long, PRIME32_1), 0xc9e8_9e68);
+        assert_eq!(xxhash32(b"", PRIME32_1), 0x36b7_8ae7);
+    }
+
+    #[test]
+    fn test_flow_key() {
+        let key = [10, 0, 0, 1, 10, 0, 0, 2, 0x30, 0x39, 0, 80];
+        assert_eq!(fnv1a(&key), 0x5eef_79f3);
+        assert_eq!(xxhash32(&key, 0), 0x1076_ad49);
+        assert_eq!(xxhash32(&key, 42), 0x3e1f_8b53);
+    }
+}
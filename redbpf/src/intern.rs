@@ -0,0 +1,153 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # String tables
+//!
+//! User-space end of `redbpf_probes::intern::Interner`, which sends each
+//! string it interns once, along with its id, through an event channel.
+//! `StringTable` collects the strings from the channel, and resolves the ids
+//! that events carry instead of the strings.
+//!
+//! Events referring to an id can arrive before the string does, when they
+//! come from another CPU, so ids that don't resolve yet are worth holding on
+//! to for a little while:
+//!
+//! ```no_run
+//! use redbpf::{EventChannel, Module, StringTable};
+//!
+//! let code = std::fs::read("open_paths.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.load(module.version, module.license.clone()).unwrap();
+//! }
+//! let mut channel = |name: &str| {
+//!     let map = module.maps.iter_mut().find(|m| m.name == name).unwrap();
+//!     EventChannel::bind(map).unwrap()
+//! };
+//! let paths = channel("paths");
+//! let opens = channel("open_events");
+//!
+//! let mut table = StringTable::new();
+//! let mut pending = vec![];
+//! loop {
+//!     paths.poll(100, |event| {
+//!         table.insert(event);
+//!     }).unwrap();
+//!     opens.poll(100, |event| {
+//!         // `pid` and `path_id` fields
+//!         let path_id = u32::from_ne_bytes([event[4], event[5], event[6], event[7]]);
+//!         pending.push(path_id);
+//!     }).unwrap();
+//!     pending.retain(|&id| match table.resolve(id) {
+//!         Some(path) => {
+//!             println!("open {}", path);
+//!             false
+//!         }
+//!         None => true,
+//!     });
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+/// Size of the id and the length in front of the bytes of an interned
+/// string, as laid out by `redbpf_probes::intern::InternedStr`.
+const INTERNED_HEADER_LEN: usize = 8;
+
+/// Strings interned by a program, by id.
+#[derive(Debug, Default)]
+pub struct StringTable {
+    strings: HashMap<u32, String>,
+}
+
+impl StringTable {
+    pub fn new() -> StringTable {
+        StringTable::default()
+    }
+
+    /// Records the string sent in `event`, an `InternedStr` read from the
+    /// channel, and returns its id, or `None` if the event is too short.
+    ///
+    /// Bytes that aren't valid UTF-8 are replaced with U+FFFD.
+    pub fn insert(&mut self, event: &[u8]) -> Option<u32> {
+        if event.len() < INTERNED_HEADER_LEN {
+            return None;
+        }
+        let id = u32::from_ne_bytes([event[0], event[1], event[2], event[3]]);
+        let len = u32::from_ne_bytes([event[4], event[5], event[6], event[7]]) as usize;
+        // perf buffers pad events, so the length is only bounded by them
+        let bytes = event[INTERNED_HEADER_LEN..].get(..len)?;
+        let string = String::from_utf8_lossy(bytes).into_owned();
+        self.strings.insert(id, string);
+        Some(id)
+    }
+
+    /// Returns the string interned as `id`, if it was received.
+    pub fn resolve(&self, id: u32) -> Option<&str> {
+        self.strings.get(&id).map(String::as_str)
+    }
+
+    /// Returns the number of strings received.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Lays out an `InternedStr<16>` with trailing padding, as read from a
+    /// perf buffer.
+    fn interned(id: u32, s: &str) -> Vec<u8> {
+        let mut event = id.to_ne_bytes().to_vec();
+        event.extend_from_slice(&(s.len() as u32).to_ne_bytes());
+        let mut buf = [0u8; 16];
+        buf[..s.len()].copy_from_slice(s.as_bytes());
+        event.extend_from_slice(&buf);
+        event.extend_from_slice(&[0xaa; 4]);
+        event
+    }
+
+    #[test]
+    fn test_string_table() {
+        let mut table = StringTable::new();
+        assert!(table.is_empty());
+        assert_eq!(table.resolve(1), None);
+
+        assert_eq!(table.insert(&interned(1, "/usr/bin")), Some(1));
+        assert_eq!(
+            table.insert(&interned(1 << 24, "/etc/hosts")),
+            Some(1 << 24)
+        );
+        assert_eq!(table.insert(&interned(2, "")), Some(2));
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.resolve(1), Some("/usr/bin"));
+        assert_eq!(table.resolve(1 << 24), Some("/etc/hosts"));
+        assert_eq!(table.resolve(2), Some(""));
+        assert_eq!(table.resolve(3), None);
+
+        let mut bad = interned(3, "\u{e9}t\u{e9}");
+        bad[8] = 0xff;
+        table.insert(&bad).unwrap();
+        assert_eq!(table.resolve(3), Some("\u{fffd}\u{a9}t\u{e9}"));
+    }
+
+    #[test]
+    fn test_truncated_events() {
+        let mut table = StringTable::new();
+        let event = interned(1, "/usr/bin");
+        assert_eq!(table.insert(&event[..7]), None);
+        assert_eq!(table.insert(&event[..8 + 7]), None);
+        assert_eq!(table.insert(&event[..8 + 8]), Some(1));
+        assert_eq!(table.resolve(1), Some("/usr/bin"));
+    }
+}
@@ -0,0 +1,334 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Stale kprobe cleanup
+//!
+//! On kernels without the `perf_kprobe` PMU, kprobes are created by writing
+//! to tracefs' `kprobe_events`, under names of the form
+//! `kprobes/<event>_bcc_<pid>`. They are removed when the program is
+//! detached, so a process that crashes leaves them behind, and a restarted
+//! process may then fail to add them again.
+//!
+//! `cleanup_stale_kprobes` removes such leftovers, and is meant to be called
+//! at startup:
+//!
+//! ```no_run
+//! // kprobe event names start with the name of the probed function
+//! redbpf::cleanup_stale_kprobes("__x64_sys_").unwrap();
+//! ```
+//!
+//! # Module functions
+//!
+//! Functions of loadable modules are named `module:function` when attaching
+//! kprobes, e.g. `#[kprobe("nf_conntrack:nf_conntrack_in")]`. The function is
+//! looked up in `/proc/kallsyms` first, so that a module that isn't loaded
+//! or a function it doesn't have fail with a clear error:
+//!
+//! ```no_run
+//! let sym = redbpf::resolve_kernel_symbol("nf_conntrack:nf_conntrack_in").unwrap();
+//! assert_eq!(sym.module.as_deref(), Some("nf_conntrack"));
+//! ```
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+
+use crate::Result;
+
+const KALLSYMS: &str = "/proc/kallsyms";
+
+const KPROBE_EVENTS: [&str; 2] = [
+    "/sys/kernel/debug/tracing/kprobe_events",
+    "/sys/kernel/tracing/kprobe_events",
+];
+
+/// Removes the kprobe events whose name starts with `prefix`.
+///
+/// Events following the `_bcc_<pid>` naming are only removed if the process
+/// that created them is gone, so that concurrently running instances of a
+/// tool keep their probes. Events still in use by the kernel are skipped.
+/// Returns the names of the removed events.
+pub fn cleanup_stale_kprobes(prefix: &str) -> Result<Vec<String>> {
+    let path = KPROBE_EVENTS
+        .iter()
+        .find(|p| fs::metadata(p).is_ok())
+        .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+    let events = fs::read_to_string(path)?;
+
+    let mut removed = vec![];
+    for event in stale_events(&events, prefix, process_exists) {
+        let mut file = OpenOptions::new().append(true).open(path)?;
+        match file.write_all(format!("-:{}", event).as_bytes()) {
+            Ok(_) => removed.push(event),
+            // somebody still has the event open
+            Err(ref e) if e.raw_os_error() == Some(libc::EBUSY) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// A symbol of the running kernel or of one of its modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelSymbol {
+    pub name: String,
+    /// The module defining the symbol, or `None` for `vmlinux`.
+    pub module: Option<String>,
+    /// The address of the symbol, which reads as 0 without `CAP_SYSLOG`.
+    pub addr: u64,
+}
+
+/// Looks the function `target` up in `/proc/kallsyms`.
+///
+/// Functions of modules are named `module:function`. Unqualified names are
+/// looked up in `vmlinux` first, then in the modules.
+pub fn resolve_kernel_symbol(target: &str) -> Result<KernelSymbol> {
+    let kallsyms = fs::read_to_string(KALLSYMS)?;
+    let (module, name) = split_target(target);
+    find_symbol(&kallsyms, module, name).ok_or_else(|| {
+        let msg = match module {
+            Some(module) => format!("no function {} in module {}", name, module),
+            None => format!("no function {}", name),
+        };
+        io::Error::new(io::ErrorKind::NotFound, msg).into()
+    })
+}
+
+/// Splits a probe target into the module and the function.
+pub(crate) fn split_target(target: &str) -> (Option<&str>, &str) {
+    match target.split_once(':') {
+        Some((module, name)) => (Some(module), name),
+        None => (None, target),
+    }
+}
+
+/// Finds the text symbol `name` in the contents of `/proc/kallsyms`.
+fn find_symbol(kallsyms: &str, module: Option<&str>, name: &str) -> Option<KernelSymbol> {
+    let mut found: Option<KernelSymbol> = None;
+    for line in kallsyms.lines() {
+        // ffffffffc0a01230 t nf_conntrack_in\t[nf_conntrack]
+        let mut fields = line.split_whitespace();
+        let (addr, kind, sym) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(addr), Some(kind), Some(sym)) => (addr, kind, sym),
+            _ => continue,
+        };
+        if sym != name || !(kind == "t" || kind == "T") {
+            continue;
+        }
+        let sym_module = fields
+            .next()
+            .map(|m| m.trim_start_matches('[').trim_end_matches(']'));
+        if module.is_some() && sym_module != module {
+            continue;
+        }
+
+        let sym = KernelSymbol {
+            name: name.to_string(),
+            module: sym_module.map(str::to_string),
+            addr: u64::from_str_radix(addr, 16).unwrap_or(0),
+        };
+        // vmlinux symbols are listed first, so they win over modules
+        if sym.module.is_none() {
+            return Some(sym);
+        }
+        found = found.or(Some(sym));
+    }
+    found
+}
+
+fn process_exists(pid: i32) -> bool {
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Returns the `group/event` names in `kprobe_events` whose event starts
+/// with `prefix` and whose owner, if known, is not alive.
+fn stale_events<F: Fn(i32) -> bool>(events: &str, prefix: &str, alive: F) -> Vec<String> {
+    events
+        .lines()
+        .filter_map(|line| {
+            // p:kprobes/do_sys_open0_bcc_1234 do_sys_open
+            let (_, name) = line.split_whitespace().next()?.split_once(':')?;
+            let (_, event) = name.split_once('/')?;
+            if !event.starts_with(prefix) {
+                return None;
+            }
+            let mut parts = event.rsplitn(2, "_bcc_");
+            if let (Some(pid), Some(_)) = (parts.next(), parts.next()) {
+                if pid.parse::<i32>().map(&alive).unwrap_or(false) {
+                    return None;
+                }
+            }
+            Some(name.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::maps::HashMap;
+    use crate::test_util::{create_map, insn, load_program};
+    use crate::Map;
+
+    /// Offsets of the first five arguments and of the return value in
+    /// `pt_regs`.
+    #[cfg(target_arch = "x86_64")]
+    const PARM_OFFSETS: [i16; 5] = [112, 104, 96, 88, 72];
+    #[cfg(target_arch = "x86_64")]
+    const RC_OFFSET: i16 = 80;
+    #[cfg(target_arch = "aarch64")]
+    const PARM_OFFSETS: [i16; 5] = [0, 8, 16, 24, 32];
+    #[cfg(target_arch = "aarch64")]
+    const RC_OFFSET: i16 = 0;
+
+    fn map_fd(dst: u8, map: &Map) -> Vec<u8> {
+        [insn(0x18, dst, 1, 0, map.fd), insn(0, 0, 0, 0, 0)].concat()
+    }
+
+    /// A kprobe stashing its arguments in `entries` by `pid_tgid`, as
+    /// `KProbeContext::stash_entry_args` does.
+    fn stash_args(entries: &Map) -> Vec<u8> {
+        let mut code = vec![
+            insn(0xbf, 6, 1, 0, 0),   // r6 = ctx
+            insn(0x85, 0, 0, 0, 14),  // call bpf_get_current_pid_tgid
+            insn(0x7b, 10, 0, -8, 0), // *(u64 *)(r10 - 8) = r0
+        ];
+        for (i, off) in PARM_OFFSETS.iter().enumerate() {
+            code.push(insn(0x79, 1, 6, *off, 0)); // r1 = PT_REGS_PARM(i + 1)
+            code.push(insn(0x7b, 10, 1, -48 + 8 * i as i16, 0)); // args[i] = r1
+        }
+        code.extend(vec![
+            map_fd(1, entries),       // r1 = entries
+            insn(0xbf, 2, 10, 0, 0),  // r2 = r10
+            insn(0x07, 2, 0, 0, -8),  // r2 += -8
+            insn(0xbf, 3, 10, 0, 0),  // r3 = r10
+            insn(0x07, 3, 0, 0, -48), // r3 += -48
+            insn(0xb7, 4, 0, 0, 0),   // r4 = 0
+            insn(0x85, 0, 0, 0, 2),   // call bpf_map_update_elem
+            insn(0xb7, 0, 0, 0, 0),   // r0 = 0
+            insn(0x95, 0, 0, 0, 0),   // exit
+        ]);
+        code.concat()
+    }
+
+    /// A kretprobe taking the arguments of its call out of `entries`, as
+    /// `KRetProbeContext::new` does, and storing the third one along with
+    /// the return value in `results`.
+    fn correlate(entries: &Map, results: &Map) -> Vec<u8> {
+        [
+            insn(0xbf, 6, 1, 0, 0),         // r6 = ctx
+            insn(0x85, 0, 0, 0, 14),        // call bpf_get_current_pid_tgid
+            insn(0x7b, 10, 0, -8, 0),       // *(u64 *)(r10 - 8) = r0
+            map_fd(1, entries),             // r1 = entries
+            insn(0xbf, 2, 10, 0, 0),        // r2 = r10
+            insn(0x07, 2, 0, 0, -8),        // r2 += -8
+            insn(0x85, 0, 0, 0, 1),         // call bpf_map_lookup_elem
+            insn(0x15, 0, 0, 17, 0),        // if r0 == 0 goto exit
+            insn(0x79, 1, 0, 16, 0),        // r1 = args[2]
+            insn(0x7b, 10, 1, -24, 0),      // *(u64 *)(r10 - 24) = r1
+            insn(0x79, 1, 6, RC_OFFSET, 0), // r1 = PT_REGS_RC
+            insn(0x7b, 10, 1, -16, 0),      // *(u64 *)(r10 - 16) = r1
+            map_fd(1, entries),             // r1 = entries
+            insn(0xbf, 2, 10, 0, 0),        // r2 = r10
+            insn(0x07, 2, 0, 0, -8),        // r2 += -8
+            insn(0x85, 0, 0, 0, 3),         // call bpf_map_delete_elem
+            map_fd(1, results),             // r1 = results
+            insn(0xbf, 2, 10, 0, 0),        // r2 = r10
+            insn(0x07, 2, 0, 0, -8),        // r2 += -8
+            insn(0xbf, 3, 10, 0, 0),        // r3 = r10
+            insn(0x07, 3, 0, 0, -24),       // r3 += -24
+            insn(0xb7, 4, 0, 0, 0),         // r4 = 0
+            insn(0x85, 0, 0, 0, 2),         // call bpf_map_update_elem
+            insn(0xb7, 0, 0, 0, 0),         // exit: r0 = 0
+            insn(0x95, 0, 0, 0, 0),         // exit
+        ]
+        .concat()
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_entry_args_correlation() {
+        let lru_hash = bpf_sys::bpf_map_type_BPF_MAP_TYPE_LRU_HASH;
+        let hash = bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH;
+        let entries = create_map("entry_args", lru_hash, 8, 40, 1024);
+        let results = create_map("results", hash, 8, 16, 1024);
+        let mut kprobe = load_program("kprobe", "stash_args", &stash_args(&entries));
+        let mut kretprobe = load_program("kretprobe", "correlate", &correlate(&entries, &results));
+        kprobe.attach_probe_to_name("vfs_write").unwrap();
+        kretprobe.attach_probe_to_name("vfs_write").unwrap();
+
+        let mut null = OpenOptions::new().write(true).open("/dev/null").unwrap();
+        null.write_all(&[0u8; 12345]).unwrap();
+
+        let pid_tgid =
+            unsafe { (libc::getpid() as u64) << 32 | libc::syscall(libc::SYS_gettid) as u64 };
+        let results = HashMap::<u64, [u64; 2]>::new(&results).unwrap();
+        // the count the write was called with, and what it returned
+        assert_eq!(results.get(pid_tgid), Some([12345, 12345]));
+        // the entry was taken out by the return probe
+        let entries = HashMap::<u64, [u64; 5]>::new(&entries).unwrap();
+        assert_eq!(entries.get(pid_tgid), None);
+    }
+
+    #[test]
+    fn test_stale_events() {
+        let events = "p:kprobes/tool_open0_bcc_100 do_sys_open\n\
+                      r:kprobes/tool_open1_bcc_200 do_sys_open\n\
+                      r16:kprobes/tool_read1_bcc_100 vfs_read\n\
+                      p:kprobes/other0_bcc_100 vfs_write\n\
+                      p:kprobes/tool_manual vfs_write\n";
+        let stale = stale_events(events, "tool_", |pid| pid == 200);
+        assert_eq!(
+            stale,
+            vec![
+                "kprobes/tool_open0_bcc_100",
+                "kprobes/tool_read1_bcc_100",
+                "kprobes/tool_manual"
+            ]
+        );
+        assert!(stale_events("", "tool_", |_| false).is_empty());
+    }
+
+    #[test]
+    fn test_find_symbol() {
+        let kallsyms = "ffffffff81000000 T _stext\n\
+                        ffffffff81234560 T dup_name\n\
+                        ffffffffc0a01230 t nf_conntrack_in\t[nf_conntrack]\n\
+                        ffffffffc0a05670 d nf_ct_data\t[nf_conntrack]\n\
+                        ffffffffc0b00000 t dup_name\t[i915]\n";
+
+        let sym = find_symbol(kallsyms, Some("nf_conntrack"), "nf_conntrack_in").unwrap();
+        assert_eq!(sym.module.as_deref(), Some("nf_conntrack"));
+        assert_eq!(sym.addr, 0xffff_ffff_c0a0_1230);
+        assert_eq!(find_symbol(kallsyms, None, "nf_conntrack_in"), Some(sym));
+        assert_eq!(find_symbol(kallsyms, Some("i915"), "nf_conntrack_in"), None);
+        // data symbols can't be probed
+        assert_eq!(find_symbol(kallsyms, None, "nf_ct_data"), None);
+
+        assert_eq!(
+            find_symbol(kallsyms, None, "dup_name").unwrap().module,
+            None
+        );
+        assert_eq!(
+            find_symbol(kallsyms, Some("i915"), "dup_name")
+                .unwrap()
+                .module
+                .as_deref(),
+            Some("i915")
+        );
+    }
+
+    #[test]
+    fn test_split_target() {
+        assert_eq!(
+            split_target("i915:intel_runtime_pm_get"),
+            (Some("i915"), "intel_runtime_pm_get")
+        );
+        assert_eq!(split_target("do_sys_open"), (None, "do_sys_open"));
+    }
+}
@@ -26,10 +26,15 @@
 //!
 //! The ELF sections loaded by RedBPF should follow the following naming convention:
 //!  * `maps/name` for maps
+//!  * `pinning/name` to share the map `name` through a pin, see `Module::parse`
 //!  * `kprobe/function_name` for entry probes for `function_name`
 //!  * `kretprobe/function_name` for return probes for `function_name`
+//!  * `fentry/function_name` for BTF-based entry probes for `function_name`
 //!  * `xdp/name` for XDP probes. Names can be anything.
 //!  * `socketfilter/name` for socket filters. Names can be anything.
+//!  * `tc_action/name` for traffic control programs. Names can be anything.
+//!  * `cgroup_connect4/name`, `cgroup_bind6/name` and so on for cgroup
+//!    socket address programs, see `SockAddrHook`.
 //!
 //! Additionally, as per convention, the following sections should be present in
 //! the ELF object:
@@ -41,9 +46,135 @@
 //!
 //! If the license is not GPL, some in-kernel functionality is not available for eBPF modules.
 //!
+//! ### libbpf conventions
+//!
+//! Objects built for libbpf, from C or any other toolchain, load as well.
+//! Their maps are declared in the `.maps` section and described by the
+//! object's BTF, so it must be compiled with `-g`. Maps with `pinning` set
+//! to `LIBBPF_PIN_BY_NAME` are shared like the ones declared with
+//! `pinning = by_name`. Programs are named after their function when the
+//! section doesn't name them, and these `SEC()` names are understood:
+//!  * `xdp`, `kprobe/function_name`, `kretprobe/function_name` and
+//!    `fentry/function_name`, like above
+//!  * `socket` for socket filters
+//!  * `tc` and `classifier` for traffic control programs
+//!  * `tracepoint/category/name` and `tp/category/name` for tracepoints
+//!  * `cgroup_skb/ingress` and `cgroup_skb/egress` for cgroup programs
+//!
+//! Global variables and calls between functions aren't supported.
+//!
+//! ```c
+//! // clang -O2 -g -target bpf -c count.c -o count.o
+//! #include <linux/bpf.h>
+//! #include <bpf/bpf_helpers.h>
+//!
+//! struct {
+//!     __uint(type, BPF_MAP_TYPE_ARRAY);
+//!     __uint(max_entries, 1);
+//!     __type(key, __u32);
+//!     __type(value, __u64);
+//! } packets SEC(".maps");
+//!
+//! SEC("xdp")
+//! int count_packets(struct xdp_md *ctx)
+//! {
+//!     __u32 key = 0;
+//!     __u64 *count = bpf_map_lookup_elem(&packets, &key);
+//!     if (count)
+//!         __sync_fetch_and_add(count, 1);
+//!     return XDP_PASS;
+//! }
+//!
+//! char _license[] SEC("license") = "GPL";
+//! ```
+//!
+//! ```no_run
+//! use redbpf::{Module, XdpFlags};
+//!
+//! let mut module = Module::parse(&std::fs::read("count.o").unwrap()).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.load(module.version, module.license.clone()).unwrap();
+//!     prog.attach_xdp("eth0", XdpFlags::default()).unwrap();
+//! }
+//! let packets = module.maps.iter().find(|m| m.name == "packets").unwrap();
+//! ```
+//!
 //! The magic version number is compatible with GoBPF's convention: during
 //! loading it is replaced with the currently running kernel's internal version,
 //! as returned by `uname()`.
+//!
+//! ## Kernel functions
+//!
+//! Programs may call kernel functions exported to BPF (kfuncs) by declaring
+//! them as external functions. Calls to undefined symbols are resolved
+//! against the kernel's BTF when the program is loaded, first in `vmlinux`,
+//! then in the BTF of loaded modules.
+//!
+//! A call to a function that can't be found fails the load, unless the
+//! program checks for the function with `redbpf_probes::kfunc_exists!`. The
+//! check is resolved to a constant, and the verifier drops the calls it
+//! guards on kernels lacking the function.
+//!
+//! ```ignore
+//! extern "C" {
+//!     fn bpf_rcu_read_lock();
+//!     fn bpf_rcu_read_unlock();
+//! }
+//!
+//! #[xdp]
+//! pub extern "C" fn probe(ctx: XdpContext) -> XdpAction {
+//!     unsafe {
+//!         bpf_rcu_read_lock();
+//!         bpf_rcu_read_unlock();
+//!     }
+//!     XdpAction::Pass
+//! }
+//! ```
+//!
+//! ## CO-RE
+//!
+//! Field accesses recorded in the `.BTF.ext` section of objects compiled
+//! with `__builtin_preserve_access_index` are relocated against the kernel's
+//! BTF when the program is loaded, so that the program runs on kernels whose
+//! structs are laid out differently from those it was compiled against.
+//! `Module::load_with_core_against` relocates them against BTF from
+//! elsewhere, for kernels that don't expose their own.
+//!
+//! Programs written in Rust read fields at the offsets
+//! `redbpf_probes::core_offset!` returns, which are relocated the same way.
+//! A field the kernel lacks fails the load with `LoadError::CoreReloc`.
+//!
+//! ## Verifier failures
+//!
+//! When the verifier rejects a program, `LoadError::ProgramLoad` carries the
+//! verifier log, the index of the instruction it rejected and its reason.
+//! Instructions are 8 bytes and loading doesn't move them, so the index
+//! points into the program's section of the ELF object, and the line info
+//! the compiler emitted with `-g` maps it back to the source. For a module
+//! of kprobes:
+//!
+//! ```no_run
+//! use redbpf::{LoadError, Module};
+//! use std::process::Command;
+//!
+//! let path = "probe.elf";
+//! let mut module = Module::parse(&std::fs::read(path).unwrap()).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     if let Err(LoadError::ProgramLoad {
+//!         failed_insn: Some(insn),
+//!         reason,
+//!         ..
+//!     }) = prog.load(module.version, module.license.clone())
+//!     {
+//!         let section = format!("kprobe/{}", prog.name);
+//!         let line = Command::new("llvm-addr2line")
+//!             .args(&["-e", path, "-j", &section, &format!("{:#x}", insn * 8)])
+//!             .output()
+//!             .unwrap();
+//!         eprintln!("{}: {}", String::from_utf8_lossy(&line.stdout).trim(), reason);
+//!     }
+//! }
+//! ```
 #![deny(clippy::all)]
 
 #[cfg(feature = "build")]
@@ -53,31 +184,92 @@ extern crate lazy_static;
 #[macro_use]
 extern crate serde_derive;
 
+pub mod btf;
 #[cfg(feature = "build")]
 pub mod build;
+mod cgroup;
+mod core_reloc;
 pub mod cpus;
 #[cfg(feature = "load")]
 pub mod load;
 mod error;
+mod ethtool;
+mod event_channel;
+mod fanout;
+pub mod features;
+pub mod hash;
+mod intern;
+mod kprobe;
+mod link;
+mod loader;
+mod map_data;
+mod maps;
+mod pcap;
 mod perf;
+mod pin;
+mod provider;
+mod psi;
+mod ringbuf;
+mod sock_addr;
 pub mod sys;
+mod syscalls;
+mod tc;
+mod test_run;
+#[cfg(test)]
+mod test_util;
+mod token;
 pub use bpf_sys::uname;
 
-use bpf_sys::{bpf_insn, bpf_map_def};
+use bpf_sys::{bpf_insn, bpf_map_def, bpf_map_info};
 use bpf_sys::{XDP_FLAGS_UPDATE_IF_NOEXIST, XDP_FLAGS_SKB_MODE,
               XDP_FLAGS_DRV_MODE, XDP_FLAGS_HW_MODE, XDP_FLAGS_MODES, XDP_FLAGS_MASK};
 use goblin::elf::{section_header as hdr, Elf, SectionHeader, Sym,
                   reloc::RelocSection};
+use goblin::strtab::Strtab;
 
-use std::collections::HashMap;
+use std::collections::HashMap as RSHashMap;
 use std::default::Default;
 use std::ffi::CString;
 use std::io;
 use std::mem;
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
 
+use crate::btf::{AttachTarget, Btf, KfuncResolver, ObjectBtf, LIBBPF_PIN_BY_NAME, MAPS_SECTION};
+pub use crate::cgroup::{CgroupAttachMode, CgroupAttachType, CgroupSkb, ProgInfo};
+use crate::core_reloc::{parse_core_relos, CoreRelo};
 pub use crate::error::{LoadError, Result};
+pub use crate::ethtool::{xdp_stats, XdpStats};
+pub use crate::event_channel::*;
+pub use crate::fanout::{EventFanout, Subscriber};
+pub use crate::intern::StringTable;
+use crate::kprobe::split_target;
+pub use crate::kprobe::{cleanup_stale_kprobes, resolve_kernel_symbol, KernelSymbol};
+pub use crate::link::{
+    list_links, xdp_frame_limits, xdp_max_headroom, xdp_prog_id, LinkInfo, XdpFrameLimits, XdpMode,
+    XDP_PACKET_HEADROOM,
+};
+pub use crate::loader::Loader;
+pub use crate::map_data::MapData;
+pub use crate::maps::{Counter, CpuMap, HashMap, IpKey, LockedIter, PerCpuArray, PerCpuHashMap};
+pub use crate::pcap::{LinkType, PcapWriter};
 pub use crate::perf::*;
+pub use crate::pin::PIN_BY_NAME_DIR;
+pub use crate::provider::{
+    reset_syscall_provider, set_syscall_provider, DirectSyscall, SyscallProvider,
+};
+pub use crate::psi::{Pressure, PressureAvg, Psi, SamplingTuner};
+pub use crate::ringbuf::*;
+pub use crate::sock_addr::{CgroupSockAddr, SockAddrHook};
+use crate::sys::bpf::{
+    link_create, obj_get_info_by_fd, LinkCreateAttr, MapCreateAttr, ProgLoadAttr, BPF_F_TOKEN_FD,
+    BPF_F_XDP_DEV_BOUND_ONLY, BPF_F_XDP_HAS_FRAGS, BPF_PROG_TYPE_TRACING, BPF_PSEUDO_KFUNC_CALL,
+    BPF_TRACE_FENTRY,
+};
+pub use crate::syscalls::syscall_name;
+pub use crate::tc::{Direction, Link, TcClassifier, TcxOrder};
+pub use crate::test_run::XdpAction;
+pub use crate::token::BpfToken;
 use crate::uname::get_kernel_internal_version;
 
 pub type VoidPtr = *mut std::os::raw::c_void;
@@ -150,21 +342,103 @@ pub struct Module {
 /// }
 /// ```
 pub struct Program {
-    pfd: Option<RawFd>,
+    attachments: Vec<Attachment>,
     fd: Option<RawFd>,
     pub kind: ProgramKind,
     pub name: String,
     code: Vec<bpf_insn>,
-    code_bytes: i32,
+    kfuncs: Vec<KfuncRef>,
+    kfunc_checks: Vec<KfuncRef>,
+    /// CO-RE relocations, each with the instruction as compiled.
+    core_relos: Vec<(CoreRelo, bpf_insn)>,
+    core_relocated: bool,
+    /// Maps the program uses that weren't created on the running kernel.
+    missing_maps: Vec<String>,
+    autoload: bool,
+    dev_bound: Option<u32>,
+    xdp_frags: bool,
+    xdp_devmap: bool,
+    log_size: usize,
+    token: Option<RawFd>,
+}
+
+/// Something a program is attached to, undone by `Program::detach`.
+enum Attachment {
+    /// A perf event, link or socket, which lets go of the program when
+    /// closed.
+    Fd(RawFd),
+    /// A kprobe, whose event is removed after its perf event is closed.
+    Kprobe { fd: RawFd, ev_name: CString },
+    /// An interface running an XDP program.
+    Xdp { iface: CString, flags: XdpFlags },
+}
+
+impl Attachment {
+    fn detach(self) -> Result<()> {
+        let close_fd = |fd| match unsafe { libc::close(fd) } {
+            0 => Ok(()),
+            _ => Err(LoadError::IO(io::Error::last_os_error())),
+        };
+        match self {
+            Attachment::Fd(fd) => close_fd(fd),
+            Attachment::Kprobe { fd, ev_name } => {
+                close_fd(fd)?;
+                match unsafe { bpf_sys::bpf_detach_kprobe(ev_name.as_ptr()) } {
+                    0 => Ok(()),
+                    _ => Err(LoadError::BPF),
+                }
+            }
+            Attachment::Xdp { iface, flags } => {
+                // replacing the program with nothing, whether there's one or not
+                let flags = flags as u32 & !XDP_FLAGS_UPDATE_IF_NOEXIST;
+                match unsafe { bpf_sys::bpf_attach_xdp(iface.as_ptr(), -1, flags) } {
+                    0 => Ok(()),
+                    _ => Err(LoadError::BPF),
+                }
+            }
+        }
+    }
+}
+
+/// An instruction referring to a kernel function, resolved during
+/// `Program::load`.
+struct KfuncRef {
+    insn_idx: usize,
+    name: String,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Prefix of the symbols `redbpf_probes::kfunc_exists!` loads whether a
+/// kfunc exists from, followed by the name of the kfunc.
+const KFUNC_EXISTS_SYM_PREFIX: &str = "__redbpf_kfunc_exists:";
+/// Prefix of the symbols `redbpf_probes::helper_exists!` loads whether a
+/// helper exists from, followed by the id of the helper.
+const HELPER_EXISTS_SYM_PREFIX: &str = "__redbpf_helper_exists:";
+
+/// Name of the map `XdpContext::drop_with_reason` counts drops in.
+const DROP_REASONS_MAP: &str = "xdp_drop_reasons";
+/// Name of the map `PerfMap::insert_sampled` counts events in, with the
+/// number of calls at index 0 and of inserted events at index 1.
+const SAMPLES_MAP: &str = "xdp_samples";
+
+/// Initial size of the buffer for the verifier log of failed loads.
+const LOG_SIZE_DEFAULT: usize = 64 * 1024;
+/// The log buffer is grown up to this size if the log doesn't fit.
+const LOG_SIZE_MAX: usize = 16 * 1024 * 1024;
+
+/// Helper id that unresolved kfunc calls are replaced with.
+const KFUNC_POISON_CALL: i32 = 2_002_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProgramKind {
     Kprobe,
     Kretprobe,
     XDP,
     SocketFilter,
     Tracepoint,
+    TcAction,
+    CgroupSkb,
+    CgroupSockAddr(SockAddrHook),
+    Fentry,
 }
 
 /// Maps are loaded automatically, so you normally do not have to do anything to
@@ -183,6 +457,7 @@ pub struct Map {
     pub name: String,
     pub kind: u32,
     fd: RawFd,
+    config: bpf_map_def,
 }
 
 #[allow(dead_code)]
@@ -213,6 +488,20 @@ impl ProgramKind {
             XDP => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_XDP,
             SocketFilter => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SOCKET_FILTER,
             Tracepoint => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_TRACEPOINT,
+            TcAction => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SCHED_CLS,
+            CgroupSkb => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_CGROUP_SKB,
+            CgroupSockAddr(_) => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+            Fentry => BPF_PROG_TYPE_TRACING,
+        }
+    }
+
+    /// Returns the attach type the kernel expects programs of this kind to
+    /// be loaded with, or 0 if it doesn't check it.
+    fn expected_attach_type(&self) -> u32 {
+        match self {
+            ProgramKind::Fentry => BPF_TRACE_FENTRY,
+            ProgramKind::CgroupSockAddr(hook) => hook.to_attach_type(),
+            _ => 0,
         }
     }
 
@@ -224,6 +513,10 @@ impl ProgramKind {
             a @ Tracepoint => panic!("Program type cannot be used with attach(): {:?}", a),
             a @ SocketFilter => panic!("Program type cannot be used with attach(): {:?}", a),
             a @ XDP => panic!("Program type cannot be used with attach(): {:?}", a),
+            a @ TcAction => panic!("Program type cannot be used with attach(): {:?}", a),
+            a @ CgroupSkb => panic!("Program type cannot be used with attach(): {:?}", a),
+            a @ CgroupSockAddr(_) => panic!("Program type cannot be used with attach(): {:?}", a),
+            a @ Fentry => panic!("Program type cannot be used with attach(): {:?}", a),
         }
     }
 
@@ -235,25 +528,39 @@ impl ProgramKind {
             "xdp" => Ok(XDP),
             "socketfilter" => Ok(SocketFilter),
             "tracepoint" => Ok(Tracepoint),
-            sec => Err(LoadError::Section(sec.to_string())),
+            "tc_action" => Ok(TcAction),
+            "cgroup_skb" => Ok(CgroupSkb),
+            "fentry" => Ok(Fentry),
+            sec => SockAddrHook::from_section(sec)
+                .map(CgroupSockAddr)
+                .ok_or_else(|| LoadError::Section(sec.to_string())),
         }
     }
 }
 
 impl Program {
     pub fn new(kind: &str, name: &str, code: &[u8]) -> Result<Program> {
-        let code_bytes = code.len() as i32;
         let code = zero::read_array(code).to_vec();
         let name = name.to_string();
         let kind = ProgramKind::from_section(kind)?;
 
         Ok(Program {
-            pfd: None,
+            attachments: vec![],
             fd: None,
             kind,
             name,
             code,
-            code_bytes,
+            kfuncs: vec![],
+            kfunc_checks: vec![],
+            core_relos: vec![],
+            core_relocated: false,
+            missing_maps: vec![],
+            autoload: true,
+            dev_bound: None,
+            xdp_frags: false,
+            xdp_devmap: false,
+            log_size: LOG_SIZE_DEFAULT,
+            token: None,
         })
     }
 
@@ -261,45 +568,294 @@ impl Program {
         self.fd.is_some()
     }
 
+    /// Returns whether `Module::load` loads the program, which it does
+    /// unless `set_autoload(false)` was called.
+    pub fn autoload(&self) -> bool {
+        self.autoload
+    }
+
+    /// Sets whether `Module::load` loads the program. See
+    /// `Module::set_autoload`.
+    pub fn set_autoload(&mut self, autoload: bool) {
+        self.autoload = autoload;
+    }
+
     pub fn is_attached(&self) -> bool {
-        self.pfd.is_some()
+        !self.attachments.is_empty()
+    }
+
+    /// Returns the id the kernel gave the loaded program, or `None` if the
+    /// program isn't loaded.
+    ///
+    /// The id stays the same for as long as the program is loaded, however
+    /// often it is attached and detached.
+    pub fn id(&self) -> Option<u32> {
+        let mut info = unsafe { mem::zeroed::<bpf_sys::bpf_prog_info>() };
+        unsafe { obj_get_info_by_fd(self.fd?, &mut info).ok()? };
+        Some(info.id)
+    }
+
+    /// Detaches the program from everything it was attached to, and closes
+    /// the fds the `attach_*` methods returned.
+    ///
+    /// Every attachment is undone even if some fail, and the first error is
+    /// returned.
+    pub fn detach(&mut self) -> Result<()> {
+        let mut res = Ok(());
+        for attachment in self.attachments.drain(..).rev() {
+            let detached = attachment.detach();
+            if res.is_ok() {
+                res = detached;
+            }
+        }
+        res
+    }
+
+    /// Returns the kernel memory charged for the loaded program, in bytes,
+    /// or 0 if the program isn't loaded.
+    pub fn memlock_bytes(&self) -> u64 {
+        let fd = match self.fd {
+            Some(fd) => fd,
+            None => return 0,
+        };
+        fdinfo_memlock(fd).unwrap_or_else(|| {
+            let size = (self.code.len() * mem::size_of::<bpf_insn>()) as u64;
+            round_up(size, page_size())
+        })
+    }
+
+    /// Returns the attach type the kernel expects the program to be loaded
+    /// with.
+    pub(crate) fn expected_attach_type(&self) -> u32 {
+        match self.kind {
+            ProgramKind::XDP if self.xdp_devmap => sys::bpf::BPF_XDP_DEVMAP,
+            kind => kind.expected_attach_type(),
+        }
+    }
+
+    /// Returns the flags the program is loaded with, other than the ones
+    /// naming a token.
+    pub(crate) fn prog_flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.dev_bound.is_some() {
+            flags |= BPF_F_XDP_DEV_BOUND_ONLY;
+        }
+        if self.xdp_frags {
+            flags |= BPF_F_XDP_HAS_FRAGS;
+        }
+        flags
     }
 
     pub fn load(&mut self, kernel_version: u32, license: String) -> Result<RawFd> {
         let clicense = CString::new(license)?;
-        let cname = CString::new(self.name.clone())?;
-        let log_buffer: MutDataPtr =
-            unsafe { libc::malloc(mem::size_of::<i8>() * 16 * 65535) as MutDataPtr };
-        let buf_size = 64 * 65535 as u32;
+        if let Some(map) = self.missing_maps.first() {
+            return Err(LoadError::MissingMap {
+                program: self.name.clone(),
+                map: map.clone(),
+            });
+        }
+        let prog_type = self.kind.to_prog_type();
+        let expected_attach_type = self.expected_attach_type();
+        if features::prog_type_supported(prog_type, expected_attach_type) == Some(false) {
+            return Err(LoadError::UnsupportedProgramType {
+                ty: self.kind,
+                kernel: features::kernel_release(),
+            });
+        }
+        if !self.core_relocated && !self.core_relos.is_empty() {
+            self.relocate_core(&btf::vmlinux()?)?;
+        }
+        let resolver = self.relocate_kfuncs()?;
+        let fd_array = resolver.as_ref().and_then(KfuncResolver::fd_array);
+        let target = match self.kind {
+            ProgramKind::Fentry => Some(AttachTarget::resolve(&self.name)?),
+            _ => None,
+        };
 
-        let fd = unsafe {
-            bpf_sys::bcc_prog_load(
-                self.kind.to_prog_type(),
-                cname.as_ptr() as DataPtr,
-                self.code.as_ptr(),
-                self.code_bytes,
-                clicense.as_ptr() as DataPtr,
-                kernel_version as u32,
-                0 as i32,
-                log_buffer,
-                buf_size,
-            )
+        let mut attr = ProgLoadAttr {
+            prog_type,
+            expected_attach_type,
+            insn_cnt: self.code.len() as u32,
+            insns: self.code.as_ptr() as u64,
+            license: clicense.as_ptr() as u64,
+            kern_version: kernel_version,
+            fd_array: fd_array.as_ref().map(|a| a.as_ptr() as u64).unwrap_or(0),
+            prog_flags: self.prog_flags(),
+            ..Default::default()
         };
+        if let Some(target) = &target {
+            attr.attach_btf_id = target.btf_id;
+            // attach_btf_obj_fd, sharing its place with attach_prog_fd
+            attr.attach_prog_fd = target.module_fd.unwrap_or(0) as u32;
+        }
+        if let Some(ifindex) = self.dev_bound {
+            attr.prog_ifindex = ifindex;
+        }
+        if let Some(token) = self.token {
+            attr.prog_flags |= BPF_F_TOKEN_FD;
+            attr.prog_token_fd = token as u32;
+        }
+        // the kernel rejects names it would not print in fdinfo
+        if self
+            .name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'.')
+        {
+            let len = self.name.len().min(attr.prog_name.len() - 1);
+            attr.prog_name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        }
 
-        if fd < 0 {
-            Err(LoadError::BPF)
-        } else {
-            self.fd = Some(fd);
-            Ok(fd)
+        let res = match sys::bpf::prog_load(&mut attr) {
+            Ok(fd) => Ok(fd),
+            Err(error) => {
+                // reload with the verifier log on, so there's something to go on
+                let (res, log) = load_with_log(self.log_size, |log| {
+                    attr.log_level = 1;
+                    attr.log_size = log.len() as u32;
+                    attr.log_buf = log.as_mut_ptr() as u64;
+                    attr.log_true_size = 0;
+                    let res = sys::bpf::prog_load(&mut attr);
+                    (res, attr.log_true_size as usize)
+                });
+                res.map_err(|_| {
+                    let (failed_insn, reason) = verifier_failure(&log);
+                    LoadError::ProgramLoad {
+                        name: self.name.clone(),
+                        error,
+                        log,
+                        failed_insn,
+                        reason,
+                    }
+                })
+            }
+        };
+
+        let fd = res?;
+        self.fd = Some(fd);
+        Ok(fd)
+    }
+
+    /// Sets the initial size of the buffer the verifier log is read into if
+    /// loading fails.
+    ///
+    /// The buffer is grown until the log fits, up to 16MB, so this only
+    /// saves reloads for programs known to produce long logs.
+    pub fn set_log_size(&mut self, size: usize) {
+        self.log_size = size;
+    }
+
+    /// Loads the program through the BPF token `token`, which must be kept
+    /// open until `load()` returns. See `BpfToken`.
+    pub fn set_token(&mut self, token: &BpfToken) {
+        self.token = Some(token.as_raw_fd());
+    }
+
+    /// Binds an XDP program to the device `iface` at load time.
+    ///
+    /// Device-bound programs can only be attached to that device, but can
+    /// read RX metadata provided by its driver, see
+    /// `redbpf_probes::xdp::XdpContext::hw_vlan()`. Must be called before
+    /// `load()`, and requires kernel 6.3 or later.
+    pub fn set_xdp_dev_bound(&mut self, iface: &str) -> Result<()> {
+        let ciface = CString::new(iface)?;
+        let ifindex = unsafe { libc::if_nametoindex(ciface.as_ptr()) };
+        if ifindex == 0 {
+            return Err(LoadError::IO(io::Error::last_os_error()));
+        }
+        self.dev_bound = Some(ifindex);
+        Ok(())
+    }
+
+    /// Lets an XDP program handle multi-buffer packets, whose data doesn't
+    /// fit a single page, such as jumbo frames.
+    ///
+    /// Programs only have direct access to the linear part at the start of
+    /// these packets, see `redbpf_probes::xdp::XdpContext::frame_len()`.
+    /// Drivers refuse to attach other programs to interfaces with an MTU
+    /// too large for a page. Must be called before `load()`, and requires
+    /// kernel 5.18 or later.
+    pub fn set_xdp_frags(&mut self) {
+        self.xdp_frags = true;
+    }
+
+    /// Loads an XDP program to be run on the packets redirected through an
+    /// entry of a `BPF_MAP_TYPE_DEVMAP`, rather than to be attached to an
+    /// interface.
+    ///
+    /// Only these programs can read the interface the packet leaves
+    /// through, see `redbpf_probes::xdp::XdpContext::egress_ifindex()`.
+    /// Must be called before `load()`, and requires kernel 5.8 or later.
+    pub fn set_xdp_devmap(&mut self) {
+        self.xdp_devmap = true;
+    }
+
+    /// Patches the field accesses of the program with the layout of the
+    /// types in `target`, instead of the running kernel's, which `load()`
+    /// relocates against otherwise.
+    ///
+    /// Can be called again with another `target`, the instructions being
+    /// patched from scratch each time.
+    pub fn relocate_core(&mut self, target: &Btf) -> Result<()> {
+        for (relo, insn) in self.core_relos.iter() {
+            relo.apply(&mut self.code, *insn, target)?;
+        }
+        self.core_relocated = true;
+        Ok(())
+    }
+
+    /// Patches kfunc calls and existence checks with the BTF ids of their
+    /// targets.
+    ///
+    /// Calls to functions the kernel doesn't have are left to the verifier
+    /// if the program checks for them, since it removes unreachable calls.
+    /// The returned resolver holds on to the module BTF the program uses.
+    fn relocate_kfuncs(&mut self) -> Result<Option<KfuncResolver>> {
+        if self.kfuncs.is_empty() && self.kfunc_checks.is_empty() {
+            return Ok(None);
+        }
+
+        let mut resolver = KfuncResolver::new()?;
+        for check in self.kfunc_checks.iter() {
+            let found = resolver.resolve(&check.name)?.is_some();
+            self.code[check.insn_idx].imm = found as i32;
+            self.code[check.insn_idx + 1].imm = 0;
+        }
+
+        for call in self.kfuncs.iter() {
+            let insn = &mut self.code[call.insn_idx];
+            match resolver.resolve(&call.name)? {
+                Some((btf_id, off)) => {
+                    insn.set_src_reg(BPF_PSEUDO_KFUNC_CALL);
+                    insn.imm = btf_id as i32;
+                    insn.off = off;
+                }
+                None if self.kfunc_checks.iter().any(|c| c.name == call.name) => {
+                    // an invalid helper call, only rejected if reachable
+                    insn.set_src_reg(0);
+                    insn.imm = KFUNC_POISON_CALL;
+                    insn.off = 0;
+                }
+                None => return Err(LoadError::Kfunc(call.name.clone())),
+            }
         }
+
+        Ok(Some(resolver))
     }
 
     pub fn attach_probe(&mut self) -> Result<RawFd> {
         self.attach_probe_to_name(&self.name.clone())
     }
 
+    /// Attaches a kprobe or kretprobe to the function `name`, which is
+    /// `module:function` for functions of loadable modules.
     pub fn attach_probe_to_name(&mut self, name: &str) -> Result<RawFd> {
-        let ev_name = CString::new(format!("{}{}", name, self.kind.to_attach_type())).unwrap();
+        if let (Some(_), _) = split_target(name) {
+            // fail early, and with a better error than the kernel's EINVAL
+            resolve_kernel_symbol(name)?;
+        }
+        // event names can't contain the module separator
+        let ev_name = format!("{}{}", name.replace(':', "_"), self.kind.to_attach_type());
+        let ev_name = CString::new(ev_name).unwrap();
         let cname = CString::new(name).unwrap();
         let pfd = unsafe {
             bpf_sys::bpf_attach_kprobe(
@@ -315,11 +871,24 @@ impl Program {
         if pfd < 0 {
             Err(LoadError::BPF)
         } else {
-            self.pfd = Some(pfd);
+            self.attachments
+                .push(Attachment::Kprobe { fd: pfd, ev_name });
             Ok(pfd)
         }
     }
 
+    /// Attaches a loaded fentry program to the function it was loaded for.
+    pub fn attach_fentry(&mut self) -> Result<RawFd> {
+        let mut attr = LinkCreateAttr {
+            prog_fd: self.fd.ok_or(LoadError::BPF)? as u32,
+            attach_type: BPF_TRACE_FENTRY,
+            ..Default::default()
+        };
+        let fd = link_create(&mut attr)?;
+        self.attachments.push(Attachment::Fd(fd));
+        Ok(fd)
+    }
+
     pub fn attach_tracepoint(&mut self, category: &str, name: &str) -> Result<RawFd> {
         let category = CString::new(category)?;
         let name = CString::new(name)?;
@@ -334,6 +903,7 @@ impl Program {
         if res < 0 {
             Err(LoadError::BPF)
         } else {
+            self.attachments.push(Attachment::Fd(res));
             Ok(res)
         }
     }
@@ -345,10 +915,51 @@ impl Program {
         if res < 0 {
             Err(LoadError::BPF)
         } else {
+            self.attachments.push(Attachment::Xdp {
+                iface: ciface,
+                flags,
+            });
             Ok(())
         }
     }
 
+    /// Detaches the program from the interface `iface` only, leaving it
+    /// loaded and attached everywhere else.
+    ///
+    /// The program can then be attached again, to `iface` or to another
+    /// interface, without being loaded and verified again. Moving a program
+    /// from one interface to another:
+    ///
+    /// ```no_run
+    /// use redbpf::{Module, XdpFlags};
+    ///
+    /// let code = std::fs::read("firewall.elf").unwrap();
+    /// let mut module = Module::parse(&code).unwrap();
+    /// let prog = module
+    ///     .programs
+    ///     .iter_mut()
+    ///     .find(|prog| prog.name == "firewall")
+    ///     .unwrap();
+    /// prog.load(module.version, module.license.clone()).unwrap();
+    ///
+    /// prog.attach_xdp("eth0", XdpFlags::Unset).unwrap();
+    /// // ...
+    /// prog.detach_xdp("eth0").unwrap();
+    /// prog.attach_xdp("eth1", XdpFlags::Unset).unwrap();
+    /// ```
+    pub fn detach_xdp(&mut self, iface: &str) -> Result<()> {
+        let ciface = CString::new(iface)?;
+        let pos = self
+            .attachments
+            .iter()
+            .position(|attachment| match attachment {
+                Attachment::Xdp { iface, .. } => *iface == ciface,
+                _ => false,
+            })
+            .ok_or(LoadError::BPF)?;
+        self.attachments.remove(pos).detach()
+    }
+
     pub fn attach_socketfilter(&mut self, iface: &str) -> Result<RawFd> {
         let ciface = CString::new(iface).unwrap();
         let sfd = unsafe { bpf_sys::bpf_open_raw_sock(ciface.as_ptr()) };
@@ -359,7 +970,7 @@ impl Program {
 
         match unsafe { bpf_sys::bpf_attach_socket(sfd, self.fd.ok_or(LoadError::BPF)?) } {
             0 => {
-                self.pfd = Some(sfd);
+                self.attachments.push(Attachment::Fd(sfd));
                 Ok(sfd)
             }
             _ => Err(LoadError::IO(io::Error::last_os_error())),
@@ -367,18 +978,331 @@ impl Program {
     }
 }
 
+impl Drop for Module {
+    fn drop(&mut self) {
+        let _ = self.teardown();
+    }
+}
+
 impl Module {
+    /// Detaches and unloads the programs of the module, then closes its
+    /// maps, and returns the first error.
+    ///
+    /// Dropping the module tears it down in the same order, but can't report
+    /// errors.
+    ///
+    /// ```no_run
+    /// use redbpf::Module;
+    ///
+    /// let code = std::fs::read("bpf.elf").unwrap();
+    /// let mut module = Module::parse(&code).unwrap();
+    /// for prog in module.programs.iter_mut() {
+    ///     prog.load(module.version, module.license.clone()).unwrap();
+    ///     prog.attach_probe().unwrap();
+    /// }
+    ///
+    /// // ...
+    ///
+    /// module.close().unwrap();
+    /// ```
+    pub fn close(mut self) -> Result<()> {
+        self.teardown()
+    }
+
+    /// Tears the module down, with no attached program referring to a map
+    /// or program fd closed underneath it.
+    fn teardown(&mut self) -> Result<()> {
+        let mut res = Ok(());
+        let mut keep_first = |closed: Result<()>| {
+            if res.is_ok() {
+                res = closed;
+            }
+        };
+        let close_fd = |fd| match unsafe { libc::close(fd) } {
+            0 => Ok(()),
+            _ => Err(LoadError::IO(io::Error::last_os_error())),
+        };
+
+        for prog in self.programs.iter_mut() {
+            keep_first(prog.detach());
+        }
+        for prog in self.programs.iter_mut() {
+            if let Some(fd) = prog.fd.take() {
+                keep_first(close_fd(fd));
+            }
+        }
+        for map in self.maps.iter_mut() {
+            if map.fd >= 0 {
+                keep_first(close_fd(map.fd));
+                map.fd = -1;
+            }
+        }
+        res
+    }
+
+    /// Returns the kernel memory charged for all maps and loaded programs of
+    /// the module, in bytes.
+    ///
+    /// Before kernel 5.11, this memory counts against `RLIMIT_MEMLOCK`.
+    ///
+    /// ```no_run
+    /// use redbpf::Module;
+    ///
+    /// let code = std::fs::read("bpf.elf").unwrap();
+    /// let mut module = Module::parse(&code).unwrap();
+    /// for prog in module.programs.iter_mut() {
+    ///     prog.load(module.version, module.license.clone()).unwrap();
+    /// }
+    ///
+    /// for map in module.maps.iter() {
+    ///     println!("{}: {} bytes", map.name, map.memlock_bytes().unwrap());
+    /// }
+    /// println!("total: {} bytes", module.total_memlock().unwrap());
+    /// ```
+    pub fn total_memlock(&self) -> Result<u64> {
+        let mut total = 0;
+        for map in self.maps.iter() {
+            total += map.memlock_bytes()?;
+        }
+        total += self.programs.iter().map(Program::memlock_bytes).sum::<u64>();
+
+        Ok(total)
+    }
+
+    /// Returns the number of packets dropped for each reason by the XDP
+    /// programs of the module, through `XdpContext::drop_with_reason`.
+    ///
+    /// The counts are indexed by reason and summed over all CPUs. Returns an
+    /// empty `Vec` if no program of the module drops with reasons.
+    ///
+    /// ```no_run
+    /// use redbpf::Module;
+    ///
+    /// const REASONS: &[&str] = &["unknown", "bad port", "not ip"];
+    ///
+    /// let code = std::fs::read("firewall.elf").unwrap();
+    /// let module = Module::parse(&code).unwrap();
+    /// for (reason, count) in module.drop_reasons().iter().enumerate() {
+    ///     if let (Some(name), true) = (REASONS.get(reason), *count > 0) {
+    ///         println!("{}: {}", name, count);
+    ///     }
+    /// }
+    /// ```
+    pub fn drop_reasons(&self) -> Vec<u64> {
+        let map = match self.maps.iter().find(|map| map.name == DROP_REASONS_MAP) {
+            Some(map) => map,
+            None => return vec![],
+        };
+        let counts = match PerCpuArray::<u64>::new(map) {
+            Ok(counts) => counts,
+            Err(_) => return vec![],
+        };
+
+        (0..map.config.max_entries)
+            .map(|reason| counts.get_per_cpu(reason).iter().sum())
+            .collect()
+    }
+
+    /// Returns the number of events the XDP programs of the module saw and
+    /// inserted through `PerfMap::insert_sampled`, as `(seen, exported)`
+    /// summed over all CPUs.
+    ///
+    /// Returns `None` if no program of the module samples events.
+    ///
+    /// ```no_run
+    /// use redbpf::Module;
+    ///
+    /// let code = std::fs::read("capture.elf").unwrap();
+    /// let module = Module::parse(&code).unwrap();
+    /// let packets = 1234; // events read from the `packets` perf map
+    /// if let Some((seen, exported)) = module.sample_counts() {
+    ///     let scale = seen as f64 / exported.max(1) as f64;
+    ///     println!("about {} packets", (packets as f64 * scale) as u64);
+    /// }
+    /// ```
+    pub fn sample_counts(&self) -> Option<(u64, u64)> {
+        let map = self.maps.iter().find(|map| map.name == SAMPLES_MAP)?;
+        let counts = PerCpuArray::<u64>::new(map).ok()?;
+        let sum = |index: u32| counts.get_per_cpu(index).iter().sum();
+
+        Some((sum(0), sum(1)))
+    }
+
+    /// Parses the module and creates its maps.
+    ///
+    /// Maps declared with `pinning = by_name` are pinned to
+    /// `/sys/fs/bpf/<map name>`, or opened from there if a module parsed
+    /// earlier, in any process, pinned them already.
     pub fn parse(bytes: &[u8]) -> Result<Module> {
+        Module::parse_with(bytes, None, Path::new(PIN_BY_NAME_DIR), &[])
+    }
+
+    /// Parses the module like `parse`, sharing the maps declared with
+    /// `pinning = by_name` through pins in `dir` instead of `/sys/fs/bpf`.
+    ///
+    /// ```no_run
+    /// use redbpf::Module;
+    /// use std::path::Path;
+    ///
+    /// // both declare `BLOCKLIST` with `pinning = by_name`
+    /// let dir = Path::new("/sys/fs/bpf/firewall");
+    /// let ingress = Module::parse_pinned(&std::fs::read("ingress.elf").unwrap(), dir).unwrap();
+    /// let egress = Module::parse_pinned(&std::fs::read("egress.elf").unwrap(), dir).unwrap();
+    /// ```
+    pub fn parse_pinned(bytes: &[u8], dir: &Path) -> Result<Module> {
+        Module::parse_with(bytes, None, dir, &[])
+    }
+
+    /// Parses the module like `parse`, creating its maps through the BPF
+    /// token `token`, which its programs are then loaded with as well.
+    ///
+    /// The token must be kept open until the programs are loaded.
+    pub fn parse_with_token(bytes: &[u8], token: &BpfToken) -> Result<Module> {
+        let token = Some(token.as_raw_fd());
+        Module::parse_with(bytes, token, Path::new(PIN_BY_NAME_DIR), &[])
+    }
+
+    /// Relocates the field accesses of all programs against `target`. See
+    /// `Program::relocate_core`.
+    pub fn relocate_core(&mut self, target: &Btf) -> Result<()> {
+        for prog in self.programs.iter_mut() {
+            prog.relocate_core(target)?;
+        }
+        Ok(())
+    }
+
+    /// Loads the programs of the module, skipping those whose autoload flag
+    /// is off, see `set_autoload`.
+    pub fn load(&mut self) -> Result<()> {
+        for prog in self.programs.iter_mut().filter(|prog| prog.autoload) {
+            prog.load(self.version, self.license.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Sets whether `load` loads the program `name`, like libbpf's
+    /// `bpf_program__set_autoload`.
+    ///
+    /// This lets an ELF file hold programs for different kernels, and load
+    /// only those the running kernel supports, as found out with the
+    /// `features` module. The maps of the module are all created by
+    /// `parse`, except ring buffers on kernels without them, which only the
+    /// programs using them miss: they fail to load with
+    /// `LoadError::MissingMap`, unless they are skipped.
+    ///
+    /// Fails with `LoadError::Section` if the module has no program `name`.
+    ///
+    /// Picking between a program sending events through a ring buffer, and
+    /// one sending them through a perf buffer:
+    ///
+    /// ```no_run
+    /// use redbpf::features::probe_helper;
+    /// use redbpf::Module;
+    ///
+    /// const BPF_FUNC_RINGBUF_OUTPUT: u32 = 130;
+    ///
+    /// let code = std::fs::read("trace_open.elf").unwrap();
+    /// let mut module = Module::parse(&code).unwrap();
+    /// let prog_type = bpf_sys::bpf_prog_type_BPF_PROG_TYPE_KPROBE;
+    /// let ringbuf = probe_helper(prog_type, BPF_FUNC_RINGBUF_OUTPUT);
+    /// module.set_autoload("trace_open_ringbuf", ringbuf).unwrap();
+    /// module.set_autoload("trace_open_perfbuf", !ringbuf).unwrap();
+    /// module.load().unwrap();
+    ///
+    /// for prog in module.programs.iter_mut().filter(|prog| prog.is_loaded()) {
+    ///     prog.attach_probe_to_name("do_sys_open").unwrap();
+    /// }
+    /// ```
+    pub fn set_autoload(&mut self, name: &str, autoload: bool) -> Result<()> {
+        let prog = self
+            .programs
+            .iter_mut()
+            .find(|prog| prog.name == name)
+            .ok_or_else(|| LoadError::Section(name.to_string()))?;
+        prog.set_autoload(autoload);
+        Ok(())
+    }
+
+    /// Loads the programs like `load`, with their field accesses relocated
+    /// against the kernel BTF at `btf_path` rather than the running
+    /// kernel's.
+    ///
+    /// This is for kernels that don't expose their BTF, with BTF extracted
+    /// elsewhere, e.g. from BTFHub's archive for distribution kernels:
+    ///
+    /// ```no_run
+    /// use redbpf::Module;
+    /// use std::process::Command;
+    ///
+    /// let url = "https://github.com/aquasecurity/btfhub-archive/raw/main/\
+    ///            centos/7/x86_64/3.10.0-1160.el7.x86_64.btf.tar.xz";
+    /// Command::new("curl").args(&["-sLo", "btf.tar.xz", url]).status().unwrap();
+    /// Command::new("tar").args(&["xf", "btf.tar.xz"]).status().unwrap();
+    ///
+    /// let code = std::fs::read("probe.elf").unwrap();
+    /// let mut module = Module::parse(&code).unwrap();
+    /// module
+    ///     .load_with_core_against("3.10.0-1160.el7.x86_64.btf")
+    ///     .unwrap();
+    /// ```
+    ///
+    /// To check programs against the kernels they will run on, without
+    /// these kernels at hand, relocate them against each with
+    /// `relocate_core`, which doesn't load them.
+    pub fn load_with_core_against<P: AsRef<Path>>(&mut self, btf_path: P) -> Result<()> {
+        let target = Btf::parse(&std::fs::read(btf_path)?)?;
+        self.relocate_core(&target)?;
+        self.load()
+    }
+
+    /// Parses the module, creating the maps named in `max_entries` with the
+    /// number of entries given there rather than the one they declare.
+    fn parse_with(
+        bytes: &[u8],
+        token: Option<RawFd>,
+        pin_dir: &Path,
+        max_entries: &[(String, u32)],
+    ) -> Result<Module> {
         let object = Elf::parse(&bytes[..])?;
         let symtab = object.syms.to_vec();
         let shdr_relocs = &object.shdr_relocs;
 
+        // maps are created as their sections come up, so their pinning, and
+        // whether the maps given a size exist, have to be known up front
+        let mut pinned = vec![];
+        let mut map_names = vec![];
+        for (shndx, shdr) in object.section_headers.iter().enumerate() {
+            match get_split_section_name(&object, &shdr, shndx)? {
+                (Some("pinning"), Some(name)) => pinned.push(name),
+                (Some("maps"), Some(name)) => map_names.push(name),
+                _ => (),
+            }
+        }
+
         let mut rels = vec![];
-        let mut programs = HashMap::new();
-        let mut maps = HashMap::new();
+        let mut programs = RSHashMap::new();
+        let mut maps = RSHashMap::new();
 
         let mut license = String::new();
         let mut version = 0u32;
+        let btf = object_btf(&object, bytes, &symtab);
+        let btf_maps = match btf {
+            Some(ref btf) => btf.maps()?,
+            None => vec![],
+        };
+        map_names.extend(btf_maps.iter().map(|def| def.name.as_str()));
+        if let Some((name, _)) = max_entries
+            .iter()
+            .find(|(name, _)| !map_names.contains(&name.as_str()))
+        {
+            return Err(LoadError::UnknownMap(name.clone()));
+        }
+        let core_relos = match (&btf, find_section(&object, ".BTF.ext")) {
+            (Some(btf), Some(shdr)) => parse_core_relos(data(bytes, shdr), btf)?,
+            _ => vec![],
+        };
+        let btf = btf.and_then(|btf| ObjectBtf::load(btf, token).ok());
+        let symbol_name = |sym: &Sym| object.strtab.get_unsafe(sym.st_name);
 
         for (shndx, shdr) in object.section_headers.iter().enumerate() {
             let (kind, name) = get_split_section_name(&object, &shdr, shndx)?;
@@ -394,13 +1318,64 @@ impl Module {
                 }
                 (hdr::SHT_PROGBITS, Some("maps"), Some(name)) => {
                     // Maps are immediately bcc_create_map'd
-                    maps.insert(shndx, Map::load(name, &content)?);
+                    let content = resized_map_def(name, content, max_entries)?;
+                    let map = if pinned.contains(&name) {
+                        Map::load_pinned(name, &content, btf.as_ref(), token, pin_dir)?
+                    } else {
+                        Map::load_with_btf(name, &content, btf.as_ref(), token)?
+                    };
+                    maps.insert((shndx, 0), map);
+                }
+                (_, Some(MAPS_SECTION), None) => {
+                    // the maps are described by the BTF of the object
+                    if btf_maps.is_empty() && shdr.sh_size > 0 {
+                        return Err(LoadError::BTF);
+                    }
+                    for def in btf_maps.iter() {
+                        let sym = symtab
+                            .iter()
+                            .find(|sym| {
+                                sym.st_shndx == shndx && symbol_name(sym) == Some(def.name.as_str())
+                            })
+                            .ok_or(LoadError::Map)?;
+                        let name = def.name.as_str();
+                        let code = &resized_map_def(name, map_def_bytes(&def.def), max_entries)?;
+                        let map = if def.pinning == LIBBPF_PIN_BY_NAME || pinned.contains(&name) {
+                            Map::load_pinned(name, code, btf.as_ref(), token, pin_dir)?
+                        } else {
+                            Map::load_with_btf(name, code, btf.as_ref(), token)?
+                        };
+                        maps.insert((shndx, sym.st_value), map);
+                    }
                 }
-                (hdr::SHT_PROGBITS, Some(kind @ "kprobe"), Some(name))
-                | (hdr::SHT_PROGBITS, Some(kind @ "kretprobe"), Some(name))
-                | (hdr::SHT_PROGBITS, Some(kind @ "xdp"), Some(name))
-                | (hdr::SHT_PROGBITS, Some(kind @ "socketfilter"), Some(name)) => {
-                    programs.insert(shndx, Program::new(kind, name, &content)?);
+                (hdr::SHT_PROGBITS, Some(prefix), name) => {
+                    if let Some(kind) = program_kind(prefix) {
+                        let mut functions: Vec<&Sym> = symtab
+                            .iter()
+                            .filter(|sym| sym.st_shndx == shndx && sym.is_function())
+                            .collect();
+                        functions.sort_by_key(|sym| sym.st_value);
+                        if functions.len() > 1 {
+                            // libbpf makes a program of each function of the
+                            // section
+                            for sym in functions {
+                                let name = symbol_name(sym)
+                                    .ok_or_else(|| LoadError::Section(prefix.to_string()))?;
+                                let code = content
+                                    .get(sym.st_value as usize..)
+                                    .and_then(|code| code.get(..sym.st_size as usize))
+                                    .ok_or_else(|| LoadError::Section(name.to_string()))?;
+                                let prog = Program::new(kind, name, code)?;
+                                programs.insert((shndx, sym.st_value), prog);
+                            }
+                            continue;
+                        }
+                        // libbpf names programs after their function
+                        let name = name
+                            .or_else(|| functions.first().and_then(|sym| symbol_name(sym)))
+                            .ok_or_else(|| LoadError::Section(prefix.to_string()))?;
+                        programs.insert((shndx, 0), Program::new(kind, name, &content)?);
+                    }
                 }
                 _ => {}
             }
@@ -408,12 +1383,30 @@ impl Module {
 
         // Rewrite programs with relocation data
         for rel in rels.iter() {
-            if programs.contains_key(&rel.target) {
-                rel.apply(&mut programs, &maps, &symtab)?;
+            if programs.keys().any(|&(shndx, _)| shndx == rel.target) {
+                rel.apply(&mut programs, &maps, &symtab, &object.strtab)?;
+            }
+        }
+        for (section, mut relo) in core_relos {
+            let shndx = object.section_headers.iter().position(|shdr| {
+                object.shdr_strtab.get_unsafe(shdr.sh_name) == Some(section.as_str())
+            });
+            let offset = (relo.insn_idx * mem::size_of::<bpf_insn>()) as u64;
+            if let Some((prog, insn_idx)) =
+                shndx.and_then(|shndx| program_at(&mut programs, shndx, offset))
+            {
+                relo.insn_idx = insn_idx;
+                prog.core_relos.push((relo, prog.code[insn_idx]));
             }
         }
 
-        let programs = programs.drain().map(|(_, v)| v).collect();
+        let programs = programs
+            .drain()
+            .map(|(_, mut prog)| {
+                prog.token = token;
+                prog
+            })
+            .collect();
         let maps = maps.drain().map(|(_, v)| v).collect();
         Ok(Module {
             programs,
@@ -424,6 +1417,65 @@ impl Module {
     }
 }
 
+/// Finds the program of the section `shndx` holding the instruction at byte
+/// `offset` of the section, and the index of the instruction in the program.
+fn program_at(
+    programs: &mut RSHashMap<(usize, u64), Program>,
+    shndx: usize,
+    offset: u64,
+) -> Option<(&mut Program, usize)> {
+    programs.iter_mut().find_map(|(&(section, start), prog)| {
+        let insn_idx = offset.checked_sub(start)? as usize / mem::size_of::<bpf_insn>();
+        if section == shndx && insn_idx < prog.code.len() {
+            Some((prog, insn_idx))
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the kind of programs in sections starting with `prefix`, for
+/// the section names of both cargo-bpf and libbpf's `SEC()`.
+fn program_kind(prefix: &str) -> Option<&'static str> {
+    let kind = match prefix {
+        "kprobe" => "kprobe",
+        "kretprobe" => "kretprobe",
+        "fentry" => "fentry",
+        "xdp" => "xdp",
+        "socketfilter" | "socket" => "socketfilter",
+        "tracepoint" | "tp" => "tracepoint",
+        "tc_action" | "tc" | "classifier" => "tc_action",
+        "cgroup_skb" => "cgroup_skb",
+        "cgroup_bind4" => "cgroup_bind4",
+        "cgroup_bind6" => "cgroup_bind6",
+        "cgroup_connect4" => "cgroup_connect4",
+        "cgroup_connect6" => "cgroup_connect6",
+        "cgroup_sendmsg4" => "cgroup_sendmsg4",
+        "cgroup_sendmsg6" => "cgroup_sendmsg6",
+        _ => return None,
+    };
+    Some(kind)
+}
+
+fn map_def_bytes(def: &bpf_map_def) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(def as *const _ as *const u8, mem::size_of_val(def)) }
+}
+
+/// Returns the definition `code` of the map `name`, with the number of
+/// entries overridden if `max_entries` names the map.
+fn resized_map_def(name: &str, code: &[u8], max_entries: &[(String, u32)]) -> Result<Vec<u8>> {
+    let mut code = code.to_vec();
+    if let Some(&(_, entries)) = max_entries.iter().find(|(map, _)| map == name) {
+        if code.len() < mem::size_of::<bpf_map_def>() {
+            return Err(LoadError::Map);
+        }
+        // after `type_`, `key_size` and `value_size`
+        let offset = 3 * mem::size_of::<u32>();
+        code[offset..offset + 4].copy_from_slice(&entries.to_ne_bytes());
+    }
+    Ok(code)
+}
+
 #[inline]
 fn get_split_section_name<'o>(
     object: &'o Elf<'_>,
@@ -450,16 +1502,63 @@ impl Rel {
     #[inline]
     pub fn apply(
         &self,
-        programs: &mut HashMap<usize, Program>,
-        maps: &HashMap<usize, Map>,
+        programs: &mut RSHashMap<(usize, u64), Program>,
+        maps: &RSHashMap<(usize, u64), Map>,
         symtab: &[Sym],
+        strtab: &Strtab<'_>,
     ) -> Result<()> {
-        let prog = programs.get_mut(&self.target).ok_or(LoadError::Reloc)?;
+        let (prog, insn_idx) =
+            program_at(programs, self.target, self.offset).ok_or(LoadError::Reloc)?;
+        let sym = &symtab[self.sym];
+
+        // calls to undefined symbols are kfunc calls, patched at load time
+        if prog.code[insn_idx].code == (bpf_sys::BPF_JMP | bpf_sys::BPF_CALL) as u8
+            && sym.st_shndx == hdr::SHN_UNDEF as usize
+        {
+            let name = strtab.get_unsafe(sym.st_name).ok_or(LoadError::Reloc)?;
+            prog.kfuncs.push(KfuncRef {
+                insn_idx,
+                name: name.to_string(),
+            });
+            return Ok(());
+        }
+
+        // load-time constants: ring buffer support for `EventChannel`, the
+        // offset of a field for `core_offset!`, or whether a kfunc or a
+        // helper exists for `kfunc_exists!` and `helper_exists!`
+        if prog.code[insn_idx].code == (bpf_sys::BPF_LD | bpf_sys::BPF_IMM | bpf_sys::BPF_DW) as u8
+            && sym.st_shndx == hdr::SHN_UNDEF as usize
+        {
+            let name = strtab.get_unsafe(sym.st_name).ok_or(LoadError::Reloc)?;
+            if name == RINGBUF_SUPPORTED_SYM {
+                prog.code[insn_idx].imm = ringbuf_supported() as i32;
+                prog.code[insn_idx + 1].imm = 0;
+            } else if let Some(relo) = CoreRelo::from_symbol(name, insn_idx) {
+                let insn = prog.code[insn_idx];
+                prog.core_relos.push((relo, insn));
+            } else if let Some(name) = name.strip_prefix(KFUNC_EXISTS_SYM_PREFIX) {
+                prog.kfunc_checks.push(KfuncRef {
+                    insn_idx,
+                    name: name.to_string(),
+                });
+            } else if let Some(id) = name.strip_prefix(HELPER_EXISTS_SYM_PREFIX) {
+                let id = id.parse().map_err(|_| LoadError::Reloc)?;
+                let exists = features::probe_helper(prog.kind.to_prog_type(), id);
+                prog.code[insn_idx].imm = exists as i32;
+                prog.code[insn_idx + 1].imm = 0;
+            } else {
+                return Err(LoadError::Reloc);
+            }
+            return Ok(());
+        }
+
         let map = maps
-            .get(&symtab[self.sym].st_shndx)
+            .get(&(sym.st_shndx, sym.st_value))
             .ok_or(LoadError::Reloc)?;
-        let insn_idx = (self.offset / std::mem::size_of::<bpf_insn>() as u64) as usize;
 
+        if map.fd < 0 && !prog.missing_maps.contains(&map.name) {
+            prog.missing_maps.push(map.name.clone());
+        }
         prog.code[insn_idx].set_src_reg(bpf_sys::BPF_PSEUDO_MAP_FD as u8);
         prog.code[insn_idx].imm = map.fd;
 
@@ -469,7 +1568,47 @@ impl Rel {
 
 impl Map {
     pub fn load(name: &str, code: &[u8]) -> Result<Map> {
-        let config: &bpf_map_def = zero::read(code);
+        Map::load_with_btf(name, code, None, None)
+    }
+
+    /// Creates the map with the key and value types declared in `btf`, if
+    /// any, so that tools like `bpftool` can show its contents typed.
+    ///
+    /// The map is created without types if the kernel rejects them.
+    fn load_with_btf(
+        name: &str,
+        code: &[u8],
+        btf: Option<&ObjectBtf>,
+        token: Option<RawFd>,
+    ) -> Result<Map> {
+        let mut config: bpf_map_def = *zero::read(code);
+        if config.type_ == EVENT_CHANNEL_MAP_TYPE {
+            config = event_channel_def(&config)?;
+        }
+        let map = |fd| Map {
+            name: name.to_string(),
+            kind: config.type_,
+            fd,
+            config,
+        };
+        // left out on kernels without ring buffers, for the programs using
+        // it to be skipped rather than the whole module failing
+        if config.type_ == BPF_MAP_TYPE_RINGBUF && !ringbuf_supported() {
+            return Ok(map(-1));
+        }
+
+        let type_ids = btf.and_then(|btf| Some((btf.fd, btf.btf.map_type_ids(name)?)));
+        if type_ids.is_some() {
+            if let Ok(fd) = create_map(name, &config, type_ids, token) {
+                return Ok(map(fd));
+            }
+        }
+        // maps created through a token or a syscall provider can't go
+        // through libbpf
+        if token.is_some() || provider::syscall_provider().is_some() {
+            return Ok(map(create_map(name, &config, None, token)?));
+        }
+
         let cname = CString::new(name.to_owned())?;
         let fd = unsafe {
             bpf_sys::bcc_create_map(
@@ -485,30 +1624,125 @@ impl Map {
             return Err(LoadError::Map);
         }
 
-        Ok(Map {
-            name: name.to_string(),
-            kind: config.type_,
-            fd,
-        })
+        Ok(map(fd))
     }
     pub fn set(&self, key: VoidPtr, value: VoidPtr) {
         unsafe {
-            bpf_sys::bpf_update_elem(self.fd, key, value, 0);
+            let _ = sys::bpf::map_update_elem(self.fd, key as *const u8, value as *const u8, 0);
         }
     }
 
     pub fn get(&self, key: VoidPtr, value: VoidPtr) {
         unsafe {
-            bpf_sys::bpf_lookup_elem(self.fd, key, value);
+            let _ = sys::bpf::map_lookup_elem(self.fd, key as *const u8, value as *mut u8, 0);
         }
     }
 
     pub fn delete(&self, key: VoidPtr) {
         unsafe {
-            bpf_sys::bpf_delete_elem(self.fd, key);
+            let _ = sys::bpf::map_delete_elem(self.fd, key as *const u8);
+        }
+    }
+
+    /// Returns the kernel's information about the map, including the ids of
+    /// its BTF key and value types if it was created with them.
+    pub fn info(&self) -> Result<bpf_map_info> {
+        let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+        unsafe { obj_get_info_by_fd(self.fd, &mut info)? };
+        Ok(info)
+    }
+
+    /// Returns the kernel memory charged for the map, in bytes.
+    ///
+    /// The figure is the one the kernel reports in the map's fdinfo. Where
+    /// that isn't available, it is estimated from the map's `bpf_map_info`.
+    pub fn memlock_bytes(&self) -> Result<u64> {
+        if let Some(memlock) = fdinfo_memlock(self.fd) {
+            return Ok(memlock);
         }
+
+        Ok(estimate_map_memlock(
+            &self.info()?,
+            cpus::get_possible()?.len() as u64,
+            page_size(),
+        ))
+    }
+}
+
+/// Creates a map through `BPF_MAP_CREATE`, with the BTF types
+/// `(btf_fd, (key_type_id, value_type_id))` if given.
+fn create_map(
+    name: &str,
+    config: &bpf_map_def,
+    type_ids: Option<(RawFd, (u32, u32))>,
+    token: Option<RawFd>,
+) -> io::Result<RawFd> {
+    let mut attr = MapCreateAttr {
+        map_type: config.type_,
+        key_size: config.key_size,
+        value_size: config.value_size,
+        max_entries: config.max_entries,
+        map_flags: config.map_flags,
+        ..Default::default()
+    };
+    let len = name.len().min(attr.map_name.len() - 1);
+    attr.map_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    if let Some((btf_fd, (key_type_id, value_type_id))) = type_ids {
+        attr.btf_fd = btf_fd as u32;
+        attr.btf_key_type_id = key_type_id;
+        attr.btf_value_type_id = value_type_id;
     }
+    if let Some(token) = token {
+        attr.map_flags |= BPF_F_TOKEN_FD;
+        attr.map_token_fd = token as u32;
+    }
+
+    sys::bpf::map_create(&mut attr)
+}
+
+/// Parses the object's `.BTF` section, if there is one, with the sizes and
+/// offsets the compiler left out filled in.
+fn object_btf(object: &Elf<'_>, bytes: &[u8], symtab: &[Sym]) -> Option<Btf> {
+    let mut btf = Btf::parse(data(bytes, find_section(object, ".BTF")?)).ok()?;
+    btf.fixup_datasecs(
+        |name| find_section(object, name).map(|shdr| shdr.sh_size as u32),
+        |name| {
+            symtab
+                .iter()
+                .find(|sym| object.strtab.get_unsafe(sym.st_name) == Some(name))
+                .map(|sym| sym.st_value as u32)
+        },
+    );
+
+    Some(btf)
+}
+
+fn find_section<'o>(object: &'o Elf<'_>, name: &str) -> Option<&'o SectionHeader> {
+    object
+        .section_headers
+        .iter()
+        .find(|shdr| object.shdr_strtab.get_unsafe(shdr.sh_name) == Some(name))
+}
+
+/// Picks the map backing an `EventChannel` for the running kernel.
+fn event_channel_def(config: &bpf_map_def) -> Result<bpf_map_def> {
+    if ringbuf_supported() {
+        return Ok(bpf_map_def {
+            type_: BPF_MAP_TYPE_RINGBUF,
+            ..*config
+        });
+    }
+
+    let cpus = cpus::get_possible()?;
+    Ok(bpf_map_def {
+        type_: bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERF_EVENT_ARRAY,
+        key_size: mem::size_of::<u32>() as u32,
+        value_size: mem::size_of::<u32>() as u32,
+        max_entries: cpus.iter().max().map(|&id| id as u32 + 1).unwrap_or(1),
+        map_flags: 0,
+    })
 }
+
 #[inline]
 fn add_rel(
     rels: &mut Vec<Rel>,
@@ -526,6 +1760,140 @@ fn add_rel(
     }));
 }
 
+/// Calls `load` with growing log buffers until the verifier log fits, and
+/// returns the result of the last call along with the log.
+///
+/// `load` returns the size of the complete log if the kernel reports it
+/// (kernel 6.4 or later), or 0. Truncated logs fail with `ENOSPC`.
+fn load_with_log<F>(initial: usize, mut load: F) -> (io::Result<RawFd>, String)
+where
+    F: FnMut(&mut [u8]) -> (io::Result<RawFd>, usize),
+{
+    let mut size = initial.clamp(1024, LOG_SIZE_MAX);
+    loop {
+        let mut log = vec![0u8; size];
+        let (res, true_size) = load(&mut log);
+        let truncated = res.as_ref().err().and_then(io::Error::raw_os_error) == Some(libc::ENOSPC);
+        if truncated && size < LOG_SIZE_MAX {
+            size = true_size.max(size * 4).min(LOG_SIZE_MAX);
+            continue;
+        }
+
+        let len = log.iter().position(|&c| c == 0).unwrap_or(log.len());
+        return (res, String::from_utf8_lossy(&log[..len]).into_owned());
+    }
+}
+
+/// Finds the instruction the verifier rejected and its reason in the tail
+/// of the verifier log.
+///
+/// The verifier prints each instruction it walks as `<index>: (<opcode>)
+/// <insn>`, followed by the register state, and stops with the error after
+/// the failing instruction, before the statistics. Errors that aren't
+/// about an instruction, such as a too complex program, leave the
+/// instruction `None`.
+fn verifier_failure(log: &str) -> (Option<usize>, String) {
+    let is_stats = |line: &str| {
+        [
+            "processed ",
+            "stack depth ",
+            "verification time ",
+            "max_states_per_insn ",
+        ]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+    };
+    let insn = |line: &str| {
+        let (index, rest) = line.split_once(": (")?;
+        // the opcode is two hex digits
+        if rest.get(2..3) != Some(")") {
+            return None;
+        }
+        index.parse::<usize>().ok()
+    };
+
+    let lines: Vec<&str> = log
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !is_stats(line))
+        .collect();
+    match lines.iter().rposition(|line| insn(line).is_some()) {
+        Some(pos) => {
+            let reason = lines[pos + 1..].join("\n");
+            (insn(lines[pos]), reason)
+        }
+        None => (None, lines.last().copied().unwrap_or_default().to_string()),
+    }
+}
+
+/// Reads the memory charged for a BPF object from `/proc/self/fdinfo`.
+fn fdinfo_memlock(fd: RawFd) -> Option<u64> {
+    let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", fd)).ok()?;
+    parse_fdinfo_memlock(&fdinfo)
+}
+
+fn parse_fdinfo_memlock(fdinfo: &str) -> Option<u64> {
+    fdinfo
+        .lines()
+        .find(|line| line.starts_with("memlock:"))
+        .and_then(|line| line["memlock:".len()..].trim().parse().ok())
+}
+
+/// Estimates the memory charged for a map the way older kernels account
+/// for it: the elements, rounded up to whole pages, plus a page for the map
+/// itself. Values of per-CPU maps are charged once for every possible CPU.
+fn estimate_map_memlock(info: &bpf_map_info, cpus: u64, page_size: u64) -> u64 {
+    let key_size = round_up(u64::from(info.key_size), 8);
+    let mut value_size = round_up(u64::from(info.value_size), 8);
+    let per_cpu = [
+        bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_HASH,
+        bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY,
+        bpf_sys::bpf_map_type_BPF_MAP_TYPE_LRU_PERCPU_HASH,
+    ];
+    if per_cpu.contains(&info.type_) {
+        value_size *= cpus;
+    }
+    let elem_size = match info.type_ {
+        bpf_sys::bpf_map_type_BPF_MAP_TYPE_ARRAY
+        | bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY => value_size,
+        _ => key_size + value_size,
+    };
+
+    round_up(elem_size * u64::from(info.max_entries), page_size) + page_size
+}
+
+fn round_up(n: u64, to: u64) -> u64 {
+    match n % to {
+        0 => n,
+        rem => n + to - rem,
+    }
+}
+
+fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+/// Lifts the locked memory limit of the process, which maps and programs
+/// are charged against before kernel 5.11. With the default limit, loading
+/// fails with `EPERM` after a few maps.
+///
+/// The limit applies to the whole process, so it is up to the application
+/// to lift it, usually first thing in `main`:
+///
+/// ```no_run
+/// redbpf::bump_memlock_rlimit().unwrap();
+/// ```
+pub fn bump_memlock_rlimit() -> Result<()> {
+    let rlim = libc::rlimit {
+        rlim_cur: libc::RLIM_INFINITY,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    match unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &rlim) } {
+        0 => Ok(()),
+        _ => Err(LoadError::IO(io::Error::last_os_error())),
+    }
+}
+
 #[inline]
 fn get_version(bytes: &[u8]) -> u32 {
     let version = zero::read::<u32>(bytes);
@@ -548,3 +1916,598 @@ impl Default for XdpFlags {
         XdpFlags::Unset
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::features::probe_helper;
+    use crate::test_util::{create_map, insn, load_program, map_def, test_run};
+
+    #[test]
+    fn test_memlock() {
+        let fdinfo = "pos:\t0\nflags:\t02000002\nmemlock:\t8192\nmap_id:\t12\n";
+        assert_eq!(parse_fdinfo_memlock(fdinfo), Some(8192));
+        assert_eq!(parse_fdinfo_memlock("pos:\t0\n"), None);
+
+        let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+        info.type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_ARRAY;
+        info.key_size = 4;
+        info.value_size = 8;
+        info.max_entries = 1024;
+        assert_eq!(estimate_map_memlock(&info, 4, 4096), 3 * 4096);
+
+        info.type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY;
+        assert_eq!(estimate_map_memlock(&info, 4, 4096), 9 * 4096);
+
+        info.type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH;
+        assert_eq!(estimate_map_memlock(&info, 4, 4096), 5 * 4096);
+    }
+
+    #[test]
+    fn test_load_with_log() {
+        let verifier_log: String = (0..10000).map(|i| format!("{}: (b7) r0 = 0\n", i)).collect();
+        let mut sizes = vec![];
+        let (res, log) = load_with_log(LOG_SIZE_DEFAULT, |buf| {
+            sizes.push(buf.len());
+            let len = verifier_log.len().min(buf.len() - 1);
+            buf[..len].copy_from_slice(&verifier_log.as_bytes()[..len]);
+            let errno = if len < verifier_log.len() {
+                libc::ENOSPC
+            } else {
+                libc::EACCES
+            };
+            (Err(io::Error::from_raw_os_error(errno)), 0)
+        });
+
+        assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EACCES));
+        assert_eq!(log, verifier_log);
+        assert_eq!(sizes, vec![LOG_SIZE_DEFAULT, 4 * LOG_SIZE_DEFAULT]);
+    }
+
+    #[test]
+    fn test_verifier_failure() {
+        let log = "func#0 @0\n\
+                   0: R1=ctx() R10=fp0\n\
+                   0: (b7) r0 = 0                        ; R0_w=0\n\
+                   1: (79) r1 = *(u64 *)(r10 +8)\n\
+                   invalid read from stack R10 off=8 size=8\n\
+                   processed 2 insns (limit 1000000) max_states_per_insn 0 total_states 0\n";
+        assert_eq!(
+            verifier_failure(log),
+            (
+                Some(1),
+                "invalid read from stack R10 off=8 size=8".to_string()
+            )
+        );
+
+        let log = "back-edge from insn 1 to 0\n\
+                   processed 0 insns (limit 1000000) max_states_per_insn 0\n";
+        assert_eq!(
+            verifier_failure(log),
+            (None, "back-edge from insn 1 to 0".to_string())
+        );
+        assert_eq!(verifier_failure(""), (None, String::new()));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_verifier_rejection() {
+        // r0 = 0; r1 = *(u64 *)(r10 + 8); exit
+        let code = [
+            0xb7, 0, 0, 0, 0, 0, 0, 0, //
+            0x79, 0xa1, 8, 0, 0, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut prog = Program::new("socketfilter", "bad_stack_read", &code).unwrap();
+        match prog.load(0, "GPL".to_string()) {
+            Err(LoadError::ProgramLoad {
+                failed_insn,
+                reason,
+                ..
+            }) => {
+                assert_eq!(failed_insn, Some(1));
+                assert!(reason.contains("stack"), "{}", reason);
+            }
+            Err(e) => panic!("{:?}", e),
+            Ok(_) => panic!("the verifier let an out of bounds stack read through"),
+        }
+    }
+
+    /// What the fd refers to, and the id of the BPF object behind it, which
+    /// tell a closed fd apart from one reused by another test.
+    fn fd_identity(fd: RawFd) -> Option<String> {
+        let target = std::fs::read_link(format!("/proc/self/fd/{}", fd)).ok()?;
+        let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", fd)).ok()?;
+        let ids: Vec<&str> = fdinfo
+            .lines()
+            .filter(|line| line.starts_with("prog_id:") || line.starts_with("map_id:"))
+            .collect();
+        Some(format!("{} {}", target.display(), ids.join(" ")))
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_teardown() {
+        // r0 = 0; exit
+        let code = [
+            0xb7, 0, 0, 0, 0, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let kprobe = load_program("kprobe", "vfs_fsync", &code);
+        let filter = load_program("socketfilter", "teardown_test", &code);
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_ARRAY;
+        let map = create_map("teardown_test", type_, 4, 8, 1);
+        let mut fds = vec![kprobe.fd.unwrap(), filter.fd.unwrap(), map.fd];
+        let mut module = Module {
+            programs: vec![kprobe, filter],
+            maps: vec![map],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+
+        fds.push(module.programs[1].attach_socketfilter("lo").unwrap());
+        let kprobe_events = "/sys/kernel/debug/tracing/kprobe_events";
+        let event = format!("vfs_fsync0_bcc_{}", std::process::id());
+        // there's no debugfs in some containers
+        if let Ok(fd) = module.programs[0].attach_probe() {
+            fds.push(fd);
+            assert!(std::fs::read_to_string(kprobe_events)
+                .unwrap()
+                .contains(&event));
+        }
+        assert!(module.programs[1].is_attached());
+        let before: Vec<Option<String>> = fds.iter().map(|&fd| fd_identity(fd)).collect();
+        assert!(before.iter().all(Option::is_some));
+
+        drop(module);
+        for (&fd, before) in fds.iter().zip(before) {
+            assert_ne!(fd_identity(fd), before, "fd {} leaked", fd);
+        }
+        if let Ok(events) = std::fs::read_to_string(kprobe_events) {
+            assert!(!events.contains(&event));
+        }
+    }
+
+    /// Builds the object clang makes of an XDP program in the `xdp` section,
+    /// which loads the `counts` map declared in `.maps` with `btf`.
+    pub(crate) fn libbpf_object(btf: &[u8]) -> Vec<u8> {
+        libbpf_functions(btf, &[("count_packets", 2)])
+    }
+
+    /// Builds the object clang makes of the XDP programs `functions` in the
+    /// `xdp` section, each of which loads the `counts` map declared in
+    /// `.maps` with `btf`, and returns its action.
+    fn libbpf_functions(btf: &[u8], functions: &[(&str, u8)]) -> Vec<u8> {
+        let mut code = vec![];
+        for &(_, action) in functions.iter() {
+            code.extend_from_slice(&[
+                0x18, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // r1 = counts ll
+                0xb7, 0, 0, 0, action, 0, 0, 0, // r0 = action
+                0x95, 0, 0, 0, 0, 0, 0, 0, // exit
+            ]);
+        }
+        let mut strtab = vec![0u8];
+        let mut name = |name: &str| {
+            let offset = strtab.len() as u32;
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            offset
+        };
+        let sections = ["xdp", ".maps", "license", ".BTF", ".symtab", ".strtab"];
+        let names: Vec<u32> = sections
+            .iter()
+            .chain(&[".relxdp", "counts"])
+            .chain(functions.iter().map(|(function, _)| function))
+            .map(|n| name(n))
+            .collect();
+
+        // `counts`, a global object, and the functions, global too
+        let mut syms = vec![(names[7], 0x11u8, 2u16, 0u64, 40u64)];
+        for i in 0..functions.len() {
+            syms.push((names[8 + i], 0x12, 1, 32 * i as u64, 32));
+        }
+        let mut symtab = vec![0u8; 24];
+        let mut rel = vec![];
+        for &(name, info, shndx, value, size) in syms.iter() {
+            symtab.extend_from_slice(&name.to_le_bytes());
+            symtab.extend_from_slice(&[info, 0]);
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&size.to_le_bytes());
+            // R_BPF_64_64 of `counts` at the first instruction of functions
+            if shndx == 1 {
+                rel.extend_from_slice(&value.to_le_bytes());
+                rel.extend_from_slice(&(1u64 << 32 | 1).to_le_bytes());
+            }
+        }
+
+        // name, type, flags, contents, link, info, entsize
+        let sections: [(u32, u32, u64, &[u8], u32, u32, u64); 7] = [
+            (names[0], hdr::SHT_PROGBITS, 0x6, &code, 0, 0, 0),
+            (names[1], hdr::SHT_PROGBITS, 0x3, &[0; 40], 0, 0, 0),
+            (names[2], hdr::SHT_PROGBITS, 0x3, b"GPL\0", 0, 0, 0),
+            (names[3], hdr::SHT_PROGBITS, 0, btf, 0, 0, 0),
+            (names[4], hdr::SHT_SYMTAB, 0, &symtab, 6, 1, 24),
+            (names[5], hdr::SHT_STRTAB, 0, &strtab, 0, 0, 0),
+            (names[6], hdr::SHT_REL, 0, &rel, 5, 1, 16),
+        ];
+
+        let mut object = vec![0u8; 64];
+        let mut headers = vec![0u8; 64];
+        for &(name, type_, flags, contents, link, info, entsize) in sections.iter() {
+            while object.len() % 8 != 0 {
+                object.push(0);
+            }
+            headers.extend_from_slice(&name.to_le_bytes());
+            headers.extend_from_slice(&type_.to_le_bytes());
+            headers.extend_from_slice(&flags.to_le_bytes());
+            headers.extend_from_slice(&0u64.to_le_bytes());
+            headers.extend_from_slice(&(object.len() as u64).to_le_bytes());
+            headers.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+            headers.extend_from_slice(&link.to_le_bytes());
+            headers.extend_from_slice(&info.to_le_bytes());
+            headers.extend_from_slice(&8u64.to_le_bytes());
+            headers.extend_from_slice(&entsize.to_le_bytes());
+            object.extend_from_slice(contents);
+        }
+        while object.len() % 8 != 0 {
+            object.push(0);
+        }
+        let shoff = object.len() as u64;
+        object.extend_from_slice(&headers);
+
+        // ELF64, little endian, relocatable, EM_BPF, with 8 sections of 64
+        // bytes, and the section names in .strtab
+        object[..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        object[16..24].copy_from_slice(&[1, 0, 247, 0, 1, 0, 0, 0]);
+        object[40..48].copy_from_slice(&shoff.to_le_bytes());
+        object[52..54].copy_from_slice(&64u16.to_le_bytes());
+        object[58..64].copy_from_slice(&[64, 0, 8, 0, 6, 0]);
+        object
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_load_task_fields() {
+        if !probe_helper(bpf_sys::bpf_prog_type_BPF_PROG_TYPE_KPROBE, 158) {
+            // bpf_get_current_task_btf, and the kernel's BTF the offsets are
+            // resolved against, are available since kernel 5.11
+            return;
+        }
+        // Task::current().real_parent().tgid(), from an fentry probe
+        let code = [
+            insn(0x85, 0, 0, 0, 158), // call bpf_get_current_task_btf
+            insn(0x79, 0, 0, 0, 0),   // r0 = task->real_parent
+            insn(0x61, 0, 0, 0, 0),   // r0 = r0->tgid
+            insn(0xb7, 0, 0, 0, 0),   // r0 = 0
+            insn(0x95, 0, 0, 0, 0),   // exit
+        ]
+        .concat();
+        let mut prog = Program::new("fentry", "vfs_read", &code).unwrap();
+        for (insn_idx, field) in [(1, "real_parent"), (2, "tgid")].iter() {
+            let sym = format!("__redbpf_core_offset:task_struct:{}", field);
+            let relo = CoreRelo::from_symbol(&sym, *insn_idx).unwrap();
+            prog.core_relos.push((relo, prog.code[*insn_idx]));
+        }
+        prog.load(0, "GPL".to_string()).unwrap();
+        assert!(prog.code[1].off > 0);
+        assert!(prog.code[2].off > 0);
+    }
+
+    #[test]
+    fn test_undefined_ld_imm64() {
+        let strtab = b"\0__redbpf_kfunc_exists:bpf_rcu_read_lock\0bpf_rcu_read_lock\0\
+            __redbpf_helper_exists:188\0__redbpf_helper_exists:foo\0";
+        let kfunc = "\0__redbpf_kfunc_exists:bpf_rcu_read_lock\0".len();
+        let helper = kfunc + "bpf_rcu_read_lock\0".len();
+        let bad_helper = helper + "__redbpf_helper_exists:188\0".len();
+        let strtab = Strtab::new(strtab, 0);
+        let sym = |st_name| Sym {
+            st_name,
+            ..Default::default()
+        };
+        let symtab = [sym(0), sym(1), sym(kfunc), sym(helper), sym(bad_helper)];
+        // r1 = sym ll; r0 = 0; exit
+        let code = [
+            0x18, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+            0xb7, 0, 0, 0, 0, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut programs = RSHashMap::new();
+        programs.insert((1, 0), Program::new("xdp", "check", &code).unwrap());
+        let rel = |sym| Rel {
+            shndx: 2,
+            target: 1,
+            offset: 0,
+            sym,
+        };
+
+        rel(1)
+            .apply(&mut programs, &RSHashMap::new(), &symtab, &strtab)
+            .unwrap();
+        let checks = &programs[&(1, 0)].kfunc_checks;
+        assert_eq!(checks.len(), 1);
+        assert_eq!(
+            (checks[0].insn_idx, checks[0].name.as_str()),
+            (0, "bpf_rcu_read_lock")
+        );
+        // the address of the kfunc itself is no check
+        match rel(2).apply(&mut programs, &RSHashMap::new(), &symtab, &strtab) {
+            Err(LoadError::Reloc) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // helper checks are resolved right away, BPF_FUNC_xdp_get_buff_len
+        rel(3)
+            .apply(&mut programs, &RSHashMap::new(), &symtab, &strtab)
+            .unwrap();
+        let exists = probe_helper(bpf_sys::bpf_prog_type_BPF_PROG_TYPE_XDP, 188);
+        assert_eq!(programs[&(1, 0)].code[0].imm, exists as i32);
+        match rel(4).apply(&mut programs, &RSHashMap::new(), &symtab, &strtab) {
+            Err(LoadError::Reloc) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_resized_map_def() {
+        let def = map_def(bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH, 4, 8, 1024);
+        let code = map_def_bytes(&def);
+        let resize = [("counts".to_string(), 64)];
+        assert_eq!(resized_map_def("other", code, &resize).unwrap(), code);
+
+        let resized = resized_map_def("counts", code, &resize).unwrap();
+        let resized: bpf_map_def = *zero::read(&resized);
+        assert_eq!(resized.max_entries, 64);
+        assert_eq!((resized.key_size, resized.value_size), (4, 8));
+        assert!(resized_map_def("counts", &code[..8], &resize).is_err());
+
+        // unknown maps are found before any map is created
+        let object = libbpf_object(&btf::test::maps_section());
+        let resize = [("sizes".to_string(), 64)];
+        match Module::parse_with(&object, None, Path::new(PIN_BY_NAME_DIR), &resize) {
+            Err(LoadError::UnknownMap(name)) => assert_eq!(name, "sizes"),
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_program_kind() {
+        assert_eq!(program_kind("xdp"), Some("xdp"));
+        assert_eq!(program_kind("socket"), Some("socketfilter"));
+        assert_eq!(program_kind("classifier"), Some("tc_action"));
+        assert_eq!(program_kind("tp"), Some("tracepoint"));
+        assert_eq!(program_kind("cgroup_connect4"), Some("cgroup_connect4"));
+        assert_eq!(program_kind("maps"), None);
+        assert_eq!(program_kind(".text"), None);
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_parse_libbpf_object() {
+        let object = libbpf_object(&btf::test::maps_section());
+        let dir = std::path::PathBuf::from(format!(
+            "/sys/fs/bpf/redbpf_libbpf_test_{}",
+            std::process::id()
+        ));
+        let mut module = Module::parse_pinned(&object, &dir).unwrap();
+        assert_eq!(module.license, "GPL");
+        assert_eq!(module.maps.len(), 1);
+        let map = &module.maps[0];
+        assert_eq!(map.name, "counts");
+        assert_eq!(map.kind, bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH);
+        assert_eq!((map.config.key_size, map.config.value_size), (4, 8));
+        assert_eq!(map.config.max_entries, 1024);
+        // pinned by name
+        assert!(dir.join("counts").exists());
+        let map_fd = map.fd;
+
+        assert_eq!(module.programs.len(), 1);
+        let prog = &mut module.programs[0];
+        assert_eq!(prog.name, "count_packets");
+        assert_eq!(prog.kind, ProgramKind::XDP);
+        assert_eq!(prog.code[0].imm, map_fd);
+        prog.load(module.version, module.license.clone()).unwrap();
+
+        // the BTF of the object is required
+        let mut no_btf = libbpf_object(&btf::test::encode(&[], b"\0"));
+        assert!(Module::parse_pinned(&no_btf, &dir).is_err());
+        // with no map, the relocation fails
+        let maps_section = no_btf.windows(6).position(|w| w == b".maps\0").unwrap();
+        no_btf[maps_section..maps_section + 5].copy_from_slice(b".mapz");
+        assert!(Module::parse_pinned(&no_btf, &dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_parse_libbpf_functions() {
+        let functions = [("pass_packets", 2), ("drop_packets", 1)];
+        let object = libbpf_functions(&btf::test::maps_section(), &functions);
+        let dir = std::path::PathBuf::from(format!(
+            "/sys/fs/bpf/redbpf_libbpf_functions_test_{}",
+            std::process::id()
+        ));
+        let mut module = Module::parse_pinned(&object, &dir).unwrap();
+        let map_fd = module.maps[0].fd;
+        module.programs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // a program of each function, with its own code and relocations
+        assert_eq!(module.programs.len(), 2);
+        for (prog, &(name, action)) in module.programs.iter().zip(&[functions[1], functions[0]]) {
+            assert_eq!(prog.name, name);
+            assert_eq!(prog.kind, ProgramKind::XDP);
+            assert_eq!(prog.code.len(), 4);
+            assert_eq!(prog.code[0].imm, map_fd);
+            assert_eq!(prog.code[2].imm, i32::from(action));
+        }
+        module.load().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_drop_reasons() {
+        let mut module = Module {
+            programs: vec![],
+            maps: vec![],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+        assert!(module.drop_reasons().is_empty());
+
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY;
+        let map = create_map(DROP_REASONS_MAP, type_, 4, 8, 64);
+        // what drop_with_reason(2) on each CPU leaves behind
+        let cpus = cpus::get_possible().unwrap();
+        let counts: Vec<u64> = cpus.iter().map(|&cpu| cpu as u64 + 1).collect();
+        unsafe {
+            let key = &2u32 as *const _ as *const u8;
+            sys::bpf::map_update_elem(map.fd, key, counts.as_ptr() as *const u8, 0).unwrap();
+        }
+        module.maps.push(map);
+
+        let reasons = module.drop_reasons();
+        assert_eq!(reasons.len(), 64);
+        assert_eq!(reasons[2], counts.iter().sum::<u64>());
+        assert!(reasons
+            .iter()
+            .enumerate()
+            .all(|(i, &count)| i == 2 || count == 0));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_sample_counts() {
+        let mut module = Module {
+            programs: vec![],
+            maps: vec![],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+        assert_eq!(module.sample_counts(), None);
+
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY;
+        let map = create_map(SAMPLES_MAP, type_, 4, 8, 2);
+        let cpus = cpus::get_possible().unwrap();
+        let seen = vec![10u64; cpus.len()];
+        let exported = vec![1u64; cpus.len()];
+        unsafe {
+            for (key, counts) in [(0u32, &seen), (1u32, &exported)].iter() {
+                let key = key as *const _ as *const u8;
+                sys::bpf::map_update_elem(map.fd, key, counts.as_ptr() as *const u8, 0).unwrap();
+            }
+        }
+        module.maps.push(map);
+
+        let n = cpus.len() as u64;
+        assert_eq!(module.sample_counts(), Some((10 * n, n)));
+    }
+
+    /// A module with a program for either backend of an `events` map, on a
+    /// kernel without ring buffers.
+    fn events_module() -> Module {
+        // r0 = 0; exit
+        let code = [0xb7, 0, 0, 0, 0, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0];
+        let mut ringbuf = Program::new("socketfilter", "events_ringbuf", &code).unwrap();
+        ringbuf.missing_maps.push("events".to_string());
+        let perfbuf = Program::new("socketfilter", "events_perfbuf", &code).unwrap();
+        Module {
+            programs: vec![ringbuf, perfbuf],
+            maps: vec![],
+            license: "GPL".to_string(),
+            version: 0,
+        }
+    }
+
+    #[test]
+    fn test_autoload() {
+        let mut module = events_module();
+        match module.load() {
+            Err(LoadError::MissingMap { program, map }) => {
+                assert_eq!(program, "events_ringbuf");
+                assert_eq!(map, "events");
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(module.set_autoload("events", false).is_err());
+        module.set_autoload("events_ringbuf", false).unwrap();
+        assert!(!module.programs[0].autoload());
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_load_autoloaded() {
+        let mut module = events_module();
+        module.set_autoload("events_ringbuf", false).unwrap();
+        module.load().unwrap();
+        assert!(!module.programs[0].is_loaded());
+        assert!(module.programs[1].is_loaded());
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_redirect_peer() {
+        // r0 = 0; exit
+        let code = [0xb7, 0, 0, 0, 0, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0];
+        let prog = load_program("tc_action", "tc_ok", &code);
+        assert_eq!(
+            prog.kind.to_prog_type(),
+            bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SCHED_CLS
+        );
+
+        // r1 = 1; r2 = 0; call bpf_redirect_peer; exit
+        let code = [
+            0xb7, 0x01, 0, 0, 1, 0, 0, 0, //
+            0xb7, 0x02, 0, 0, 0, 0, 0, 0, //
+            0x85, 0, 0, 0, 155, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut prog = Program::new("tc_action", "redirect_peer", &code).unwrap();
+        match prog.load(0, "GPL".to_string()) {
+            Ok(_) => (),
+            // before kernel 5.10
+            Err(LoadError::ProgramLoad { ref reason, .. }) if reason.contains("unknown func") => {}
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    /// The `net_cls` classid of the cgroup v1 the test runs in, if any.
+    fn net_cls_classid() -> u32 {
+        let cgroups = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let path = cgroups.lines().find_map(|line| {
+            let mut fields = line.splitn(3, ':').skip(1);
+            if !fields.next()?.split(',').any(|c| c == "net_cls") {
+                return None;
+            }
+            fields.next().map(str::to_string)
+        });
+        path.and_then(|path| {
+            std::fs::read_to_string(format!("/sys/fs/cgroup/net_cls{}/net_cls.classid", path)).ok()
+        })
+        .and_then(|classid| classid.trim().parse().ok())
+        .unwrap_or(0)
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_cgroup_classid() {
+        let packet = [0u8; 64];
+        // call bpf_get_cgroup_classid; exit
+        let code = [insn(0x85, 0, 0, 0, 17), insn(0x95, 0, 0, 0, 0)].concat();
+        let task = load_program("tc_action", "task_classid", &code);
+        assert_eq!(test_run(&task, &packet).0, net_cls_classid());
+
+        if !probe_helper(bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SCHED_CLS, 151) {
+            // bpf_skb_cgroup_classid is supported since kernel 5.10
+            return;
+        }
+        // call bpf_skb_cgroup_classid; exit
+        let code = [insn(0x85, 0, 0, 0, 151), insn(0x95, 0, 0, 0, 0)].concat();
+        let skb = load_program("tc_action", "skb_classid", &code);
+        // the packet doesn't belong to a socket
+        assert_eq!(test_run(&skb, &packet).0, 0);
+    }
+}
@@ -0,0 +1,414 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # XDP frame limits
+//!
+//! XDP programs growing packets with `bpf_xdp_adjust_head` can only grow
+//! them into the headroom the driver left in front of the frame, and native
+//! XDP frames must fit in the buffers of the driver, which restricts the
+//! MTU. Past these limits `bpf_xdp_adjust_head` fails, or the driver refuses
+//! to attach the program, so programs pushing headers should check their
+//! budget up front.
+//!
+//! Checking an interface can take IP-in-IP encapsulation:
+//!
+//! ```no_run
+//! const ENCAP_LEN: u32 = 20;
+//!
+//! let ifindex = 2;
+//! let limits = redbpf::xdp_frame_limits(ifindex).unwrap();
+//! if limits.headroom < ENCAP_LEN {
+//!     panic!("no room for the outer IP header");
+//! }
+//! if let Some(max_mtu) = limits.max_mtu {
+//!     println!("MTU {}, up to {}", limits.mtu, max_mtu);
+//! }
+//! ```
+
+use crate::sys::netlink::{parse_attrs, Message, Socket, NLM_F_DUMP, NLM_F_REQUEST, RTM_GETLINK};
+use crate::Result;
+use std::io;
+
+/// Headroom the kernel reserves in front of XDP frames, `XDP_PACKET_HEADROOM`.
+pub const XDP_PACKET_HEADROOM: u32 = 256;
+
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_XDP: u16 = 43;
+const IFLA_MAX_MTU: u16 = 51;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_XDP_ATTACHED: u16 = 2;
+const IFLA_XDP_PROG_ID: u16 = 4;
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_LOOPBACK: u16 = 772;
+const IFF_UP: u32 = 0x1;
+/// Size of `struct ifinfomsg`.
+const IFINFOMSG_LEN: usize = 16;
+
+/// Frame size limits of an interface running XDP programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XdpFrameLimits {
+    /// Room in front of the frame that `bpf_xdp_adjust_head` can grow into.
+    pub headroom: u32,
+    /// The current MTU.
+    pub mtu: u32,
+    /// The largest MTU the driver accepts, which drivers lower while an XDP
+    /// program is attached if frames must fit in a page. `None` before
+    /// kernel 4.18.
+    pub max_mtu: Option<u32>,
+}
+
+/// How an XDP program is attached to an interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XdpMode {
+    /// Run by the driver, `XdpFlags::DrvMode`.
+    Native,
+    /// Run by the kernel for drivers without XDP support, `XdpFlags::SkbMode`.
+    Generic,
+    /// Run by the NIC, `XdpFlags::HwMode`.
+    Offloaded,
+    /// Programs are attached in several modes at once.
+    Multiple,
+}
+
+impl XdpMode {
+    /// Reads the `IFLA_XDP_ATTACHED` attribute, `XDP_ATTACHED_*`.
+    fn from_attached(attached: u8) -> Option<XdpMode> {
+        use XdpMode::*;
+        let mode = match attached {
+            1 => Native,
+            2 => Generic,
+            3 => Offloaded,
+            4 => Multiple,
+            _ => return None,
+        };
+        Some(mode)
+    }
+}
+
+/// An interface of the network namespace, returned by `list_links`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkInfo {
+    pub ifindex: u32,
+    pub name: String,
+    /// The hardware type, `ARPHRD_*`.
+    pub link_type: u16,
+    /// The `IFF_*` flags.
+    pub flags: u32,
+    pub mtu: u32,
+    /// The kind of virtual interfaces, such as `veth` or `bridge`.
+    pub kind: Option<String>,
+    /// How the XDP program of the interface is attached, if there's one.
+    pub xdp_mode: Option<XdpMode>,
+    /// The id of the XDP program of the interface, unless there's none or
+    /// programs are attached in several modes.
+    pub xdp_prog_id: Option<u32>,
+}
+
+impl LinkInfo {
+    /// Returns whether the interface is administratively up.
+    pub fn is_up(&self) -> bool {
+        self.flags & IFF_UP != 0
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.link_type == ARPHRD_LOOPBACK
+    }
+
+    /// Returns whether this is an Ethernet interface that isn't of a
+    /// virtual kind, which is the best guess rtnetlink allows at whether a
+    /// NIC backs it.
+    pub fn is_physical(&self) -> bool {
+        self.link_type == ARPHRD_ETHER && self.kind.is_none()
+    }
+}
+
+/// Lists the interfaces of the network namespace.
+///
+/// # Example
+///
+/// Show the XDP programs of the interfaces that are up:
+///
+/// ```no_run
+/// for link in redbpf::list_links().unwrap() {
+///     if !link.is_up() {
+///         continue;
+///     }
+///     match (link.xdp_mode, link.xdp_prog_id) {
+///         (Some(mode), Some(id)) => println!("{}: program {} ({:?})", link.name, id, mode),
+///         (Some(mode), None) => println!("{}: {:?}", link.name, mode),
+///         _ => println!("{}: no XDP program", link.name),
+///     }
+/// }
+/// ```
+pub fn list_links() -> Result<Vec<LinkInfo>> {
+    let header = [0u8; IFINFOMSG_LEN];
+    let mut sock = Socket::open()?;
+    let mut msg = Message::new(RTM_GETLINK, NLM_F_REQUEST | NLM_F_DUMP, &header);
+    let links = sock.dump(&mut msg)?;
+    Ok(links.iter().filter_map(|link| link_info(link)).collect())
+}
+
+/// Returns the frame size limits of the interface `ifindex`.
+///
+/// The headroom is the `XDP_PACKET_HEADROOM` the kernel asks drivers with
+/// native XDP support to reserve, and always provides in generic mode. It is
+/// a hint: the kernel doesn't report the headroom drivers actually reserve,
+/// so `bpf_xdp_adjust_head` must still be checked for failure.
+pub fn xdp_frame_limits(ifindex: u32) -> Result<XdpFrameLimits> {
+    frame_limits(&get_link(ifindex)?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated link info").into())
+}
+
+/// Returns the id of the XDP program attached to the interface `ifindex`,
+/// or `None` if there's none.
+pub fn xdp_prog_id(ifindex: u32) -> Result<Option<u32>> {
+    let link = get_link(ifindex)?;
+    if link.len() < IFINFOMSG_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated link info").into());
+    }
+    Ok(attached_prog_id(&link))
+}
+
+/// Returns the payload of the `RTM_NEWLINK` message describing `ifindex`.
+fn get_link(ifindex: u32) -> Result<Vec<u8>> {
+    let mut header = [0u8; IFINFOMSG_LEN];
+    header[4..8].copy_from_slice(&ifindex.to_ne_bytes());
+    let mut sock = Socket::open()?;
+    Ok(sock.query(&mut Message::new(RTM_GETLINK, NLM_F_REQUEST, &header))?)
+}
+
+/// Returns the headroom XDP programs on the interface `ifindex` can push
+/// headers into, see `xdp_frame_limits`.
+pub fn xdp_max_headroom(ifindex: u32) -> Result<u32> {
+    xdp_frame_limits(ifindex).map(|limits| limits.headroom)
+}
+
+/// Reads the limits from the payload of an `RTM_NEWLINK` message.
+fn frame_limits(link: &[u8]) -> Option<XdpFrameLimits> {
+    if link.len() < IFINFOMSG_LEN {
+        return None;
+    }
+    let attr_u32 = |kind| {
+        parse_attrs(&link[IFINFOMSG_LEN..])
+            .into_iter()
+            .find(|&(k, data)| k == kind && data.len() == 4)
+            .map(|(_, data)| u32::from_ne_bytes([data[0], data[1], data[2], data[3]]))
+    };
+
+    Some(XdpFrameLimits {
+        headroom: XDP_PACKET_HEADROOM,
+        mtu: attr_u32(IFLA_MTU)?,
+        max_mtu: attr_u32(IFLA_MAX_MTU),
+    })
+}
+
+/// Reads the description of an interface from the payload of an
+/// `RTM_NEWLINK` message.
+fn link_info(link: &[u8]) -> Option<LinkInfo> {
+    if link.len() < IFINFOMSG_LEN {
+        return None;
+    }
+    let attrs = parse_attrs(&link[IFINFOMSG_LEN..]);
+    let attr = |kind| {
+        attrs
+            .iter()
+            .find(|&&(k, _)| k == kind)
+            .map(|&(_, data)| data)
+    };
+    let string = |data: &[u8]| {
+        let len = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+        String::from_utf8_lossy(&data[..len]).into_owned()
+    };
+    let kind = attr(IFLA_LINKINFO).and_then(|info| {
+        parse_attrs(info)
+            .into_iter()
+            .find(|&(kind, _)| kind == IFLA_INFO_KIND)
+            .map(|(_, data)| string(data))
+    });
+    let xdp_mode = attr(IFLA_XDP).and_then(|xdp| {
+        parse_attrs(xdp)
+            .into_iter()
+            .find(|&(kind, data)| kind == IFLA_XDP_ATTACHED && data.len() == 1)
+            .and_then(|(_, data)| XdpMode::from_attached(data[0]))
+    });
+
+    Some(LinkInfo {
+        ifindex: u32::from_ne_bytes([link[4], link[5], link[6], link[7]]),
+        name: string(attr(IFLA_IFNAME)?),
+        link_type: u16::from_ne_bytes([link[2], link[3]]),
+        flags: u32::from_ne_bytes([link[8], link[9], link[10], link[11]]),
+        mtu: frame_limits(link)?.mtu,
+        kind,
+        xdp_mode,
+        xdp_prog_id: attached_prog_id(link),
+    })
+}
+
+/// Reads the program id nested in the `IFLA_XDP` attribute of an
+/// `RTM_NEWLINK` message, which is 0 or missing if no program is attached.
+fn attached_prog_id(link: &[u8]) -> Option<u32> {
+    let (_, xdp) = parse_attrs(&link[IFINFOMSG_LEN..])
+        .into_iter()
+        .find(|&(kind, _)| kind == IFLA_XDP)?;
+    parse_attrs(xdp)
+        .into_iter()
+        .find(|&(kind, data)| kind == IFLA_XDP_PROG_ID && data.len() == 4)
+        .map(|(_, data)| u32::from_ne_bytes([data[0], data[1], data[2], data[3]]))
+        .filter(|&id| id != 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::load_program;
+    use crate::XdpFlags;
+
+    fn attr(kind: u16, value: u32) -> Vec<u8> {
+        let mut attr = attr_header(8, kind);
+        attr.extend_from_slice(&value.to_ne_bytes());
+        attr
+    }
+
+    fn attr_header(len: u16, kind: u16) -> Vec<u8> {
+        let mut header = len.to_ne_bytes().to_vec();
+        header.extend_from_slice(&kind.to_ne_bytes());
+        header
+    }
+
+    #[test]
+    fn test_frame_limits() {
+        let mut link = vec![0u8; IFINFOMSG_LEN];
+        assert_eq!(frame_limits(&link[..8]), None);
+        assert_eq!(frame_limits(&link), None);
+
+        link.extend_from_slice(&attr(IFLA_MTU, 1500));
+        assert_eq!(
+            frame_limits(&link),
+            Some(XdpFrameLimits {
+                headroom: 256,
+                mtu: 1500,
+                max_mtu: None,
+            })
+        );
+        link.extend_from_slice(&attr(IFLA_MAX_MTU, 3506));
+        assert_eq!(frame_limits(&link).unwrap().max_mtu, Some(3506));
+    }
+
+    #[test]
+    fn test_xdp_frame_limits() {
+        // the loopback device supports generic XDP
+        let limits = xdp_frame_limits(1).unwrap();
+        assert_eq!(limits.headroom, XDP_PACKET_HEADROOM);
+        assert!(limits.mtu > 0);
+        assert_eq!(xdp_max_headroom(1).unwrap(), XDP_PACKET_HEADROOM);
+
+        assert!(xdp_frame_limits(u32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_attached_prog_id() {
+        let mut link = vec![0u8; IFINFOMSG_LEN];
+        link.extend_from_slice(&attr(IFLA_MTU, 1500));
+        assert_eq!(attached_prog_id(&link), None);
+
+        // no program attached
+        let mut xdp = 12u16.to_ne_bytes().to_vec();
+        xdp.extend_from_slice(&(IFLA_XDP | 0x8000).to_ne_bytes());
+        xdp.extend_from_slice(&[5, 0, 2, 0, 0, 0, 0, 0]);
+        let mut empty = link.clone();
+        empty.extend_from_slice(&xdp);
+        assert_eq!(attached_prog_id(&empty), None);
+
+        xdp[0..2].copy_from_slice(&20u16.to_ne_bytes());
+        xdp.extend_from_slice(&attr(IFLA_XDP_PROG_ID, 42));
+        link.extend_from_slice(&xdp);
+        assert_eq!(attached_prog_id(&link), Some(42));
+    }
+
+    #[test]
+    fn test_link_info() {
+        let mut link = vec![0u8; IFINFOMSG_LEN];
+        link[2..4].copy_from_slice(&ARPHRD_ETHER.to_ne_bytes());
+        link[4..8].copy_from_slice(&7u32.to_ne_bytes());
+        link[8..12].copy_from_slice(&(IFF_UP | 0x1000).to_ne_bytes());
+        link.extend_from_slice(&attr(IFLA_MTU, 9000));
+        // the name is required
+        assert_eq!(link_info(&link), None);
+        link.extend_from_slice(&attr_header(10, IFLA_IFNAME));
+        link.extend_from_slice(b"eth10\0\0\0");
+
+        let info = link_info(&link).unwrap();
+        assert_eq!(info.ifindex, 7);
+        assert_eq!(info.name, "eth10");
+        assert_eq!(info.mtu, 9000);
+        assert_eq!(info.kind, None);
+        assert_eq!(info.xdp_mode, None);
+        assert_eq!(info.xdp_prog_id, None);
+        assert!(info.is_up() && info.is_physical() && !info.is_loopback());
+
+        // a veth with a program attached in generic mode
+        let mut veth = link.clone();
+        veth.extend_from_slice(&attr_header(16, IFLA_LINKINFO | 0x8000));
+        veth.extend_from_slice(&attr_header(9, IFLA_INFO_KIND));
+        veth.extend_from_slice(b"veth\0\0\0\0");
+        veth.extend_from_slice(&attr_header(20, IFLA_XDP | 0x8000));
+        veth.extend_from_slice(&attr_header(5, IFLA_XDP_ATTACHED));
+        veth.extend_from_slice(&[2, 0, 0, 0]);
+        veth.extend_from_slice(&attr(IFLA_XDP_PROG_ID, 42));
+        let info = link_info(&veth).unwrap();
+        assert_eq!(info.kind.as_deref(), Some("veth"));
+        assert!(!info.is_physical());
+        assert_eq!(info.xdp_mode, Some(XdpMode::Generic));
+        assert_eq!(info.xdp_prog_id, Some(42));
+    }
+
+    #[test]
+    fn test_list_links() {
+        let links = list_links().unwrap();
+        let lo = links.iter().find(|link| link.ifindex == 1).unwrap();
+        assert_eq!(lo.name, "lo");
+        assert!(lo.is_loopback());
+        assert!(!lo.is_physical());
+        assert!(lo.is_up());
+        assert_eq!(lo.mtu, xdp_frame_limits(1).unwrap().mtu);
+        assert_eq!(lo.kind, None);
+        assert_eq!(lo.xdp_prog_id, xdp_prog_id(1).unwrap());
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_xdp_reattach() {
+        if xdp_prog_id(1).unwrap().is_some() {
+            // the loopback device runs an XDP program already
+            return;
+        }
+        // r0 = XDP_PASS; exit
+        let code = [0xb7, 0, 0, 0, 2, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0];
+        let mut prog = load_program("xdp", "reattach", &code);
+        let id = prog.id().unwrap();
+        let flags = XdpFlags::SkbMode;
+        prog.attach_xdp("lo", flags).unwrap();
+        assert_eq!(xdp_prog_id(1).unwrap(), Some(id));
+
+        // the same program, every time it's attached
+        for _ in 0..2 {
+            prog.detach_xdp("lo").unwrap();
+            assert_eq!(xdp_prog_id(1).unwrap(), None);
+            assert!(prog.is_loaded());
+            assert!(!prog.is_attached());
+            assert!(prog.detach_xdp("lo").is_err());
+
+            prog.attach_xdp("lo", flags).unwrap();
+            assert_eq!(xdp_prog_id(1).unwrap(), Some(id));
+            assert_eq!(prog.id(), Some(id));
+        }
+        prog.detach().unwrap();
+        assert_eq!(xdp_prog_id(1).unwrap(), None);
+    }
+}
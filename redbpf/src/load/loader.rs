@@ -5,10 +5,8 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-use bpf_sys;
 use futures::channel::mpsc;
 use futures::prelude::*;
-use std::ffi::CString;
 use std::fs;
 use std::io;
 use std::path::PathBuf;
@@ -77,6 +75,11 @@ impl Loader {
                 .map_err(|e| LoaderError::KprobeError(prog.name.clone(), e))?;
             println!("Loaded: {}, {:?}", prog.name, prog.kind);
         }
+        for prog in module.programs.iter_mut().filter(|p| p.kind == Fentry) {
+            prog.attach_fentry()
+                .map_err(|e| LoaderError::KprobeError(prog.name.clone(), e))?;
+            println!("Loaded: {}, {:?}", prog.name, prog.kind);
+        }
         let online_cpus = cpus::get_online().unwrap();
         let (sender, receiver) = mpsc::unbounded();
         for m in module.maps.iter_mut().filter(|m| m.kind == 4) {
@@ -94,7 +97,7 @@ impl Loader {
         }
 
         Ok(Loaded {
-            xdp: self.xdp.clone(),
+            module,
             events: receiver
         })
     }
@@ -109,8 +112,10 @@ impl Loader {
 }
 
 /// The `Loaded` object returned by `load()`.
+///
+/// The programs stay attached until it's dropped.
 pub struct Loaded {
-    xdp: XdpConfig,
+    module: Module,
     /// The stream of events emitted by the BPF programs.
     ///
     /// # Example
@@ -127,12 +132,10 @@ pub struct Loaded {
     pub events: mpsc::UnboundedReceiver<(String, <PerfMessageStream as Stream>::Item)>,
 }
 
-impl Drop for Loaded {
-    fn drop(&mut self) {
-        if let Some(interface) = &self.xdp.interface {
-            let ciface = CString::new(interface.as_bytes()).unwrap();
-            let _ = unsafe { bpf_sys::bpf_attach_xdp(ciface.as_ptr(), -1, 0) };
-        }
+impl Loaded {
+    /// Returns the module the programs were loaded from, to access its maps.
+    pub fn module(&self) -> &Module {
+        &self.module
     }
 }
 
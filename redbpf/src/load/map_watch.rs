@@ -0,0 +1,120 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use futures::prelude::*;
+use std::collections::{HashSet, VecDeque};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::ptr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{interval, Interval};
+
+use crate::sys::bpf::map_get_next_key;
+use crate::Map;
+
+/// A change to the keys of a map, see `HashMap::watch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapEvent {
+    Inserted,
+    Deleted,
+}
+
+/// Stream of the keys inserted into and deleted from a map, found by
+/// comparing snapshots of its keys.
+pub struct MapWatcher<'a, K> {
+    map: &'a Map,
+    interval: Interval,
+    keys: HashSet<Vec<u8>>,
+    events: VecDeque<(Vec<u8>, MapEvent)>,
+    _k: PhantomData<fn() -> K>,
+}
+
+impl<'a, K: Copy> MapWatcher<'a, K> {
+    pub(crate) fn new(map: &'a Map, period: Duration) -> MapWatcher<'a, K> {
+        MapWatcher {
+            map,
+            interval: interval(period),
+            keys: snapshot(map),
+            events: VecDeque::new(),
+            _k: PhantomData,
+        }
+    }
+
+    fn diff(&mut self) {
+        let keys = snapshot(self.map);
+        for key in keys.difference(&self.keys) {
+            self.events.push_back((key.clone(), MapEvent::Inserted));
+        }
+        for key in self.keys.difference(&keys) {
+            self.events.push_back((key.clone(), MapEvent::Deleted));
+        }
+        self.keys = keys;
+    }
+}
+
+impl<K: Copy> Stream for MapWatcher<'_, K> {
+    type Item = (K, MapEvent);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some((key, event)) = self.events.pop_front() {
+                let key = unsafe { ptr::read_unaligned(key.as_ptr() as *const K) };
+                return Poll::Ready(Some((key, event)));
+            }
+            if let Poll::Pending = self.interval.poll_tick(cx) {
+                return Poll::Pending;
+            }
+            self.diff();
+        }
+    }
+}
+
+/// Returns the keys currently in `map`.
+fn snapshot(map: &Map) -> HashSet<Vec<u8>> {
+    let mut keys = HashSet::new();
+    let mut key = vec![];
+    let mut next = vec![0u8; map.config.key_size as usize];
+    // the iteration starts over when the current key is deleted meanwhile,
+    // so it is cut short on busy maps
+    for _ in 0..2 * map.config.max_entries {
+        let prev = if key.is_empty() {
+            ptr::null()
+        } else {
+            key.as_ptr()
+        };
+        if unsafe { map_get_next_key(map.fd, prev, next.as_mut_ptr()) }.is_err() {
+            break;
+        }
+        keys.insert(next.clone());
+        key.clone_from(&next);
+    }
+
+    keys
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::create_map;
+    use crate::HashMap;
+
+    #[tokio::test]
+    #[ignore = "needs root"]
+    async fn test_watch() {
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH;
+        let map = create_map("watched", type_, 4, 8, 16);
+        let hash = HashMap::<u32, u64>::new(&map).unwrap();
+        hash.set(1, 10).unwrap();
+        let mut events = hash.watch(Duration::from_millis(10));
+
+        hash.set(7, 70).unwrap();
+        assert_eq!(events.next().await, Some((7, MapEvent::Inserted)));
+        hash.delete(1);
+        assert_eq!(events.next().await, Some((1, MapEvent::Deleted)));
+    }
+}
@@ -1,4 +1,6 @@
 pub mod map_io;
 mod loader;
+mod map_watch;
 
-pub use loader::*;
\ No newline at end of file
+pub use loader::*;
+pub use map_watch::{MapEvent, MapWatcher};
@@ -0,0 +1,308 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Loading and attaching in one call
+//!
+//! Most tools parse a module, pick some of its programs, load them and
+//! attach each of them somewhere, then keep the module around until they
+//! exit. Spelled out, a firewall with its blocklist sized for the machine
+//! it runs on, and shared with other modules through a pin, reads:
+//!
+//! ```no_run
+//! use redbpf::{Module, XdpFlags};
+//! use std::path::Path;
+//!
+//! let code = std::fs::read("firewall.elf").unwrap();
+//! let dir = Path::new("/sys/fs/bpf/firewall");
+//! let mut module = Module::parse_pinned(&code, dir).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.set_autoload(prog.name == "firewall");
+//! }
+//! module.load().unwrap();
+//! let prog = module
+//!     .programs
+//!     .iter_mut()
+//!     .find(|prog| prog.name == "firewall")
+//!     .unwrap();
+//! prog.attach_xdp("eth0", XdpFlags::SkbMode).unwrap();
+//! ```
+//!
+//! which, on top of that, can't resize the blocklist, as maps are created
+//! while the module is parsed. With `Loader`:
+//!
+//! ```no_run
+//! use redbpf::{Loader, XdpFlags};
+//!
+//! let module = Loader::new()
+//!     .pin_maps("/sys/fs/bpf/firewall")
+//!     .max_entries("blocklist", 1 << 20)
+//!     .program("firewall")
+//!     .attach_xdp("eth0")
+//!     .xdp_flags(XdpFlags::SkbMode)
+//!     .load_and_attach_file("firewall.elf")
+//!     .unwrap();
+//! ```
+//!
+//! The returned `Module` is the handle to the programs and maps: dropping it
+//! detaches and unloads the programs, and closes the maps.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ProgramKind::*;
+use crate::{Module, Program, ProgramKind, Result, XdpFlags, PIN_BY_NAME_DIR};
+
+/// Parses, loads and attaches a module, as configured with its builder
+/// methods.
+///
+/// `program` selects a program to load, and the `attach_*` methods that
+/// follow it attach that program. Until a program is selected, all programs
+/// are loaded, and the `attach_*` methods attach all programs of their kind.
+#[derive(Debug, Clone, Default)]
+pub struct Loader {
+    programs: Vec<String>,
+    /// Where to attach the program they name, or the programs of the kind
+    /// of the target if none, in the order they were configured.
+    attachments: Vec<(Option<String>, Target)>,
+    max_entries: Vec<(String, u32)>,
+    pin_dir: Option<PathBuf>,
+    xdp_flags: XdpFlags,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Xdp(String),
+    Probe(Option<String>),
+    Tracepoint(String, String),
+    Fentry,
+    SocketFilter(String),
+}
+
+impl Loader {
+    /// Creates a loader that loads all programs, and attaches none.
+    pub fn new() -> Loader {
+        Loader::default()
+    }
+
+    /// Selects the program `name` to be loaded, and attached by the
+    /// `attach_*` methods called next. Programs that aren't selected aren't
+    /// loaded.
+    pub fn program(&mut self, name: &str) -> &mut Self {
+        self.programs.push(name.to_string());
+        self
+    }
+
+    /// Attaches to the interface `iface`, with the flags set with
+    /// `xdp_flags`.
+    pub fn attach_xdp(&mut self, iface: &str) -> &mut Self {
+        self.attach(Target::Xdp(iface.to_string()))
+    }
+
+    /// Sets the flags XDP programs are attached with, `XdpFlags::Unset` by
+    /// default.
+    pub fn xdp_flags(&mut self, flags: XdpFlags) -> &mut Self {
+        self.xdp_flags = flags;
+        self
+    }
+
+    /// Attaches kprobes and kretprobes to the function they are named after.
+    pub fn attach_probe(&mut self) -> &mut Self {
+        self.attach(Target::Probe(None))
+    }
+
+    /// Attaches kprobes and kretprobes to the function `name`, see
+    /// `Program::attach_probe_to_name`.
+    pub fn attach_probe_to(&mut self, name: &str) -> &mut Self {
+        self.attach(Target::Probe(Some(name.to_string())))
+    }
+
+    /// Attaches to the tracepoint `category:name`.
+    pub fn attach_tracepoint(&mut self, category: &str, name: &str) -> &mut Self {
+        self.attach(Target::Tracepoint(category.to_string(), name.to_string()))
+    }
+
+    /// Attaches fentry programs to the function they were loaded for.
+    pub fn attach_fentry(&mut self) -> &mut Self {
+        self.attach(Target::Fentry)
+    }
+
+    /// Attaches socket filters to a raw socket bound to the interface
+    /// `iface`.
+    pub fn attach_socketfilter(&mut self, iface: &str) -> &mut Self {
+        self.attach(Target::SocketFilter(iface.to_string()))
+    }
+
+    /// Creates the map `map` with `entries` entries, rather than the number
+    /// it declares, e.g. to size it for the machine the module runs on.
+    pub fn max_entries(&mut self, map: &str, entries: u32) -> &mut Self {
+        self.max_entries.push((map.to_string(), entries));
+        self
+    }
+
+    /// Shares the maps declared with `pinning = by_name` through pins in
+    /// `dir`, rather than in `/sys/fs/bpf`. See `Module::parse_pinned`.
+    pub fn pin_maps<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.pin_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Parses the module in `code`, creating its maps, then loads the
+    /// selected programs, and attaches them in the order the attachments
+    /// were configured.
+    ///
+    /// Fails with `LoadError::Section` if a selected program isn't in the
+    /// module, and with `LoadError::UnknownMap` if a map given a size isn't,
+    /// before any map is created. Whatever was loaded and attached before a
+    /// failure is torn down.
+    pub fn load_and_attach(&self, code: &[u8]) -> Result<Module> {
+        let pin_dir = match &self.pin_dir {
+            Some(dir) => dir.as_path(),
+            None => Path::new(PIN_BY_NAME_DIR),
+        };
+        let mut module = Module::parse_with(code, None, pin_dir, &self.max_entries)?;
+
+        if !self.programs.is_empty() {
+            for prog in module.programs.iter_mut() {
+                prog.set_autoload(false);
+            }
+            for name in self.programs.iter() {
+                module.set_autoload(name, true)?;
+            }
+        }
+        module.load()?;
+
+        for (name, target) in self.attachments.iter() {
+            let selected = module.programs.iter_mut().filter(|prog| match name {
+                Some(name) => prog.name == *name,
+                None => prog.is_loaded() && target.attaches(prog.kind),
+            });
+            for prog in selected {
+                target.attach(prog, self.xdp_flags)?;
+            }
+        }
+
+        Ok(module)
+    }
+
+    /// Loads and attaches the module in `file`, see `load_and_attach`.
+    pub fn load_and_attach_file<P: AsRef<Path>>(&self, file: P) -> Result<Module> {
+        self.load_and_attach(&fs::read(file)?)
+    }
+
+    fn attach(&mut self, target: Target) -> &mut Self {
+        let name = self.programs.last().cloned();
+        self.attachments.push((name, target));
+        self
+    }
+}
+
+impl Target {
+    /// Whether programs of kind `kind` can be attached to the target.
+    fn attaches(&self, kind: ProgramKind) -> bool {
+        match self {
+            Target::Xdp(_) => kind == XDP,
+            Target::Probe(_) => kind == Kprobe || kind == Kretprobe,
+            Target::Tracepoint(..) => kind == Tracepoint,
+            Target::Fentry => kind == Fentry,
+            Target::SocketFilter(_) => kind == SocketFilter,
+        }
+    }
+
+    fn attach(&self, prog: &mut Program, xdp_flags: XdpFlags) -> Result<()> {
+        let fd = match self {
+            Target::Xdp(iface) => return prog.attach_xdp(iface, xdp_flags),
+            Target::Probe(None) => prog.attach_probe(),
+            Target::Probe(Some(name)) => prog.attach_probe_to_name(name),
+            Target::Tracepoint(category, name) => prog.attach_tracepoint(category, name),
+            Target::Fentry => prog.attach_fentry(),
+            Target::SocketFilter(iface) => prog.attach_socketfilter(iface),
+        };
+        fd.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::in_netns;
+    use crate::{btf, xdp_prog_id, LoadError};
+
+    #[test]
+    fn test_attachments() {
+        let mut loader = Loader::new();
+        loader
+            .attach_probe()
+            .program("count_packets")
+            .attach_xdp("eth0")
+            .attach_xdp("eth1")
+            .program("trace_open")
+            .attach_probe_to("do_sys_open");
+        let targets: Vec<(Option<&str>, bool)> = loader
+            .attachments
+            .iter()
+            .map(|(name, target)| (name.as_deref(), target.attaches(XDP)))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (None, false),
+                (Some("count_packets"), true),
+                (Some("count_packets"), true),
+                (Some("trace_open"), false),
+            ]
+        );
+        assert_eq!(loader.programs, ["count_packets", "trace_open"]);
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_load_and_attach_xdp() {
+        in_netns(|| {
+            let object = crate::test::libbpf_object(&btf::test::maps_section());
+            let dir = PathBuf::from(format!(
+                "/sys/fs/bpf/redbpf_loader_test_{}",
+                std::process::id()
+            ));
+            let mut loader = Loader::new();
+            loader.pin_maps(&dir).max_entries("counts", 64);
+
+            // unknown maps are found before any map is pinned
+            let unknown_map = loader
+                .clone()
+                .max_entries("sizes", 64)
+                .load_and_attach(&object);
+            match unknown_map {
+                Err(LoadError::UnknownMap(name)) => assert_eq!(name, "sizes"),
+                res => panic!("unexpected result {:?}", res.map(|_| ())),
+            }
+            assert!(!dir.exists());
+            let missing = loader
+                .clone()
+                .program("count_bytes")
+                .load_and_attach(&object);
+            match missing {
+                Err(LoadError::Section(name)) => assert_eq!(name, "count_bytes"),
+                res => panic!("unexpected result {:?}", res.map(|_| ())),
+            }
+
+            let module = loader
+                .program("count_packets")
+                .attach_xdp("lo")
+                .xdp_flags(XdpFlags::SkbMode)
+                .load_and_attach(&object)
+                .unwrap();
+            assert_eq!(module.maps[0].config.max_entries, 64);
+            let prog = &module.programs[0];
+            assert!(prog.is_attached());
+            assert_eq!(xdp_prog_id(1).unwrap(), prog.id());
+
+            drop(module);
+            assert_eq!(xdp_prog_id(1).unwrap(), None);
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+}
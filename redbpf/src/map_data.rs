@@ -0,0 +1,217 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # XDP samples
+//!
+//! XDP programs export packets as a `redbpf_probes::xdp::MapData<T>`: the
+//! `data` the program filled in, followed by the start of the packet.
+//! `MapData` splits the samples read from the map back into the two, checking
+//! that the sample holds as much of the packet as the program asked for:
+//!
+//! ```no_run
+//! use redbpf::{Event, MapData, Module, PerfMap};
+//! use std::slice;
+//!
+//! /// The metadata the program exports along with the packet.
+//! #[repr(C)]
+//! #[derive(Clone, Copy)]
+//! struct Capture {
+//!     ifindex: u32,
+//!     len: u32,
+//! }
+//!
+//! let code = std::fs::read("capture.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter_mut().find(|m| m.name == "packets").unwrap();
+//! let perfmap = PerfMap::bind(map, -1, 0, 16, -1, 0).unwrap();
+//! while let Some(Event::Sample(sample)) = perfmap.read() {
+//!     let sample = unsafe { slice::from_raw_parts(sample.data.as_ptr(), sample.size as usize) };
+//!     // any bytes make a valid `Capture`
+//!     let event = match unsafe { MapData::<Capture>::parse(sample) } {
+//!         Some(event) => event,
+//!         None => continue,
+//!     };
+//!     let capture = event.data();
+//!     println!(
+//!         "{} of {} bytes on {}: {:02x?}",
+//!         event.packet().len(),
+//!         capture.len,
+//!         capture.ifindex,
+//!         event.payload()
+//!     );
+//! }
+//! ```
+
+use std::mem;
+
+/// The start of the samples `redbpf_probes::xdp::MapData<T>` is output as,
+/// ahead of the packet.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct MapDataHeader<T> {
+    pub(crate) data: T,
+    pub(crate) offset: u32,
+    pub(crate) size: u32,
+}
+
+/// A `MapData<T>` sample read from a map, with its packet bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct MapData<'a, T> {
+    data: T,
+    offset: usize,
+    packet: &'a [u8],
+}
+
+impl<'a, T: Copy> MapData<'a, T> {
+    /// Splits `sample` into the data and the packet.
+    ///
+    /// Returns `None` if `sample` is too short to hold the data and the
+    /// packet bytes, or if the payload offset lies past the packet. The
+    /// padding perf adds to samples is left out.
+    ///
+    /// `T` must be laid out the same as in the program, which `#[repr(C)]`
+    /// on both sides ensures.
+    ///
+    /// # Safety
+    ///
+    /// The data is read from the bytes of `sample`, which the program, or
+    /// whatever else wrote to the map, chose. Any bytes must make a valid
+    /// `T`, which rules out `bool`, enums, references and the like.
+    pub unsafe fn parse(sample: &'a [u8]) -> Option<MapData<'a, T>> {
+        let start = mem::size_of::<MapDataHeader<T>>();
+        if sample.len() < start {
+            return None;
+        }
+        let header = (sample.as_ptr() as *const MapDataHeader<T>).read_unaligned();
+        let size = header.size as usize;
+        let offset = header.offset as usize;
+        if offset > size {
+            return None;
+        }
+        let packet = sample[start..].get(..size)?;
+
+        Some(MapData {
+            data: header.data,
+            offset,
+            packet,
+        })
+    }
+
+    /// Returns the data the program filled in.
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    /// Returns the packet bytes exported, from the start of the packet.
+    pub fn packet(&self) -> &'a [u8] {
+        self.packet
+    }
+
+    /// Returns the packet bytes past the offset the program set, like
+    /// `redbpf_probes::xdp::MapData::payload()` does.
+    pub fn payload(&self) -> &'a [u8] {
+        &self.packet[self.offset..]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::slice;
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Capture {
+        ifindex: u32,
+        len: u16,
+        proto: u8,
+    }
+
+    /// `redbpf_probes::xdp::MapData<Capture>` as the program outputs it.
+    #[repr(C)]
+    struct ProbeMapData {
+        data: Capture,
+        offset: u32,
+        size: u32,
+        payload: [u8; 0],
+    }
+
+    fn parse(sample: &[u8]) -> Option<MapData<'_, Capture>> {
+        // any bytes make a valid `Capture`
+        unsafe { MapData::parse(sample) }
+    }
+
+    /// Lays out a sample the way perf does, the packet following the
+    /// `MapData`, and padded to 8 bytes with the size in front.
+    fn sample(data: Capture, offset: u32, packet: &[u8]) -> Vec<u8> {
+        let map_data = ProbeMapData {
+            data,
+            offset,
+            size: packet.len() as u32,
+            payload: [],
+        };
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                &map_data as *const _ as *const u8,
+                mem::size_of::<ProbeMapData>(),
+            )
+        };
+        let mut sample = bytes.to_vec();
+        sample.extend_from_slice(packet);
+        while (sample.len() + 4) % 8 != 0 {
+            sample.push(0);
+        }
+        sample
+    }
+
+    #[test]
+    fn test_map_data() {
+        let capture = Capture {
+            ifindex: 2,
+            len: 1500,
+            proto: 17,
+        };
+        let packet = (0..42).collect::<Vec<u8>>();
+        let sample = sample(capture, 14, &packet);
+        let event = parse(&sample).unwrap();
+        assert_eq!(*event.data(), capture);
+        assert_eq!(event.packet(), &packet[..]);
+        assert_eq!(event.payload(), &packet[14..]);
+
+        // the sample, unaligned
+        let mut unaligned = vec![0];
+        unaligned.extend_from_slice(&sample);
+        let event = parse(&unaligned[1..]).unwrap();
+        assert_eq!(*event.data(), capture);
+        assert_eq!(event.payload(), &packet[14..]);
+
+        let sample = self::sample(capture, 0, &[]);
+        let event = parse(&sample).unwrap();
+        assert!(event.packet().is_empty());
+        assert!(event.payload().is_empty());
+    }
+
+    #[test]
+    fn test_map_data_bounds() {
+        let capture = Capture {
+            ifindex: 2,
+            len: 60,
+            proto: 6,
+        };
+        let packet = [0xaa; 60];
+        let header = mem::size_of::<MapDataHeader<Capture>>();
+        let sample = sample(capture, 14, &packet);
+        assert!(parse(&sample[..header - 1]).is_none());
+        // the packet bytes are cut short
+        assert!(parse(&sample[..header + 59]).is_none());
+        assert!(parse(&sample[..header + 60]).is_some());
+
+        // the offset lies past the packet
+        let sample = self::sample(capture, 61, &packet);
+        assert!(parse(&sample).is_none());
+    }
+}
@@ -0,0 +1,1052 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Typed maps
+//!
+//! Typed views over the maps of a loaded module. The key and value types
+//! must have the same layout as the ones used by the BPF program, and are
+//! checked against the sizes the map was created with.
+//!
+//! Updating a single field of a map value, such as a threshold in a
+//! configuration struct, doesn't need the rest of the value:
+//!
+//! ```no_run
+//! use redbpf::{HashMap, Module};
+//! use std::mem;
+//!
+//! #[repr(C)]
+//! #[derive(Clone, Copy)]
+//! struct Config {
+//!     enabled: u32,
+//!     threshold: u32,
+//! }
+//!
+//! let code = std::fs::read("bpf.elf").unwrap();
+//! let module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter().find(|m| m.name == "config").unwrap();
+//!
+//! let config = HashMap::<u32, Config>::new(map).unwrap();
+//! let offset = mem::size_of::<u32>();
+//! config.update_field(0, offset, &500u32.to_ne_bytes()).unwrap();
+//! ```
+//!
+//! Per-CPU maps hold a copy of each value for every CPU, which are read all
+//! at once:
+//!
+//! ```no_run
+//! # use redbpf::{Module, PerCpuArray};
+//! # let code = std::fs::read("bpf.elf").unwrap();
+//! # let module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter().find(|m| m.name == "packets").unwrap();
+//! let packets = PerCpuArray::<u64>::new(map).unwrap();
+//! let total: u64 = packets.get_per_cpu(0).iter().sum();
+//! ```
+//!
+//! Maps keyed by IP address use `IpKey`, which has the same byte order as
+//! `redbpf_probes::byteorder::IpKey` on the probe side:
+//!
+//! ```no_run
+//! # use redbpf::{HashMap, IpKey, Module};
+//! # use std::net::Ipv4Addr;
+//! # let code = std::fs::read("bpf.elf").unwrap();
+//! # let module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter().find(|m| m.name == "blocklist").unwrap();
+//! let blocklist = HashMap::<IpKey, u8>::new(map).unwrap();
+//! blocklist.set(Ipv4Addr::new(192, 168, 0, 1).into(), 1).unwrap();
+//! ```
+//!
+//! Values that programs update under a `bpf_spin_lock` must be read under
+//! the lock as well, or a read may see one field before and another after
+//! an update. `HashMap::iter_locked` takes the lock for each value:
+//!
+//! ```no_run
+//! # use redbpf::{HashMap, Module};
+//! # let code = std::fs::read("bpf.elf").unwrap();
+//! # let module = Module::parse(&code).unwrap();
+//! #[repr(C)]
+//! #[derive(Clone, Copy)]
+//! struct Average {
+//!     lock: u32,
+//!     sum: u64,
+//!     count: u64,
+//! }
+//!
+//! let map = module.maps.iter().find(|m| m.name == "latencies").unwrap();
+//! let latencies = HashMap::<u32, Average>::new(map).unwrap();
+//! for entry in latencies.iter_locked() {
+//!     let (pid, avg) = entry.unwrap();
+//!     // sum and count are from the same update
+//!     println!("{}: {}", pid, avg.sum / avg.count.max(1));
+//! }
+//! ```
+//!
+//! XDP programs redirecting packets to other CPUs through a `CPUMAP` queue
+//! them on the target CPU, which processes them in batches. The `qsize` of
+//! each CPU's entry bounds its queue: a large queue absorbs bursts and lets
+//! the target CPU handle more packets at once, for throughput, while a small
+//! queue drops packets early rather than letting them wait, for latency.
+//! The queues are flushed at the end of each NAPI poll, which can't be
+//! tuned. Keeping the queues short for latency sensitive traffic:
+//!
+//! ```no_run
+//! # use redbpf::{CpuMap, Module};
+//! # let code = std::fs::read("bpf.elf").unwrap();
+//! # let module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter().find(|m| m.name == "cpus").unwrap();
+//! let cpus = CpuMap::new(map).unwrap();
+//! for cpu in 2..4 {
+//!     cpus.set_qsize(cpu, 32).unwrap();
+//! }
+//! ```
+
+#[cfg(feature = "load")]
+use crate::load::MapWatcher;
+use crate::sys::bpf::{map_delete_elem, map_get_next_key, map_lookup_elem, map_update_elem};
+use crate::{cpus, LoadError, Map, Result};
+use bpf_sys::{BPF_EXIST, BPF_F_LOCK};
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ptr;
+#[cfg(feature = "load")]
+use std::time::Duration;
+
+/// Typed view of `BPF_MAP_TYPE_HASH` and `BPF_MAP_TYPE_LRU_HASH` maps.
+pub struct HashMap<'a, K, V> {
+    base: &'a Map,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, K: Copy, V: Copy> HashMap<'a, K, V> {
+    pub fn new(base: &'a Map) -> Result<HashMap<'a, K, V>> {
+        check_map(
+            base,
+            &[
+                bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH,
+                bpf_sys::bpf_map_type_BPF_MAP_TYPE_LRU_HASH,
+            ],
+            mem::size_of::<K>(),
+            mem::size_of::<V>(),
+        )?;
+
+        Ok(HashMap {
+            base,
+            _k: PhantomData,
+            _v: PhantomData,
+        })
+    }
+
+    pub fn set(&self, key: K, value: V) -> Result<()> {
+        unsafe {
+            map_update_elem(self.base.fd, as_bytes(&key), as_bytes(&value), 0)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        let mut value = mem::MaybeUninit::<V>::uninit();
+        unsafe {
+            map_lookup_elem(self.base.fd, as_bytes(&key), value.as_mut_ptr() as *mut u8, 0).ok()?;
+            Some(value.assume_init())
+        }
+    }
+
+    pub fn delete(&self, key: K) {
+        unsafe {
+            let _ = map_delete_elem(self.base.fd, as_bytes(&key));
+        }
+    }
+
+    /// Overwrites the value of `key` with `bytes`, starting `offset` bytes
+    /// into the value, and leaves the rest of the value intact.
+    ///
+    /// The value is read, patched and written back. If the value holds a
+    /// `bpf_spin_lock`, which requires the map to be created with BTF, both
+    /// copies are taken under the lock through `BPF_F_LOCK` (kernel 5.1 or
+    /// later), so neither sees a value that a program is halfway through
+    /// updating. Otherwise fields written concurrently by programs may be
+    /// torn.
+    ///
+    /// Either way, the update isn't atomic: changes programs make to other
+    /// fields between the read and the write are lost. The fields patched
+    /// from userspace should therefore not be written by programs, as is
+    /// usually the case for configuration.
+    pub fn update_field(&self, key: K, offset: usize, bytes: &[u8]) -> Result<()> {
+        let mut value = vec![0u8; mem::size_of::<V>()];
+        let key = as_bytes(&key);
+        let mut flags = u64::from(BPF_F_LOCK);
+        unsafe {
+            if let Err(e) = map_lookup_elem(self.base.fd, key, value.as_mut_ptr(), flags) {
+                // the value has no lock, or the kernel doesn't know the flag
+                if e.raw_os_error() != Some(libc::EINVAL) {
+                    return Err(e.into());
+                }
+                flags = 0;
+                map_lookup_elem(self.base.fd, key, value.as_mut_ptr(), flags)?;
+            }
+            let size = value.len();
+            patch_values(&mut value, size, size, offset, bytes)?;
+            map_update_elem(self.base.fd, key, value.as_ptr(), flags | u64::from(BPF_EXIST))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over the entries of the map, whose values are read
+    /// under their `bpf_spin_lock` through `BPF_F_LOCK` (kernel 5.1 or
+    /// later).
+    ///
+    /// Each value is a consistent snapshot, but the map as a whole isn't:
+    /// entries may change while the iteration goes on. Entries deleted
+    /// meanwhile are skipped, and when the current key is deleted, the
+    /// iteration starts over, so entries can be seen twice. Fails with
+    /// `EINVAL` if the values hold no lock, which requires the map to be
+    /// created with BTF, and with `EAGAIN` if the map keeps changing so much
+    /// that the iteration doesn't get to its end.
+    ///
+    /// The iteration ends after the first error.
+    pub fn iter_locked(&self) -> LockedIter<'a, K, V> {
+        LockedIter {
+            map: self.base,
+            key: None,
+            remaining: 2 * self.base.config.max_entries,
+            done: false,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns a stream of the keys inserted into and deleted from the map,
+    /// which is polled for changes every `period`.
+    ///
+    /// The kernel doesn't notify about changes to maps, so the keys of the
+    /// map are compared to the ones seen last time. Keys present when
+    /// watching starts aren't reported, and neither are keys inserted and
+    /// deleted again between two polls. The stream needs a tokio runtime.
+    ///
+    /// Reacting to new connections tracked by a program:
+    ///
+    /// ```no_run
+    /// use futures::prelude::*;
+    /// use redbpf::load::MapEvent;
+    /// use redbpf::{HashMap, Module};
+    /// use std::time::Duration;
+    ///
+    /// #[repr(C)]
+    /// #[derive(Clone, Copy, Debug)]
+    /// struct Connection {
+    ///     saddr: u32,
+    ///     daddr: u32,
+    ///     sport: u16,
+    ///     dport: u16,
+    /// }
+    ///
+    /// # async fn run() {
+    /// let code = std::fs::read("conntrack.elf").unwrap();
+    /// let module = Module::parse(&code).unwrap();
+    /// let map = module.maps.iter().find(|m| m.name == "connections").unwrap();
+    /// let connections = HashMap::<Connection, u64>::new(map).unwrap();
+    ///
+    /// let mut events = connections.watch(Duration::from_secs(1));
+    /// while let Some((conn, event)) = events.next().await {
+    ///     if event == MapEvent::Inserted {
+    ///         println!("new connection {:?}", conn);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "load")]
+    pub fn watch(&self, period: Duration) -> MapWatcher<'a, K> {
+        MapWatcher::new(self.base, period)
+    }
+}
+
+/// Iterator over the entries of a `HashMap`, see `HashMap::iter_locked`.
+pub struct LockedIter<'a, K, V> {
+    map: &'a Map,
+    key: Option<K>,
+    /// Bounds the restarts on busy maps.
+    remaining: u32,
+    done: bool,
+    _v: PhantomData<V>,
+}
+
+impl<K: Copy, V: Copy> Iterator for LockedIter<'_, K, V> {
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            let prev = self.key.as_ref().map_or(ptr::null(), as_bytes);
+            let mut key = mem::MaybeUninit::<K>::uninit();
+            let mut value = mem::MaybeUninit::<V>::uninit();
+            let next = unsafe { map_get_next_key(self.map.fd, prev, key.as_mut_ptr() as *mut u8) };
+            let key = match next {
+                Ok(_) => unsafe { key.assume_init() },
+                // past the last key
+                Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            };
+            self.key = Some(key);
+
+            let flags = u64::from(BPF_F_LOCK);
+            let ptr = value.as_mut_ptr() as *mut u8;
+            match unsafe { map_lookup_elem(self.map.fd, as_bytes(&key), ptr, flags) } {
+                Ok(_) => return Some(Ok((key, unsafe { value.assume_init() }))),
+                // deleted since its key was read
+                Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+
+        // restarted too often to get through the map
+        self.done = true;
+        Some(Err(io::Error::from_raw_os_error(libc::EAGAIN).into()))
+    }
+}
+
+/// Typed view of `BPF_MAP_TYPE_PERCPU_HASH` and
+/// `BPF_MAP_TYPE_LRU_PERCPU_HASH` maps.
+///
+/// Each CPU has its own copy of every value, which programs update without
+/// synchronization since they only ever access the copy of the CPU they run
+/// on.
+pub struct PerCpuHashMap<'a, K, V> {
+    base: &'a Map,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, K: Copy, V: Copy> PerCpuHashMap<'a, K, V> {
+    pub fn new(base: &'a Map) -> Result<PerCpuHashMap<'a, K, V>> {
+        check_map(
+            base,
+            &[
+                bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_HASH,
+                bpf_sys::bpf_map_type_BPF_MAP_TYPE_LRU_PERCPU_HASH,
+            ],
+            mem::size_of::<K>(),
+            mem::size_of::<V>(),
+        )?;
+
+        Ok(PerCpuHashMap {
+            base,
+            _k: PhantomData,
+            _v: PhantomData,
+        })
+    }
+
+    /// Returns the values of `key` on all possible CPUs, indexed by CPU id.
+    pub fn get_per_cpu(&self, key: K) -> Option<Vec<V>> {
+        lookup_per_cpu(self.base, as_bytes(&key))
+    }
+
+    /// Zeroes the value of `key` on all CPUs. Fails if `key` isn't in the
+    /// map, rather than adding it.
+    ///
+    /// Updates made between reading the values and resetting them are lost,
+    /// see `PerCpuArray::reset`.
+    pub fn reset(&self, key: K) -> Result<()> {
+        reset_per_cpu::<V>(self.base, as_bytes(&key), u64::from(BPF_EXIST))
+    }
+
+    /// Overwrites the value of `key` with `bytes` on every CPU, starting
+    /// `offset` bytes into the value, and leaves the rest of the values
+    /// intact.
+    ///
+    /// No lock is taken: the copies of all CPUs are read, patched and
+    /// written back. Since programs only write the copy of their own CPU,
+    /// this is safe for fields programs only read, such as per-CPU
+    /// configuration. Changes programs make to other fields between the read
+    /// and the write are lost.
+    pub fn update_field(&self, key: K, offset: usize, bytes: &[u8]) -> Result<()> {
+        let stride = per_cpu_stride::<V>();
+        let mut values = vec![0u8; stride * cpus::get_possible()?.len()];
+        let key = as_bytes(&key);
+        unsafe {
+            map_lookup_elem(self.base.fd, key, values.as_mut_ptr(), 0)?;
+            patch_values(&mut values, mem::size_of::<V>(), stride, offset, bytes)?;
+            map_update_elem(self.base.fd, key, values.as_ptr(), u64::from(BPF_EXIST))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Typed view of the per-CPU counts kept by `redbpf_probes::maps::Counter`,
+/// in `BPF_MAP_TYPE_PERCPU_HASH` maps of `u64` values.
+///
+/// Reading packet counts by source address:
+///
+/// ```no_run
+/// use redbpf::{Counter, Module};
+/// use std::net::Ipv4Addr;
+///
+/// let code = std::fs::read("packets.elf").unwrap();
+/// let module = Module::parse(&code).unwrap();
+/// let map = module.maps.iter().find(|m| m.name == "packets").unwrap();
+/// let packets = Counter::<u32>::new(map).unwrap();
+/// // the address is in network byte order, as in the IP header
+/// let addr = u32::from_ne_bytes(Ipv4Addr::new(10, 0, 0, 1).octets());
+/// println!("{} packets", packets.get(addr));
+/// ```
+pub struct Counter<'a, K> {
+    map: PerCpuHashMap<'a, K, u64>,
+}
+
+impl<'a, K: Copy> Counter<'a, K> {
+    pub fn new(base: &'a Map) -> Result<Counter<'a, K>> {
+        Ok(Counter {
+            map: PerCpuHashMap::new(base)?,
+        })
+    }
+
+    /// Returns the count of `key` summed over all CPUs, or 0 if `key` was
+    /// never counted.
+    pub fn get(&self, key: K) -> u64 {
+        self.map
+            .get_per_cpu(key)
+            .map_or(0, |counts| counts.iter().sum())
+    }
+
+    /// Sets the count of `key` back to 0 on all CPUs, see
+    /// `PerCpuHashMap::reset`.
+    pub fn reset(&self, key: K) -> Result<()> {
+        self.map.reset(key)
+    }
+}
+
+/// An IPv4 or IPv6 address to key maps with.
+///
+/// The address is stored as the 16 bytes of an IPv6 address in network
+/// byte order, with IPv4 addresses mapped to `::ffff:a.b.c.d`, the same as
+/// the keys probes build from header fields with
+/// `redbpf_probes::byteorder::IpKey`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpKey {
+    addr: [u8; 16],
+}
+
+impl IpKey {
+    /// Returns the address of the key, IPv4 addresses being unmapped.
+    pub fn addr(&self) -> IpAddr {
+        let addr = Ipv6Addr::from(self.addr);
+        match addr.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => {
+                IpAddr::V4(Ipv4Addr::from(u32::from(hi) << 16 | u32::from(lo)))
+            }
+            _ => IpAddr::V6(addr),
+        }
+    }
+
+    /// Returns the address as IPv6 bytes in network byte order.
+    pub fn octets(&self) -> [u8; 16] {
+        self.addr
+    }
+}
+
+impl From<Ipv4Addr> for IpKey {
+    fn from(addr: Ipv4Addr) -> IpKey {
+        IpKey {
+            addr: addr.to_ipv6_mapped().octets(),
+        }
+    }
+}
+
+impl From<Ipv6Addr> for IpKey {
+    fn from(addr: Ipv6Addr) -> IpKey {
+        IpKey {
+            addr: addr.octets(),
+        }
+    }
+}
+
+impl From<IpAddr> for IpKey {
+    fn from(addr: IpAddr) -> IpKey {
+        match addr {
+            IpAddr::V4(addr) => addr.into(),
+            IpAddr::V6(addr) => addr.into(),
+        }
+    }
+}
+
+/// Typed view of `BPF_MAP_TYPE_PERCPU_ARRAY` maps.
+pub struct PerCpuArray<'a, V> {
+    base: &'a Map,
+    _v: PhantomData<V>,
+}
+
+impl<'a, V: Copy> PerCpuArray<'a, V> {
+    pub fn new(base: &'a Map) -> Result<PerCpuArray<'a, V>> {
+        check_map(
+            base,
+            &[bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY],
+            mem::size_of::<u32>(),
+            mem::size_of::<V>(),
+        )?;
+
+        Ok(PerCpuArray {
+            base,
+            _v: PhantomData,
+        })
+    }
+
+    /// Returns the values at `index` on all possible CPUs, indexed by CPU
+    /// id, or an empty vector if `index` is out of bounds.
+    pub fn get_per_cpu(&self, index: u32) -> Vec<V> {
+        lookup_per_cpu(self.base, as_bytes(&index)).unwrap_or_default()
+    }
+
+    /// Zeroes the value at `index` on all CPUs.
+    ///
+    /// Reading the values and resetting them can't be done at once, and
+    /// whatever programs add in between is lost. Counters that must not
+    /// miss events are better left running, and rates computed from the
+    /// difference between two reads:
+    ///
+    /// ```no_run
+    /// # use redbpf::{Module, PerCpuArray};
+    /// # use std::{thread, time::Duration};
+    /// # let code = std::fs::read("bpf.elf").unwrap();
+    /// # let module = Module::parse(&code).unwrap();
+    /// let map = module.maps.iter().find(|m| m.name == "packets").unwrap();
+    /// let packets = PerCpuArray::<u64>::new(map).unwrap();
+    /// let mut last: u64 = packets.get_per_cpu(0).iter().sum();
+    /// loop {
+    ///     thread::sleep(Duration::from_secs(1));
+    ///     let total: u64 = packets.get_per_cpu(0).iter().sum();
+    ///     println!("{} packets/s", total - last);
+    ///     last = total;
+    /// }
+    /// ```
+    pub fn reset(&self, index: u32) -> Result<()> {
+        reset_per_cpu::<V>(self.base, as_bytes(&index), 0)
+    }
+}
+
+/// Typed view of `BPF_MAP_TYPE_CPUMAP` maps, indexed by CPU id.
+///
+/// Values are either a `u32` queue size, or a `struct bpf_cpumap_val`
+/// (kernel 5.9) whose queue size is followed by a program to run on the
+/// target CPU.
+pub struct CpuMap<'a> {
+    base: &'a Map,
+}
+
+impl<'a> CpuMap<'a> {
+    pub fn new(base: &'a Map) -> Result<CpuMap<'a>> {
+        let value_size = base.config.value_size as usize;
+        if value_size != 4 && value_size != 8 {
+            return Err(LoadError::Map);
+        }
+        check_map(
+            base,
+            &[bpf_sys::bpf_map_type_BPF_MAP_TYPE_CPUMAP],
+            mem::size_of::<u32>(),
+            value_size,
+        )?;
+
+        Ok(CpuMap { base })
+    }
+
+    /// Sets the size of the queue of packets redirected to `cpu`, which
+    /// the kernel allocates right away. A `qsize` of 0 removes the CPU from
+    /// the map, so packets redirected to it are dropped.
+    ///
+    /// Replaces the entry, dropping the program attached to it, if any.
+    pub fn set_qsize(&self, cpu: u32, qsize: u32) -> Result<()> {
+        // the program fd, 0 for none, follows the queue size
+        let value = [qsize, 0];
+        unsafe {
+            map_update_elem(self.base.fd, as_bytes(&cpu), as_bytes(&value), 0)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the queue of `cpu`, or `None` if the CPU isn't
+    /// in the map.
+    pub fn qsize(&self, cpu: u32) -> Option<u32> {
+        let mut value = [0u32; 2];
+        unsafe {
+            map_lookup_elem(
+                self.base.fd,
+                as_bytes(&cpu),
+                value.as_mut_ptr() as *mut u8,
+                0,
+            )
+            .ok()?;
+        }
+        Some(value[0])
+    }
+}
+
+/// Reads the values of all CPUs, which the kernel copies out in one go.
+fn lookup_per_cpu<V: Copy>(map: &Map, key: *const u8) -> Option<Vec<V>> {
+    let stride = per_cpu_stride::<V>();
+    let mut values = vec![0u8; stride * cpus::get_possible().ok()?.len()];
+    unsafe {
+        map_lookup_elem(map.fd, key, values.as_mut_ptr(), 0).ok()?;
+    }
+    Some(split_per_cpu(&values, stride))
+}
+
+/// Writes a zeroed value for every CPU, laid out like `lookup_per_cpu`
+/// reads them.
+fn reset_per_cpu<V>(map: &Map, key: *const u8, flags: u64) -> Result<()> {
+    let values = vec![0u8; per_cpu_stride::<V>() * cpus::get_possible()?.len()];
+    unsafe {
+        map_update_elem(map.fd, key, values.as_ptr(), flags)?;
+    }
+    Ok(())
+}
+
+fn split_per_cpu<V: Copy>(values: &[u8], stride: usize) -> Vec<V> {
+    values
+        .chunks(stride)
+        .map(|value| unsafe { ptr::read_unaligned(value.as_ptr() as *const V) })
+        .collect()
+}
+
+fn check_map(map: &Map, kinds: &[u32], key_size: usize, value_size: usize) -> Result<()> {
+    if !kinds.contains(&map.kind)
+        || map.config.key_size as usize != key_size
+        || map.config.value_size as usize != value_size
+    {
+        return Err(LoadError::Map);
+    }
+
+    Ok(())
+}
+
+/// Per-CPU values are laid out in an array with 8 byte aligned elements.
+fn per_cpu_stride<V>() -> usize {
+    (mem::size_of::<V>() + 7) & !7
+}
+
+fn as_bytes<T>(v: &T) -> *const u8 {
+    v as *const T as *const u8
+}
+
+/// Copies `bytes` to `offset` in each of the `size` long values in `values`,
+/// which are `stride` bytes apart. Fails if `bytes` would overrun a value.
+fn patch_values(
+    values: &mut [u8],
+    size: usize,
+    stride: usize,
+    offset: usize,
+    bytes: &[u8],
+) -> Result<()> {
+    let end = match offset.checked_add(bytes.len()) {
+        Some(end) if end <= size && size <= stride && values.len() % stride == 0 => end,
+        _ => return Err(LoadError::IO(io::Error::from(io::ErrorKind::InvalidInput))),
+    };
+    for value in values.chunks_exact_mut(stride) {
+        value[offset..end].copy_from_slice(bytes);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::btf::test::encode;
+    use crate::btf::{Btf, ObjectBtf, BTF_KIND_INT, BTF_KIND_STRUCT};
+    use crate::map_def_bytes;
+    use crate::sys::bpf::{prog_test_run, TestRunAttr};
+    use crate::test_util::{create_map, insn, load_program, map_def, test_run};
+    use std::os::unix::io::RawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    #[test]
+    fn test_patch_values() {
+        let mut values = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        patch_values(&mut values, 6, 8, 2, &[0xaa, 0xbb]).unwrap();
+        assert_eq!(
+            values,
+            [1, 2, 0xaa, 0xbb, 5, 6, 7, 8, 9, 10, 0xaa, 0xbb, 13, 14, 15, 16]
+        );
+        // the padding between per-CPU values isn't part of the value
+        assert!(patch_values(&mut values, 6, 8, 5, &[0, 0]).is_err());
+        assert!(patch_values(&mut values, 6, 8, usize::MAX, &[0, 0]).is_err());
+        assert!(patch_values(&mut values[..12], 6, 8, 2, &[0, 0]).is_err());
+        assert!(patch_values(&mut values, 12, 8, 8, &[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_ip_key() {
+        let v4 = Ipv4Addr::new(192, 168, 0, 1);
+        let key = IpKey::from(v4);
+        assert_eq!(
+            key.addr,
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 168, 0, 1]
+        );
+        assert_eq!(key.addr(), IpAddr::V4(v4));
+
+        let v6 = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        let key = IpKey::from(IpAddr::V6(v6));
+        assert_eq!(
+            key.addr,
+            [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        assert_eq!(key.addr(), IpAddr::V6(v6));
+    }
+
+    #[test]
+    fn test_split_per_cpu() {
+        let values = [1, 2, 3, 0, 0, 0, 0, 0, 4, 5, 6, 0, 0, 0, 0, 0];
+        assert_eq!(per_cpu_stride::<[u8; 3]>(), 8);
+        assert_eq!(
+            split_per_cpu::<[u8; 3]>(&values, 8),
+            vec![[1, 2, 3], [4, 5, 6]]
+        );
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_get_per_cpu() {
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY;
+        let map = create_map("percpu", type_, 4, 12, 1);
+        let array = PerCpuArray::<[u32; 3]>::new(&map).unwrap();
+
+        let cpus = cpus::get_possible().unwrap().len();
+        let stride = per_cpu_stride::<[u32; 3]>();
+        let mut values = vec![0u8; stride * cpus];
+        for cpu in 0..cpus {
+            let value = [cpu as u32, 10 * cpu as u32, 100 * cpu as u32];
+            for (i, v) in value.iter().enumerate() {
+                values[cpu * stride + 4 * i..][..4].copy_from_slice(&v.to_ne_bytes());
+            }
+        }
+        unsafe {
+            map_update_elem(map.fd, as_bytes(&0u32), values.as_ptr(), 0).unwrap();
+        }
+
+        let values = array.get_per_cpu(0);
+        assert_eq!(values.len(), cpus);
+        for (cpu, value) in values.iter().enumerate() {
+            assert_eq!(*value, [cpu as u32, 10 * cpu as u32, 100 * cpu as u32]);
+        }
+        assert!(array.get_per_cpu(1).is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_reset() {
+        let array = create_map(
+            "array",
+            bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY,
+            4,
+            12,
+            2,
+        );
+        let hash = create_map(
+            "hash",
+            bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_HASH,
+            4,
+            12,
+            2,
+        );
+        // padded to 16 bytes for every CPU
+        let cpus = cpus::get_possible().unwrap().len();
+        let values = vec![0xffu8; per_cpu_stride::<[u32; 3]>() * cpus];
+        for map in &[&array, &hash] {
+            unsafe {
+                map_update_elem(map.fd, as_bytes(&1u32), values.as_ptr(), 0).unwrap();
+            }
+        }
+
+        let array = PerCpuArray::<[u32; 3]>::new(&array).unwrap();
+        assert!(array.get_per_cpu(1).iter().all(|&v| v == [u32::MAX; 3]));
+        array.reset(1).unwrap();
+        assert_eq!(array.get_per_cpu(1), vec![[0; 3]; cpus]);
+        assert!(array.reset(2).is_err());
+
+        let hash = PerCpuHashMap::<u32, [u32; 3]>::new(&hash).unwrap();
+        hash.reset(1).unwrap();
+        assert_eq!(hash.get_per_cpu(1), Some(vec![[0; 3]; cpus]));
+        // resetting doesn't add keys
+        assert!(hash.reset(0).is_err());
+        assert_eq!(hash.get_per_cpu(0), None);
+    }
+
+    /// Counts the packets of an XDP program in the `Counter<u32>` `fd`,
+    /// under key 0, as `redbpf_probes::maps::Counter::inc` does.
+    fn count_packets(fd: RawFd) -> Vec<u8> {
+        let map_fd = || [insn(0x18, 1, 1, 0, fd), insn(0, 0, 0, 0, 0)].concat();
+        [
+            insn(0x62, 10, 0, -4, 0),  // *(u32 *)(r10 - 4) = 0
+            map_fd(),                  // r1 = map
+            insn(0xbf, 2, 10, 0, 0),   // r2 = r10
+            insn(0x07, 2, 0, 0, -4),   // r2 += -4
+            insn(0x85, 0, 0, 0, 1),    // r0 = bpf_map_lookup_elem(r1, r2)
+            insn(0x15, 0, 0, 4, 0),    // if r0 == 0 goto insert
+            insn(0x79, 1, 0, 0, 0),    // r1 = *(u64 *)r0
+            insn(0x07, 1, 0, 0, 1),    // r1 += 1
+            insn(0x7b, 0, 1, 0, 0),    // *(u64 *)r0 = r1
+            insn(0x05, 0, 0, 19, 0),   // goto out
+            insn(0x7a, 10, 0, -16, 1), // insert: *(u64 *)(r10 - 16) = 1
+            map_fd(),                  // r1 = map
+            insn(0xbf, 2, 10, 0, 0),   // r2 = r10
+            insn(0x07, 2, 0, 0, -4),   // r2 += -4
+            insn(0xbf, 3, 10, 0, 0),   // r3 = r10
+            insn(0x07, 3, 0, 0, -16),  // r3 += -16
+            insn(0xb7, 4, 0, 0, 1),    // r4 = BPF_NOEXIST
+            insn(0x85, 0, 0, 0, 2),    // r0 = bpf_map_update_elem(r1, r2, r3, r4)
+            insn(0x15, 0, 0, 9, 0),    // if r0 == 0 goto out
+            map_fd(),                  // inserted on another CPU: r1 = map
+            insn(0xbf, 2, 10, 0, 0),   // r2 = r10
+            insn(0x07, 2, 0, 0, -4),   // r2 += -4
+            insn(0x85, 0, 0, 0, 1),    // r0 = bpf_map_lookup_elem(r1, r2)
+            insn(0x15, 0, 0, 3, 0),    // if r0 == 0 goto out
+            insn(0x79, 1, 0, 0, 0),    // r1 = *(u64 *)r0
+            insn(0x07, 1, 0, 0, 1),    // r1 += 1
+            insn(0x7b, 0, 1, 0, 0),    // *(u64 *)r0 = r1
+            insn(0xb7, 0, 0, 0, 2),    // out: r0 = XDP_PASS
+            insn(0x95, 0, 0, 0, 0),    // exit
+        ]
+        .concat()
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_counter() {
+        const REPEAT: u32 = 10_000;
+
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_HASH;
+        let map = create_map("packets", type_, 4, 8, 1);
+        let prog = load_program("xdp", "count_packets", &count_packets(map.fd));
+        let prog_fd = prog.fd.unwrap();
+        let counter = Counter::<u32>::new(&map).unwrap();
+        assert_eq!(counter.get(0), 0);
+
+        // every CPU starts counting at once, racing to add the key
+        let online = cpus::get_online().unwrap();
+        let barrier = Arc::new(Barrier::new(online.len()));
+        let threads: Vec<_> = online
+            .iter()
+            .map(|&cpu| {
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    unsafe {
+                        let mut set: libc::cpu_set_t = mem::zeroed();
+                        libc::CPU_SET(cpu as usize, &mut set);
+                        libc::sched_setaffinity(0, mem::size_of_val(&set), &set);
+                    }
+                    let packet = [0u8; 64];
+                    let mut out = [0u8; 64];
+                    let mut attr = TestRunAttr {
+                        prog_fd: prog_fd as u32,
+                        data_size_in: packet.len() as u32,
+                        data_size_out: out.len() as u32,
+                        data_in: packet.as_ptr() as u64,
+                        data_out: out.as_mut_ptr() as u64,
+                        repeat: REPEAT,
+                        ..Default::default()
+                    };
+                    barrier.wait();
+                    prog_test_run(&mut attr).unwrap();
+                    assert_eq!(attr.retval, 2);
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(counter.get(0), u64::from(REPEAT) * online.len() as u64);
+        assert_eq!(counter.get(1), 0);
+        counter.reset(0).unwrap();
+        assert_eq!(counter.get(0), 0);
+    }
+
+    /// A socket filter returning the value at `index & mask` of the array
+    /// `map_fd`, as `redbpf_probes::maps::Array::get_masked` looks it up,
+    /// or 1000 if there's none.
+    fn masked_lookup(map_fd: RawFd, index: u32, mask: u32) -> Vec<u8> {
+        let map = [insn(0x18, 1, 1, 0, map_fd), insn(0, 0, 0, 0, 0)].concat();
+        [
+            insn(0xb4, 1, 0, 0, index as i32), // w1 = index
+            insn(0x54, 1, 0, 0, mask as i32),  // w1 &= mask
+            insn(0x63, 10, 1, -4, 0),          // *(u32 *)(r10 - 4) = w1
+            map,                               // r1 = map
+            insn(0xbf, 2, 10, 0, 0),           // r2 = r10
+            insn(0x07, 2, 0, 0, -4),           // r2 += -4
+            insn(0x85, 0, 0, 0, 1),            // r0 = bpf_map_lookup_elem(r1, r2)
+            insn(0x15, 0, 0, 2, 0),            // if r0 == 0 goto out
+            insn(0x61, 0, 0, 0, 0),            // w0 = *(u32 *)r0
+            insn(0x95, 0, 0, 0, 0),            // exit
+            insn(0xb7, 0, 0, 0, 1000),         // out: r0 = 1000
+            insn(0x95, 0, 0, 0, 0),            // exit
+        ]
+        .concat()
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_masked_lookup() {
+        let map = create_map("array", bpf_sys::bpf_map_type_BPF_MAP_TYPE_ARRAY, 4, 4, 4);
+        for index in 0..4u32 {
+            let value = 10 + index;
+            unsafe { map_update_elem(map.fd, as_bytes(&index), as_bytes(&value), 0) }.unwrap();
+        }
+
+        let lookup = |index, mask| {
+            let code = masked_lookup(map.fd, index, mask);
+            let prog = load_program("socketfilter", "masked_lookup", &code);
+            let value = test_run(&prog, &[0u8; 64]).0;
+            unsafe { libc::close(prog.fd.unwrap()) };
+            value
+        };
+        // with the mask max_entries - 1, every index lands in the array
+        for &(index, value) in [(0, 10), (3, 13), (4, 10), (7, 13), (u32::MAX, 13)].iter() {
+            assert_eq!(lookup(index, 3), value);
+        }
+        // other masks only bound the index
+        assert_eq!(lookup(9, 7), 11);
+        assert_eq!(lookup(5, 7), 1000);
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_cpu_map_qsize() {
+        for &value_size in &[4, 8] {
+            let def = map_def(bpf_sys::bpf_map_type_BPF_MAP_TYPE_CPUMAP, 4, value_size, 1);
+            let map = match Map::load("cpus", map_def_bytes(&def)) {
+                Ok(map) => map,
+                // a kernel predating bpf_cpumap_val takes 4 byte values only
+                Err(_) if value_size == 8 => continue,
+                Err(e) => panic!("{:?}", e),
+            };
+            let cpus = CpuMap::new(&map).unwrap();
+
+            assert_eq!(cpus.qsize(0), None);
+            cpus.set_qsize(0, 64).unwrap();
+            assert_eq!(cpus.qsize(0), Some(64));
+            cpus.set_qsize(0, 2048).unwrap();
+            assert_eq!(cpus.qsize(0), Some(2048));
+            cpus.set_qsize(0, 0).unwrap();
+            assert_eq!(cpus.qsize(0), None);
+            // out of bounds
+            assert!(cpus.set_qsize(1, 64).is_err());
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug)]
+    struct Average {
+        lock: u32,
+        sum: u64,
+        count: u64,
+    }
+
+    /// The BTF of a hash map `averages` of `Average`s.
+    #[rustfmt::skip]
+    fn averages_btf() -> Btf {
+        // [1] INT "int", [2] STRUCT "bpf_spin_lock" { int val; },
+        // [3] INT "u64", [4] STRUCT "avg" { lock, sum, count },
+        // [5] STRUCT "____btf_map_averages" { int key; avg value; }
+        let types = [
+            1, BTF_KIND_INT << 24, 4, 32,
+            5, BTF_KIND_STRUCT << 24 | 1, 4, 19, 1, 0,
+            23, BTF_KIND_INT << 24, 8, 64,
+            27, BTF_KIND_STRUCT << 24 | 3, 24, 31, 2, 0, 36, 3, 64, 40, 3, 128,
+            46, BTF_KIND_STRUCT << 24 | 2, 32, 67, 1, 0, 71, 4, 64,
+        ];
+        let strings = b"\0int\0bpf_spin_lock\0val\0u64\0avg\0lock\0sum\0count\0\
+                        ____btf_map_averages\0key\0value\0";
+        Btf::parse(&encode(&types, strings)).unwrap()
+    }
+
+    #[test]
+    fn test_averages_btf() {
+        assert_eq!(averages_btf().map_type_ids("averages"), Some((1, 4)));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_iter_locked() {
+        let btf = ObjectBtf::load(averages_btf(), None).unwrap();
+        let value_size = mem::size_of::<Average>() as u32;
+        let def = map_def(bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH, 4, value_size, 16);
+        let map = Map::load_with_btf("averages", map_def_bytes(&def), Some(&btf), None).unwrap();
+        let averages = HashMap::<u32, Average>::new(&map).unwrap();
+        let avg = |n| Average {
+            lock: 0,
+            sum: 10 * n,
+            count: n,
+        };
+        for key in 0..4 {
+            averages.set(key, avg(1)).unwrap();
+        }
+        if averages.iter_locked().next().unwrap().is_err() {
+            // the kernel predates BPF_F_LOCK lookups
+            return;
+        }
+
+        // a writer taking the lock as programs do, keeping sum == 10 * count
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let done = done.clone();
+            let fd = map.fd;
+            thread::spawn(move || {
+                let mut n = 1;
+                while !done.load(Ordering::Relaxed) {
+                    let value = avg(n);
+                    let key = (n % 4) as u32;
+                    let flags = u64::from(BPF_F_LOCK);
+                    unsafe { map_update_elem(fd, as_bytes(&key), as_bytes(&value), flags) }
+                        .unwrap();
+                    n += 1;
+                }
+            })
+        };
+
+        for _ in 0..1000 {
+            let entries = averages.iter_locked().collect::<Result<Vec<_>>>().unwrap();
+            assert!(!entries.is_empty());
+            for (_, value) in entries {
+                assert_eq!(value.sum, 10 * value.count);
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_iter_locked_error() {
+        let map = create_map("no_lock", bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH, 4, 8, 16);
+        let values = HashMap::<u32, u64>::new(&map).unwrap();
+        for key in 0..4 {
+            values.set(key, 1).unwrap();
+        }
+        // the values hold no lock, and the iteration ends at the error
+        let entries = values.iter_locked().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        match &entries[0] {
+            Err(LoadError::IO(e)) => assert_eq!(e.raw_os_error(), Some(libc::EINVAL)),
+            _ => panic!("expected EINVAL"),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Packet captures
+//!
+//! `PcapWriter` writes packets in the pcap format that `tcpdump` and
+//! Wireshark read. Together with an XDP program exporting packets through
+//! `redbpf_probes::xdp::PerfMap`, this makes for a capture tool:
+//!
+//! ```no_run
+//! use redbpf::{Event, LinkType, Module, PcapWriter, PerfMap};
+//! use std::fs::File;
+//! use std::mem;
+//! use std::slice;
+//! use std::time::{SystemTime, UNIX_EPOCH};
+//!
+//! let code = std::fs::read("capture.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter_mut().find(|m| m.name == "packets").unwrap();
+//! let perfmap = PerfMap::bind(map, -1, 0, 16, -1, 0).unwrap();
+//!
+//! let out = File::create("capture.pcap").unwrap();
+//! let mut pcap = PcapWriter::new(out, LinkType::for_interface("eth0").unwrap()).unwrap();
+//! loop {
+//!     while let Some(Event::Sample(sample)) = perfmap.read() {
+//!         let sample = unsafe {
+//!             slice::from_raw_parts(sample.data.as_ptr(), sample.size as usize)
+//!         };
+//!         let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+//!         // the programs export `MapData<u32>`
+//!         pcap.write_map_data(ts, sample, mem::size_of::<u32>()).unwrap();
+//!     }
+//! }
+//! ```
+
+use std::fs;
+use std::io::{self, Write};
+use std::time::Duration;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+/// Packets are exported from XDP programs whole, up to the largest frame.
+const PCAP_SNAPLEN: u32 = 65535;
+
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_LOOPBACK: u16 = 772;
+const ARPHRD_NONE: u16 = 0xfffe;
+
+/// The link-layer header type of the packets in a capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum LinkType {
+    Ethernet = 1,
+    /// Packets starting with their IPv4 or IPv6 header.
+    Raw = 101,
+    LinuxSll = 113,
+}
+
+impl LinkType {
+    /// Returns the link type of interfaces of the `ARPHRD_*` type `arphrd`.
+    pub fn from_arphrd(arphrd: u16) -> Option<LinkType> {
+        match arphrd {
+            ARPHRD_ETHER | ARPHRD_LOOPBACK => Some(LinkType::Ethernet),
+            ARPHRD_NONE => Some(LinkType::Raw),
+            _ => None,
+        }
+    }
+
+    /// Returns the link type of the packets XDP programs see on `iface`.
+    pub fn for_interface(iface: &str) -> io::Result<LinkType> {
+        let arphrd = fs::read_to_string(format!("/sys/class/net/{}/type", iface))?;
+        arphrd
+            .trim()
+            .parse()
+            .ok()
+            .and_then(LinkType::from_arphrd)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown link type of {}", iface),
+                )
+            })
+    }
+}
+
+/// Writes packets to a pcap file.
+pub struct PcapWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the file header to `out`, for packets of `link_type`.
+    pub fn new(mut out: W, link_type: LinkType) -> io::Result<PcapWriter<W>> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_ne_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MAJOR.to_ne_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MINOR.to_ne_bytes());
+        // time zone offset and timestamp accuracy
+        header.extend_from_slice(&0i32.to_ne_bytes());
+        header.extend_from_slice(&0u32.to_ne_bytes());
+        header.extend_from_slice(&PCAP_SNAPLEN.to_ne_bytes());
+        header.extend_from_slice(&(link_type as u32).to_ne_bytes());
+        out.write_all(&header)?;
+
+        Ok(PcapWriter { out })
+    }
+
+    /// Writes `packet`, captured at `ts` since the epoch, which was
+    /// `orig_len` bytes long before it was truncated.
+    pub fn write_packet(&mut self, ts: Duration, packet: &[u8], orig_len: usize) -> io::Result<()> {
+        let packet = &packet[..packet.len().min(PCAP_SNAPLEN as usize)];
+        let mut header = Vec::with_capacity(16);
+        header.extend_from_slice(&(ts.as_secs() as u32).to_ne_bytes());
+        header.extend_from_slice(&ts.subsec_micros().to_ne_bytes());
+        header.extend_from_slice(&(packet.len() as u32).to_ne_bytes());
+        header.extend_from_slice(&(orig_len.max(packet.len()) as u32).to_ne_bytes());
+        self.out.write_all(&header)?;
+        self.out.write_all(packet)
+    }
+
+    /// Writes the packet in `sample`, a `MapData<T>` output by an XDP
+    /// program through `redbpf_probes::xdp::PerfMap`, with `data_size` the
+    /// size of `T`, which must be aligned to 8 bytes at most.
+    ///
+    /// The packet is written from its start, where its link-layer header
+    /// is, whatever offset the program set for `MapData::payload()`. The
+    /// data isn't looked at, and the padding perf adds to samples is left
+    /// out.
+    pub fn write_map_data(
+        &mut self,
+        ts: Duration,
+        sample: &[u8],
+        data_size: usize,
+    ) -> io::Result<()> {
+        // the u32 offset and size follow the data, and the packet them
+        let fields = data_size.saturating_add(3) & !3;
+        let start = fields.saturating_add(8);
+        if sample.len() < start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "sample shorter than MapData",
+            ));
+        }
+        let mut size = [0u8; 4];
+        size.copy_from_slice(&sample[fields + 4..start]);
+        let size = u32::from_ne_bytes(size) as usize;
+        let packet = &sample[start..sample.len().min(start.saturating_add(size))];
+
+        self.write_packet(ts, packet, packet.len())
+    }
+
+    /// Flushes the packets written so far.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pcap_parser::{parse_pcap, Linktype};
+
+    #[test]
+    fn test_pcap_writer() {
+        let mut pcap = PcapWriter::new(vec![], LinkType::Ethernet).unwrap();
+        let packet = (0..60).collect::<Vec<u8>>();
+        pcap.write_packet(Duration::new(1_600_000_000, 5_000), &packet, 60)
+            .unwrap();
+
+        // MapData<u32> with an offset of 14 and 42 bytes of packet, and perf
+        // padding
+        let mut sample = vec![];
+        sample.extend_from_slice(&7u32.to_ne_bytes());
+        sample.extend_from_slice(&14u32.to_ne_bytes());
+        sample.extend_from_slice(&42u32.to_ne_bytes());
+        sample.extend_from_slice(&packet[..42]);
+        sample.extend_from_slice(&[0; 6]);
+        pcap.write_map_data(Duration::new(1_600_000_001, 0), &sample, 4)
+            .unwrap();
+        assert!(pcap
+            .write_map_data(Duration::new(0, 0), &sample[..8], 4)
+            .is_err());
+        // MapData<[u8; 3]>, with the data padded
+        let mut padded = vec![1, 2, 3, 0];
+        padded.extend_from_slice(&sample[4..]);
+        pcap.write_map_data(Duration::new(1_600_000_002, 0), &padded, 3)
+            .unwrap();
+
+        let file = pcap.into_inner();
+        let (rest, capture) = parse_pcap(&file).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(capture.header.network, Linktype::ETHERNET);
+        assert_eq!(capture.blocks.len(), 3);
+        assert_eq!(capture.blocks[0].ts_sec, 1_600_000_000);
+        assert_eq!(capture.blocks[0].ts_usec, 5);
+        assert_eq!(capture.blocks[0].origlen, 60);
+        assert_eq!(capture.blocks[0].data, &packet[..]);
+        assert_eq!(capture.blocks[1].ts_sec, 1_600_000_001);
+        assert_eq!(capture.blocks[1].caplen, 42);
+        assert_eq!(capture.blocks[1].data, &packet[..42]);
+        assert_eq!(capture.blocks[2].data, &packet[..42]);
+    }
+
+    #[test]
+    fn test_link_type() {
+        assert_eq!(LinkType::from_arphrd(1), Some(LinkType::Ethernet));
+        assert_eq!(LinkType::from_arphrd(0xfffe), Some(LinkType::Raw));
+        assert_eq!(LinkType::from_arphrd(0xffff), None);
+        if let Ok(link_type) = LinkType::for_interface("lo") {
+            assert_eq!(link_type, LinkType::Ethernet);
+        }
+    }
+}
@@ -46,6 +46,10 @@
 //! The `PerfMap::bind` call semantics closely follow that of the
 //! `perf_event_open(2)`
 //! [syscall](http://www.man7.org/linux/man-pages/man2/perf_event_open.2.html).
+//!
+//! A map holds one perf buffer per CPU, so binding a map on a CPU again
+//! takes the events of that CPU away from the `PerfMap` bound before. To
+//! hand every event to several readers, see `EventFanout`.
 #![allow(non_upper_case_globals)]
 #![allow(clippy::cast_lossless)]
 #![allow(clippy::cast_ptr_alignment)]
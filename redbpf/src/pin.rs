@@ -0,0 +1,714 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Pinning modules
+//!
+//! `Module::pin_all` pins the programs and maps of a module to a BPF
+//! filesystem, in a layout that tools such as `bpftool` can find them in:
+//!
+//! ```text
+//! <base>/<program name>
+//! <base>/maps/<map name>
+//! ```
+//!
+//! Pinned objects stay around after the process exits, until their pins are
+//! removed. `Module::from_pinned_dir` opens them again, for example to read
+//! the maps from another process:
+//!
+//! ```no_run
+//! use redbpf::{HashMap, Module};
+//! use std::path::Path;
+//!
+//! let code = std::fs::read("firewall.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.load(module.version, module.license.clone()).unwrap();
+//! }
+//! let base = Path::new("/sys/fs/bpf/firewall");
+//! module.pin_all(base).unwrap();
+//!
+//! // later, elsewhere
+//! let pinned = Module::from_pinned_dir(base).unwrap();
+//! let map = pinned.maps.iter().find(|m| m.name == "blocklist").unwrap();
+//! let blocklist = HashMap::<u32, u8>::new(map).unwrap();
+//! ```
+//!
+//! # Sharing maps by name
+//!
+//! Maps declared with `pinning = by_name` are pinned to
+//! `/sys/fs/bpf/<map name>` by the first module that creates them, and
+//! reused by every module parsed later, in any process, like libbpf's
+//! `LIBBPF_PIN_BY_NAME`. A pinned map is only reused if its type, key and
+//! value sizes, maximum entries and flags match the definition in the
+//! module, and parsing fails otherwise.
+//!
+//! A firewall and a separate tool feeding it addresses, both declaring
+//! `#[map("blocklist", pinning = by_name)]`:
+//!
+//! ```no_run
+//! use redbpf::{HashMap, Module};
+//!
+//! // in the firewall process
+//! let module = Module::parse(&std::fs::read("firewall.elf").unwrap()).unwrap();
+//!
+//! // in the tool, which gets the firewall's map
+//! let module = Module::parse(&std::fs::read("blocklist.elf").unwrap()).unwrap();
+//! let map = module.maps.iter().find(|m| m.name == "blocklist").unwrap();
+//! let blocklist = HashMap::<u32, u8>::new(map).unwrap();
+//! blocklist.set(0x0a00_0001, 1).unwrap();
+//! ```
+//!
+//! The pins are left behind when the processes exit, and have to be
+//! removed to release the maps.
+//!
+//! # Load caching
+//!
+//! Verifying large programs can take seconds. `Program::load_cached` pins
+//! the programs it loads under a name derived from their code, and reuses
+//! the pinned program on the next start if the code is unchanged, which
+//! skips the verifier:
+//!
+//! ```no_run
+//! use redbpf::Module;
+//! use std::path::Path;
+//!
+//! // a deep parser, with many branches for the verifier to walk
+//! let code = std::fs::read("dpi.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! let cache = Path::new("/sys/fs/bpf/dpi_cache");
+//! for prog in module.programs.iter_mut() {
+//!     prog.load_cached(module.version, module.license.clone(), cache).unwrap();
+//! }
+//! ```
+
+use crate::btf::ObjectBtf;
+use crate::sys::bpf::{obj_get, obj_get_info_by_fd, obj_pin, ProgInfo};
+use crate::{
+    event_channel_def, LoadError, Map, Module, Program, ProgramKind, Result,
+    EVENT_CHANNEL_MAP_TYPE, LOG_SIZE_DEFAULT,
+};
+use bpf_sys::{bpf_insn, bpf_map_def, bpf_map_info};
+use libc::close;
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+/// Directory under the base directory that maps are pinned in.
+const MAPS_DIR: &str = "maps";
+
+/// Directory maps declared with `pinning = by_name` are pinned to.
+pub const PIN_BY_NAME_DIR: &str = "/sys/fs/bpf";
+
+/// Number of hex digits of the hash in the pin names of `load_cached`.
+const CACHE_KEY_DIGITS: usize = 16;
+
+impl Module {
+    /// Pins the loaded programs of the module to `base/<program name>`, and
+    /// its maps to `base/maps/<map name>`, creating the directories as
+    /// needed.
+    ///
+    /// `base` must be on a BPF filesystem, usually mounted at
+    /// `/sys/fs/bpf`. Programs that aren't loaded are skipped.
+    ///
+    /// Nothing is pinned if two programs or two maps have the same name, or
+    /// if any of the paths exists already. If pinning fails halfway, the
+    /// pins made so far and the directories created are removed again.
+    pub fn pin_all(&self, base: &Path) -> Result<()> {
+        let maps_dir = base.join(MAPS_DIR);
+        let programs = self
+            .programs
+            .iter()
+            .filter_map(|prog| Some((&prog.name, base, prog.fd?)));
+        let maps = self.maps.iter().map(|map| (&map.name, &*maps_dir, map.fd));
+        let mut pins = vec![];
+        let mut paths = HashSet::new();
+        for (name, dir, fd) in programs.chain(maps) {
+            if name.is_empty() || name.contains('/') || (dir == base && name.as_str() == MAPS_DIR) {
+                return Err(LoadError::IO(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("can't pin `{}`", name),
+                )));
+            }
+            let path = dir.join(name);
+            if !paths.insert(path.clone()) || path.exists() {
+                return Err(LoadError::IO(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is pinned already", path.display()),
+                )));
+            }
+            pins.push((path, fd));
+        }
+
+        let created = create_dirs(&maps_dir)?;
+        for (i, (path, fd)) in pins.iter().enumerate() {
+            if let Err(e) = pin(path, *fd) {
+                for (path, _) in pins[..i].iter() {
+                    let _ = fs::remove_file(path);
+                }
+                for dir in created.iter() {
+                    let _ = fs::remove_dir(dir);
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Opens the programs and maps pinned to `base` by `pin_all`.
+    ///
+    /// The programs are loaded already, and can be attached. Their code
+    /// isn't available, and since kprobes and kretprobes are the same type
+    /// of program, pinned kretprobes come back as `ProgramKind::Kprobe`.
+    /// The license and version of the module are not pinned, and are empty.
+    pub fn from_pinned_dir(base: &Path) -> Result<Module> {
+        // closes what was opened so far if opening the rest fails
+        let mut module = Module {
+            programs: vec![],
+            maps: vec![],
+            license: String::new(),
+            version: 0,
+        };
+        for (name, path) in pinned_objects(base)? {
+            let fd = obj_get(&cpath(&path)?)?;
+            let mut info = ProgInfo::default();
+            let kind = unsafe { obj_get_info_by_fd(fd, &mut info) }
+                .ok()
+                .and_then(|_| ProgramKind::from_prog_type(info.prog_type));
+            let kind = match kind {
+                Some(kind) => kind,
+                None => {
+                    unsafe { close(fd) };
+                    return Err(LoadError::Section(name));
+                }
+            };
+            module.programs.push(Program {
+                attachments: vec![],
+                fd: Some(fd),
+                kind,
+                name,
+                code: vec![],
+                kfuncs: vec![],
+                kfunc_checks: vec![],
+                core_relos: vec![],
+                core_relocated: false,
+                missing_maps: vec![],
+                autoload: true,
+                dev_bound: None,
+                xdp_frags: false,
+                xdp_devmap: false,
+                log_size: LOG_SIZE_DEFAULT,
+                token: None,
+            });
+        }
+
+        let maps_dir = base.join(MAPS_DIR);
+        if maps_dir.is_dir() {
+            for (name, path) in pinned_objects(&maps_dir)? {
+                let fd = obj_get(&cpath(&path)?)?;
+                let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+                if let Err(e) = unsafe { obj_get_info_by_fd(fd, &mut info) } {
+                    unsafe { close(fd) };
+                    return Err(e.into());
+                }
+                module.maps.push(Map {
+                    name,
+                    kind: info.type_,
+                    fd,
+                    config: bpf_map_def {
+                        type_: info.type_,
+                        key_size: info.key_size,
+                        value_size: info.value_size,
+                        max_entries: info.max_entries,
+                        map_flags: info.map_flags,
+                    },
+                });
+            }
+        }
+
+        Ok(module)
+    }
+}
+
+impl ProgramKind {
+    fn from_prog_type(prog_type: bpf_sys::bpf_prog_type) -> Option<ProgramKind> {
+        use crate::ProgramKind::*;
+        match prog_type {
+            bpf_sys::bpf_prog_type_BPF_PROG_TYPE_KPROBE => Some(Kprobe),
+            bpf_sys::bpf_prog_type_BPF_PROG_TYPE_XDP => Some(XDP),
+            bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SOCKET_FILTER => Some(SocketFilter),
+            bpf_sys::bpf_prog_type_BPF_PROG_TYPE_TRACEPOINT => Some(Tracepoint),
+            bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SCHED_CLS => Some(TcAction),
+            bpf_sys::bpf_prog_type_BPF_PROG_TYPE_CGROUP_SKB => Some(CgroupSkb),
+            crate::sys::bpf::BPF_PROG_TYPE_TRACING => Some(Fentry),
+            _ => None,
+        }
+    }
+}
+
+impl Map {
+    /// Opens the map pinned to `dir/<name>`, or creates the map and pins it
+    /// there. See `Module::parse_pinned`.
+    ///
+    /// Creating the map and pinning it isn't atomic, so when two processes
+    /// race to create the map, the loser fails to pin it, and retries
+    /// opening the winner's map.
+    pub(crate) fn load_pinned(
+        name: &str,
+        code: &[u8],
+        btf: Option<&ObjectBtf>,
+        token: Option<RawFd>,
+        dir: &Path,
+    ) -> Result<Map> {
+        let path = dir.join(name);
+        if !path.exists() {
+            let map = Map::load_with_btf(name, code, btf, token)?;
+            let pinned = match create_dirs(dir) {
+                Ok(_) => pin(&path, map.fd),
+                Err(e) => Err(e.into()),
+            };
+            match pinned {
+                Ok(_) => return Ok(map),
+                // another process pinned its map first
+                Err(LoadError::IO(ref e)) if e.kind() == io::ErrorKind::AlreadyExists => unsafe {
+                    close(map.fd);
+                },
+                Err(e) => {
+                    unsafe { close(map.fd) };
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut config: bpf_map_def = *zero::read(code);
+        if config.type_ == EVENT_CHANNEL_MAP_TYPE {
+            config = event_channel_def(&config)?;
+        }
+        let fd = obj_get(&cpath(&path)?)?;
+        let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+        if let Err(e) = unsafe { obj_get_info_by_fd(fd, &mut info) } {
+            unsafe { close(fd) };
+            return Err(e.into());
+        }
+        if let Some(field) = pinned_map_mismatch(&info, &config) {
+            unsafe { close(fd) };
+            return Err(LoadError::IO(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the {} of {} differs from the map definition",
+                    field,
+                    path.display()
+                ),
+            )));
+        }
+
+        Ok(Map {
+            name: name.to_string(),
+            kind: info.type_,
+            fd,
+            config,
+        })
+    }
+}
+
+impl Program {
+    /// Loads the program like `load`, reusing the program pinned to
+    /// `cache_dir` by an earlier call if the program is unchanged.
+    ///
+    /// The program is pinned to `cache_dir/<name>-<hash>`, where the hash
+    /// covers the code, the expected attach type, the load flags, the
+    /// kernel version and the license, and the programs pinned for other
+    /// versions of the code are removed. The maps a program uses are part of its code, so programs
+    /// using maps are only reused if their maps are the same, for example
+    /// because they are opened from pins themselves. `cache_dir` must be on
+    /// a BPF filesystem, which is emptied on reboot.
+    pub fn load_cached(
+        &mut self,
+        kernel_version: u32,
+        license: String,
+        cache_dir: &Path,
+    ) -> Result<RawFd> {
+        if self.name.is_empty() || self.name.contains('/') {
+            return Err(LoadError::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't pin `{}`", self.name),
+            )));
+        }
+        let key = self.cache_key(kernel_version, &license)?;
+        let path = cache_dir.join(format!("{}-{:016x}", self.name, key));
+        if let Ok(fd) = obj_get(&cpath(&path)?) {
+            let mut info = ProgInfo::default();
+            // the info has no expected attach type, which the path covers
+            if unsafe { obj_get_info_by_fd(fd, &mut info) }.is_ok()
+                && info.prog_type == self.kind.to_prog_type()
+            {
+                self.fd = Some(fd);
+                return Ok(fd);
+            }
+            unsafe { close(fd) };
+        }
+
+        let fd = self.load(kernel_version, license)?;
+        create_dirs(cache_dir)?;
+        let prefix = format!("{}-", self.name);
+        for (name, stale) in pinned_objects(cache_dir)? {
+            if is_cache_pin(&name, &prefix) {
+                fs::remove_file(stale)?;
+            }
+        }
+        pin(&path, fd)?;
+
+        Ok(fd)
+    }
+
+    /// Hashes everything the loaded program depends on, with map fds
+    /// replaced by the ids of the maps, which don't depend on the process.
+    fn cache_key(&self, kernel_version: u32, license: &str) -> Result<u64> {
+        let mut hash = Fnv1a::new();
+        hash.write(&self.kind.to_prog_type().to_ne_bytes());
+        hash.write(&self.expected_attach_type().to_ne_bytes());
+        hash.write(&kernel_version.to_ne_bytes());
+        hash.write(license.as_bytes());
+        hash.write(&self.dev_bound.unwrap_or(0).to_ne_bytes());
+        hash.write(&self.prog_flags().to_ne_bytes());
+        for insn in self.code.iter() {
+            let mut insn = *insn;
+            if insn.code == (bpf_sys::BPF_LD | bpf_sys::BPF_IMM | bpf_sys::BPF_DW) as u8
+                && insn.src_reg() == bpf_sys::BPF_PSEUDO_MAP_FD as u8
+            {
+                let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+                unsafe { obj_get_info_by_fd(insn.imm, &mut info)? };
+                insn.imm = info.id as i32;
+            }
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    &insn as *const bpf_insn as *const u8,
+                    mem::size_of::<bpf_insn>(),
+                )
+            };
+            hash.write(bytes);
+        }
+
+        Ok(hash.finish())
+    }
+}
+
+/// Returns `true` if `name` is the name of a pin of `load_cached`, for the
+/// program whose pins start with `prefix`.
+fn is_cache_pin(name: &str, prefix: &str) -> bool {
+    name.starts_with(prefix)
+        && name.len() == prefix.len() + CACHE_KEY_DIGITS
+        && name[prefix.len()..].bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The 64 bit FNV-1a hash, which unlike `DefaultHasher` is the same across
+/// Rust releases.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Returns the first property of the pinned map `info` that differs from
+/// the definition `config`.
+fn pinned_map_mismatch(info: &bpf_map_info, config: &bpf_map_def) -> Option<&'static str> {
+    if info.type_ != config.type_ {
+        Some("type")
+    } else if info.key_size != config.key_size {
+        Some("key size")
+    } else if info.value_size != config.value_size {
+        Some("value size")
+    } else if info.max_entries != config.max_entries {
+        Some("maximum number of entries")
+    } else if info.map_flags != config.map_flags {
+        Some("flags")
+    } else {
+        None
+    }
+}
+
+fn pin(path: &Path, fd: RawFd) -> Result<()> {
+    obj_pin(fd, &cpath(path)?)?;
+    Ok(())
+}
+
+fn cpath(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// Creates `dir` and its missing parents, and returns the directories that
+/// were created, innermost first.
+fn create_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let missing = dir
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    fs::create_dir_all(dir)?;
+
+    Ok(missing)
+}
+
+/// Returns the names and paths of the pins in `dir`, skipping directories.
+fn pinned_objects(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut objects = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|_| LoadError::StringConversion)?;
+        objects.push((name, entry.path()));
+    }
+    objects.sort();
+
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::map_def_bytes;
+    use crate::sys::bpf::{prog_load, ProgLoadAttr};
+    use crate::test_util::{create_map, load_program, map_def};
+
+    fn prog_id(prog: &Program) -> u32 {
+        let mut info = ProgInfo::default();
+        unsafe { obj_get_info_by_fd(prog.fd.unwrap(), &mut info).unwrap() };
+        info.id
+    }
+
+    /// Counts the fds of the process referring to the program `id`.
+    fn prog_fds(id: u32) -> usize {
+        let line = format!("prog_id:\t{}", id);
+        fs::read_dir("/proc/self/fdinfo")
+            .unwrap()
+            .filter_map(|entry| fs::read_to_string(entry.ok()?.path()).ok())
+            .filter(|info| info.lines().any(|l| l == line))
+            .count()
+    }
+
+    fn map(name: &str) -> Map {
+        create_map(name, bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH, 4, 8, 1)
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_pin_all() {
+        // r0 = 0; exit
+        let code = [
+            0xb7, 0, 0, 0, 0, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let module = Module {
+            programs: vec![load_program("socketfilter", "pin_test", &code)],
+            maps: vec![map("counts"), map("config")],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+
+        let base = PathBuf::from(format!(
+            "/sys/fs/bpf/redbpf_pin_test_{}",
+            std::process::id()
+        ));
+        module.pin_all(&base).unwrap();
+        assert!(base.join("pin_test").exists());
+        assert!(base.join("maps/counts").exists());
+        // the paths are taken now
+        assert!(module.pin_all(&base).is_err());
+
+        let pinned = Module::from_pinned_dir(&base).unwrap();
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(pinned.programs.len(), 1);
+        assert_eq!(pinned.programs[0].name, "pin_test");
+        assert_eq!(pinned.programs[0].kind, ProgramKind::SocketFilter);
+        assert_eq!(prog_id(&pinned.programs[0]), prog_id(&module.programs[0]));
+        assert_eq!(pinned.maps.len(), 2);
+        for map in module.maps.iter() {
+            let pinned = pinned.maps.iter().find(|m| m.name == map.name).unwrap();
+            assert_eq!(pinned.info().unwrap().id, map.info().unwrap().id);
+            assert_eq!(pinned.config.key_size, 4);
+            assert_eq!(pinned.config.value_size, 8);
+        }
+
+        // two maps named the same, nothing is pinned
+        let module = Module {
+            programs: vec![],
+            maps: vec![map("counts"), map("counts")],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+        assert!(module.pin_all(&base).is_err());
+        assert!(!base.exists());
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_from_pinned_dir_error() {
+        // r0 = 0; exit
+        let code = [
+            0xb7, 0, 0, 0, 0, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let prog = load_program("socketfilter", "a_pinned", &code);
+        // a program of a type the module can't have, pinned after it
+        let mut attr = ProgLoadAttr {
+            prog_type: bpf_sys::bpf_prog_type_BPF_PROG_TYPE_CGROUP_SOCK,
+            insn_cnt: 2,
+            insns: code.as_ptr() as u64,
+            license: b"GPL\0".as_ptr() as u64,
+            ..Default::default()
+        };
+        let unknown = prog_load(&mut attr).unwrap();
+
+        let base = PathBuf::from(format!(
+            "/sys/fs/bpf/redbpf_pin_error_test_{}",
+            std::process::id()
+        ));
+        create_dirs(&base).unwrap();
+        pin(&base.join("a_pinned"), prog.fd.unwrap()).unwrap();
+        pin(&base.join("b_unknown"), unknown).unwrap();
+        let res = Module::from_pinned_dir(&base);
+        fs::remove_dir_all(&base).unwrap();
+        unsafe { close(unknown) };
+
+        match res {
+            Err(LoadError::Section(name)) => assert_eq!(name, "b_unknown"),
+            _ => panic!("b_unknown was opened"),
+        }
+        // the fd opened for a_pinned was closed again
+        assert_eq!(prog_fds(prog_id(&prog)), 1);
+    }
+
+    #[test]
+    fn test_is_cache_pin() {
+        assert!(is_cache_pin("parse-0123456789abcdef", "parse-"));
+        assert!(!is_cache_pin("parse-0123456789abcde", "parse-"));
+        assert!(!is_cache_pin("parse-tcp-0123456789abcdef", "parse-"));
+        assert!(!is_cache_pin("parser-0123456789abcdef", "parse-"));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_load_cached() {
+        // r0 = 0; exit
+        let code = [
+            0xb7, 0, 0, 0, 0, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        // r0 = 1; exit
+        let changed = [
+            0xb7, 0, 0, 0, 1, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let cache = PathBuf::from(format!(
+            "/sys/fs/bpf/redbpf_cache_test_{}",
+            std::process::id()
+        ));
+        let load = |code: &[u8]| {
+            let mut prog = Program::new("socketfilter", "cache_test", code).unwrap();
+            prog.load_cached(0, "GPL".to_string(), &cache).unwrap();
+            prog
+        };
+
+        let first = load(&code);
+        let second = load(&code);
+        assert_eq!(prog_id(&second), prog_id(&first));
+
+        let third = load(&changed);
+        assert_ne!(prog_id(&third), prog_id(&first));
+        // the pin of the old code is gone
+        assert_eq!(pinned_objects(&cache).unwrap().len(), 1);
+
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_cache_key() {
+        // r0 = 2; exit
+        let code = [
+            0xb7, 0, 0, 0, 2, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut prog = Program::new("xdp", "cache_test", &code).unwrap();
+        let key = prog.cache_key(0, "GPL").unwrap();
+        assert_eq!(prog.cache_key(0, "GPL").unwrap(), key);
+        prog.set_xdp_frags();
+        let frags_key = prog.cache_key(0, "GPL").unwrap();
+        assert_ne!(frags_key, key);
+        prog.set_xdp_devmap();
+        assert_ne!(prog.cache_key(0, "GPL").unwrap(), frags_key);
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_load_pinned() {
+        let def = |max_entries| map_def(bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH, 4, 8, max_entries);
+        let dir = PathBuf::from(format!(
+            "/sys/fs/bpf/redbpf_pinned_map_test_{}",
+            std::process::id()
+        ));
+
+        let shared = def(16);
+        let code = map_def_bytes(&shared);
+        let first = Map::load_pinned("shared", code, None, None, &dir).unwrap();
+        // the second loader gets the first one's map
+        let second = Map::load_pinned("shared", code, None, None, &dir).unwrap();
+        assert_eq!(second.info().unwrap().id, first.info().unwrap().id);
+        // a different definition doesn't silently share the map
+        let other = def(32);
+        assert!(Map::load_pinned("shared", map_def_bytes(&other), None, None, &dir).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pinned_map_mismatch() {
+        let config = bpf_map_def {
+            type_: bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH,
+            key_size: 4,
+            value_size: 8,
+            max_entries: 16,
+            map_flags: 0,
+        };
+        let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+        info.type_ = config.type_;
+        info.key_size = 4;
+        info.value_size = 8;
+        info.max_entries = 16;
+        assert_eq!(pinned_map_mismatch(&info, &config), None);
+
+        info.map_flags = 1;
+        assert_eq!(pinned_map_mismatch(&info, &config), Some("flags"));
+        info.value_size = 16;
+        assert_eq!(pinned_map_mismatch(&info, &config), Some("value size"));
+        info.type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_ARRAY;
+        assert_eq!(pinned_map_mismatch(&info, &config), Some("type"));
+    }
+}
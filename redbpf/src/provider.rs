@@ -0,0 +1,167 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Syscall providers
+//!
+//! Sandboxes built on seccomp often deny the `bpf()` syscall to the
+//! sandboxed process, and have a privileged broker issue it on its behalf.
+//! `set_syscall_provider` routes every `bpf()` call redbpf makes, from
+//! parsing modules and creating their maps to loading programs and
+//! accessing maps, through a `SyscallProvider`, which can forward them to
+//! such a broker.
+//!
+//! The provider is process-wide, as maps and programs only keep their fds
+//! around. The default, `DirectSyscall`, issues the syscall itself.
+//!
+//! Attaching programs to kprobes, tracepoints, sockets and interfaces goes
+//! through perf events, `setsockopt()` and netlink instead of `bpf()`, and
+//! isn't routed through the provider.
+//!
+//! A provider logging the commands issued, while still calling `bpf()`
+//! directly:
+//!
+//! ```no_run
+//! use redbpf::{set_syscall_provider, DirectSyscall, Module, SyscallProvider};
+//! use std::io;
+//!
+//! struct Logger;
+//!
+//! impl SyscallProvider for Logger {
+//!     unsafe fn bpf(&self, cmd: u32, attr: *mut u8, size: u32) -> io::Result<i64> {
+//!         let res = DirectSyscall.bpf(cmd, attr, size);
+//!         println!("bpf({}) = {:?}", cmd, res);
+//!         res
+//!     }
+//! }
+//!
+//! set_syscall_provider(Logger);
+//! let code = std::fs::read("bpf.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.load(module.version, module.license.clone()).unwrap();
+//! }
+//! ```
+
+use lazy_static::lazy_static;
+use libc::{syscall, SYS_bpf};
+use std::io;
+use std::sync::{Arc, RwLock};
+
+/// Issues the `bpf()` syscall on behalf of redbpf.
+pub trait SyscallProvider: Send + Sync {
+    /// Issues the `bpf()` command `cmd` with the `union bpf_attr` of `size`
+    /// bytes at `attr`, and returns what the syscall returns, or the error
+    /// it fails with.
+    ///
+    /// The attribute holds pointers into the memory of the calling process,
+    /// to the program instructions, keys, values and log buffers, which a
+    /// broker in another process must read and write through, e.g. with
+    /// `process_vm_readv`. Fds in the attribute and the fd returned belong
+    /// to the calling process as well, and have to be passed across with
+    /// `SCM_RIGHTS`.
+    ///
+    /// # Safety
+    ///
+    /// `attr` must point to `size` bytes laid out as the kernel expects for
+    /// `cmd`, and every pointer stored in it must be valid for the call.
+    unsafe fn bpf(&self, cmd: u32, attr: *mut u8, size: u32) -> io::Result<i64>;
+}
+
+/// Calls `bpf()` directly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirectSyscall;
+
+impl SyscallProvider for DirectSyscall {
+    unsafe fn bpf(&self, cmd: u32, attr: *mut u8, size: u32) -> io::Result<i64> {
+        let ret = syscall(SYS_bpf, cmd, attr, size);
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret as i64)
+        }
+    }
+}
+
+impl<P: SyscallProvider + ?Sized> SyscallProvider for Arc<P> {
+    unsafe fn bpf(&self, cmd: u32, attr: *mut u8, size: u32) -> io::Result<i64> {
+        (**self).bpf(cmd, attr, size)
+    }
+}
+
+lazy_static! {
+    static ref PROVIDER: RwLock<Option<Arc<dyn SyscallProvider>>> = RwLock::new(None);
+}
+
+/// Routes the `bpf()` calls of the whole process through `provider`.
+pub fn set_syscall_provider<P: SyscallProvider + 'static>(provider: P) {
+    *PROVIDER.write().unwrap() = Some(Arc::new(provider));
+}
+
+/// Goes back to calling `bpf()` directly.
+pub fn reset_syscall_provider() {
+    *PROVIDER.write().unwrap() = None;
+}
+
+/// Returns the provider set with `set_syscall_provider`, if any.
+pub(crate) fn syscall_provider() -> Option<Arc<dyn SyscallProvider>> {
+    PROVIDER.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sys::bpf::MapCreateAttr;
+    use crate::test_util::map_def;
+    use crate::{map_def_bytes, Map};
+    use std::fs::File;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::sync::Mutex;
+
+    /// Creates the maps named `mock_map` itself, handing out `fd`, and
+    /// passes the other calls on, as tests run in parallel.
+    struct MockProvider {
+        fd: RawFd,
+        created: Mutex<Vec<String>>,
+    }
+
+    impl SyscallProvider for MockProvider {
+        unsafe fn bpf(&self, cmd: u32, attr: *mut u8, size: u32) -> io::Result<i64> {
+            if cmd == bpf_sys::bpf_cmd_BPF_MAP_CREATE {
+                let create = &*(attr as *const MapCreateAttr);
+                let name = &create.map_name;
+                let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                let name = String::from_utf8_lossy(&name[..len]).into_owned();
+                if name == "mock_map" {
+                    assert_eq!(create.max_entries, 16);
+                    self.created.lock().unwrap().push(name);
+                    return Ok(libc::dup(self.fd) as i64);
+                }
+            }
+            DirectSyscall.bpf(cmd, attr, size)
+        }
+    }
+
+    #[test]
+    fn test_syscall_provider() {
+        let def = map_def(bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH, 4, 4, 16);
+        let code = map_def_bytes(&def);
+        let null = File::open("/dev/null").unwrap();
+        let provider = Arc::new(MockProvider {
+            fd: null.as_raw_fd(),
+            created: Mutex::new(vec![]),
+        });
+        set_syscall_provider(provider.clone());
+        let map = Map::load("mock_map", code);
+        reset_syscall_provider();
+
+        let map = map.unwrap();
+        assert_eq!(*provider.created.lock().unwrap(), vec!["mock_map"]);
+        let target = std::fs::read_link(format!("/proc/self/fd/{}", map.fd)).unwrap();
+        assert_eq!(target, std::path::Path::new("/dev/null"));
+        unsafe { libc::close(map.fd) };
+    }
+}
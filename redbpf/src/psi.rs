@@ -0,0 +1,294 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Pressure stall information
+//!
+//! The kernel reports how much of the time tasks were stalled waiting for
+//! CPU or memory in `/proc/pressure` (kernel 4.20 or later, with
+//! `CONFIG_PSI`). Tools tracing hot paths can read it to shed load when the
+//! system is busy, by exporting fewer events.
+//!
+//! Programs sampling their events, e.g. through
+//! `redbpf_probes::xdp::PerfMap::insert_sampled`, can read the sampling rate
+//! from a configuration map:
+//!
+//! ```ignore
+//! #[map("config")]
+//! static mut config: HashMap<u32, u32> = HashMap::with_max_entries(1);
+//!
+//! #[xdp]
+//! pub extern "C" fn sample_packets(ctx: XdpContext) -> XdpAction {
+//!     let rate_inv = unsafe { config.get(0) }.copied().unwrap_or(1);
+//!     let data = MapData::with_payload(PacketInfo::default(), 0, ctx.len());
+//!     unsafe { packets.insert_sampled(&ctx, data, rate_inv) };
+//!     XdpAction::Pass
+//! }
+//! ```
+//!
+//! which `SamplingTuner` then adjusts to the pressure every few seconds:
+//!
+//! ```no_run
+//! use redbpf::{HashMap, Module, Pressure, SamplingTuner};
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! let code = std::fs::read("sample_packets.elf").unwrap();
+//! let module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter().find(|m| m.name == "config").unwrap();
+//! let config = HashMap::<u32, u32>::new(map).unwrap();
+//!
+//! // down to one in 1024 packets while more than 25% of the time is stalled
+//! let mut tuner = SamplingTuner::new(1024);
+//! loop {
+//!     let cpu = Pressure::cpu().unwrap();
+//!     let rate_inv = tuner.update(cpu.some.avg10);
+//!     config.set(0, rate_inv).unwrap();
+//!     thread::sleep(Duration::from_secs(5));
+//! }
+//! ```
+
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+const PROC_PRESSURE_CPU: &str = "/proc/pressure/cpu";
+const PROC_PRESSURE_MEMORY: &str = "/proc/pressure/memory";
+
+/// The share of the time, in percent, some or all tasks were stalled.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PressureAvg {
+    /// Over the last 10 seconds.
+    pub avg10: f64,
+    /// Over the last 60 seconds.
+    pub avg60: f64,
+    /// Over the last 300 seconds.
+    pub avg300: f64,
+    /// The total time stalled, in microseconds.
+    pub total: u64,
+}
+
+/// The pressure on a resource, as reported by a `/proc/pressure` file.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Pressure {
+    /// The time at least one task was stalled.
+    pub some: PressureAvg,
+    /// The time all tasks were stalled at once. Only reported for CPUs from
+    /// kernel 5.13 on, where it is always 0 system-wide.
+    pub full: Option<PressureAvg>,
+}
+
+impl Pressure {
+    /// Reads the CPU pressure from `/proc/pressure/cpu`, see `Psi::read`.
+    pub fn cpu() -> Result<Pressure, Error> {
+        Pressure::read(PROC_PRESSURE_CPU)
+    }
+
+    /// Reads the memory pressure from `/proc/pressure/memory`, see
+    /// `Psi::read`.
+    pub fn memory() -> Result<Pressure, Error> {
+        Pressure::read(PROC_PRESSURE_MEMORY)
+    }
+
+    /// Reads the pressure file at `path`.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Pressure, Error> {
+        let content = read_to_string(path)?;
+        Pressure::parse(&content)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid pressure file"))
+    }
+
+    /// Parses the content of a pressure file, or returns `None` if it lacks
+    /// the `some` line or a line is malformed.
+    pub fn parse(content: &str) -> Option<Pressure> {
+        let mut some = None;
+        let mut full = None;
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let avg = match fields.next() {
+                Some("some") => &mut some,
+                Some("full") => &mut full,
+                Some(_) => return None,
+                None => continue,
+            };
+            *avg = Some(parse_avg(fields)?);
+        }
+
+        Some(Pressure { some: some?, full })
+    }
+}
+
+fn parse_avg<'a>(fields: impl Iterator<Item = &'a str>) -> Option<PressureAvg> {
+    let mut avg = PressureAvg::default();
+    for field in fields {
+        let mut kv = field.splitn(2, '=');
+        let (key, value) = (kv.next()?, kv.next()?);
+        match key {
+            "avg10" => avg.avg10 = value.parse().ok()?,
+            "avg60" => avg.avg60 = value.parse().ok()?,
+            "avg300" => avg.avg300 = value.parse().ok()?,
+            "total" => avg.total = value.parse().ok()?,
+            // fields added by later kernels
+            _ => (),
+        }
+    }
+
+    Some(avg)
+}
+
+/// The CPU and memory pressure of the system.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Psi {
+    pub cpu: Pressure,
+    pub memory: Pressure,
+}
+
+impl Psi {
+    /// Reads the CPU and memory pressure from `/proc/pressure`.
+    ///
+    /// Fails with `ErrorKind::NotFound` on kernels without PSI support, and
+    /// with `ErrorKind::InvalidData` if the files can't be parsed. Either
+    /// file failing fails the whole read, so callers interested in one of
+    /// the resources only read it with `Pressure::cpu` or `Pressure::memory`.
+    pub fn read() -> Result<Psi, Error> {
+        Ok(Psi {
+            cpu: Pressure::cpu()?,
+            memory: Pressure::memory()?,
+        })
+    }
+
+    /// Returns the share of the last 10 seconds some tasks waited for a
+    /// CPU, in percent.
+    pub fn cpu_some_avg10(&self) -> f64 {
+        self.cpu.some.avg10
+    }
+
+    /// Returns the share of the last 10 seconds some tasks waited for
+    /// memory, in percent.
+    pub fn memory_some_avg10(&self) -> f64 {
+        self.memory.some.avg10
+    }
+
+    /// Returns the share of the last 10 seconds all tasks waited for memory
+    /// at once, in percent.
+    pub fn memory_full_avg10(&self) -> f64 {
+        self.memory.full.map_or(0.0, |full| full.avg10)
+    }
+}
+
+/// Adjusts a sampling rate to the pressure on the system.
+///
+/// The rate is given as its inverse, one event in `rate_inv` being sampled,
+/// as programs expect it. It doubles each time the pressure is above the
+/// high threshold, up to a maximum, and halves each time the pressure is
+/// below the low threshold, down to 1. In between, it is left alone, so that
+/// it doesn't flap around a single threshold.
+#[derive(Debug, Clone)]
+pub struct SamplingTuner {
+    rate_inv: u32,
+    max_rate_inv: u32,
+    low: f64,
+    high: f64,
+}
+
+impl SamplingTuner {
+    /// Creates a tuner sampling every event at first, and one in
+    /// `max_rate_inv` at most, with thresholds of 10% and 25%.
+    pub fn new(max_rate_inv: u32) -> SamplingTuner {
+        SamplingTuner {
+            rate_inv: 1,
+            max_rate_inv: max_rate_inv.max(1),
+            low: 10.0,
+            high: 25.0,
+        }
+    }
+
+    /// Sets the pressure, in percent, below which the rate goes up, and the
+    /// one above which it goes down.
+    pub fn with_thresholds(mut self, low: f64, high: f64) -> SamplingTuner {
+        self.low = low;
+        self.high = high;
+        self
+    }
+
+    /// Returns the current `rate_inv`.
+    pub fn rate_inv(&self) -> u32 {
+        self.rate_inv
+    }
+
+    /// Adjusts the rate to `pressure`, in percent, and returns the new
+    /// `rate_inv`.
+    pub fn update(&mut self, pressure: f64) -> u32 {
+        if pressure > self.high {
+            self.rate_inv = self.rate_inv.saturating_mul(2).min(self.max_rate_inv);
+        } else if pressure < self.low {
+            self.rate_inv = (self.rate_inv / 2).max(1);
+        }
+        self.rate_inv
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_pressure() {
+        let cpu = "some avg10=1.53 avg60=0.87 avg300=0.72 total=182764901\n\
+                   full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n";
+        let pressure = Pressure::parse(cpu).unwrap();
+        assert_eq!(
+            pressure.some,
+            PressureAvg {
+                avg10: 1.53,
+                avg60: 0.87,
+                avg300: 0.72,
+                total: 182764901,
+            }
+        );
+        assert_eq!(pressure.full, Some(PressureAvg::default()));
+        let psi = Psi {
+            cpu: pressure,
+            memory: pressure,
+        };
+        assert_eq!(psi.cpu_some_avg10(), 1.53);
+        assert_eq!(psi.memory_full_avg10(), 0.0);
+
+        // CPUs only report some before 5.13
+        let cpu = "some avg10=42.00 avg60=12.50 avg300=3.10 total=5\n";
+        let pressure = Pressure::parse(cpu).unwrap();
+        assert_eq!(pressure.some.avg10, 42.0);
+        assert_eq!(pressure.full, None);
+    }
+
+    #[test]
+    fn test_parse_bad_pressure() {
+        assert_eq!(Pressure::parse(""), None);
+        // no some line
+        assert_eq!(Pressure::parse("full avg10=0.00 total=0"), None);
+        assert_eq!(Pressure::parse("some avg10=x total=0"), None);
+        assert_eq!(Pressure::parse("some avg10"), None);
+        assert_eq!(Pressure::parse("partial avg10=0.00"), None);
+    }
+
+    #[test]
+    fn test_sampling_tuner() {
+        let mut tuner = SamplingTuner::new(8);
+        assert_eq!(tuner.rate_inv(), 1);
+        assert_eq!(tuner.update(30.0), 2);
+        assert_eq!(tuner.update(30.0), 4);
+        // between the thresholds
+        assert_eq!(tuner.update(15.0), 4);
+        assert_eq!(tuner.update(90.0), 8);
+        assert_eq!(tuner.update(90.0), 8);
+        assert_eq!(tuner.update(2.0), 4);
+        assert_eq!(tuner.update(2.0), 2);
+        assert_eq!(tuner.update(2.0), 1);
+        assert_eq!(tuner.update(0.0), 1);
+
+        let mut tuner = SamplingTuner::new(0).with_thresholds(1.0, 2.0);
+        assert_eq!(tuner.update(3.0), 1);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Ring buffers
+//!
+//! Consumer side of `BPF_MAP_TYPE_RINGBUF` maps, available since kernel 5.8.
+//!
+//! Unlike perf buffers, a ring buffer is a single buffer shared by all CPUs,
+//! so samples are read in the order in which they were committed. The map fd
+//! itself can be polled for new data.
+//!
+//! The consumer position is shared by all the `RingBuffer`s bound to a map,
+//! each sample being read by one of them only. To hand every sample to
+//! several readers, see `EventFanout`.
+//!
+//! ```no_run
+//! use redbpf::{Module, RingBuffer};
+//!
+//! let code = std::fs::read("bpf.elf").unwrap();
+//! let module = Module::parse(&code).unwrap();
+//! let map = module.maps.iter().find(|m| m.name == "events").unwrap();
+//!
+//! let ringbuf = RingBuffer::bind(map).unwrap();
+//! ringbuf.read(|sample| println!("{} bytes", sample.len()));
+//! ```
+#![allow(clippy::cast_ptr_alignment)]
+
+use crate::sys::bpf::obj_get_info_by_fd;
+use crate::{LoadError, Map, Result};
+use bpf_sys::bpf_map_info;
+use libc::{c_void, mmap, munmap, sysconf, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE, _SC_PAGESIZE};
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr::null_mut;
+use std::slice;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+pub const BPF_MAP_TYPE_RINGBUF: u32 = 27;
+
+const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+const BPF_RINGBUF_HDR_SZ: usize = 8;
+
+/// Returns `true` if the running kernel supports ring buffers.
+pub fn ringbuf_supported() -> bool {
+    crate::uname::get_kernel_internal_version()
+        .map(|v| v >= (5 << 16) + (8 << 8))
+        .unwrap_or(false)
+}
+
+pub struct RingBuffer {
+    consumer: *mut c_void,
+    producer: *mut c_void,
+    mask: usize,
+    page_size: usize,
+    pub fd: RawFd,
+}
+
+impl RingBuffer {
+    /// Maps the ring buffer of `map` into memory.
+    pub fn bind(map: &Map) -> Result<RingBuffer> {
+        let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+        unsafe { obj_get_info_by_fd(map.fd, &mut info)? };
+        if info.type_ != BPF_MAP_TYPE_RINGBUF {
+            return Err(LoadError::Map);
+        }
+
+        let size = info.max_entries as usize;
+        unsafe {
+            let page_size = sysconf(_SC_PAGESIZE) as usize;
+            let consumer = mmap(
+                null_mut(),
+                page_size,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                map.fd,
+                0,
+            );
+            if consumer == MAP_FAILED {
+                return Err(LoadError::IO(io::Error::last_os_error()));
+            }
+
+            // the data pages are mapped twice in a row, so that samples
+            // wrapping around the end of the buffer can be read in one go
+            let producer = mmap(
+                null_mut(),
+                page_size + 2 * size,
+                PROT_READ,
+                MAP_SHARED,
+                map.fd,
+                page_size as i64,
+            );
+            if producer == MAP_FAILED {
+                let err = io::Error::last_os_error();
+                munmap(consumer, page_size);
+                return Err(LoadError::IO(err));
+            }
+
+            Ok(RingBuffer {
+                consumer,
+                producer,
+                mask: size - 1,
+                page_size,
+                fd: map.fd,
+            })
+        }
+    }
+
+    /// Calls `f` on every committed sample and returns the number of
+    /// samples read.
+    pub fn read<F: FnMut(&[u8])>(&self, f: F) -> usize {
+        unsafe {
+            let consumer = &*(self.consumer as *const AtomicUsize);
+            let producer = &*(self.producer as *const AtomicUsize);
+            let data = (self.producer as *const u8).add(self.page_size);
+            consume(consumer, producer, data, self.mask, f)
+        }
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.consumer, self.page_size);
+            munmap(self.producer, self.page_size + 2 * (self.mask + 1));
+        }
+    }
+}
+
+unsafe fn consume<F: FnMut(&[u8])>(
+    consumer: &AtomicUsize,
+    producer: &AtomicUsize,
+    data: *const u8,
+    mask: usize,
+    mut f: F,
+) -> usize {
+    let mut count = 0;
+    let mut cons = consumer.load(Ordering::Acquire);
+    loop {
+        let prod = producer.load(Ordering::Acquire);
+        if cons >= prod {
+            return count;
+        }
+
+        while cons < prod {
+            let header = &*(data.add(cons & mask) as *const AtomicU32);
+            let len = header.load(Ordering::Acquire);
+            if len & BPF_RINGBUF_BUSY_BIT != 0 {
+                return count;
+            }
+
+            let sample_len = (len & !BPF_RINGBUF_DISCARD_BIT) as usize;
+            if len & BPF_RINGBUF_DISCARD_BIT == 0 {
+                let sample = data.add((cons & mask) + BPF_RINGBUF_HDR_SZ);
+                f(slice::from_raw_parts(sample, sample_len));
+                count += 1;
+            }
+
+            cons += (sample_len + BPF_RINGBUF_HDR_SZ + 7) & !7;
+            consumer.store(cons, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_consume() {
+        let mut data = vec![0u64; 8];
+        let bytes = data.as_mut_ptr() as *mut u8;
+        unsafe {
+            // 4 byte sample, 12 byte discarded sample, 8 bytes reserved
+            *(bytes as *mut u32) = 4;
+            *bytes.add(8) = 42;
+            *(bytes.add(16) as *mut u32) = 12 | BPF_RINGBUF_DISCARD_BIT;
+            *(bytes.add(40) as *mut u32) = 8 | BPF_RINGBUF_BUSY_BIT;
+        }
+
+        let consumer = AtomicUsize::new(0);
+        let producer = AtomicUsize::new(56);
+        let mut samples = vec![];
+        let count = unsafe {
+            consume(&consumer, &producer, bytes, 63, |s| samples.push(s.to_vec()))
+        };
+
+        assert_eq!(count, 1);
+        assert_eq!(samples, vec![vec![42, 0, 0, 0]]);
+        // stops in front of the uncommitted sample
+        assert_eq!(consumer.load(Ordering::Relaxed), 40);
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Cgroup socket address programs
+//!
+//! `cgroup_sock_addr` programs run when the sockets of the processes in a
+//! cgroup bind, connect, or send UDP datagrams to an address, and can
+//! rewrite the address before the kernel uses it. This is how connections
+//! are transparently redirected to a proxy, or DNS queries to a local
+//! resolver, without touching the applications.
+//!
+//! Each program runs at one hook, which it must be loaded for. cargo-bpf
+//! puts the programs of `#[cgroup_sockaddr(connect4)]` in `cgroup_connect4/`
+//! sections, and likewise for the other hooks:
+//!
+//! ```no_run
+//! use redbpf::{CgroupAttachMode, Module};
+//! use std::fs::File;
+//! use std::os::unix::io::AsRawFd;
+//!
+//! let code = std::fs::read("dns_redirect.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! let cgroup = File::open("/sys/fs/cgroup/system.slice").unwrap();
+//! let prog = module
+//!     .programs
+//!     .iter_mut()
+//!     .find(|prog| prog.name == "redirect_dns")
+//!     .unwrap();
+//! prog.load(module.version, module.license.clone()).unwrap();
+//! let _attached = prog
+//!     .attach_cgroup_sock_addr(cgroup.as_raw_fd(), CgroupAttachMode::Multi)
+//!     .unwrap();
+//! ```
+
+use crate::cgroup::CgroupLink;
+use crate::{CgroupAttachMode, LoadError, Program, ProgramKind, Result};
+use std::os::unix::io::RawFd;
+
+/// The hook of a cgroup `cgroup_sock_addr` programs run at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SockAddrHook {
+    /// `bind()` of IPv4 sockets.
+    Bind4,
+    /// `bind()` of IPv6 sockets.
+    Bind6,
+    /// `connect()` of IPv4 sockets.
+    Connect4,
+    /// `connect()` of IPv6 sockets.
+    Connect6,
+    /// Sending IPv4 UDP datagrams to an address, without `connect()`.
+    SendMsg4,
+    /// Sending IPv6 UDP datagrams to an address, without `connect()`.
+    SendMsg6,
+}
+
+impl SockAddrHook {
+    pub(crate) fn to_attach_type(self) -> bpf_sys::bpf_attach_type {
+        use SockAddrHook::*;
+        match self {
+            Bind4 => bpf_sys::bpf_attach_type_BPF_CGROUP_INET4_BIND,
+            Bind6 => bpf_sys::bpf_attach_type_BPF_CGROUP_INET6_BIND,
+            Connect4 => bpf_sys::bpf_attach_type_BPF_CGROUP_INET4_CONNECT,
+            Connect6 => bpf_sys::bpf_attach_type_BPF_CGROUP_INET6_CONNECT,
+            SendMsg4 => bpf_sys::bpf_attach_type_BPF_CGROUP_UDP4_SENDMSG,
+            SendMsg6 => bpf_sys::bpf_attach_type_BPF_CGROUP_UDP6_SENDMSG,
+        }
+    }
+
+    /// Returns the hook of programs in sections starting with `prefix`.
+    pub(crate) fn from_section(prefix: &str) -> Option<SockAddrHook> {
+        use SockAddrHook::*;
+        let hook = match prefix {
+            "cgroup_bind4" => Bind4,
+            "cgroup_bind6" => Bind6,
+            "cgroup_connect4" => Connect4,
+            "cgroup_connect6" => Connect6,
+            "cgroup_sendmsg4" => SendMsg4,
+            "cgroup_sendmsg6" => SendMsg6,
+            _ => return None,
+        };
+        Some(hook)
+    }
+}
+
+/// A program attached to a cgroup socket address hook, which is detached
+/// when the `CgroupSockAddr` is dropped, even if the program was unloaded
+/// or the cgroup closed meanwhile.
+pub struct CgroupSockAddr {
+    _link: CgroupLink,
+}
+
+impl Program {
+    /// Attaches a loaded `cgroup_sock_addr` program to the cgroup
+    /// `cgroup_fd`, at the hook it was loaded for.
+    pub fn attach_cgroup_sock_addr(
+        &mut self,
+        cgroup_fd: RawFd,
+        mode: CgroupAttachMode,
+    ) -> Result<CgroupSockAddr> {
+        let hook = match self.kind {
+            ProgramKind::CgroupSockAddr(hook) => hook,
+            _ => return Err(LoadError::BPF),
+        };
+        let prog_fd = self.fd.ok_or(LoadError::BPF)?;
+        let link = CgroupLink::attach(cgroup_fd, prog_fd, hook.to_attach_type(), mode as u32)?;
+
+        Ok(CgroupSockAddr { _link: link })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::load_program;
+    use std::fs::{self, File};
+    use std::os::unix::io::AsRawFd;
+
+    /// Offset of `user_ip4` in `struct bpf_sock_addr`.
+    const USER_IP4: u8 = 4;
+    /// Offset of `user_port` in `struct bpf_sock_addr`.
+    const USER_PORT: u8 = 24;
+    /// Offset of `family` in `struct bpf_sock_addr`.
+    const FAMILY: u8 = 28;
+
+    /// Stores `value` at `offset` of the context, then allows the call.
+    fn store_program(offset: u8, value: i32) -> Vec<u8> {
+        let mut code = vec![];
+        // r2 = value; *(u32 *)(r1 + offset) = r2; r0 = 1; exit
+        code.extend_from_slice(&[0xb7, 0x02, 0, 0]);
+        code.extend_from_slice(&value.to_le_bytes());
+        code.extend_from_slice(&[0x63, 0x21, offset, 0, 0, 0, 0, 0]);
+        code.extend_from_slice(&[0xb7, 0, 0, 0, 1, 0, 0, 0]);
+        code.extend_from_slice(&[0x95, 0, 0, 0, 0, 0, 0, 0]);
+        code
+    }
+
+    #[test]
+    fn test_from_section() {
+        assert_eq!(
+            SockAddrHook::from_section("cgroup_connect4"),
+            Some(SockAddrHook::Connect4)
+        );
+        assert_eq!(
+            SockAddrHook::from_section("cgroup_sendmsg6"),
+            Some(SockAddrHook::SendMsg6)
+        );
+        assert_eq!(SockAddrHook::from_section("cgroup_skb"), None);
+        assert_eq!(
+            Program::new("cgroup_connect4", "redirect", &store_program(USER_PORT, 0))
+                .unwrap()
+                .kind,
+            ProgramKind::CgroupSockAddr(SockAddrHook::Connect4)
+        );
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_attach_connect4() {
+        // 127.0.0.53:53 in network byte order
+        let ip4 = i32::from_ne_bytes([127, 0, 0, 53]);
+        let port = u32::from(53u16.to_be()) as i32;
+        let mut prog = load_program("cgroup_connect4", "dns_ip", &store_program(USER_IP4, ip4));
+        let mut port_prog = load_program(
+            "cgroup_connect4",
+            "dns_port",
+            &store_program(USER_PORT, port),
+        );
+
+        // the family is read-only
+        let mut bad =
+            Program::new("cgroup_connect4", "family", &store_program(FAMILY, 10)).unwrap();
+        match bad.load(0, "GPL".to_string()) {
+            Err(LoadError::ProgramLoad { .. }) => (),
+            _ => panic!("the verifier let a write to the family through"),
+        }
+
+        let path = format!(
+            "/sys/fs/cgroup/redbpf_sock_addr_test_{}",
+            std::process::id()
+        );
+        if fs::create_dir(&path).is_err() {
+            // no cgroup v2 hierarchy to experiment in
+            return;
+        }
+        let cgroup = File::open(&path).unwrap();
+        let fd = cgroup.as_raw_fd();
+        let attached = prog
+            .attach_cgroup_sock_addr(fd, CgroupAttachMode::Multi)
+            .unwrap();
+        let port_attached = port_prog
+            .attach_cgroup_sock_addr(fd, CgroupAttachMode::Multi)
+            .unwrap();
+        let ids = crate::sys::bpf::prog_query(fd, SockAddrHook::Connect4.to_attach_type()).unwrap();
+        assert_eq!(ids.len(), 2);
+
+        drop(attached);
+        drop(port_attached);
+        let ids = crate::sys::bpf::prog_query(fd, SockAddrHook::Connect4.to_attach_type()).unwrap();
+        assert!(ids.is_empty());
+
+        // only programs loaded for a socket address hook attach there
+        let code = [0xb7, 0, 0, 0, 1, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0];
+        let mut skb = Program::new("cgroup_skb", "skb", &code).unwrap();
+        skb.load(0, "GPL".to_string()).unwrap();
+        assert!(skb
+            .attach_cgroup_sock_addr(fd, CgroupAttachMode::Multi)
+            .is_err());
+
+        drop(cgroup);
+        fs::remove_dir(&path).unwrap();
+    }
+}
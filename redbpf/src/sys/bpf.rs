@@ -0,0 +1,489 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Direct `bpf(2)` calls.
+//!
+//! Most of the kernel interface is reached through `bpf_sys`. The commands
+//! and attribute fields that are newer than the bundled libbpf headers are
+//! issued from here instead, with the attribute layouts mirroring the
+//! anonymous structs of `union bpf_attr` in the kernel's uapi `bpf.h`.
+//!
+//! All attribute structs are zero-initialised through `Default`. The kernel
+//! accepts attributes larger than what it knows about as long as the unknown
+//! tail is zeroed, so unused fields are safe to pass to older kernels.
+
+use crate::provider::{syscall_provider, DirectSyscall, SyscallProvider};
+use libc::{c_long, close};
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+pub const BPF_PSEUDO_KFUNC_CALL: u8 = 2;
+pub const BPF_F_XDP_HAS_FRAGS: u32 = 1 << 5;
+pub const BPF_F_XDP_DEV_BOUND_ONLY: u32 = 1 << 6;
+
+pub const BPF_LINK_CREATE: u32 = 28;
+pub const BPF_TOKEN_CREATE: u32 = 36;
+pub const BPF_F_TOKEN_FD: u32 = 1 << 16;
+pub const BPF_TCX_INGRESS: u32 = 46;
+pub const BPF_TCX_EGRESS: u32 = 47;
+pub const BPF_F_BEFORE: u32 = 1 << 3;
+pub const BPF_F_AFTER: u32 = 1 << 4;
+pub const BPF_F_LINK: u32 = 1 << 13;
+pub const BPF_PROG_TYPE_TRACING: u32 = 26;
+pub const BPF_TRACE_FENTRY: u32 = 24;
+pub const BPF_XDP_DEVMAP: u32 = 33;
+pub const BPF_F_TEST_XDP_LIVE_FRAMES: u32 = 1 << 1;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct ProgLoadAttr {
+    pub prog_type: u32,
+    pub insn_cnt: u32,
+    pub insns: u64,
+    pub license: u64,
+    pub log_level: u32,
+    pub log_size: u32,
+    pub log_buf: u64,
+    pub kern_version: u32,
+    pub prog_flags: u32,
+    pub prog_name: [u8; bpf_sys::BPF_OBJ_NAME_LEN as usize],
+    pub prog_ifindex: u32,
+    pub expected_attach_type: u32,
+    pub prog_btf_fd: u32,
+    pub func_info_rec_size: u32,
+    pub func_info: u64,
+    pub func_info_cnt: u32,
+    pub line_info_rec_size: u32,
+    pub line_info: u64,
+    pub line_info_cnt: u32,
+    pub attach_btf_id: u32,
+    pub attach_prog_fd: u32,
+    pub core_relo_cnt: u32,
+    pub fd_array: u64,
+    pub core_relos: u64,
+    pub core_relo_rec_size: u32,
+    pub log_true_size: u32,
+    pub prog_token_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct MapCreateAttr {
+    pub map_type: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+    pub map_flags: u32,
+    pub inner_map_fd: u32,
+    pub numa_node: u32,
+    pub map_name: [u8; bpf_sys::BPF_OBJ_NAME_LEN as usize],
+    pub map_ifindex: u32,
+    pub btf_fd: u32,
+    pub btf_key_type_id: u32,
+    pub btf_value_type_id: u32,
+    pub btf_vmlinux_value_type_id: u32,
+    pub map_extra: u64,
+    pub value_type_btf_obj_fd: u32,
+    pub map_token_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct BtfLoadAttr {
+    pub btf: u64,
+    pub btf_log_buf: u64,
+    pub btf_size: u32,
+    pub btf_log_size: u32,
+    pub btf_log_level: u32,
+    pub btf_log_true_size: u32,
+    pub btf_flags: u32,
+    pub btf_token_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TokenCreateAttr {
+    pub flags: u32,
+    pub bpffs_fd: u32,
+}
+
+/// Used by the `BPF_MAP_*_ELEM` commands.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct MapElemAttr {
+    pub map_fd: u32,
+    pub key: u64,
+    pub value: u64,
+    pub flags: u64,
+}
+
+/// Used by `BPF_LINK_CREATE` for the attach types of multi-program hooks,
+/// such as TCX.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct LinkCreateAttr {
+    pub prog_fd: u32,
+    pub target_ifindex: u32,
+    pub attach_type: u32,
+    pub flags: u32,
+    pub relative_fd: u32,
+    pub expected_revision: u64,
+}
+
+/// Used by the `*_GET_NEXT_ID` and `*_GET_FD_BY_ID` commands.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct GetIdAttr {
+    pub id: u32,
+    pub next_id: u32,
+    pub open_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct InfoAttr {
+    pub bpf_fd: u32,
+    pub info_len: u32,
+    pub info: u64,
+}
+
+/// Used by `BPF_PROG_TEST_RUN`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TestRunAttr {
+    pub prog_fd: u32,
+    pub retval: u32,
+    pub data_size_in: u32,
+    pub data_size_out: u32,
+    pub data_in: u64,
+    pub data_out: u64,
+    pub repeat: u32,
+    pub duration: u32,
+    pub ctx_size_in: u32,
+    pub ctx_size_out: u32,
+    pub ctx_in: u64,
+    pub ctx_out: u64,
+    pub flags: u32,
+    pub cpu: u32,
+    pub batch_size: u32,
+}
+
+/// Used by `BPF_PROG_ATTACH` and `BPF_PROG_DETACH`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct ProgAttachAttr {
+    pub target_fd: u32,
+    pub attach_bpf_fd: u32,
+    pub attach_type: u32,
+    pub attach_flags: u32,
+    pub replace_bpf_fd: u32,
+}
+
+/// Used by `BPF_PROG_QUERY`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct ProgQueryAttr {
+    pub target_fd: u32,
+    pub attach_type: u32,
+    pub query_flags: u32,
+    pub attach_flags: u32,
+    pub prog_ids: u64,
+    pub prog_cnt: u32,
+}
+
+/// Used by `BPF_OBJ_PIN` and `BPF_OBJ_GET`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct ObjAttr {
+    pub pathname: u64,
+    pub bpf_fd: u32,
+    pub file_flags: u32,
+}
+
+/// The leading fields of `struct bpf_prog_info`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct ProgInfo {
+    pub prog_type: u32,
+    pub id: u32,
+    pub tag: [u8; 8],
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct BtfInfo {
+    pub btf: u64,
+    pub btf_size: u32,
+    pub id: u32,
+    pub name: u64,
+    pub name_len: u32,
+    pub kernel_btf: u32,
+}
+
+/// Issues a `bpf(2)` command with `attr` as its attribute.
+///
+/// # Safety
+///
+/// `attr` must have the layout the kernel expects for `cmd`, and every
+/// pointer stored in it must be valid for the duration of the call.
+///
+/// The call goes through the provider set with `set_syscall_provider`, if
+/// any.
+pub unsafe fn bpf<T>(cmd: u32, attr: &mut T) -> io::Result<c_long> {
+    let attr = attr as *mut T as *mut u8;
+    let size = mem::size_of::<T>() as u32;
+    let ret = match syscall_provider() {
+        Some(provider) => provider.bpf(cmd, attr, size),
+        None => DirectSyscall.bpf(cmd, attr, size),
+    };
+    ret.map(|ret| ret as c_long)
+}
+
+pub fn prog_load(attr: &mut ProgLoadAttr) -> io::Result<RawFd> {
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_PROG_LOAD, attr).map(|fd| fd as RawFd) }
+}
+
+pub fn prog_test_run(attr: &mut TestRunAttr) -> io::Result<()> {
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_PROG_TEST_RUN, attr).map(|_| ()) }
+}
+
+pub fn map_create(attr: &mut MapCreateAttr) -> io::Result<RawFd> {
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_MAP_CREATE, attr).map(|fd| fd as RawFd) }
+}
+
+pub fn btf_load(data: &[u8], token: Option<RawFd>) -> io::Result<RawFd> {
+    let mut attr = BtfLoadAttr {
+        btf: data.as_ptr() as u64,
+        btf_size: data.len() as u32,
+        ..Default::default()
+    };
+    if let Some(token) = token {
+        attr.btf_flags |= BPF_F_TOKEN_FD;
+        attr.btf_token_fd = token as u32;
+    }
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_BTF_LOAD, &mut attr).map(|fd| fd as RawFd) }
+}
+
+/// Creates a BPF token from a BPF filesystem instance set up for
+/// delegation.
+pub fn token_create(bpffs_fd: RawFd) -> io::Result<RawFd> {
+    let mut attr = TokenCreateAttr {
+        flags: 0,
+        bpffs_fd: bpffs_fd as u32,
+    };
+    unsafe { bpf(BPF_TOKEN_CREATE, &mut attr).map(|fd| fd as RawFd) }
+}
+
+pub fn link_create(attr: &mut LinkCreateAttr) -> io::Result<RawFd> {
+    unsafe { bpf(BPF_LINK_CREATE, attr).map(|fd| fd as RawFd) }
+}
+
+/// Looks up `key` in the map `fd`, copying the value to `value`.
+///
+/// # Safety
+///
+/// `key` and `value` must point to buffers of the map's key and value size.
+pub unsafe fn map_lookup_elem(fd: RawFd, key: *const u8, value: *mut u8, flags: u64) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: fd as u32,
+        key: key as u64,
+        value: value as u64,
+        flags,
+    };
+    bpf(bpf_sys::bpf_cmd_BPF_MAP_LOOKUP_ELEM, &mut attr).map(|_| ())
+}
+
+/// Sets the value of `key` in the map `fd` to `value`.
+///
+/// # Safety
+///
+/// `key` and `value` must point to buffers of the map's key and value size.
+pub unsafe fn map_update_elem(fd: RawFd, key: *const u8, value: *const u8, flags: u64) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: fd as u32,
+        key: key as u64,
+        value: value as u64,
+        flags,
+    };
+    bpf(bpf_sys::bpf_cmd_BPF_MAP_UPDATE_ELEM, &mut attr).map(|_| ())
+}
+
+/// Removes `key` from the map `fd`.
+///
+/// # Safety
+///
+/// `key` must point to a buffer of the map's key size.
+pub unsafe fn map_delete_elem(fd: RawFd, key: *const u8) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: fd as u32,
+        key: key as u64,
+        ..Default::default()
+    };
+    bpf(bpf_sys::bpf_cmd_BPF_MAP_DELETE_ELEM, &mut attr).map(|_| ())
+}
+
+/// Pins the object `fd` to `path`, which must be on a BPF filesystem.
+pub fn obj_pin(fd: RawFd, path: &CStr) -> io::Result<()> {
+    let mut attr = ObjAttr {
+        pathname: path.as_ptr() as u64,
+        bpf_fd: fd as u32,
+        ..Default::default()
+    };
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_OBJ_PIN, &mut attr).map(|_| ()) }
+}
+
+/// Opens the object pinned to `path`.
+pub fn obj_get(path: &CStr) -> io::Result<RawFd> {
+    let mut attr = ObjAttr {
+        pathname: path.as_ptr() as u64,
+        ..Default::default()
+    };
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_OBJ_GET, &mut attr).map(|fd| fd as RawFd) }
+}
+
+/// Copies the key following `key` in the map `fd` to `next_key`, or the
+/// first key if `key` is null or not in the map. Fails with `ENOENT` after
+/// the last key.
+///
+/// # Safety
+///
+/// `key`, unless null, and `next_key` must point to buffers of the map's key
+/// size.
+pub unsafe fn map_get_next_key(fd: RawFd, key: *const u8, next_key: *mut u8) -> io::Result<()> {
+    let mut attr = MapElemAttr {
+        map_fd: fd as u32,
+        key: key as u64,
+        value: next_key as u64,
+        flags: 0,
+    };
+    bpf(bpf_sys::bpf_cmd_BPF_MAP_GET_NEXT_KEY, &mut attr).map(|_| ())
+}
+
+pub fn prog_attach(attr: &mut ProgAttachAttr) -> io::Result<()> {
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_PROG_ATTACH, attr).map(|_| ()) }
+}
+
+pub fn prog_detach(attr: &mut ProgAttachAttr) -> io::Result<()> {
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_PROG_DETACH, attr).map(|_| ()) }
+}
+
+/// Returns the ids of the programs attached to `target_fd` at `attach_type`,
+/// in the order they run.
+pub fn prog_query(target_fd: RawFd, attach_type: u32) -> io::Result<Vec<u32>> {
+    let mut attr = ProgQueryAttr {
+        target_fd: target_fd as u32,
+        attach_type,
+        ..Default::default()
+    };
+    // the first pass only counts the programs
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_PROG_QUERY, &mut attr)? };
+    loop {
+        let mut ids = vec![0u32; attr.prog_cnt as usize];
+        attr.prog_ids = ids.as_mut_ptr() as u64;
+        match unsafe { bpf(bpf_sys::bpf_cmd_BPF_PROG_QUERY, &mut attr) } {
+            Ok(_) => {
+                ids.truncate(attr.prog_cnt as usize);
+                return Ok(ids);
+            }
+            // more programs were attached meanwhile, and prog_cnt holds
+            // their new count
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOSPC) => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn prog_get_fd_by_id(id: u32) -> io::Result<RawFd> {
+    let mut attr = GetIdAttr {
+        id,
+        ..Default::default()
+    };
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_PROG_GET_FD_BY_ID, &mut attr).map(|fd| fd as RawFd) }
+}
+
+pub fn btf_get_next_id(id: u32) -> io::Result<u32> {
+    let mut attr = GetIdAttr {
+        id,
+        ..Default::default()
+    };
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_BTF_GET_NEXT_ID, &mut attr)? };
+    Ok(attr.next_id)
+}
+
+pub fn btf_get_fd_by_id(id: u32) -> io::Result<RawFd> {
+    let mut attr = GetIdAttr {
+        id,
+        ..Default::default()
+    };
+    unsafe { bpf(bpf_sys::bpf_cmd_BPF_BTF_GET_FD_BY_ID, &mut attr).map(|fd| fd as RawFd) }
+}
+
+/// Fills `info` through `BPF_OBJ_GET_INFO_BY_FD`.
+///
+/// # Safety
+///
+/// `T` must be the info struct matching the kind of object `fd` refers to.
+pub unsafe fn obj_get_info_by_fd<T>(fd: RawFd, info: &mut T) -> io::Result<()> {
+    let mut attr = InfoAttr {
+        bpf_fd: fd as u32,
+        info_len: mem::size_of::<T>() as u32,
+        info: info as *mut T as u64,
+    };
+    bpf(bpf_sys::bpf_cmd_BPF_OBJ_GET_INFO_BY_FD, &mut attr).map(|_| ())
+}
+
+/// Finds the kernel BTF object of a loaded module and returns an fd to it.
+///
+/// The caller owns the returned fd.
+pub fn module_btf_fd(module: &str) -> io::Result<RawFd> {
+    let mut id = 0;
+    loop {
+        id = btf_get_next_id(id)?;
+        let fd = match btf_get_fd_by_id(id) {
+            Ok(fd) => fd,
+            // the object went away between the two calls
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+            Err(e) => return Err(e),
+        };
+
+        let mut name = [0u8; 64];
+        let mut info = BtfInfo {
+            name: name.as_mut_ptr() as u64,
+            name_len: name.len() as u32,
+            ..Default::default()
+        };
+        let res = unsafe { obj_get_info_by_fd(fd, &mut info) };
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        if res.is_ok() && info.kernel_btf != 0 && &name[..len] == module.as_bytes() {
+            return Ok(fd);
+        }
+
+        unsafe { close(fd) };
+        res?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn offset<T, F>(attr: &T, field: &F) -> usize {
+        field as *const F as usize - attr as *const T as usize
+    }
+
+    #[test]
+    fn test_token_fd_offsets() {
+        let attr = ProgLoadAttr::default();
+        assert_eq!(offset(&attr, &attr.prog_token_fd), 144);
+        let attr = MapCreateAttr::default();
+        assert_eq!(offset(&attr, &attr.map_token_fd), 76);
+        let attr = BtfLoadAttr::default();
+        assert_eq!(offset(&attr, &attr.btf_token_fd), 36);
+    }
+}
@@ -5,4 +5,6 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+pub(crate) mod bpf;
+pub(crate) mod netlink;
 pub mod perf;
@@ -0,0 +1,360 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A minimal `rtnetlink(7)` client.
+//!
+//! Only what's needed to configure interfaces for BPF programs is supported:
+//! requests are built as a `nlmsghdr`, a fixed family header, and a list of
+//! (possibly nested) attributes, and are sent one at a time, waiting for the
+//! kernel's acknowledgement, or for its reply to a query.
+
+use libc::{
+    bind, close, recv, send, sockaddr, sockaddr_nl, socket, AF_NETLINK, NETLINK_ROUTE,
+    SOCK_CLOEXEC, SOCK_RAW,
+};
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+pub use libc::{RTM_DELTFILTER, RTM_GETLINK, RTM_NEWQDISC, RTM_NEWTFILTER};
+
+pub const NLM_F_REQUEST: u16 = 0x1;
+pub const NLM_F_ACK: u16 = 0x4;
+pub const NLM_F_DUMP: u16 = 0x300;
+pub const NLM_F_EXCL: u16 = 0x200;
+pub const NLM_F_CREATE: u16 = 0x400;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLMSG_HDRLEN: usize = 16;
+const NLA_F_NESTED: u16 = 1 << 15;
+const NLA_F_NET_BYTEORDER: u16 = 1 << 14;
+
+/// Rounds `len` up to the 4 byte alignment of netlink messages and attributes.
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// A netlink request under construction.
+pub struct Message {
+    buf: Vec<u8>,
+    nested: Vec<usize>,
+}
+
+impl Message {
+    /// Starts a message of type `kind`, with the family specific `header`
+    /// following the `nlmsghdr`.
+    pub fn new(kind: u16, flags: u16, header: &[u8]) -> Message {
+        let mut buf = vec![0u8; NLMSG_HDRLEN];
+        buf[4..6].copy_from_slice(&kind.to_ne_bytes());
+        buf[6..8].copy_from_slice(&flags.to_ne_bytes());
+        let mut msg = Message {
+            buf,
+            nested: vec![],
+        };
+        msg.push(header);
+        msg
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.buf.resize(align(self.buf.len()), 0);
+    }
+
+    pub fn attr(&mut self, kind: u16, data: &[u8]) -> &mut Message {
+        let len = (4 + data.len()) as u16;
+        self.buf.extend_from_slice(&len.to_ne_bytes());
+        self.buf.extend_from_slice(&kind.to_ne_bytes());
+        self.push(data);
+        self
+    }
+
+    pub fn attr_u32(&mut self, kind: u16, value: u32) -> &mut Message {
+        self.attr(kind, &value.to_ne_bytes())
+    }
+
+    /// Adds a NUL terminated string attribute.
+    pub fn attr_str(&mut self, kind: u16, value: &str) -> &mut Message {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        self.attr(kind, &data)
+    }
+
+    /// Starts an attribute holding the attributes added until the matching
+    /// `end_nested`.
+    pub fn begin_nested(&mut self, kind: u16) -> &mut Message {
+        self.nested.push(self.buf.len());
+        self.attr(kind | NLA_F_NESTED, &[])
+    }
+
+    pub fn end_nested(&mut self) -> &mut Message {
+        let start = self.nested.pop().expect("unbalanced nested attribute");
+        let len = (self.buf.len() - start) as u16;
+        self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self
+    }
+
+    /// Returns the finished message, with sequence number `seq`.
+    fn finish(&mut self, seq: u32) -> &[u8] {
+        assert!(self.nested.is_empty(), "unbalanced nested attribute");
+        let len = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        &self.buf
+    }
+}
+
+/// A `NETLINK_ROUTE` socket.
+pub struct Socket {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl Socket {
+    pub fn open() -> io::Result<Socket> {
+        let fd = unsafe { socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let sock = Socket { fd, seq: 0 };
+
+        let mut addr: sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = AF_NETLINK as u16;
+        let ret = unsafe {
+            bind(
+                fd,
+                &addr as *const sockaddr_nl as *const sockaddr,
+                mem::size_of::<sockaddr_nl>() as u32,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(sock)
+    }
+
+    /// Sends `msg`, which must request an acknowledgement with
+    /// `NLM_F_ACK`, and waits for the kernel to process it.
+    pub fn request(&mut self, msg: &mut Message) -> io::Result<()> {
+        let seq = self.send(msg)?;
+        self.recv(|buf| parse_ack(buf, seq))
+    }
+
+    /// Sends `msg`, a query such as `RTM_GETLINK`, and returns the payload
+    /// of the kernel's reply, following its `nlmsghdr`.
+    pub fn query(&mut self, msg: &mut Message) -> io::Result<Vec<u8>> {
+        let seq = self.send(msg)?;
+        self.recv(|buf| parse_reply(buf, seq))
+    }
+
+    /// Sends `msg`, a query with `NLM_F_DUMP` such as `RTM_GETLINK`, and
+    /// returns the payloads of all the messages of the kernel's reply.
+    pub fn dump(&mut self, msg: &mut Message) -> io::Result<Vec<Vec<u8>>> {
+        let seq = self.send(msg)?;
+        let mut payloads = vec![];
+        self.recv(|buf| parse_dump(buf, seq, &mut payloads))?;
+        Ok(payloads)
+    }
+
+    fn send(&mut self, msg: &mut Message) -> io::Result<u32> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        let buf = msg.finish(seq);
+        if unsafe { send(self.fd, buf.as_ptr() as *const _, buf.len(), 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(seq)
+    }
+
+    /// Receives messages until `parse` finds the result in one of them.
+    fn recv<T>(&mut self, mut parse: impl FnMut(&[u8]) -> Option<io::Result<T>>) -> io::Result<T> {
+        // large enough for the batches of messages of dumps
+        let mut buf = vec![0u8; 32768];
+        loop {
+            let len = unsafe { recv(self.fd, buf.as_mut_ptr() as *mut _, buf.len(), 0) };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(res) = parse(&buf[..len as usize]) {
+                return res;
+            }
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { close(self.fd) };
+    }
+}
+
+/// Finds the acknowledgement of request `seq` among the messages in `buf`.
+fn parse_ack(buf: &[u8], seq: u32) -> Option<io::Result<()>> {
+    parse_reply(buf, seq).map(|res| res.map(|_| ()))
+}
+
+/// Finds the reply to request `seq` among the messages in `buf`, and returns
+/// its payload. Acknowledgements have an empty payload.
+fn parse_reply(mut buf: &[u8], seq: u32) -> Option<io::Result<Vec<u8>>> {
+    let u32_at = |buf: &[u8], i: usize| {
+        let mut word = [0u8; 4];
+        word.copy_from_slice(&buf[i..i + 4]);
+        u32::from_ne_bytes(word)
+    };
+
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32_at(buf, 0) as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return Some(Err(io::Error::from(io::ErrorKind::InvalidData)));
+        }
+        let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+        if u32_at(buf, 8) == seq {
+            if kind != NLMSG_ERROR {
+                return Some(Ok(buf[NLMSG_HDRLEN..len].to_vec()));
+            }
+            if len >= NLMSG_HDRLEN + 4 {
+                let errno = -(u32_at(buf, NLMSG_HDRLEN) as i32);
+                return Some(match errno {
+                    0 => Ok(vec![]),
+                    errno => Err(io::Error::from_raw_os_error(errno)),
+                });
+            }
+        }
+        buf = &buf[align(len).min(buf.len())..];
+    }
+
+    None
+}
+
+/// Collects the payloads of the messages of the dump `seq` in `buf`, until
+/// the `NLMSG_DONE` message ending it.
+fn parse_dump(mut buf: &[u8], seq: u32, payloads: &mut Vec<Vec<u8>>) -> Option<io::Result<()>> {
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return Some(Err(io::Error::from(io::ErrorKind::InvalidData)));
+        }
+        let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+        let msg_seq = u32::from_ne_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        if msg_seq == seq {
+            match kind {
+                NLMSG_DONE => return Some(Ok(())),
+                NLMSG_ERROR => return parse_ack(&buf[..len], seq),
+                _ => payloads.push(buf[NLMSG_HDRLEN..len].to_vec()),
+            }
+        }
+        buf = &buf[align(len).min(buf.len())..];
+    }
+
+    None
+}
+
+/// Splits `buf` into attributes, as `(kind, data)`, skipping whatever
+/// doesn't parse.
+pub fn parse_attrs(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = vec![];
+    while buf.len() >= 4 {
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        if len < 4 || len > buf.len() {
+            break;
+        }
+        let kind = u16::from_ne_bytes([buf[2], buf[3]]) & !(NLA_F_NESTED | NLA_F_NET_BYTEORDER);
+        attrs.push((kind, &buf[4..len]));
+        buf = &buf[align(len).min(buf.len())..];
+    }
+
+    attrs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        let mut msg = Message::new(RTM_NEWTFILTER, NLM_F_REQUEST, &[1, 2, 3, 4]);
+        msg.attr_str(1, "bpf")
+            .begin_nested(2)
+            .attr_u32(6, 7)
+            .end_nested();
+        let buf = msg.finish(5).to_vec();
+
+        assert_eq!(buf.len(), 16 + 4 + 8 + 4 + 8);
+        assert_eq!(&buf[0..4], &(buf.len() as u32).to_ne_bytes());
+        assert_eq!(&buf[8..12], &5u32.to_ne_bytes());
+        assert_eq!(&buf[20..22], &8u16.to_ne_bytes());
+        assert_eq!(&buf[24..28], b"bpf\0");
+        // the nested attribute spans the attribute inside it
+        assert_eq!(&buf[28..30], &12u16.to_ne_bytes());
+        assert_eq!(&buf[30..32], &(2 | NLA_F_NESTED).to_ne_bytes());
+    }
+
+    #[test]
+    fn test_parse_ack() {
+        let mut ack = vec![0u8; 36];
+        ack[0..4].copy_from_slice(&36u32.to_ne_bytes());
+        ack[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        ack[8..12].copy_from_slice(&3u32.to_ne_bytes());
+        assert!(parse_ack(&ack, 2).is_none());
+        assert!(parse_ack(&ack, 3).unwrap().is_ok());
+
+        ack[16..20].copy_from_slice(&(-libc::EEXIST).to_ne_bytes());
+        let err = parse_ack(&ack, 3).unwrap().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let mut reply = vec![0u8; 16];
+        reply[0..4].copy_from_slice(&28u32.to_ne_bytes());
+        reply[4..6].copy_from_slice(&libc::RTM_NEWLINK.to_ne_bytes());
+        reply[8..12].copy_from_slice(&3u32.to_ne_bytes());
+        // IFLA_MTU 1500, then a nested attribute with only a header
+        reply.extend_from_slice(&8u16.to_ne_bytes());
+        reply.extend_from_slice(&4u16.to_ne_bytes());
+        reply.extend_from_slice(&1500u32.to_ne_bytes());
+        reply.extend_from_slice(&4u16.to_ne_bytes());
+        reply.extend_from_slice(&(9 | NLA_F_NESTED).to_ne_bytes());
+        assert!(parse_reply(&reply, 2).is_none());
+
+        let payload = parse_reply(&reply, 3).unwrap().unwrap();
+        assert_eq!(payload.len(), 12);
+        let attrs = parse_attrs(&payload);
+        assert_eq!(attrs, vec![(4, &1500u32.to_ne_bytes()[..]), (9, &[][..])]);
+        // truncated attributes are dropped
+        assert_eq!(parse_attrs(&payload[..6]), vec![]);
+    }
+
+    #[test]
+    fn test_parse_dump() {
+        let msg = |kind: u16, seq: u32, payload: &[u8]| {
+            let mut msg = vec![0u8; 16];
+            msg[0..4].copy_from_slice(&(16 + payload.len() as u32).to_ne_bytes());
+            msg[4..6].copy_from_slice(&kind.to_ne_bytes());
+            msg[8..12].copy_from_slice(&seq.to_ne_bytes());
+            msg.extend_from_slice(payload);
+            msg
+        };
+        let mut payloads = vec![];
+        let mut batch = msg(libc::RTM_NEWLINK, 3, &[1, 2, 3, 4]);
+        batch.extend(msg(libc::RTM_NEWLINK, 2, &[9, 9, 9, 9]));
+        batch.extend(msg(libc::RTM_NEWLINK, 3, &[5, 6, 7, 8]));
+        assert!(parse_dump(&batch, 3, &mut payloads).is_none());
+        assert_eq!(payloads, vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]]);
+
+        // the dump goes on in the next batch
+        let mut batch = msg(libc::RTM_NEWLINK, 3, &[]);
+        batch.extend(msg(NLMSG_DONE, 3, &[0, 0, 0, 0]));
+        assert!(parse_dump(&batch, 3, &mut payloads).unwrap().is_ok());
+        assert_eq!(payloads.len(), 3);
+
+        let err = msg(NLMSG_ERROR, 3, &(-libc::EPERM).to_ne_bytes());
+        let err = parse_dump(&err, 3, &mut payloads).unwrap().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    }
+}
@@ -0,0 +1,430 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Traffic control programs
+//!
+//! `tc_action` programs run on the ingress and egress paths of an interface.
+//! On kernel 6.6 or later they are attached to the TCX hook, which keeps
+//! the programs of an interface in an explicit order and detaches each one
+//! when its `Link` is dropped, without touching the others.
+//!
+//! Older kernels don't have TCX. There, the program is attached as a
+//! direct-action `cls_bpf` filter of the interface's `clsact` qdisc, which
+//! is created if needed, and the order is mapped onto filter priorities.
+//!
+//! Running a firewall ahead of an accounting program, whatever order they
+//! are attached in:
+//!
+//! ```no_run
+//! use redbpf::{Direction, Module, TcxOrder};
+//!
+//! let code = std::fs::read("bpf.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.load(module.version, module.license.clone()).unwrap();
+//! }
+//! let ifindex = 2;
+//!
+//! let firewall = module.programs[0].tc_classifier().unwrap();
+//! let accounting = module.programs[1].tc_classifier().unwrap();
+//! let accounting = accounting
+//!     .attach_tcx(ifindex, Direction::Ingress, TcxOrder::Last)
+//!     .unwrap();
+//! let firewall = firewall
+//!     .attach_tcx(ifindex, Direction::Ingress, TcxOrder::Before(&accounting))
+//!     .unwrap();
+//!
+//! // both programs are detached when the links are dropped
+//! drop(firewall);
+//! drop(accounting);
+//! ```
+
+use crate::sys::bpf::{
+    link_create, LinkCreateAttr, BPF_F_AFTER, BPF_F_BEFORE, BPF_F_LINK, BPF_TCX_EGRESS,
+    BPF_TCX_INGRESS,
+};
+use crate::sys::netlink::{
+    Message, Socket, NLM_F_ACK, NLM_F_CREATE, NLM_F_EXCL, NLM_F_REQUEST, RTM_DELTFILTER,
+    RTM_NEWQDISC, RTM_NEWTFILTER,
+};
+use crate::{LoadError, Program, ProgramKind, Result};
+use libc::close;
+use std::io;
+use std::os::unix::io::RawFd;
+
+const TC_H_CLSACT: u32 = 0xFFFF_FFF1;
+const TC_H_MIN_INGRESS: u32 = 0xFFF2;
+const TC_H_MIN_EGRESS: u32 = 0xFFF3;
+const TCA_KIND: u16 = 1;
+const TCA_OPTIONS: u16 = 2;
+const TCA_BPF_FD: u16 = 6;
+const TCA_BPF_NAME: u16 = 7;
+const TCA_BPF_FLAGS: u16 = 8;
+const TCA_BPF_FLAG_ACT_DIRECT: u32 = 1;
+const ETH_P_ALL: u16 = 0x0003;
+/// Handle of the filters created by the fallback, which are told apart by
+/// their priority.
+const FILTER_HANDLE: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Ingress,
+    Egress,
+}
+
+/// Position of a program among the programs attached to the same hook.
+///
+/// Programs run in order, for as long as they return `TC_ACT_UNSPEC`.
+#[derive(Clone, Copy)]
+pub enum TcxOrder<'a> {
+    /// Run ahead of all programs attached so far.
+    First,
+    /// Run after all programs attached so far.
+    Last,
+    /// Run right before the program of the link.
+    Before(&'a Link),
+    /// Run right after the program of the link.
+    After(&'a Link),
+}
+
+/// An attached program, which is detached when the `Link` is dropped.
+pub struct Link {
+    inner: LinkInner,
+}
+
+enum LinkInner {
+    Tcx(RawFd),
+    Filter {
+        ifindex: u32,
+        parent: u32,
+        priority: u16,
+    },
+}
+
+impl Link {
+    /// Returns the `bpf_link` fd, or `None` if the program was attached as a
+    /// `cls_bpf` filter on a kernel without TCX.
+    pub fn fd(&self) -> Option<RawFd> {
+        match self.inner {
+            LinkInner::Tcx(fd) => Some(fd),
+            LinkInner::Filter { .. } => None,
+        }
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        match self.inner {
+            LinkInner::Tcx(fd) => unsafe {
+                close(fd);
+            },
+            LinkInner::Filter {
+                ifindex,
+                parent,
+                priority,
+            } => {
+                let _ = Socket::open().and_then(|mut sock| {
+                    let header = tcmsg(ifindex, FILTER_HANDLE, parent, filter_info(priority));
+                    let mut msg = Message::new(RTM_DELTFILTER, NLM_F_REQUEST | NLM_F_ACK, &header);
+                    msg.attr_str(TCA_KIND, "bpf");
+                    sock.request(&mut msg)
+                });
+            }
+        }
+    }
+}
+
+/// A loaded `tc_action` program, see `Program::tc_classifier`.
+pub struct TcClassifier<'a> {
+    prog: &'a Program,
+}
+
+impl Program {
+    /// Returns the program as a classifier to attach to TC hooks, or fails
+    /// with `LoadError::BPF` unless it's a loaded `tc_action` program.
+    pub fn tc_classifier(&self) -> Result<TcClassifier<'_>> {
+        match (self.kind, self.fd) {
+            (ProgramKind::TcAction, Some(_)) => Ok(TcClassifier { prog: self }),
+            _ => Err(LoadError::BPF),
+        }
+    }
+}
+
+impl TcClassifier<'_> {
+    /// Attaches the program to the `direction` hook of the interface
+    /// `ifindex`, at the position given by `priority`.
+    ///
+    /// Uses TCX where the kernel supports it, and a `cls_bpf` filter on the
+    /// `clsact` qdisc otherwise. The filter's priority is derived from
+    /// `priority`: `First` and `Last` take the highest and lowest priority,
+    /// while `Before` and `After` take the priority next to the one of the
+    /// link. Attaching fails with `EEXIST` if the priority is already taken.
+    pub fn attach_tcx(
+        &self,
+        ifindex: u32,
+        direction: Direction,
+        priority: TcxOrder,
+    ) -> Result<Link> {
+        let fd = self.prog.fd.ok_or(LoadError::BPF)?;
+
+        let relative = match priority {
+            TcxOrder::Before(link) | TcxOrder::After(link) => link.fd(),
+            _ => None,
+        };
+        let tcx_order = match priority {
+            TcxOrder::First => Some((BPF_F_BEFORE, 0)),
+            TcxOrder::Last => Some((BPF_F_AFTER, 0)),
+            TcxOrder::Before(_) => relative.map(|fd| (BPF_F_BEFORE | BPF_F_LINK, fd)),
+            TcxOrder::After(_) => relative.map(|fd| (BPF_F_AFTER | BPF_F_LINK, fd)),
+        };
+        // a link to a filter means the kernel has no TCX
+        if let Some((flags, relative_fd)) = tcx_order {
+            let mut attr = LinkCreateAttr {
+                prog_fd: fd as u32,
+                target_ifindex: ifindex,
+                attach_type: tcx_attach_type(direction),
+                flags,
+                relative_fd: relative_fd as u32,
+                ..Default::default()
+            };
+            match link_create(&mut attr) {
+                Ok(link) => {
+                    return Ok(Link {
+                        inner: LinkInner::Tcx(link),
+                    })
+                }
+                Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) && !tcx_supported(fd) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let filter_priority = filter_priority(priority)?;
+        attach_filter(fd, &self.prog.name, ifindex, direction, filter_priority)
+            .map_err(LoadError::from)
+    }
+}
+
+fn tcx_attach_type(direction: Direction) -> u32 {
+    match direction {
+        Direction::Ingress => BPF_TCX_INGRESS,
+        Direction::Egress => BPF_TCX_EGRESS,
+    }
+}
+
+/// Tells whether the kernel has TCX, by attaching the program `prog_fd` to
+/// an interface that doesn't exist: kernels with TCX look the interface up
+/// and fail with `ENODEV`, while older ones reject the attach type with
+/// `EINVAL` right away.
+fn tcx_supported(prog_fd: RawFd) -> bool {
+    let mut attr = LinkCreateAttr {
+        prog_fd: prog_fd as u32,
+        target_ifindex: 0,
+        attach_type: BPF_TCX_INGRESS,
+        ..Default::default()
+    };
+    match link_create(&mut attr) {
+        Ok(fd) => {
+            unsafe { close(fd) };
+            true
+        }
+        Err(e) => e.raw_os_error() == Some(libc::ENODEV),
+    }
+}
+
+fn filter_priority(order: TcxOrder) -> io::Result<u16> {
+    let relative = |link: &Link| match link.inner {
+        LinkInner::Filter { priority, .. } => Ok(priority),
+        LinkInner::Tcx(_) => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+    };
+    let priority = match order {
+        TcxOrder::First => Some(1),
+        TcxOrder::Last => Some(u16::MAX),
+        TcxOrder::Before(link) => relative(link)?.checked_sub(1).filter(|&p| p > 0),
+        TcxOrder::After(link) => relative(link)?.checked_add(1),
+    };
+
+    priority.ok_or_else(|| io::Error::from_raw_os_error(libc::ERANGE))
+}
+
+fn attach_filter(
+    prog_fd: RawFd,
+    name: &str,
+    ifindex: u32,
+    direction: Direction,
+    priority: u16,
+) -> io::Result<Link> {
+    let mut sock = Socket::open()?;
+
+    let header = tcmsg(ifindex, TC_H_CLSACT & 0xFFFF_0000, TC_H_CLSACT, 0);
+    let flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL;
+    let mut msg = Message::new(RTM_NEWQDISC, flags, &header);
+    msg.attr_str(TCA_KIND, "clsact");
+    match sock.request(&mut msg) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EEXIST) => (),
+        res => res?,
+    }
+
+    let parent = (TC_H_CLSACT & 0xFFFF_0000)
+        | match direction {
+            Direction::Ingress => TC_H_MIN_INGRESS,
+            Direction::Egress => TC_H_MIN_EGRESS,
+        };
+    let header = tcmsg(ifindex, FILTER_HANDLE, parent, filter_info(priority));
+    let mut msg = Message::new(RTM_NEWTFILTER, flags, &header);
+    msg.attr_str(TCA_KIND, "bpf")
+        .begin_nested(TCA_OPTIONS)
+        .attr_u32(TCA_BPF_FD, prog_fd as u32)
+        .attr_str(TCA_BPF_NAME, name)
+        .attr_u32(TCA_BPF_FLAGS, TCA_BPF_FLAG_ACT_DIRECT)
+        .end_nested();
+    sock.request(&mut msg)?;
+
+    Ok(Link {
+        inner: LinkInner::Filter {
+            ifindex,
+            parent,
+            priority,
+        },
+    })
+}
+
+/// Filters keep their priority and protocol in `tcm_info`.
+fn filter_info(priority: u16) -> u32 {
+    u32::from(priority) << 16 | u32::from(ETH_P_ALL.to_be())
+}
+
+/// Returns a `struct tcmsg`.
+fn tcmsg(ifindex: u32, handle: u32, parent: u32, info: u32) -> Vec<u8> {
+    let mut header = vec![0u8; 4];
+    header.extend_from_slice(&ifindex.to_ne_bytes());
+    header.extend_from_slice(&handle.to_ne_bytes());
+    header.extend_from_slice(&parent.to_ne_bytes());
+    header.extend_from_slice(&info.to_ne_bytes());
+    header
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sys::bpf::prog_query;
+    use crate::test_util::{create_veth, in_netns, insn, load_program, test_run};
+
+    // r0 = TC_ACT_UNSPEC; exit
+    const UNSPEC: [u8; 16] = [
+        0xb7, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0x95, 0, 0, 0, 0, 0, 0, 0,
+    ];
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_attach_tcx() {
+        in_netns(|| {
+            let lo = 1;
+            let first = load_program("tc_action", "tcx_first", &UNSPEC);
+            let second = load_program("tc_action", "tcx_second", &UNSPEC);
+            let first_tc = first.tc_classifier().unwrap();
+            let second_tc = second.tc_classifier().unwrap();
+
+            for _ in 0..2 {
+                let second_link = second_tc
+                    .attach_tcx(lo, Direction::Ingress, TcxOrder::Last)
+                    .unwrap();
+                let first_link = first_tc
+                    .attach_tcx(lo, Direction::Ingress, TcxOrder::Before(&second_link))
+                    .unwrap();
+                // the programs of filters can't be queried
+                let tcx = second_link.fd().is_some();
+                if tcx {
+                    let ids = prog_query(lo as RawFd, BPF_TCX_INGRESS).unwrap();
+                    assert_eq!(ids, [first.id().unwrap(), second.id().unwrap()]);
+                }
+                // detaching must leave nothing behind that the next round
+                // trips over
+                drop(second_link);
+                drop(first_link);
+                if tcx {
+                    assert!(prog_query(lo as RawFd, BPF_TCX_INGRESS).unwrap().is_empty());
+                }
+            }
+        });
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_attach_filter() {
+        in_netns(|| {
+            let lo = 1;
+            let prog = load_program("tc_action", "tc_filter", &UNSPEC);
+            let fd = prog.fd.unwrap();
+            let link = attach_filter(fd, &prog.name, lo, Direction::Egress, 1).unwrap();
+            assert!(link.fd().is_none());
+            let err = attach_filter(fd, &prog.name, lo, Direction::Egress, 1).err();
+            assert_eq!(err.unwrap().raw_os_error(), Some(libc::EEXIST));
+
+            let after = TcxOrder::After(&link);
+            assert_eq!(filter_priority(after).unwrap(), 2);
+            assert!(filter_priority(TcxOrder::Before(&link)).is_err());
+            drop(link);
+            attach_filter(fd, &prog.name, lo, Direction::Egress, 1).unwrap();
+        });
+    }
+
+    /// Waits up to a second for a packet on the raw socket `fd`.
+    fn recv_packet(fd: RawFd) -> Option<Vec<u8>> {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut pollfd, 1, 1000) } != 1 {
+            return None;
+        }
+        let mut buf = vec![0u8; 2048];
+        let len = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut _, buf.len(), 0) };
+        buf.truncate(len.max(0) as usize);
+        Some(buf)
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_mirror_to() {
+        in_netns(|| {
+            let (veth, _) = create_veth("mirror0", "capture0");
+            let capture = unsafe { bpf_sys::bpf_open_raw_sock(b"capture0\0".as_ptr() as *const _) };
+            assert!(capture >= 0);
+            // what SkBuffContext::mirror_to(veth, false) does
+            let code = [
+                insn(0xb7, 2, 0, 0, veth as i32), // r2 = veth
+                insn(0xb7, 3, 0, 0, 0),           // r3 = 0, to its egress
+                insn(0x85, 0, 0, 0, 13),          // call bpf_clone_redirect
+                insn(0xb7, 0, 0, 0, 0),           // r0 = TC_ACT_OK
+                insn(0x95, 0, 0, 0, 0),           // exit
+            ]
+            .concat();
+            let prog = load_program("tc_action", "mirror", &code);
+
+            // a broadcast frame of the local experimental ethertype
+            let mut packet = vec![0xff; 6];
+            packet.extend_from_slice(&[2, 0, 0, 0, 0, 1, 0x88, 0xb5]);
+            packet.resize(64, 0x42);
+            // the original goes on unchanged
+            assert_eq!(test_run(&prog, &packet), (0, packet.clone()));
+            // among neighbour discovery of the devices coming up, the copy
+            // arrives at the peer
+            let mirrored = std::iter::from_fn(|| recv_packet(capture)).any(|p| p == packet);
+            unsafe { close(capture) };
+            assert!(mirrored);
+        });
+    }
+
+    #[test]
+    fn test_tc_classifier() {
+        let prog = Program::new("tc_action", "tc_unloaded", &UNSPEC).unwrap();
+        assert!(prog.tc_classifier().is_err());
+        let prog = Program::new("xdp", "xdp_unloaded", &UNSPEC).unwrap();
+        assert!(prog.tc_classifier().is_err());
+    }
+}
@@ -0,0 +1,519 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Test runs
+//!
+//! The kernel can run a loaded program on a packet handed to it, without
+//! attaching it anywhere, through `BPF_PROG_TEST_RUN` (kernel 4.12 or
+//! later). `Module::xdp_test_runner` wraps this in a closure, which makes
+//! testing the packet logic of an XDP program look like calling a function:
+//!
+//! ```no_run
+//! use redbpf::{Module, XdpAction};
+//!
+//! let code = std::fs::read("firewall.elf").unwrap();
+//! let mut module = Module::parse(&code).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.load(module.version, module.license.clone()).unwrap();
+//! }
+//!
+//! let run = module.xdp_test_runner("block_port_80").unwrap();
+//! let packet = std::fs::read("http_syn.bin").unwrap();
+//! let (action, out) = run(&packet).unwrap();
+//! assert_eq!(action, XdpAction::Drop);
+//! assert_eq!(out, packet);
+//! ```
+
+use crate::sys::bpf::{prog_test_run, TestRunAttr};
+use crate::{LoadError, Module, ProgramKind, Result};
+use std::convert::TryFrom;
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// Room for the packet to grow by, on top of its size, in the output of a
+/// test run.
+const TEST_RUN_TAILROOM: usize = 256;
+
+/// The return value of an XDP program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum XdpAction {
+    Aborted = 0,
+    Drop = 1,
+    Pass = 2,
+    Tx = 3,
+    Redirect = 4,
+}
+
+impl TryFrom<u32> for XdpAction {
+    type Error = u32;
+
+    /// Converts the return value of a program, failing with the value if it
+    /// isn't a known action.
+    fn try_from(value: u32) -> std::result::Result<XdpAction, u32> {
+        use XdpAction::*;
+        match value {
+            0 => Ok(Aborted),
+            1 => Ok(Drop),
+            2 => Ok(Pass),
+            3 => Ok(Tx),
+            4 => Ok(Redirect),
+            _ => Err(value),
+        }
+    }
+}
+
+impl Module {
+    /// Returns a closure running the loaded XDP program `name` on a packet,
+    /// which returns the action the program took along with the packet as
+    /// the program left it.
+    ///
+    /// The packet must hold at least an Ethernet header. The closure fails
+    /// if the kernel can't run the program, or if the program returns
+    /// something other than an `XdpAction`. It borrows the module, which
+    /// keeps the program loaded for as long as the closure is around.
+    pub fn xdp_test_runner(
+        &self,
+        name: &str,
+    ) -> Result<impl Fn(&[u8]) -> Result<(XdpAction, Vec<u8>)> + '_> {
+        let fd = self
+            .programs
+            .iter()
+            .find(|prog| prog.name == name && prog.kind == ProgramKind::XDP)
+            .and_then(|prog| prog.fd)
+            .ok_or_else(|| {
+                LoadError::IO(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no loaded XDP program `{}`", name),
+                ))
+            })?;
+
+        Ok(move |packet: &[u8]| {
+            let (retval, out) = test_run(fd, packet)?;
+            let action = XdpAction::try_from(retval).map_err(|retval| {
+                LoadError::IO(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown XDP action {}", retval),
+                ))
+            })?;
+            Ok((action, out))
+        })
+    }
+}
+
+/// Runs the program `fd` once on `packet`, and returns its return value and
+/// the output packet.
+fn test_run(fd: RawFd, packet: &[u8]) -> io::Result<(u32, Vec<u8>)> {
+    let mut out = vec![0u8; packet.len() + TEST_RUN_TAILROOM];
+    loop {
+        let mut attr = TestRunAttr {
+            prog_fd: fd as u32,
+            data_size_in: packet.len() as u32,
+            data_size_out: out.len() as u32,
+            data_in: packet.as_ptr() as u64,
+            data_out: out.as_mut_ptr() as u64,
+            repeat: 1,
+            ..Default::default()
+        };
+        match prog_test_run(&mut attr) {
+            Ok(()) => {
+                out.truncate(attr.data_size_out as usize);
+                return Ok((attr.retval, out));
+            }
+            // the kernel reports the size of the whole output
+            Err(ref e)
+                if e.raw_os_error() == Some(libc::ENOSPC)
+                    && attr.data_size_out as usize > out.len() =>
+            {
+                out.resize(attr.data_size_out as usize, 0);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::features::probe_helper;
+    use crate::sys::bpf::{map_lookup_elem, map_update_elem, BPF_F_TEST_XDP_LIVE_FRAMES};
+    use crate::test_util::{create_map, create_veth, in_netns, insn, load_program};
+    use crate::{Program, DROP_REASONS_MAP};
+    use std::net::TcpListener;
+
+    /// Drops IPv4 TCP packets to port 80, assuming a little-endian host and
+    /// IP headers without options.
+    fn block_port_80() -> Vec<u8> {
+        [
+            insn(0x61, 2, 1, 4, 0),      // r2 = ctx->data_end
+            insn(0x61, 1, 1, 0, 0),      // r1 = ctx->data
+            insn(0xbf, 3, 1, 0, 0),      // r3 = r1
+            insn(0x07, 3, 0, 0, 38),     // r3 += 38
+            insn(0x2d, 3, 2, 8, 0),      // if r3 > r2 goto pass
+            insn(0x69, 4, 1, 12, 0),     // r4 = eth->h_proto
+            insn(0x55, 4, 0, 6, 0x08),   // if r4 != htons(ETH_P_IP) goto pass
+            insn(0x71, 4, 1, 23, 0),     // r4 = ip->protocol
+            insn(0x55, 4, 0, 4, 6),      // if r4 != IPPROTO_TCP goto pass
+            insn(0x69, 4, 1, 36, 0),     // r4 = tcp->dest
+            insn(0x55, 4, 0, 2, 0x5000), // if r4 != htons(80) goto pass
+            insn(0xb7, 0, 0, 0, 1),      // r0 = XDP_DROP
+            insn(0x95, 0, 0, 0, 0),      // exit
+            insn(0xb7, 0, 0, 0, 2),      // pass: r0 = XDP_PASS
+            insn(0x95, 0, 0, 0, 0),      // exit
+        ]
+        .concat()
+    }
+
+    /// Passes packets that, grown by `len_delta`, fit the MTU of `lo`, and
+    /// drops the others.
+    fn check_lo_mtu(len_delta: i32) -> Vec<u8> {
+        [
+            insn(0x62, 10, 0, -4, 0),       // *(u32 *)(r10 - 4) = 0
+            insn(0xb7, 2, 0, 0, 1),         // r2 = ifindex of lo
+            insn(0xbf, 3, 10, 0, 0),        // r3 = r10
+            insn(0x07, 3, 0, 0, -4),        // r3 -= 4
+            insn(0xb7, 4, 0, 0, len_delta), // r4 = len_delta
+            insn(0xb7, 5, 0, 0, 0),         // r5 = 0
+            insn(0x85, 0, 0, 0, 163),       // call bpf_check_mtu
+            insn(0x55, 0, 0, 2, 0),         // if r0 != 0 goto drop
+            insn(0xb7, 0, 0, 0, 2),         // r0 = XDP_PASS
+            insn(0x95, 0, 0, 0, 0),         // exit
+            insn(0xb7, 0, 0, 0, 1),         // drop: r0 = XDP_DROP
+            insn(0x95, 0, 0, 0, 0),         // exit
+        ]
+        .concat()
+    }
+
+    /// Looks up the socket of an IPv4 TCP packet without options, then calls
+    /// the SYN cookie helper `helper` with its headers, and writes what it
+    /// returned to the 8 bytes following the TCP header.
+    fn syncookie(helper: i32) -> Vec<u8> {
+        [
+            insn(0xbf, 6, 1, 0, 0),      // r6 = ctx
+            insn(0x61, 2, 1, 4, 0),      // r2 = ctx->data_end
+            insn(0x61, 7, 1, 0, 0),      // r7 = ctx->data
+            insn(0xbf, 3, 7, 0, 0),      // r3 = r7
+            insn(0x07, 3, 0, 0, 62),     // r3 += 62
+            insn(0x2d, 3, 2, 20, 0),     // if r3 > r2 goto pass
+            insn(0xbf, 1, 6, 0, 0),      // r1 = ctx
+            insn(0xbf, 2, 7, 0, 0),      // r2 = r7
+            insn(0x07, 2, 0, 0, 26),     // r2 = &ip->saddr, the tuple
+            insn(0xb7, 3, 0, 0, 12),     // r3 = sizeof(tuple.ipv4)
+            insn(0xb7, 4, 0, 0, -1),     // r4 = BPF_F_CURRENT_NETNS
+            insn(0xb7, 5, 0, 0, 0),      // r5 = 0
+            insn(0x85, 0, 0, 0, 99),     // call bpf_skc_lookup_tcp
+            insn(0x15, 0, 0, 12, 0),     // if r0 == 0 goto pass
+            insn(0xbf, 8, 0, 0, 0),      // r8 = sk
+            insn(0xbf, 1, 8, 0, 0),      // r1 = sk
+            insn(0xbf, 2, 7, 0, 0),      // r2 = r7
+            insn(0x07, 2, 0, 0, 14),     // r2 = ip
+            insn(0xb7, 3, 0, 0, 20),     // r3 = sizeof(*ip)
+            insn(0xbf, 4, 7, 0, 0),      // r4 = r7
+            insn(0x07, 4, 0, 0, 34),     // r4 = tcp
+            insn(0xb7, 5, 0, 0, 20),     // r5 = sizeof(*tcp)
+            insn(0x85, 0, 0, 0, helper), // call helper
+            insn(0x7b, 7, 0, 54, 0),     // *(u64 *)(r7 + 54) = r0
+            insn(0xbf, 1, 8, 0, 0),      // r1 = sk
+            insn(0x85, 0, 0, 0, 86),     // call bpf_sk_release
+            insn(0xb7, 0, 0, 0, 2),      // pass: r0 = XDP_PASS
+            insn(0x95, 0, 0, 0, 0),      // exit
+        ]
+        .concat()
+    }
+
+    /// A TCP packet from 127.0.0.1:12345 to 127.0.0.1:`dest`, with 8 bytes
+    /// of payload set to -1.
+    fn tcp_packet(dest: u16, flags: u8, seq: u32, ack_seq: u32) -> Vec<u8> {
+        let mut packet = packet(0x0800, 6, dest);
+        packet[26..30].copy_from_slice(&[127, 0, 0, 1]);
+        packet[30..34].copy_from_slice(&[127, 0, 0, 1]);
+        packet[38..42].copy_from_slice(&seq.to_be_bytes());
+        packet[42..46].copy_from_slice(&ack_seq.to_be_bytes());
+        packet[46] = 5 << 4;
+        packet[47] = flags;
+        packet.resize(62, 0xff);
+        packet
+    }
+
+    /// Runs one of the `syncookie` programs, and returns what its helper
+    /// returned, or -1 if it found no socket.
+    fn run_syncookie(fd: RawFd, packet: &[u8]) -> i64 {
+        let (action, out) = test_run(fd, packet).unwrap();
+        assert_eq!(action, XdpAction::Pass as u32);
+        let mut ret = [0u8; 8];
+        ret.copy_from_slice(&out[54..62]);
+        i64::from_ne_bytes(ret)
+    }
+
+    fn packet(ethertype: u16, protocol: u8, dest: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 54];
+        packet[12..14].copy_from_slice(&ethertype.to_be_bytes());
+        packet[14] = 0x45;
+        packet[23] = protocol;
+        packet[34..36].copy_from_slice(&12345u16.to_be_bytes());
+        packet[36..38].copy_from_slice(&dest.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_xdp_action() {
+        assert_eq!(XdpAction::try_from(1), Ok(XdpAction::Drop));
+        assert_eq!(XdpAction::try_from(4), Ok(XdpAction::Redirect));
+        assert_eq!(XdpAction::try_from(5), Err(5));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_xdp_test_runner() {
+        let prog = load_program("xdp", "block_port_80", &block_port_80());
+        let module = Module {
+            programs: vec![prog],
+            maps: vec![],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+        assert!(module.xdp_test_runner("block_port_443").is_err());
+        let run = module.xdp_test_runner("block_port_80").unwrap();
+
+        let http = packet(0x0800, 6, 80);
+        assert_eq!(run(&http).unwrap(), (XdpAction::Drop, http));
+        let https = packet(0x0800, 6, 443);
+        assert_eq!(run(&https).unwrap(), (XdpAction::Pass, https));
+        let udp = packet(0x0800, 17, 80);
+        assert_eq!(run(&udp).unwrap(), (XdpAction::Pass, udp));
+        let arp = packet(0x0806, 6, 80);
+        assert_eq!(run(&arp).unwrap(), (XdpAction::Pass, arp));
+        let truncated = packet(0x0800, 6, 80)[..30].to_vec();
+        assert_eq!(run(&truncated).unwrap(), (XdpAction::Pass, truncated));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_check_mtu() {
+        if !probe_helper(bpf_sys::bpf_prog_type_BPF_PROG_TYPE_XDP, 163) {
+            // bpf_check_mtu is supported since kernel 5.12
+            return;
+        }
+        let fits = load_program("xdp", "fits", &check_lo_mtu(0));
+        // more than the 64KiB MTU of lo
+        let too_big = load_program("xdp", "too_big", &check_lo_mtu(70000));
+        let module = Module {
+            programs: vec![fits, too_big],
+            maps: vec![],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+
+        let http = packet(0x0800, 6, 80);
+        let run = module.xdp_test_runner("fits").unwrap();
+        assert_eq!(run(&http).unwrap().0, XdpAction::Pass);
+        let run = module.xdp_test_runner("too_big").unwrap();
+        assert_eq!(run(&http).unwrap().0, XdpAction::Drop);
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_frame_len() {
+        if !probe_helper(bpf_sys::bpf_prog_type_BPF_PROG_TYPE_XDP, 188) {
+            // bpf_xdp_get_buff_len and multi-buffer test runs are supported
+            // since kernel 5.18
+            return;
+        }
+        let mut prog = Program::new("xdp", "frame_len", &frame_len()).unwrap();
+        prog.set_xdp_frags();
+        prog.load(0, "GPL".to_string()).unwrap();
+        let fd = prog.fd.unwrap();
+
+        // a jumbo frame doesn't fit the linear part, and is passed on in
+        // fragments, of which the helper counts all
+        let mut jumbo = packet(0x0800, 17, 53);
+        jumbo.resize(9000, 0xaa);
+        assert_eq!(test_run(fd, &jumbo).unwrap().0, 9000);
+        // which it does for single buffers too
+        let small = packet(0x0800, 17, 53);
+        assert_eq!(test_run(fd, &small).unwrap().0, small.len() as u32);
+    }
+
+    /// Returns the length of the whole frame, like `XdpContext::frame_len`,
+    /// if the linear part of the frame is shorter than 9000 bytes, and 0
+    /// otherwise.
+    fn frame_len() -> Vec<u8> {
+        [
+            insn(0xbf, 6, 1, 0, 0),    // r6 = ctx
+            insn(0x85, 0, 0, 0, 188),  // call bpf_xdp_get_buff_len
+            insn(0x61, 2, 6, 0, 0),    // r2 = ctx->data
+            insn(0x61, 3, 6, 4, 0),    // r3 = ctx->data_end
+            insn(0x07, 2, 0, 0, 9000), // r2 += 9000
+            insn(0xbd, 2, 3, 1, 0),    // if r2 <= r3 goto linear
+            insn(0x95, 0, 0, 0, 0),    // exit
+            insn(0xb7, 0, 0, 0, 0),    // linear: r0 = 0
+            insn(0x95, 0, 0, 0, 0),    // exit
+        ]
+        .concat()
+    }
+
+    /// Drops packets with `XdpContext::drop_with_reason(reason)`, counting
+    /// them in the per-CPU array `map`.
+    fn drop_with_reason(map: RawFd, reason: i32) -> Vec<u8> {
+        [
+            insn(0x62, 10, 0, -4, reason), // *(u32 *)(r10 - 4) = reason
+            insn(0xbf, 2, 10, 0, 0),       // r2 = r10
+            insn(0x07, 2, 0, 0, -4),       // r2 -= 4
+            insn(0x18, 1, 1, 0, map),      // r1 = map
+            insn(0, 0, 0, 0, 0),           //
+            insn(0x85, 0, 0, 0, 1),        // call bpf_map_lookup_elem
+            insn(0x15, 0, 0, 3, 0),        // if r0 == 0 goto drop
+            insn(0x79, 1, 0, 0, 0),        // r1 = *(u64 *)r0
+            insn(0x07, 1, 0, 0, 1),        // r1 += 1
+            insn(0x7b, 0, 1, 0, 0),        // *(u64 *)r0 = r1
+            insn(0xb7, 0, 0, 0, 1),        // drop: r0 = XDP_DROP
+            insn(0x95, 0, 0, 0, 0),        // exit
+        ]
+        .concat()
+    }
+
+    /// Stores the interface the packet leaves through in the array `map`.
+    fn store_egress_ifindex(map: RawFd) -> Vec<u8> {
+        [
+            insn(0x61, 6, 1, 20, 0),  // r6 = ctx->egress_ifindex
+            insn(0x62, 10, 0, -4, 0), // *(u32 *)(r10 - 4) = 0
+            insn(0xbf, 2, 10, 0, 0),  // r2 = r10
+            insn(0x07, 2, 0, 0, -4),  // r2 -= 4
+            insn(0x18, 1, 1, 0, map), // r1 = map
+            insn(0, 0, 0, 0, 0),      //
+            insn(0x85, 0, 0, 0, 1),   // call bpf_map_lookup_elem
+            insn(0x15, 0, 0, 1, 0),   // if r0 == 0 goto pass
+            insn(0x63, 0, 6, 0, 0),   // *(u32 *)r0 = r6
+            insn(0xb7, 0, 0, 0, 2),   // pass: r0 = XDP_PASS
+            insn(0x95, 0, 0, 0, 0),   // exit
+        ]
+        .concat()
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_drop_with_reason() {
+        let type_ = bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY;
+        let map = create_map(DROP_REASONS_MAP, type_, 4, 8, 64);
+        let bad_port = load_program("xdp", "bad_port", &drop_with_reason(map.fd, 1));
+        let not_ip = load_program("xdp", "not_ip", &drop_with_reason(map.fd, 2));
+        // out of range, dropped without being counted
+        let unknown = load_program("xdp", "unknown", &drop_with_reason(map.fd, 64));
+        let module = Module {
+            programs: vec![bad_port, not_ip, unknown],
+            maps: vec![map],
+            license: "GPL".to_string(),
+            version: 0,
+        };
+
+        let http = packet(0x0800, 6, 80);
+        for (name, runs) in [("bad_port", 3), ("not_ip", 1), ("unknown", 2)].iter() {
+            let run = module.xdp_test_runner(name).unwrap();
+            for _ in 0..*runs {
+                assert_eq!(run(&http).unwrap().0, XdpAction::Drop);
+            }
+        }
+        let reasons = module.drop_reasons();
+        assert_eq!(reasons.len(), 64);
+        assert_eq!(&reasons[..3], &[0, 3, 1]);
+        assert!(reasons[3..].iter().all(|&count| count == 0));
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_egress_ifindex() {
+        in_netns(|| {
+            let egress = create_map("egress", bpf_sys::bpf_map_type_BPF_MAP_TYPE_ARRAY, 4, 4, 1);
+            let code = store_egress_ifindex(egress.fd);
+            // only devmap programs know the egress interface
+            let mut prog = Program::new("xdp", "xdp_egress", &code).unwrap();
+            assert!(prog.load(0, "GPL".to_string()).is_err());
+            let mut prog = Program::new("xdp", "devmap_egress", &code).unwrap();
+            prog.set_xdp_devmap();
+            prog.load(0, "GPL".to_string()).unwrap();
+
+            let (veth, _) = create_veth("egress0", "egress1");
+            let devmap = create_map("devmap", bpf_sys::bpf_map_type_BPF_MAP_TYPE_DEVMAP, 4, 8, 1);
+            // r1 = devmap; r2 = 0; r3 = 0; call bpf_redirect_map; exit
+            let code = [
+                insn(0x18, 1, 1, 0, devmap.fd),
+                insn(0, 0, 0, 0, 0),
+                insn(0xb7, 2, 0, 0, 0),
+                insn(0xb7, 3, 0, 0, 0),
+                insn(0x85, 0, 0, 0, 51),
+                insn(0x95, 0, 0, 0, 0),
+            ]
+            .concat();
+            let redirect = load_program("xdp", "redirect", &code);
+
+            // struct bpf_devmap_val
+            let entry = |prog: &Program| [veth, prog.fd.unwrap() as u32];
+            let update = |value: [u32; 2]| unsafe {
+                let key = &0u32 as *const _ as *const u8;
+                map_update_elem(devmap.fd, key, value.as_ptr() as *const u8, 0)
+            };
+            assert!(update(entry(&redirect)).is_err());
+            update(entry(&prog)).unwrap();
+
+            // XDP test runs only redirect packets with live frames
+            let packet = vec![0xff; 64];
+            let mut attr = TestRunAttr {
+                prog_fd: redirect.fd.unwrap() as u32,
+                data_size_in: packet.len() as u32,
+                data_in: packet.as_ptr() as u64,
+                repeat: 1,
+                flags: BPF_F_TEST_XDP_LIVE_FRAMES,
+                ..Default::default()
+            };
+            match prog_test_run(&mut attr) {
+                Ok(()) => (),
+                // live frames are supported since kernel 5.18
+                Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => return,
+                Err(e) => panic!("{}", e),
+            }
+            let mut ifindex = 0u32;
+            unsafe {
+                let key = &0u32 as *const _ as *const u8;
+                map_lookup_elem(egress.fd, key, &mut ifindex as *mut _ as *mut u8, 0).unwrap();
+            }
+            assert_eq!(ifindex, veth);
+        });
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn test_syncookie() {
+        in_netns(|| {
+            // answer SYNs with cookies even while the backlog isn't full
+            std::fs::write("/proc/sys/net/ipv4/tcp_syncookies", "2").unwrap();
+            let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let gen = load_program("xdp", "gen_syncookie", &syncookie(110));
+            let check = load_program("xdp", "check_syncookie", &syncookie(100));
+            let (syn, ack) = (0x02, 0x10);
+
+            let ret = run_syncookie(gen.fd.unwrap(), &tcp_packet(port, syn, 1000, 0));
+            assert!(ret >= 0, "bpf_tcp_gen_syncookie failed with {}", ret);
+            let (cookie, mss) = (ret as u32, (ret >> 32) as u16);
+            assert!(mss > 0);
+            // SYN cookies are only made for SYNs
+            let ret = run_syncookie(gen.fd.unwrap(), &tcp_packet(port, ack, 1000, 0));
+            assert_eq!(ret, -libc::EINVAL as i64);
+            // and for ports someone listens on
+            let ret = run_syncookie(gen.fd.unwrap(), &tcp_packet(port ^ 1, syn, 1000, 0));
+            assert_eq!(ret, -1);
+
+            let valid = tcp_packet(port, ack, 1001, cookie.wrapping_add(1));
+            assert_eq!(run_syncookie(check.fd.unwrap(), &valid), 0);
+            let forged = tcp_packet(port, ack, 1001, cookie.wrapping_add(2));
+            assert_eq!(
+                run_syncookie(check.fd.unwrap(), &forged),
+                -libc::ENOENT as i64
+            );
+        });
+    }
+}
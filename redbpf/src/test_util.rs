@@ -0,0 +1,132 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Fixtures shared by the tests.
+//!
+//! Creating maps, and loading and attaching programs, takes `CAP_BPF` and
+//! `CAP_NET_ADMIN`, or root. Tests doing so are `#[ignore]`d, and fail
+//! rather than skip when run without them:
+//!
+//! ```text
+//! sudo -E cargo test -- --ignored
+//! ```
+
+use crate::sys::bpf::{prog_test_run, TestRunAttr};
+use crate::sys::netlink::{Message, Socket, NLM_F_ACK, NLM_F_CREATE, NLM_F_EXCL, NLM_F_REQUEST};
+use crate::{map_def_bytes, Map, Program};
+use bpf_sys::bpf_map_def;
+use std::ffi::CString;
+use std::io;
+use std::thread;
+
+/// Encodes an instruction, with `src` and `dst` registers.
+pub(crate) fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Vec<u8> {
+    let mut insn = vec![code, src << 4 | dst];
+    insn.extend_from_slice(&off.to_le_bytes());
+    insn.extend_from_slice(&imm.to_le_bytes());
+    insn
+}
+
+/// Returns the definition of a map without flags.
+pub(crate) fn map_def(type_: u32, key_size: u32, value_size: u32, max_entries: u32) -> bpf_map_def {
+    bpf_map_def {
+        type_,
+        key_size,
+        value_size,
+        max_entries,
+        map_flags: 0,
+    }
+}
+
+/// Creates a map without flags.
+pub(crate) fn create_map(
+    name: &str,
+    type_: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+) -> Map {
+    let def = map_def(type_, key_size, value_size, max_entries);
+    Map::load(name, map_def_bytes(&def)).unwrap()
+}
+
+/// Runs the loaded program `prog` once on `data`, with `BPF_PROG_TEST_RUN`,
+/// and returns what it returned and the data it left behind.
+pub(crate) fn test_run(prog: &Program, data: &[u8]) -> (u32, Vec<u8>) {
+    let mut out = vec![0u8; data.len() + 256];
+    let mut attr = TestRunAttr {
+        prog_fd: prog.fd.unwrap() as u32,
+        data_size_in: data.len() as u32,
+        data_size_out: out.len() as u32,
+        data_in: data.as_ptr() as u64,
+        data_out: out.as_mut_ptr() as u64,
+        repeat: 1,
+        ..Default::default()
+    };
+    prog_test_run(&mut attr).unwrap();
+    out.truncate(attr.data_size_out as usize);
+    (attr.retval, out)
+}
+
+/// Runs `f` in a network namespace of its own, on a thread of its own, so
+/// that it can attach programs to the loopback device, with ifindex 1,
+/// without disturbing the host. The namespace goes away with the thread.
+pub(crate) fn in_netns<F: FnOnce() + Send + 'static>(f: F) {
+    thread::spawn(move || {
+        if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+            panic!("unshare: {}", io::Error::last_os_error());
+        }
+        f()
+    })
+    .join()
+    .unwrap();
+}
+
+/// Creates a pair of veth devices named `name` and `peer`, both up, and
+/// returns their ifindexes. Meant to be called in `in_netns`.
+pub(crate) fn create_veth(name: &str, peer: &str) -> (u32, u32) {
+    const IFLA_IFNAME: u16 = 3;
+    const IFLA_LINKINFO: u16 = 18;
+    const IFLA_INFO_KIND: u16 = 1;
+    const IFLA_INFO_DATA: u16 = 2;
+    const VETH_INFO_PEER: u16 = 1;
+
+    // struct ifinfomsg, setting IFF_UP
+    let mut ifinfomsg = vec![0u8; 8];
+    ifinfomsg.extend_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+    ifinfomsg.extend_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+    // the peer's ifinfomsg, followed by its IFLA_IFNAME
+    let mut peer_info = ifinfomsg.clone();
+    peer_info.extend_from_slice(&(4 + peer.len() as u16 + 1).to_ne_bytes());
+    peer_info.extend_from_slice(&IFLA_IFNAME.to_ne_bytes());
+    peer_info.extend_from_slice(peer.as_bytes());
+    peer_info.resize((peer_info.len() + 1 + 3) & !3, 0);
+
+    let flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL;
+    let mut msg = Message::new(libc::RTM_NEWLINK, flags, &ifinfomsg);
+    msg.attr_str(IFLA_IFNAME, name)
+        .begin_nested(IFLA_LINKINFO)
+        .attr_str(IFLA_INFO_KIND, "veth")
+        .begin_nested(IFLA_INFO_DATA)
+        .attr(VETH_INFO_PEER, &peer_info)
+        .end_nested()
+        .end_nested();
+    Socket::open().unwrap().request(&mut msg).unwrap();
+
+    let ifindex = |name: &str| {
+        let name = CString::new(name).unwrap();
+        unsafe { libc::if_nametoindex(name.as_ptr()) }
+    };
+    (ifindex(name), ifindex(peer))
+}
+
+/// Loads a GPL program of kind `kind`, see `ProgramKind::from_section`.
+pub(crate) fn load_program(kind: &str, name: &str, code: &[u8]) -> Program {
+    let mut prog = Program::new(kind, name, code).unwrap();
+    prog.load(0, "GPL".to_string()).unwrap();
+    prog
+}
@@ -0,0 +1,147 @@
+// Copyright 2019 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # BPF tokens
+//!
+//! On kernel 6.9 or later, a privileged process can delegate a subset of
+//! BPF to an unprivileged one, such as a container, through a BPF token.
+//! Maps and programs created through the token only need `CAP_BPF` and
+//! friends in the user namespace the token belongs to.
+//!
+//! Tokens are created from a BPF filesystem that was mounted with
+//! delegation options. The setup takes both sides:
+//!
+//! 1. The unprivileged child unshares a user namespace (and mount
+//!    namespace), calls `fsopen("bpf")` and hands the fs fd to the manager,
+//!    e.g. over a unix socket.
+//! 2. The privileged manager sets what may be delegated through `fsconfig`:
+//!    `delegate_cmds`, `delegate_maps`, `delegate_progs` and
+//!    `delegate_attachs`, each a `:` separated list or `any`. It then
+//!    creates the filesystem with `FSCONFIG_CMD_CREATE`, mounts it with
+//!    `fsmount` and sends the mount fd back.
+//! 3. The child creates the token from the mount, with
+//!    `BpfToken::from_bpffs_fd`, or from its path once it is attached to the
+//!    child's mount tree.
+//!
+//! The token must be created inside the user namespace the filesystem was
+//! opened in. Tokens can't be created in the initial user namespace, where
+//! the kernel fails with `EOPNOTSUPP`.
+//!
+//! The child loading a module through the token:
+//!
+//! ```no_run
+//! use redbpf::{BpfToken, Module};
+//!
+//! // mounted by the manager with delegate_cmds=any, delegate_maps=any, ...
+//! let token = BpfToken::from_bpffs("/sys/fs/bpf").unwrap();
+//! let code = std::fs::read("bpf.elf").unwrap();
+//! let mut module = Module::parse_with_token(&code, &token).unwrap();
+//! for prog in module.programs.iter_mut() {
+//!     prog.load(module.version, module.license.clone()).unwrap();
+//! }
+//! ```
+//!
+//! The manager's side, given the fs fd `fs_fd` received from the child:
+//!
+//! ```no_run
+//! use std::ffi::CString;
+//!
+//! # let fs_fd = 3;
+//! const FSCONFIG_SET_STRING: libc::c_uint = 1;
+//! const FSCONFIG_CMD_CREATE: libc::c_uint = 6;
+//! for key in &["delegate_cmds", "delegate_maps", "delegate_progs", "delegate_attachs"] {
+//!     let key = CString::new(*key).unwrap();
+//!     let value = CString::new("any").unwrap();
+//!     unsafe {
+//!         libc::syscall(libc::SYS_fsconfig, fs_fd, FSCONFIG_SET_STRING, key.as_ptr(), value.as_ptr(), 0);
+//!     }
+//! }
+//! let mnt_fd = unsafe {
+//!     libc::syscall(libc::SYS_fsconfig, fs_fd, FSCONFIG_CMD_CREATE, 0, 0, 0);
+//!     libc::syscall(libc::SYS_fsmount, fs_fd, 0, 0)
+//! };
+//! // send mnt_fd back to the child, which calls BpfToken::from_bpffs_fd
+//! ```
+
+use crate::sys::bpf::token_create;
+use crate::uname::get_kernel_internal_version;
+use crate::{LoadError, Result};
+use libc::{close, open, O_CLOEXEC, O_DIRECTORY, O_RDONLY};
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+const TOKEN_KERNEL_VERSION: u32 = 6 << 16 | 9 << 8;
+
+/// A BPF token, through which maps and programs can be created without
+/// being privileged in the initial user namespace.
+///
+/// The token must outlive the loading of the maps and programs created
+/// through it, which keep working once it's dropped.
+pub struct BpfToken {
+    fd: RawFd,
+}
+
+impl BpfToken {
+    /// Creates a token from the BPF filesystem mounted at `path`.
+    pub fn from_bpffs<P: AsRef<Path>>(path: P) -> Result<BpfToken> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        let bpffs = unsafe { open(path.as_ptr(), O_RDONLY | O_DIRECTORY | O_CLOEXEC) };
+        if bpffs < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let token = BpfToken::from_bpffs_fd(bpffs);
+        unsafe { close(bpffs) };
+        token
+    }
+
+    /// Creates a token from the BPF filesystem `bpffs_fd`, which is either a
+    /// directory of the filesystem or the fd returned by `fsmount`.
+    ///
+    /// Fails with `LoadError::KernelRelease` on kernels older than 6.9.
+    pub fn from_bpffs_fd(bpffs_fd: RawFd) -> Result<BpfToken> {
+        match get_kernel_internal_version() {
+            Some(version) if version >= TOKEN_KERNEL_VERSION => (),
+            _ => {
+                return Err(LoadError::KernelRelease(
+                    "BPF tokens need kernel 6.9 or later".to_string(),
+                ))
+            }
+        }
+        let fd = token_create(bpffs_fd)?;
+        Ok(BpfToken { fd })
+    }
+}
+
+impl AsRawFd for BpfToken {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for BpfToken {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_bpffs() {
+        assert!(BpfToken::from_bpffs("/nonexistent").is_err());
+        // only BPF filesystems mounted with delegation options hand out tokens
+        assert!(BpfToken::from_bpffs("/").is_err());
+    }
+}